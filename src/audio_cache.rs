@@ -0,0 +1,280 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use serde::{Deserialize, Serialize};
+use tracing::{error, info, warn};
+
+use crate::youtube_dl::{AudioMetadata, Downloader};
+
+/// Keeps the audio of played tracks on disk so they don't have to be downloaded again.
+///
+/// Every track is stored as `<dir>/<md5 of webpage url>` next to a `.json` file
+/// with its metadata. The least recently used tracks are removed once the
+/// cache grows beyond `max_size` bytes.
+pub struct AudioCache {
+    dir: PathBuf,
+    max_size: u64,
+    index: Mutex<CacheIndex>,
+    downloader: Arc<Downloader>,
+}
+
+#[derive(Default)]
+struct CacheIndex {
+    entries: HashMap<String, CacheEntry>,
+    downloading: HashSet<String>,
+}
+
+struct CacheEntry {
+    size: u64,
+    last_used: SystemTime,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CachedMetadata {
+    webpage_url: String,
+    title: String,
+    thumbnail: Option<String>,
+    duration: Option<f64>,
+}
+
+impl AudioCache {
+    pub fn new(dir: PathBuf, max_size: u64, downloader: Arc<Downloader>) -> io::Result<Self> {
+        fs::create_dir_all(&dir)?;
+        // gstreamer needs an absolute path to play the files
+        let dir = dir.canonicalize()?;
+
+        let mut index = CacheIndex::default();
+        for entry in fs::read_dir(&dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            if path.extension().is_some() {
+                continue;
+            }
+
+            let key = match path.file_name().and_then(|n| n.to_str()) {
+                Some(key) => key.to_owned(),
+                None => continue,
+            };
+
+            if !path.with_extension("json").exists() {
+                // Left over from an interrupted download
+                let _ = fs::remove_file(&path);
+                continue;
+            }
+
+            let metadata = entry.metadata()?;
+            index.entries.insert(
+                key,
+                CacheEntry {
+                    size: metadata.len(),
+                    last_used: metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH),
+                },
+            );
+        }
+
+        let cache = Self {
+            dir,
+            max_size,
+            index: Mutex::new(index),
+            downloader,
+        };
+        // The limit may have been lowered since the last start
+        cache.evict();
+
+        info!(
+            "Audio cache in {:?} holds {} tracks ({} bytes)",
+            cache.dir,
+            cache.index.lock().unwrap().entries.len(),
+            cache.size()
+        );
+
+        Ok(cache)
+    }
+
+    fn key_of(webpage_url: &str) -> String {
+        format!("{:x}", md5::compute(webpage_url))
+    }
+
+    fn size(&self) -> u64 {
+        self.index
+            .lock()
+            .unwrap()
+            .entries
+            .values()
+            .map(|e| e.size)
+            .sum()
+    }
+
+    /// Returns the cached track with a `file://` url if the track is in the cache
+    pub fn get(&self, metadata: &AudioMetadata) -> Option<AudioMetadata> {
+        let key = Self::key_of(&metadata.webpage_url);
+        let path = self.dir.join(&key);
+
+        {
+            let mut index = self.index.lock().unwrap();
+            let entry = index.entries.get_mut(&key)?;
+            entry.last_used = SystemTime::now();
+        }
+
+        let cached: CachedMetadata = match fs::read(path.with_extension("json"))
+            .map_err(|e| e.to_string())
+            .and_then(|data| serde_json::from_slice(&data).map_err(|e| e.to_string()))
+        {
+            Ok(cached) => cached,
+            Err(e) => {
+                warn!("Failed to read cached metadata of {}: {}", key, e);
+                self.remove(&key);
+                return None;
+            }
+        };
+
+        info!(
+            "{} Playing {} from cache",
+            metadata.request_id, cached.webpage_url
+        );
+
+        Some(AudioMetadata {
+            url: format!("file://{}", path.display()),
+            webpage_url: cached.webpage_url,
+            title: cached.title,
+            thumbnail: cached.thumbnail,
+            duration: cached.duration.map(Duration::from_secs_f64),
+            artist: metadata.artist.clone(),
+            track: metadata.track.clone(),
+            album: metadata.album.clone(),
+            recording: metadata.recording.clone(),
+            chapters: metadata.chapters.clone(),
+            added_by: metadata.added_by.clone(),
+            request_id: metadata.request_id,
+        })
+    }
+
+    /// Downloads the track into the cache unless it is already cached
+    pub async fn store(self: Arc<Self>, metadata: AudioMetadata) {
+        // Live streams have no duration and would be downloaded until they end
+        if metadata.duration.is_none() {
+            return;
+        }
+
+        let key = Self::key_of(&metadata.webpage_url);
+        {
+            let mut index = self.index.lock().unwrap();
+            if index.entries.contains_key(&key) || !index.downloading.insert(key.clone()) {
+                return;
+            }
+        }
+
+        let request_id = metadata.request_id;
+        let path = self.dir.join(&key);
+        info!("{} Caching {}", request_id, metadata.webpage_url);
+
+        let result = self
+            .downloader
+            .download_audio(&metadata.webpage_url, &path, self.max_size, request_id)
+            .await;
+
+        self.index.lock().unwrap().downloading.remove(&key);
+
+        if let Err(e) = result {
+            warn!("{} Failed to cache track: {}", request_id, e);
+            let _ = fs::remove_file(&path);
+            return;
+        }
+        if !path.exists() {
+            info!("{} Track is larger than the audio cache", request_id);
+            return;
+        }
+
+        if let Err(e) = self.insert(key, &path, &metadata) {
+            error!("{} Failed to add track to cache: {}", request_id, e);
+        }
+    }
+
+    fn insert(&self, key: String, path: &Path, metadata: &AudioMetadata) -> io::Result<()> {
+        // The size of some downloads is only known once they are done
+        let size = fs::metadata(path)?.len();
+        if size > self.max_size {
+            info!("Track {} is larger than the audio cache", key);
+            fs::remove_file(path)?;
+            return Ok(());
+        }
+
+        let cached = CachedMetadata {
+            webpage_url: metadata.webpage_url.clone(),
+            title: metadata.title.clone(),
+            thumbnail: metadata.thumbnail.clone(),
+            duration: metadata.duration.map(|d| d.as_secs_f64()),
+        };
+        fs::write(path.with_extension("json"), serde_json::to_vec(&cached)?)?;
+
+        self.index.lock().unwrap().entries.insert(
+            key,
+            CacheEntry {
+                size,
+                last_used: SystemTime::now(),
+            },
+        );
+
+        self.evict();
+
+        Ok(())
+    }
+
+    /// Removes the least recently used tracks until the cache fits into `max_size`
+    fn evict(&self) {
+        let mut size = self.size();
+
+        while size > self.max_size {
+            let oldest = {
+                let index = self.index.lock().unwrap();
+                index
+                    .entries
+                    .iter()
+                    .min_by_key(|(_, e)| e.last_used)
+                    .map(|(key, e)| (key.clone(), e.size))
+            };
+
+            match oldest {
+                Some((key, entry_size)) => {
+                    info!("Evicting {} from audio cache", key);
+                    self.remove(&key);
+                    size -= entry_size;
+                }
+                None => break,
+            }
+        }
+    }
+
+    fn remove(&self, key: &str) {
+        self.index.lock().unwrap().entries.remove(key);
+
+        let path = self.dir.join(key);
+        let _ = fs::remove_file(path.with_extension("json"));
+        if let Err(e) = fs::remove_file(&path) {
+            if e.kind() != io::ErrorKind::NotFound {
+                error!("Failed to remove {:?} from audio cache: {}", path, e);
+            }
+        }
+    }
+}
+
+/// Opens the cache if a directory is configured, caching is disabled if that fails
+pub fn open(
+    dir: Option<PathBuf>,
+    max_size_mb: u64,
+    downloader: Arc<Downloader>,
+) -> Option<Arc<AudioCache>> {
+    let dir = dir?;
+
+    match AudioCache::new(dir, max_size_mb * 1024 * 1024, downloader) {
+        Ok(cache) => Some(Arc::new(cache)),
+        Err(e) => {
+            error!("Failed to open audio cache, caching is disabled: {}", e);
+            None
+        }
+    }
+}
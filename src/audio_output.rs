@@ -0,0 +1,163 @@
+//! Playing audio on a device of the machine the bot runs on.
+//!
+//! Used by local mode instead of sending the audio to a server and for
+//! listening in on what the music bots play.
+
+use std::fmt;
+
+use gst::prelude::*;
+use gstreamer as gst;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::audio_player::{init_gstreamer, AudioPlayerError};
+
+/// The sound system the audio is played with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AudioBackend {
+    /// Whatever GStreamer finds first
+    Auto,
+    Alsa,
+    Pulse,
+    Jack,
+}
+
+impl Default for AudioBackend {
+    fn default() -> Self {
+        AudioBackend::Auto
+    }
+}
+
+impl fmt::Display for AudioBackend {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            AudioBackend::Auto => "auto",
+            AudioBackend::Alsa => "alsa",
+            AudioBackend::Pulse => "pulse",
+            AudioBackend::Jack => "jack",
+        };
+
+        f.write_str(name)
+    }
+}
+
+impl AudioBackend {
+    fn factory(self) -> &'static str {
+        match self {
+            AudioBackend::Auto => "autoaudiosink",
+            AudioBackend::Alsa => "alsasink",
+            AudioBackend::Pulse => "pulsesink",
+            AudioBackend::Jack => "jackaudiosink",
+        }
+    }
+
+    /// The property of the sink that selects the device
+    fn device_property(self) -> Option<&'static str> {
+        match self {
+            AudioBackend::Auto => None,
+            AudioBackend::Alsa | AudioBackend::Pulse => Some("device"),
+            AudioBackend::Jack => Some("port-pattern"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AudioOutputConfig {
+    pub backend: AudioBackend,
+    /// A device as listed by `--list-devices`, the default device of the backend if not set
+    pub device: Option<String>,
+    /// The music bot whose audio is also played on this device
+    pub monitor: Option<String>,
+}
+
+/// A device audio can be played on
+#[derive(Debug, Clone)]
+pub struct OutputDevice {
+    pub name: String,
+    pub backend: Option<AudioBackend>,
+    /// What to put into `device`, if the backend has a name for it
+    pub device: Option<String>,
+}
+
+impl AudioOutputConfig {
+    /// If the audio of the music bot is played on the device as well
+    pub fn monitors(&self, bot: &str) -> bool {
+        self.monitor.as_deref() == Some(bot)
+    }
+}
+
+/// Creates the sink that plays to the configured device.
+///
+/// Falls back to the default device of the backend if the configured one can
+/// not be opened, e.g. because it was unplugged.
+pub fn make_sink(config: &AudioOutputConfig) -> Result<gst::Element, AudioPlayerError> {
+    let sink = gst::ElementFactory::make(config.backend.factory(), Some("audio output"))?;
+
+    if let Some(device) = &config.device {
+        match config.backend.device_property() {
+            Some(property) => {
+                sink.set_property(property, device)?;
+
+                // Opening the sink fails if the device is missing
+                let opened = sink.set_state(gst::State::Ready).is_ok();
+                let _ = sink.set_state(gst::State::Null);
+                if !opened {
+                    warn!(
+                        "The audio device {:?} is not available, using the default device",
+                        device
+                    );
+                    return Ok(gst::ElementFactory::make(
+                        config.backend.factory(),
+                        Some("audio output"),
+                    )?);
+                }
+            }
+            None => warn!(
+                "Ignoring the audio device {:?}, a backend has to be set to choose one",
+                device
+            ),
+        }
+    }
+
+    Ok(sink)
+}
+
+/// The audio devices GStreamer can play to
+pub fn list_devices() -> Result<Vec<OutputDevice>, AudioPlayerError> {
+    init_gstreamer();
+
+    let monitor = gst::DeviceMonitor::new();
+    monitor.add_filter(Some("Audio/Sink"), None);
+    monitor.start()?;
+    let devices = monitor.get_devices();
+    monitor.stop();
+
+    Ok(devices
+        .into_iter()
+        .map(|device| {
+            let element = device.create_element(None);
+            let backend = element
+                .as_ref()
+                .and_then(|e| e.get_factory())
+                .and_then(|f| match f.get_name().as_str() {
+                    "alsasink" => Some(AudioBackend::Alsa),
+                    "pulsesink" => Some(AudioBackend::Pulse),
+                    "jackaudiosink" => Some(AudioBackend::Jack),
+                    _ => None,
+                });
+            let device_name = backend
+                .and_then(AudioBackend::device_property)
+                .zip(element.as_ref())
+                .and_then(|(property, e)| e.get_property(property).ok())
+                .and_then(|value| value.get::<String>().ok().flatten());
+
+            OutputDevice {
+                name: device.get_display_name().to_string(),
+                backend,
+                device: device_name,
+            }
+        })
+        .collect())
+}
@@ -1,38 +1,252 @@
-use std::sync::Once;
-use std::time::Duration;
+use std::fmt;
+use std::io::Read;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Mutex, Once};
+use std::time::{Duration, Instant};
 
 use gst::prelude::*;
 use gst::GhostPad;
 use gstreamer as gst;
-use gstreamer_app::{AppSink, AppSinkCallbacks};
+use gstreamer_app::{AppSink, AppSinkCallbacks, AppSrc};
 use gstreamer_audio::{StreamVolume, StreamVolumeFormat};
+use serde::{Deserialize, Serialize};
 
-use crate::bot::{MusicBotMessage, State};
+use crate::audio_output::{self, AudioOutputConfig};
+use crate::bot::{MessageSender, MusicBotMessage, State};
+use crate::bot_log::BotLog;
 use glib::BoolError;
-use log::{debug, error, info, warn};
 use std::sync::{Arc, RwLock};
-use tokio::sync::mpsc::UnboundedSender;
+use tracing::{debug, error, info, warn};
 
 use crate::command::{Seek, VolumeChange};
-use crate::youtube_dl::AudioMetadata;
+use crate::request_id::RequestId;
+use crate::resource_usage::{ResourceCounters, ResourceUsage};
+use crate::youtube_dl::{AudioMetadata, AudioStream};
 
 static GST_INIT: Once = Once::new();
 
+/// Initializes GStreamer once for the whole process
+pub fn init_gstreamer() {
+    GST_INIT.call_once(|| gst::init().unwrap());
+}
+
 #[derive(PartialEq, Eq, Debug, Clone, Copy)]
 pub enum PollResult {
     Continue,
+    /// The preloaded track followed the last one without a gap
+    NextTrack,
     Quit,
 }
 
 pub struct AudioPlayer {
     pipeline: gst::Pipeline,
     bus: gst::Bus,
-    http_src: gst::Element,
-
+    /// Sink pad of the audio bin which the current source is linked to
+    audio_sink: gst::GhostPad,
+    /// Sink pad of the audio bin for Opus that is sent without transcoding
+    opus_sink: Option<gst::GhostPad>,
+    /// Set while Opus sources can be passed through, only exists if the player encodes
+    passthrough: Option<Arc<AtomicBool>>,
+    /// Reads and decodes the current track, replaced for every track
+    source: Mutex<Option<gst::Bin>>,
+    preloaded: Arc<Mutex<Option<Preloaded>>>,
+
+    /// The volume of the bot, kept across tracks
     volume_f64: RwLock<f64>,
+    /// Multiplied with the volume for the current track only
+    track_volume: RwLock<f64>,
+    /// Multiplied with the volume while someone is talking, 1 otherwise
+    ducking: RwLock<f64>,
+    /// Silences the output while the track keeps playing
+    muted: RwLock<bool>,
     volume: gst::Element,
-    sender: Arc<RwLock<UnboundedSender<MusicBotMessage>>>,
+    fade: Arc<Mutex<Fade>>,
+    equalizer: gst::Element,
+    pitch: Option<gst::Element>,
+    effects: RwLock<Effects>,
+    /// Only exists if the player encodes, local players play to the speakers
+    encoder: Option<gst::Element>,
+    encoder_settings: RwLock<EncoderSettings>,
+    sender: Arc<RwLock<MessageSender>>,
+    log: Arc<BotLog>,
     currently_playing: RwLock<Option<AudioMetadata>>,
+    resources: Arc<ResourceCounters>,
+    progress: Arc<Mutex<Progress>>,
+    /// Where to continue once a restarted track plays again
+    restart_position: Mutex<Option<Duration>>,
+}
+
+/// The next track, decoded ahead so it can follow the current one without a gap
+struct Preloaded {
+    source: gst::Bin,
+    metadata: AudioMetadata,
+    /// The decoded audio held back by a blocking probe, set once the decoder found it
+    pad: Option<(gst::GhostPad, gst::PadProbeId)>,
+    /// The undecoded audio of Opus sources, held back the same way
+    opus_pad: Option<(gst::GhostPad, gst::PadProbeId)>,
+    /// Set once the audio bin reads from it instead of the current track
+    linked: bool,
+}
+
+/// How much extracted audio is held back before the downloader has to wait
+const STREAM_BUFFER_BYTES: u64 = 4 * 1024 * 1024;
+const STREAM_CHUNK_BYTES: usize = 64 * 1024;
+const STREAM_SOURCE_NAME: &str = "stream source";
+const AUDIO_QUEUE_NAME: &str = "audio queue";
+
+/// Gain limits of the `equalizer-10bands` element in dB
+const EQ_MIN_GAIN: f64 = -24.0;
+const EQ_MAX_GAIN: f64 = 12.0;
+
+/// How much faster nightcore mode plays
+const NIGHTCORE_RATE: f64 = 1.25;
+
+/// How long volume changes take, stepping the volume at once makes the audio pop
+const VOLUME_FADE: Duration = Duration::from_millis(200);
+
+/// A change of the linear gain of the volume element, moved along by the buffers passing it
+#[derive(Debug, Clone, Copy)]
+struct Fade {
+    from: f64,
+    to: f64,
+    elapsed: Duration,
+}
+
+impl Fade {
+    fn done(gain: f64) -> Self {
+        Self {
+            from: gain,
+            to: gain,
+            elapsed: VOLUME_FADE,
+        }
+    }
+
+    fn is_done(&self) -> bool {
+        self.elapsed >= VOLUME_FADE
+    }
+
+    fn current(&self) -> f64 {
+        let progress = (self.elapsed.as_secs_f64() / VOLUME_FADE.as_secs_f64()).min(1.0);
+
+        self.from + (self.to - self.from) * progress
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Effects {
+    pub bands: [f64; 10],
+    pub speed: f64,
+    pub nightcore: bool,
+}
+
+impl Default for Effects {
+    fn default() -> Self {
+        Self {
+            bands: [0.0; 10],
+            speed: 1.0,
+            nightcore: false,
+        }
+    }
+}
+
+/// Bitrate limits of the Opus encoder in kbit/s
+pub const MIN_BITRATE: u32 = 6;
+pub const MAX_BITRATE: u32 = 510;
+/// Bitrate `opusenc` uses if none is set
+const DEFAULT_BITRATE: u32 = 64;
+
+/// Settings of the Opus encoder that sends the audio of a bot
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EncoderSettings {
+    /// In kbit/s, without it Opus sources are passed through as they are
+    /// and everything else is encoded at the default bitrate of the encoder
+    pub bitrate: Option<u32>,
+    /// From 0 for the least CPU time to 10 for the best quality
+    pub complexity: u8,
+    pub mono: bool,
+}
+
+impl Default for EncoderSettings {
+    fn default() -> Self {
+        Self {
+            bitrate: None,
+            complexity: 10,
+            mono: false,
+        }
+    }
+}
+
+impl EncoderSettings {
+    /// The bitrate the encoder uses in kbit/s
+    pub fn effective_bitrate(&self) -> u32 {
+        self.bitrate.unwrap_or(DEFAULT_BITRATE)
+    }
+}
+
+/// When the watchdog considers the current track stuck, a timeout of zero turns its check off.
+///
+/// Both checks are off unless a timeout is configured.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WatchdogConfig {
+    /// How long the position may stay the same while the track is playing
+    #[serde(with = "crate::duration::serde_human")]
+    pub stall_timeout: Duration,
+    /// How long the decoded audio may be nothing but silence
+    #[serde(with = "crate::duration::serde_human")]
+    pub silence_timeout: Duration,
+}
+
+impl WatchdogConfig {
+    pub fn is_enabled(&self) -> bool {
+        self.stall_timeout > Duration::from_secs(0) || self.silence_timeout > Duration::from_secs(0)
+    }
+}
+
+/// Why the watchdog considers the current track stuck
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stuck {
+    /// The position did not move for this long
+    Stalled(Duration),
+    /// Only silence was decoded for this long
+    Silent(Duration),
+}
+
+impl fmt::Display for Stuck {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Stuck::Stalled(duration) => write!(
+                f,
+                "the position did not move for {}",
+                humantime::format_duration(*duration)
+            ),
+            Stuck::Silent(duration) => write!(
+                f,
+                "only silence was decoded for {}",
+                humantime::format_duration(*duration)
+            ),
+        }
+    }
+}
+
+/// What the watchdog saw of the current track
+struct Progress {
+    position: Option<Duration>,
+    /// When the position last moved
+    moved: Instant,
+    /// When audio that is not silent was last decoded
+    sounded: Instant,
+}
+
+impl Progress {
+    fn new() -> Self {
+        Self {
+            position: None,
+            moved: Instant::now(),
+            sounded: Instant::now(),
+        }
+    }
 }
 
 fn make_element(factoryname: &str, display_name: &str) -> Result<gst::Element, AudioPlayerError> {
@@ -45,59 +259,529 @@ fn link_elements(a: &gst::Element, b: &gst::Element) -> Result<(), AudioPlayerEr
     Ok(())
 }
 
+fn media_type(pad: &gst::Pad) -> Option<String> {
+    debug!("New pad received on decode bin");
+    let caps = pad.get_current_caps()?;
+    debug!("Pad caps: {}", caps.to_string());
+
+    caps.get_structure(0).map(|s| s.get_name().to_string())
+}
+
+/// Whether the encoded audio can be sent as it is, which needs the same
+/// rate and channels the encoder of the player uses.
+fn is_passthrough_caps(caps: &gst::Caps) -> bool {
+    let structure = match caps.get_structure(0) {
+        Some(structure) => structure,
+        None => return false,
+    };
+
+    structure.get_name() == "audio/x-opus"
+        && structure.get::<i32>("rate").ok().flatten() == Some(48_000)
+        && structure.get::<i32>("channels").ok().flatten() == Some(2)
+}
+
+/// Adds the src pads of a source bin for the new pad of its decode bin.
+///
+/// Raw audio is exposed as `src`. Opus that can be passed through is exposed
+/// as `opus` as well as decoded as `src`, and only one of the two carries
+/// buffers depending on `passthrough`, so switching takes effect immediately.
+fn expose_source_pads(
+    decode_bin: &gst::Element,
+    new_pad: &gst::Pad,
+    passthrough: Option<&Arc<AtomicBool>>,
+) -> Option<(gst::Bin, GhostPad, Option<GhostPad>)> {
+    let media_type = media_type(new_pad)?;
+    let source = decode_bin
+        .get_parent()
+        .and_then(|p| p.downcast::<gst::Bin>().ok())
+        .expect("Decode bin is part of a source bin");
+
+    let (src_target, opus_target) = match (media_type.as_str(), passthrough) {
+        ("audio/x-raw", _) => (new_pad.clone(), None),
+        ("audio/x-opus", Some(passthrough)) => {
+            let (decoded, opus) = split_opus(&source, new_pad, passthrough.clone()).unwrap();
+            (decoded, Some(opus))
+        }
+        _ => return None,
+    };
+
+    let src_pad = GhostPad::with_target(Some("src"), &src_target).unwrap();
+    src_pad.set_active(true).unwrap();
+    source.add_pad(&src_pad).unwrap();
+
+    let opus_pad = opus_target.map(|target| {
+        let opus_pad = GhostPad::with_target(Some("opus"), &target).unwrap();
+        opus_pad.set_active(true).unwrap();
+        source.add_pad(&opus_pad).unwrap();
+        opus_pad
+    });
+
+    Some((source, src_pad, opus_pad))
+}
+
+/// Feeds `opus` into a decoder and a passthrough branch, returns their src pads
+fn split_opus(
+    source: &gst::Bin,
+    opus: &gst::Pad,
+    passthrough: Arc<AtomicBool>,
+) -> Result<(gst::Pad, gst::Pad), AudioPlayerError> {
+    let tee = make_element("tee", "opus tee")?;
+    let decode_queue = make_element("queue", "decode queue")?;
+    let decoder = make_element("opusdec", "opus decoder")?;
+    let passthrough_queue = make_element("queue", "passthrough queue")?;
+    source.add_many(&[&tee, &decode_queue, &decoder, &passthrough_queue])?;
+    gst::Element::link_many(&[&tee, &decode_queue, &decoder])?;
+    link_elements(&tee, &passthrough_queue)?;
+
+    let decoder_sink = decoder.get_static_pad("sink").unwrap();
+    let cpassthrough = passthrough.clone();
+    decoder_sink.add_probe(gst::PadProbeType::BUFFER, move |_, _| {
+        if cpassthrough.load(Ordering::Relaxed) {
+            gst::PadProbeReturn::Drop
+        } else {
+            gst::PadProbeReturn::Ok
+        }
+    });
+
+    let opus_src = passthrough_queue.get_static_pad("src").unwrap();
+    opus_src.add_probe(
+        gst::PadProbeType::BUFFER | gst::PadProbeType::EVENT_DOWNSTREAM,
+        move |_, info| match info.data {
+            Some(gst::PadProbeData::Buffer(_)) if !passthrough.load(Ordering::Relaxed) => {
+                gst::PadProbeReturn::Drop
+            }
+            // The decoded branch tells when the track is over
+            Some(gst::PadProbeData::Event(ref event))
+                if event.get_type() == gst::EventType::Eos =>
+            {
+                gst::PadProbeReturn::Drop
+            }
+            _ => gst::PadProbeReturn::Ok,
+        },
+    );
+
+    for element in &[&tee, &decode_queue, &decoder, &passthrough_queue] {
+        element.sync_state_with_parent()?;
+    }
+    opus.link(&tee.get_static_pad("sink").unwrap())
+        .expect("Decode bin pads can be linked to a tee");
+
+    Ok((decoder.get_static_pad("src").unwrap(), opus_src))
+}
+
+/// Links a src pad of a source to a sink pad of the audio bin, replacing what was linked before
+fn relink<P: IsA<gst::Pad>>(
+    src_pad: &GhostPad,
+    sink: &P,
+    offset: i64,
+) -> Result<(), gst::PadLinkError> {
+    if let Some(peer) = sink.get_peer() {
+        let _ = peer.unlink(sink);
+    }
+    src_pad.set_offset(offset);
+    src_pad.link(sink)?;
+
+    Ok(())
+}
+
+/// Creates a bin that reads and decodes `location`, returns it with its decode bin.
+///
+/// With `passthrough` Opus that fits the encoder is not decoded by the decode bin.
+fn make_source(
+    location: &str,
+    passthrough: bool,
+    resources: &Arc<ResourceCounters>,
+) -> Result<(gst::Bin, gst::Element), AudioPlayerError> {
+    info!("Setting location URI: {}", location);
+
+    let (src, location) = match location.strip_prefix("file://") {
+        Some(path) => (make_element("filesrc", "file source")?, path),
+        None => (make_element("souphttpsrc", "http source")?, location),
+    };
+    src.set_property("location", &location)?;
+
+    wrap_source(src, passthrough, resources)
+}
+
+/// Creates a bin that decodes audio pushed into its app source.
+///
+/// Pushing blocks while the app source holds `STREAM_BUFFER_BYTES`, so the
+/// downloader is only read as fast as the audio is played.
+fn make_stream_source(
+    passthrough: bool,
+    resources: &Arc<ResourceCounters>,
+) -> Result<(gst::Bin, gst::Element, AppSrc), AudioPlayerError> {
+    let src = make_element("appsrc", STREAM_SOURCE_NAME)?;
+    let app_src = src
+        .clone()
+        .dynamic_cast::<AppSrc>()
+        .expect("appsrc is an AppSrc");
+    app_src.set_max_bytes(STREAM_BUFFER_BYTES);
+    src.set_property("block", &true)?;
+
+    let (source, decode_bin) = wrap_source(src, passthrough, resources)?;
+
+    Ok((source, decode_bin, app_src))
+}
+
+fn wrap_source(
+    src: gst::Element,
+    passthrough: bool,
+    resources: &Arc<ResourceCounters>,
+) -> Result<(gst::Bin, gst::Element), AudioPlayerError> {
+    let source = gst::Bin::new(Some("source bin"));
+    let decode_bin = make_element("decodebin", "decode bin")?;
+    source.add_many(&[&src, &decode_bin])?;
+    link_elements(&src, &decode_bin)?;
+
+    // Runs on the thread that reads the source, which often decodes as well
+    if let Some(pad) = decode_bin.get_static_pad("sink") {
+        let resources = resources.clone();
+        pad.add_probe(gst::PadProbeType::BUFFER, move |_, info| {
+            if let Some(gst::PadProbeData::Buffer(ref buffer)) = info.data {
+                resources.claim_thread();
+                resources.add_received(buffer.get_size());
+            }
+
+            gst::PadProbeReturn::Ok
+        });
+    }
+
+    if passthrough {
+        decode_bin.connect("autoplug-continue", false, |values| {
+            let caps = values[2].get::<gst::Caps>().ok().flatten()?;
+            Some((!is_passthrough_caps(&caps)).to_value())
+        })?;
+    }
+
+    Ok((source, decode_bin))
+}
+
+/// Copies `stream` into `app_src` until either of them ends
+fn spawn_stream_reader(mut stream: AudioStream, app_src: AppSrc, request_id: RequestId) {
+    let span = tracing::Span::current();
+    std::thread::spawn(move || {
+        let _entered = span.enter();
+        let mut chunk = vec![0; STREAM_CHUNK_BYTES];
+        loop {
+            match stream.read(&mut chunk) {
+                Ok(0) => {
+                    debug!("{} Stream finished", request_id);
+                    let _ = app_src.end_of_stream();
+                    break;
+                }
+                Ok(n) => {
+                    let buffer = gst::Buffer::from_slice(chunk[..n].to_vec());
+                    // Fails once the source was removed, e.g. because the track was skipped
+                    if let Err(e) = app_src.push_buffer(buffer) {
+                        debug!("{} Stopped streaming: {:?}", request_id, e);
+                        break;
+                    }
+                }
+                Err(e) => {
+                    error!("{} Failed to read stream: {}", request_id, e);
+                    let _ = app_src.end_of_stream();
+                    break;
+                }
+            }
+        }
+        // Dropping the stream stops the downloader
+    });
+}
+
 fn add_decode_bin_new_pad_callback(
     decode_bin: &gst::Element,
-    audio_bin: gst::Bin,
-    ghost_pad: gst::GhostPad,
+    pipeline: glib::WeakRef<gst::Pipeline>,
+    audio_sink: gst::GhostPad,
+    opus_sink: Option<gst::GhostPad>,
+    passthrough: Option<Arc<AtomicBool>>,
 ) {
-    decode_bin.connect_pad_added(move |_, new_pad| {
-        debug!("New pad received on decode bin");
-        let name = if let Some(caps) = new_pad.get_current_caps() {
-            debug!("Pad caps: {}", caps.to_string());
-            if let Some(structure) = caps.get_structure(0) {
-                Some(structure.get_name().to_string())
+    decode_bin.connect_pad_added(move |decode_bin, new_pad| {
+        let (_, src_pad, opus_pad) =
+            match expose_source_pads(decode_bin, new_pad, passthrough.as_ref()) {
+                Some(pads) => pads,
+                None => return,
+            };
+
+        // Every track starts at zero, but the pipeline keeps running
+        // between tracks so it has to be moved to the current time.
+        let offset = pipeline.upgrade().map_or(0, |p| running_time(&p));
+
+        if let (Some(opus_pad), Some(opus_sink)) = (&opus_pad, &opus_sink) {
+            let passing = passthrough
+                .as_ref()
+                .map_or(false, |p| p.load(Ordering::Relaxed));
+            if passing {
+                debug!("Found Opus, passing it through without transcoding");
             } else {
-                None
+                debug!("Found Opus, transcoding it while volume or effects change it");
             }
-        } else {
-            None
+            relink(opus_pad, opus_sink, offset).unwrap();
+        }
+
+        if opus_pad.is_none() {
+            debug!("Source is not Opus, transcoding it");
+        }
+        info!("Found raw audio, linking audio bin");
+        relink(&src_pad, &audio_sink, offset).unwrap();
+    });
+}
+
+/// Keeps the decoded audio of a preloaded track waiting until the current one ends
+fn add_preload_pad_callback(
+    decode_bin: &gst::Element,
+    preloaded: Arc<Mutex<Option<Preloaded>>>,
+    passthrough: Option<Arc<AtomicBool>>,
+) {
+    decode_bin.connect_pad_added(move |decode_bin, new_pad| {
+        let (source, src_pad, opus_pad) =
+            match expose_source_pads(decode_bin, new_pad, passthrough.as_ref()) {
+                Some(pads) => pads,
+                None => return,
+            };
+
+        // The decoder fills its queues and then waits here
+        let block = |pad: GhostPad| {
+            let probe = pad
+                .add_probe(gst::PadProbeType::BLOCK_DOWNSTREAM, |_, _| {
+                    gst::PadProbeReturn::Ok
+                })
+                .expect("Blocking probes can be added to new pads");
+            (pad, probe)
         };
 
-        if let Some("audio/x-raw") = name.as_deref() {
-            if let Some(peer) = ghost_pad.get_peer() {
-                peer.unlink(&ghost_pad).unwrap();
+        let mut preloaded = preloaded.lock().unwrap();
+        match preloaded.as_mut() {
+            Some(preloaded) if preloaded.source == source => {
+                info!("Found raw audio, preloaded track is ready");
+                preloaded.pad = Some(block(src_pad));
+                preloaded.opus_pad = opus_pad.map(block);
             }
+            _ => debug!("Preloaded track was discarded while decoding"),
+        }
+    });
+}
+
+/// Lets the audio bin read the preloaded track right where the last one ended,
+/// returns false if it is not ready yet.
+///
+/// The decoders already trim their encoder padding, so nothing else is needed
+/// for the tracks to follow each other without a gap.
+fn link_preloaded(
+    preloaded: &Mutex<Option<Preloaded>>,
+    audio_sink: &gst::Pad,
+    opus_sink: Option<&gst::GhostPad>,
+    end: u64,
+) -> bool {
+    let mut preloaded = preloaded.lock().unwrap();
+    let preloaded = match preloaded.as_mut() {
+        Some(preloaded) => preloaded,
+        None => return false,
+    };
+    let (src_pad, probe) = match preloaded.pad.take() {
+        Some(pad) => pad,
+        None => return false,
+    };
+
+    if let (Some((opus_pad, probe)), Some(opus_sink)) = (preloaded.opus_pad.take(), opus_sink) {
+        if let Err(e) = relink(&opus_pad, opus_sink, end as i64) {
+            error!("Failed to link preloaded Opus: {:?}", e);
+        }
+        opus_pad.remove_probe(probe);
+    }
+    if let Err(e) = relink(&src_pad, audio_sink, end as i64) {
+        error!("Failed to link preloaded track: {:?}", e);
+        return false;
+    }
+    src_pad.remove_probe(probe);
+    preloaded.linked = true;
 
-            info!("Found raw audio, linking audio bin");
-            new_pad.link(&ghost_pad).unwrap();
+    true
+}
 
-            audio_bin.sync_state_with_parent().unwrap();
+/// Running time at which `buffer` ends, including the offset of the track
+fn buffer_end(pad: &gst::Pad, buffer: &gst::BufferRef) -> Option<u64> {
+    let end = buffer.get_pts() + buffer.get_duration();
+    let event = pad.get_sticky_event(gst::EventType::Segment, 0)?;
+    let running_time = match event.view() {
+        gst::EventView::Segment(segment) => segment
+            .get_segment()
+            .downcast_ref::<gst::ClockTime>()?
+            .to_running_time(end),
+        _ => return None,
+    };
+
+    running_time.nanoseconds()
+}
+
+/// Digital silence is zero in every sample format, integer or float
+fn is_silent(buffer: &gst::BufferRef) -> bool {
+    match buffer.map_readable() {
+        Ok(map) => map.as_slice().iter().all(|&byte| byte == 0),
+        Err(_) => false,
+    }
+}
+
+/// Tells the polling thread that the track read by `source` is over
+fn track_ended_message(source: Option<&gst::Object>, skipped: bool) -> gst::Message {
+    gst::message::Application::builder(gst::Structure::new("track-ended", &[("skipped", &skipped)]))
+        .src(source)
+        .build()
+}
+
+/// Tells the polling thread that the preloaded track followed the one read by `source`
+fn track_continued_message(source: Option<&gst::Object>) -> gst::Message {
+    gst::message::Application::builder(gst::Structure::new_empty("track-continued"))
+        .src(source)
+        .build()
+}
+
+/// How long the pipeline has been playing, which does not advance while paused
+fn running_time(pipeline: &gst::Pipeline) -> i64 {
+    let running_time = if pipeline.get_current_state() == gst::State::Playing {
+        let now = pipeline
+            .get_clock()
+            .map(|c| c.get_time())
+            .unwrap_or_default();
+        match (now.nanoseconds(), pipeline.get_base_time().nanoseconds()) {
+            (Some(now), Some(base)) => now.saturating_sub(base),
+            _ => 0,
         }
-    });
+    } else {
+        pipeline.get_start_time().nanoseconds().unwrap_or(0)
+    };
+
+    running_time as i64
 }
 
 impl AudioPlayer {
     pub fn new(
-        sender: Arc<RwLock<UnboundedSender<MusicBotMessage>>>,
+        sender: Arc<RwLock<MessageSender>>,
+        log: Arc<BotLog>,
         callback: Option<Box<dyn FnMut(&[u8]) + Send>>,
+        encoder_settings: EncoderSettings,
+        output: &AudioOutputConfig,
     ) -> Result<Self, AudioPlayerError> {
-        GST_INIT.call_once(|| gst::init().unwrap());
+        init_gstreamer();
 
         info!("Creating audio player");
 
         let pipeline = gst::Pipeline::new(Some("TeamSpeak Audio Player"));
         let bus = pipeline.get_bus().unwrap();
-        let http_src = make_element("souphttpsrc", "http source")?;
-        let decode_bin = make_element("decodebin", "decode bin")?;
-        pipeline.add_many(&[&http_src, &decode_bin])?;
 
-        link_elements(&http_src, &decode_bin)?;
+        let resources = Arc::new(ResourceCounters::default());
+        let callback = callback.map(|mut callback| {
+            let resources = resources.clone();
+            Box::new(move |samples: &[u8]| {
+                resources.add_sent(samples.len());
+                callback(samples)
+            }) as Box<dyn FnMut(&[u8]) + Send>
+        });
+
+        let (audio_bin, volume, equalizer, pitch, audio_sink, encoder, opus_sink) =
+            Self::create_audio_bin(callback, &encoder_settings, output)?;
+        pipeline.add(&audio_bin)?;
+
+        // The queue in front of the volume starts the thread that converts and encodes
+        if let Some(pad) = volume.get_static_pad("src") {
+            let resources = resources.clone();
+            pad.add_probe(gst::PadProbeType::BUFFER, move |_, _| {
+                resources.claim_thread();
+                gst::PadProbeReturn::Ok
+            });
+        }
 
-        let (audio_bin, volume, ghost_pad) = Self::create_audio_bin(callback)?;
+        // Silence is detected before the volume, turning it down is not a problem
+        let progress = Arc::new(Mutex::new(Progress::new()));
+        if let Some(pad) = volume.get_static_pad("sink") {
+            let progress = progress.clone();
+            pad.add_probe(gst::PadProbeType::BUFFER, move |_, info| {
+                if let Some(gst::PadProbeData::Buffer(ref buffer)) = info.data {
+                    if !is_silent(buffer) {
+                        progress.lock().unwrap().sounded = Instant::now();
+                    }
+                }
 
-        add_decode_bin_new_pad_callback(&decode_bin, audio_bin.clone(), ghost_pad);
+                gst::PadProbeReturn::Ok
+            });
+        }
+        // The gain is set before every buffer while fading, buffers only hold a few milliseconds
+        let fade = Arc::new(Mutex::new(Fade::done(0.0)));
+        if let Some(pad) = volume.get_static_pad("sink") {
+            let fade = fade.clone();
+            let volume = volume.downgrade();
+            pad.add_probe(gst::PadProbeType::BUFFER, move |_, info| {
+                if let Some(gst::PadProbeData::Buffer(ref buffer)) = info.data {
+                    let mut fade = fade.lock().unwrap();
+                    if !fade.is_done() {
+                        fade.elapsed += buffer
+                            .get_duration()
+                            .nseconds()
+                            .map(Duration::from_nanos)
+                            .unwrap_or(VOLUME_FADE);
+                        if let Some(volume) = volume.upgrade() {
+                            if let Err(e) = volume.set_property("volume", &fade.current()) {
+                                warn!("Failed to fade volume: {}", e);
+                            }
+                        }
+                    }
+                }
 
-        pipeline.add(&audio_bin)?;
+                gst::PadProbeReturn::Ok
+            });
+        }
+        // Opus that is passed through is not decoded, so it is never considered silent
+        if let Some(pad) = &opus_sink {
+            let progress = progress.clone();
+            pad.add_probe(gst::PadProbeType::BUFFER, move |_, _| {
+                progress.lock().unwrap().sounded = Instant::now();
+                gst::PadProbeReturn::Ok
+            });
+        }
+
+        // Where the audio of the current track ends, for the track that follows without a gap
+        let track_end = Arc::new(AtomicU64::new(0));
+        for sink in std::iter::once(&audio_sink).chain(opus_sink.as_ref()) {
+            let ctrack_end = track_end.clone();
+            let cresources = resources.clone();
+            sink.add_probe(gst::PadProbeType::BUFFER, move |pad, info| {
+                if let Some(gst::PadProbeData::Buffer(ref buffer)) = info.data {
+                    cresources.claim_thread();
+                    if let Some(end) = buffer_end(pad, buffer) {
+                        ctrack_end.store(end, Ordering::Relaxed);
+                    }
+                }
+
+                gst::PadProbeReturn::Ok
+            });
+        }
+
+        // The end of a track must not reach the encoder, otherwise the
+        // whole pipeline would have to be restarted for the next one.
+        let probe_bus = bus.clone();
+        let preloaded = Arc::new(Mutex::new(None));
+        let cpreloaded = preloaded.clone();
+        let copus_sink = opus_sink.clone();
+        audio_sink.add_probe(gst::PadProbeType::EVENT_DOWNSTREAM, move |pad, info| {
+            if let Some(gst::PadProbeData::Event(ref event)) = info.data {
+                if event.get_type() == gst::EventType::Eos {
+                    let source = pad.get_peer().and_then(|p| p.get_parent());
+                    let end = track_end.load(Ordering::Relaxed);
+                    let message = if link_preloaded(&cpreloaded, pad, copus_sink.as_ref(), end) {
+                        track_continued_message(source.as_ref())
+                    } else {
+                        track_ended_message(source.as_ref(), false)
+                    };
+                    if probe_bus.post(&message).is_err() {
+                        warn!("Tried to send \"track-ended\" app event on flushing bus.");
+                    }
+
+                    return gst::PadProbeReturn::Drop;
+                }
+            }
+
+            gst::PadProbeReturn::Ok
+        });
 
         // The documentation says that we have to make sure to handle
         // all messages if auto flushing is deactivated.
@@ -107,33 +791,101 @@ impl AudioPlayer {
         pipeline.set_auto_flush_bus(false);
         pipeline.set_state(gst::State::Ready)?;
 
+        // Enabled once the volume is set to full
+        let passthrough = opus_sink.as_ref().map(|_| Arc::new(AtomicBool::new(false)));
+
         Ok(AudioPlayer {
             pipeline,
             bus,
-            http_src,
+            audio_sink,
+            opus_sink,
+            passthrough,
+            source: Mutex::new(None),
+            preloaded,
 
             volume_f64: RwLock::new(0.0),
+            track_volume: RwLock::new(1.0),
+            ducking: RwLock::new(1.0),
+            muted: RwLock::new(false),
             volume,
+            fade,
+            equalizer,
+            pitch,
+            effects: RwLock::new(Effects::default()),
+            encoder,
+            encoder_settings: RwLock::new(encoder_settings),
             sender,
+            log,
             currently_playing: RwLock::new(None),
+            resources,
+            progress,
+            restart_position: Mutex::new(None),
         })
     }
 
     fn create_audio_bin(
         callback: Option<Box<dyn FnMut(&[u8]) + Send>>,
-    ) -> Result<(gst::Bin, gst::Element, gst::GhostPad), AudioPlayerError> {
+        encoder_settings: &EncoderSettings,
+        output: &AudioOutputConfig,
+    ) -> Result<
+        (
+            gst::Bin,
+            gst::Element,
+            gst::Element,
+            Option<gst::Element>,
+            gst::GhostPad,
+            Option<gst::Element>,
+            Option<gst::GhostPad>,
+        ),
+        AudioPlayerError,
+    > {
         let audio_bin = gst::Bin::new(Some("audio bin"));
-        let queue = make_element("queue", "audio queue")?;
+        let queue = make_element("queue", AUDIO_QUEUE_NAME)?;
         let convert = make_element("audioconvert", "audio converter")?;
+        let equalizer = make_element("equalizer-10bands", "equalizer")?;
         let volume = make_element("volume", "volume")?;
         let resample = make_element("audioresample", "audio resampler")?;
         let pads = queue.get_sink_pads();
         let queue_sink_pad = pads.first().unwrap();
 
-        audio_bin.add_many(&[&queue, &convert, &volume, &resample])?;
+        audio_bin.add_many(&[&queue, &convert, &equalizer, &volume, &resample])?;
+        gst::Element::link_many(&[&queue, &convert, &equalizer])?;
+
+        // The pitch element is part of the soundtouch plugin which is not always installed
+        let pitch = match make_element("pitch", "pitch") {
+            Ok(pitch) => {
+                let pitch_convert = make_element("audioconvert", "pitch converter")?;
+                audio_bin.add_many(&[&pitch, &pitch_convert])?;
+                gst::Element::link_many(&[&equalizer, &pitch, &pitch_convert, &volume])?;
+
+                Some(pitch)
+            }
+            Err(e) => {
+                warn!("Speed effects are not available: {:?}", e);
+                link_elements(&equalizer, &volume)?;
+
+                None
+            }
+        };
 
-        if let Some(mut callback) = callback {
+        let (encoder, opus_sink) = if let Some(mut callback) = callback {
+            let channels = if encoder_settings.mono { 1i32 } else { 2i32 };
+            let channels_filter = make_element("capsfilter", "channels filter")?;
+            channels_filter.set_property(
+                "caps",
+                &gst::Caps::new_simple("audio/x-raw", &[("channels", &channels)]),
+            )?;
             let opus_enc = make_element("opusenc", "opus encoder")?;
+            opus_enc.set_property(
+                "bitrate",
+                &(encoder_settings.effective_bitrate() as i32 * 1000),
+            )?;
+            opus_enc.set_property(
+                "complexity",
+                &i32::from(encoder_settings.complexity.min(10)),
+            )?;
+            // Opus sources that are passed through join the encoded audio here
+            let funnel = make_element("funnel", "opus funnel")?;
             let sink = make_element("appsink", "app sink")?;
 
             let appsink = sink
@@ -142,7 +894,7 @@ impl AudioPlayer {
                 .expect("Sink element is expected to be an appsink!");
             appsink.set_caps(Some(&gst::Caps::new_simple(
                 "audio/x-opus",
-                &[("channels", &(2i32)), ("rate", &(48_000i32))],
+                &[("channels", &channels), ("rate", &(48_000i32))],
             )));
             let callbacks = AppSinkCallbacks::builder()
                 .new_sample(move |sink| {
@@ -158,26 +910,67 @@ impl AudioPlayer {
                 .build();
             appsink.set_callbacks(callbacks);
 
-            audio_bin.add_many(&[&opus_enc, &sink])?;
+            audio_bin.add_many(&[&channels_filter, &opus_enc, &funnel, &sink])?;
+
+            gst::Element::link_many(&[&channels_filter, &opus_enc, &funnel, &sink])?;
+            // Without the device the bot still plays to the server
+            let monitor_sink = match output.monitor {
+                Some(_) => audio_output::make_sink(output)
+                    .map_err(|e| warn!("Not monitoring, the audio device failed: {:?}", e))
+                    .ok(),
+                None => None,
+            };
+            let monitoring = monitor_sink.is_some();
+            if let Some(monitor_sink) = monitor_sink {
+                // A slow device drops audio instead of holding up what is sent to the server
+                let tee = make_element("tee", "monitor tee")?;
+                let monitor_queue = make_element("queue", "monitor queue")?;
+                monitor_queue.set_property_from_str("leaky", "downstream");
+                let monitor_convert = make_element("audioconvert", "monitor converter")?;
+                audio_bin.add_many(&[&tee, &monitor_queue, &monitor_convert, &monitor_sink])?;
+
+                gst::Element::link_many(&[&volume, &resample, &tee, &channels_filter])?;
+                gst::Element::link_many(&[&tee, &monitor_queue, &monitor_convert, &monitor_sink])?;
+            } else {
+                gst::Element::link_many(&[&volume, &resample, &channels_filter])?;
+            }
+
+            // Passed through sources are always stereo and would not reach the monitor
+            let opus_sink = if encoder_settings.mono || monitoring {
+                None
+            } else {
+                let funnel_sink_pad = funnel.get_request_pad("sink_%u").unwrap();
+                let opus_sink = GhostPad::with_target(Some("opus sink"), &funnel_sink_pad).unwrap();
+                opus_sink.set_active(true)?;
+                audio_bin.add_pad(&opus_sink)?;
+
+                Some(opus_sink)
+            };
 
-            gst::Element::link_many(&[&queue, &convert, &volume, &resample, &opus_enc, &sink])?;
+            (Some(opus_enc), opus_sink)
         } else {
-            let sink = make_element("autoaudiosink", "auto audio sink")?;
+            let sink = audio_output::make_sink(output)?;
 
             audio_bin.add_many(&[&sink])?;
 
-            gst::Element::link_many(&[&queue, &convert, &volume, &resample, &sink])?;
+            gst::Element::link_many(&[&volume, &resample, &sink])?;
+
+            (None, None)
         };
 
         let ghost_pad = GhostPad::with_target(Some("audio bin sink"), queue_sink_pad).unwrap();
         ghost_pad.set_active(true)?;
         audio_bin.add_pad(&ghost_pad)?;
 
-        Ok((audio_bin, volume, ghost_pad))
+        Ok((
+            audio_bin, volume, equalizer, pitch, ghost_pad, encoder, opus_sink,
+        ))
     }
 
     pub fn set_metadata(&self, data: AudioMetadata) -> Result<(), AudioPlayerError> {
+        info!("{} Starting playback of {:?}", data.request_id, data.title);
         self.set_source_url(data.url.clone())?;
+        self.reset_track_volume()?;
 
         let mut currently_playing = self.currently_playing.write().unwrap();
         *currently_playing = Some(data);
@@ -185,48 +978,365 @@ impl AudioPlayer {
         Ok(())
     }
 
+    /// Plays a track while it is extracted instead of reading it from its url
+    pub fn set_stream(
+        &self,
+        data: AudioMetadata,
+        stream: AudioStream,
+    ) -> Result<(), AudioPlayerError> {
+        info!(
+            "{} Starting streamed playback of {:?}",
+            data.request_id, data.title
+        );
+        let (source, decode_bin, app_src) =
+            make_stream_source(self.passthrough.is_some(), &self.resources)?;
+        self.replace_source(source, decode_bin)?;
+        self.reset_track_volume()?;
+        spawn_stream_reader(stream, app_src, data.request_id);
+
+        let mut currently_playing = self.currently_playing.write().unwrap();
+        *currently_playing = Some(data);
+
+        Ok(())
+    }
+
+    /// How full the buffer of a streamed track is from 0 to 1, `None` for other tracks
+    pub fn buffer_level(&self) -> Option<f64> {
+        let app_src = self.stream_source()?;
+
+        Some(app_src.get_current_level_bytes() as f64 / app_src.get_max_bytes() as f64)
+    }
+
+    /// What the player costs, the byte counts include all tracks it played
+    pub fn resource_usage(&self) -> ResourceUsage {
+        let stream = self
+            .stream_source()
+            .map_or(0, |app_src| app_src.get_current_level_bytes());
+        let queue = self
+            .pipeline
+            .get_by_name(AUDIO_QUEUE_NAME)
+            .and_then(|queue| queue.get_property("current-level-bytes").ok())
+            .and_then(|level| level.get_some::<u32>().ok())
+            .unwrap_or(0);
+
+        self.resources.usage(stream + u64::from(queue))
+    }
+
+    fn stream_source(&self) -> Option<AppSrc> {
+        let source = self.source.lock().unwrap();
+
+        source
+            .as_ref()?
+            .get_by_name(STREAM_SOURCE_NAME)?
+            .dynamic_cast::<AppSrc>()
+            .ok()
+    }
+
+    /// Starts decoding the track that comes next, it is played right after
+    /// the current one if it is ready by then.
+    pub fn preload(&self, data: AudioMetadata) -> Result<(), AudioPlayerError> {
+        info!("{} Preloading {:?}", data.request_id, data.title);
+        self.discard_preloaded()?;
+
+        let (source, decode_bin) =
+            make_source(&data.url, self.passthrough.is_some(), &self.resources)?;
+        add_preload_pad_callback(
+            &decode_bin,
+            self.preloaded.clone(),
+            self.passthrough.clone(),
+        );
+        *self.preloaded.lock().unwrap() = Some(Preloaded {
+            source: source.clone(),
+            metadata: data,
+            pad: None,
+            opus_pad: None,
+            linked: false,
+        });
+
+        self.pipeline.add(&source)?;
+        source.sync_state_with_parent()?;
+
+        Ok(())
+    }
+
+    /// The track that was preloaded to follow the current one
+    pub fn preloaded(&self) -> Option<AudioMetadata> {
+        self.preloaded
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|p| p.metadata.clone())
+    }
+
+    fn discard_preloaded(&self) -> Result<(), AudioPlayerError> {
+        if let Some(preloaded) = self.preloaded.lock().unwrap().take() {
+            debug!("Discarding preloaded track");
+            self.remove_source(preloaded.source)?;
+        }
+
+        Ok(())
+    }
+
+    /// Drops the preloaded track if `element` belongs to it, so its
+    /// problems do not end the track that is playing.
+    fn discard_failed_preload(&self, element: Option<&gst::Object>) -> bool {
+        let mut preloaded = self.preloaded.lock().unwrap();
+        let failed = match (preloaded.as_ref(), element) {
+            (Some(preloaded), Some(element)) => {
+                !preloaded.linked && element.has_as_ancestor(&preloaded.source)
+            }
+            _ => false,
+        };
+        if failed {
+            if let Some(preloaded) = preloaded.take() {
+                if let Err(e) = self.remove_source(preloaded.source) {
+                    error!("Failed to remove preloaded track: {:?}", e);
+                }
+            }
+        }
+
+        failed
+    }
+
+    /// Replaces the current source while the rest of the pipeline keeps running
     fn set_source_url(&self, location: String) -> Result<(), AudioPlayerError> {
-        info!("Setting location URI: {}", location);
-        self.http_src.set_property("location", &location)?;
+        let (source, decode_bin) =
+            make_source(&location, self.passthrough.is_some(), &self.resources)?;
+
+        self.replace_source(source, decode_bin)
+    }
+
+    fn replace_source(
+        &self,
+        source: gst::Bin,
+        decode_bin: gst::Element,
+    ) -> Result<(), AudioPlayerError> {
+        self.discard_preloaded()?;
+
+        add_decode_bin_new_pad_callback(
+            &decode_bin,
+            self.pipeline.downgrade(),
+            self.audio_sink.clone(),
+            self.opus_sink.clone(),
+            self.passthrough.clone(),
+        );
+
+        let mut current = self.source.lock().unwrap();
+        if let Some(old) = current.take() {
+            self.remove_source(old)?;
+            self.flush_audio_bin();
+        }
+
+        self.pipeline.add(&source)?;
+        source.sync_state_with_parent()?;
+        *current = Some(source);
+        *self.progress.lock().unwrap() = Progress::new();
+
+        Ok(())
+    }
+
+    fn remove_source(&self, source: gst::Bin) -> Result<(), AudioPlayerError> {
+        debug!("Removing source");
+
+        source.set_state(gst::State::Null)?;
+        for name in &["src", "opus"] {
+            // Preloaded tracks and tracks that were followed without a gap are not linked
+            if let Some(src_pad) = source.get_static_pad(name) {
+                if let Some(peer) = src_pad.get_peer() {
+                    src_pad.unlink(&peer)?;
+                }
+            }
+        }
+        self.pipeline.remove(&source)?;
 
         Ok(())
     }
 
+    /// Drops audio of the previous track that is still queued up in the audio bin
+    fn flush_audio_bin(&self) {
+        debug!("Flushing audio bin");
+
+        self.audio_sink.send_event(gst::event::FlushStart::new());
+        // Resetting the time would break the offsets of the following tracks
+        self.audio_sink
+            .send_event(gst::event::FlushStop::new(false));
+    }
+
     pub fn change_volume(&self, volume: VolumeChange) -> Result<(), AudioPlayerError> {
-        let new_volume = match volume {
-            VolumeChange::Positive(vol) => self.volume() + vol,
-            VolumeChange::Negative(vol) => self.volume() - vol,
-            VolumeChange::Absolute(vol) => vol,
-        };
-        let new_volume = new_volume.max(0.0).min(1.0);
+        let new_volume = volume.apply(self.volume());
 
         *self.volume_f64.write().unwrap() = new_volume;
-        let db = 50.0 * new_volume.log10();
-        info!("Setting volume: {} -> {} dB", new_volume, db);
+        self.apply_volume()
+    }
+
+    /// Changes the volume of the current track relative to the volume of the bot
+    pub fn change_track_volume(&self, volume: VolumeChange) -> Result<(), AudioPlayerError> {
+        let new_volume = volume.apply(self.track_volume());
+
+        *self.track_volume.write().unwrap() = new_volume;
+        self.apply_volume()
+    }
+
+    fn reset_track_volume(&self) -> Result<(), AudioPlayerError> {
+        if self.track_volume() == 1.0 {
+            return Ok(());
+        }
+
+        *self.track_volume.write().unwrap() = 1.0;
+        self.apply_volume()
+    }
+
+    /// Lowers the volume without changing the one chosen by users
+    pub fn set_ducking(&self, factor: f64) -> Result<(), AudioPlayerError> {
+        *self.ducking.write().unwrap() = factor.max(0.0).min(1.0);
+        self.apply_volume()
+    }
+
+    pub fn set_muted(&self, muted: bool) -> Result<(), AudioPlayerError> {
+        *self.muted.write().unwrap() = muted;
+        self.apply_volume()
+    }
+
+    pub fn is_muted(&self) -> bool {
+        *self.muted.read().unwrap()
+    }
+
+    fn effective_volume(&self) -> f64 {
+        if self.is_muted() {
+            return 0.0;
+        }
+
+        self.volume() * self.track_volume() * *self.ducking.read().unwrap()
+    }
+
+    fn apply_volume(&self) -> Result<(), AudioPlayerError> {
+        let volume = self.effective_volume();
+        let db = 50.0 * volume.log10();
+        info!("Setting volume: {} -> {} dB", volume, db);
 
         let linear =
             StreamVolume::convert_volume(StreamVolumeFormat::Db, StreamVolumeFormat::Linear, db);
 
-        self.volume.set_property("volume", &linear)?;
+        // Without buffers flowing the fade would never finish
+        let mut fade = self.fade.lock().unwrap();
+        if self.pipeline.get_current_state() == gst::State::Playing {
+            *fade = Fade {
+                from: fade.current(),
+                to: linear,
+                elapsed: Duration::from_secs(0),
+            };
+        } else {
+            *fade = Fade::done(linear);
+            self.volume.set_property("volume", &linear)?;
+        }
+        drop(fade);
+        self.update_passthrough();
 
         Ok(())
     }
 
-    pub fn is_started(&self) -> bool {
-        let (_, current, pending) = self.pipeline.get_state(gst::ClockTime(None));
+    /// Passes Opus sources through unchanged while nothing would change their audio
+    fn update_passthrough(&self) {
+        let passthrough = match &self.passthrough {
+            Some(passthrough) => passthrough,
+            None => return,
+        };
 
-        match (current, pending) {
-            (gst::State::Null, gst::State::VoidPending) => false,
-            (_, gst::State::Null) => false,
-            (gst::State::Ready, gst::State::VoidPending) => false,
-            _ => true,
+        let unchanged = self.effective_volume() >= 1.0
+            && self.effects() == Effects::default()
+            && self.encoder_settings().bitrate.is_none();
+        if passthrough.swap(unchanged, Ordering::Relaxed) != unchanged {
+            if unchanged {
+                debug!("Passing Opus sources through without transcoding");
+            } else {
+                debug!("Transcoding Opus sources to apply volume and effects");
+            }
         }
     }
 
+    /// Sets the gain of a band of the equalizer and returns the clamped gain
+    pub fn set_equalizer_band(&self, band: usize, gain: f64) -> Result<f64, AudioPlayerError> {
+        let gain = gain.max(EQ_MIN_GAIN).min(EQ_MAX_GAIN);
+        info!("Setting equalizer band {} to {} dB", band, gain);
+
+        self.equalizer
+            .set_property(&format!("band{}", band), &gain)?;
+        self.effects.write().unwrap().bands[band] = gain;
+        self.update_passthrough();
+
+        Ok(gain)
+    }
+
+    /// Changes the tempo without changing the pitch
+    pub fn set_speed(&self, speed: f64) -> Result<(), AudioPlayerError> {
+        let pitch = self
+            .pitch
+            .as_ref()
+            .ok_or(AudioPlayerError::EffectUnavailable)?;
+        info!("Setting speed: {}", speed);
+
+        pitch.set_property("tempo", &(speed as f32))?;
+        self.effects.write().unwrap().speed = speed;
+        self.update_passthrough();
+
+        Ok(())
+    }
+
+    pub fn set_nightcore(&self, enabled: bool) -> Result<(), AudioPlayerError> {
+        let pitch = self
+            .pitch
+            .as_ref()
+            .ok_or(AudioPlayerError::EffectUnavailable)?;
+        info!("Setting nightcore: {}", enabled);
+
+        let rate = if enabled { NIGHTCORE_RATE } else { 1.0 };
+        pitch.set_property("rate", &(rate as f32))?;
+        self.effects.write().unwrap().nightcore = enabled;
+        self.update_passthrough();
+
+        Ok(())
+    }
+
+    pub fn effects(&self) -> Effects {
+        *self.effects.read().unwrap()
+    }
+
+    /// Changes the bitrate of the encoder in kbit/s, `None` uses the default
+    /// and passes Opus sources through, returns the bitrate that is used
+    pub fn set_bitrate(&self, bitrate: Option<u32>) -> Result<u32, AudioPlayerError> {
+        let bitrate = bitrate.map(|b| b.max(MIN_BITRATE).min(MAX_BITRATE));
+        let effective = {
+            let mut settings = self.encoder_settings.write().unwrap();
+            settings.bitrate = bitrate;
+            settings.effective_bitrate()
+        };
+        info!("Setting bitrate: {} kbit/s", effective);
+
+        if let Some(encoder) = &self.encoder {
+            encoder.set_property("bitrate", &(effective as i32 * 1000))?;
+        }
+        self.update_passthrough();
+
+        Ok(effective)
+    }
+
+    pub fn encoder_settings(&self) -> EncoderSettings {
+        *self.encoder_settings.read().unwrap()
+    }
+
+    /// Whether a track is loaded, the pipeline itself keeps running between tracks
+    pub fn is_started(&self) -> bool {
+        self.source.lock().unwrap().is_some()
+    }
+
     pub fn volume(&self) -> f64 {
         *self.volume_f64.read().unwrap()
     }
 
+    pub fn track_volume(&self) -> f64 {
+        *self.track_volume.read().unwrap()
+    }
+
     pub fn position(&self) -> Option<Duration> {
         self.pipeline
             .query_position::<gst::ClockTime>()
@@ -237,18 +1347,39 @@ impl AudioPlayer {
         self.currently_playing.read().unwrap().clone()
     }
 
+    fn current_request_id(&self) -> RequestId {
+        self.currently_playing
+            .read()
+            .unwrap()
+            .as_ref()
+            .map(|c| c.request_id)
+            .unwrap_or_default()
+    }
+
     pub fn reset(&self) -> Result<(), AudioPlayerError> {
         info!("Setting pipeline state to null");
 
         let mut currently_playing = self.currently_playing.write().unwrap();
         *currently_playing = None;
+        *self.restart_position.lock().unwrap() = None;
 
         self.pipeline.set_state(gst::State::Null)?;
+        if let Some(source) = self.source.lock().unwrap().take() {
+            self.remove_source(source)?;
+        }
+        self.discard_preloaded()?;
 
         Ok(())
     }
 
     pub fn play(&self) -> Result<(), AudioPlayerError> {
+        if self.pipeline.get_current_state() == gst::State::Playing {
+            // A new track was swapped in, there is no state change to report it
+            self.send_state(State::Playing);
+
+            return Ok(());
+        }
+
         info!("Setting pipeline state to playing");
 
         self.pipeline.set_state(gst::State::Playing)?;
@@ -265,6 +1396,11 @@ impl AudioPlayer {
     }
 
     pub fn seek(&self, seek: Seek) -> Result<humantime::FormattedDuration, AudioPlayerError> {
+        // Only the part that was not played yet can be read from a stream
+        if self.stream_source().is_some() {
+            return Err(AudioPlayerError::SeekError);
+        }
+
         let base = match seek {
             Seek::Positive(_) | Seek::Negative(_) => {
                 let pos = self
@@ -291,6 +1427,15 @@ impl AudioPlayer {
         let time = humantime::format_duration(absolute);
         info!("Seeking to {}", time);
 
+        // A flushing seek restarts the running time at zero
+        if let Some(source) = self.source.lock().unwrap().as_ref() {
+            for name in &["src", "opus"] {
+                if let Some(src_pad) = source.get_static_pad(name) {
+                    src_pad.set_offset(0);
+                }
+            }
+        }
+
         self.pipeline.seek_simple(
             gst::SeekFlags::FLUSH,
             gst::ClockTime::from_nseconds(absolute.as_nanos() as _),
@@ -299,14 +1444,135 @@ impl AudioPlayer {
         Ok(time)
     }
 
+    /// Checks if the current track stopped moving or went silent.
+    ///
+    /// Only a playing track is watched, the timers start over once it
+    /// is reported so it is not reported again right away.
+    pub fn check_progress(&self, config: &WatchdogConfig) -> Option<Stuck> {
+        let mut progress = self.progress.lock().unwrap();
+        if self.pipeline.get_current_state() != gst::State::Playing || !self.is_started() {
+            *progress = Progress::new();
+            return None;
+        }
+
+        let position = self.position();
+        if position != progress.position {
+            progress.position = position;
+            progress.moved = Instant::now();
+        }
+
+        let stalled = progress.moved.elapsed();
+        let silent = progress.sounded.elapsed();
+        let off = Duration::from_secs(0);
+        let stuck = if config.stall_timeout > off && stalled >= config.stall_timeout {
+            Stuck::Stalled(stalled)
+        } else if config.silence_timeout > off && silent >= config.silence_timeout {
+            Stuck::Silent(silent)
+        } else {
+            return None;
+        };
+        *progress = Progress::new();
+
+        Some(stuck)
+    }
+
+    /// Restarts the pipeline and reads the current track again from where it was.
+    ///
+    /// Returns false if there is nothing to restart, streamed tracks can not be read again.
+    pub fn restart(&self) -> Result<bool, AudioPlayerError> {
+        if self.stream_source().is_some() {
+            return Ok(false);
+        }
+        let url = match self.currently_playing() {
+            Some(data) => data.url,
+            None => return Ok(false),
+        };
+        let position = self.position();
+        info!(
+            "{} Restarting the pipeline at {:?}",
+            self.current_request_id(),
+            position
+        );
+
+        self.pipeline.set_state(gst::State::Ready)?;
+        self.set_source_url(url)?;
+        *self.restart_position.lock().unwrap() = position;
+        self.pipeline.set_state(gst::State::Playing)?;
+
+        Ok(true)
+    }
+
     pub fn stop_current(&self) -> Result<(), AudioPlayerError> {
-        info!("Stopping pipeline, sending EOS");
+        info!("Stopping current track");
 
-        self.bus.post(&gst::message::Eos::new())?;
+        let source = self.source.lock().unwrap().clone();
+        let source = source.as_ref().map(|s| s.upcast_ref::<gst::Object>());
+        self.bus.post(&track_ended_message(source, true))?;
 
         Ok(())
     }
 
+    /// Removes the finished track, leaving the pipeline running for the next one.
+    ///
+    /// Returns false if `source` was already replaced, so a late message
+    /// can not end the track that followed.
+    fn end_track(
+        &self,
+        source: Option<gst::Object>,
+        skipped: bool,
+    ) -> Result<bool, AudioPlayerError> {
+        let mut current = self.source.lock().unwrap();
+        let is_current = match (current.as_ref(), source) {
+            (Some(current), Some(source)) => current.upcast_ref::<gst::Object>() == &source,
+            // Skipping while nothing is loaded lets the bot start the next track
+            (None, None) => true,
+            _ => false,
+        };
+        if !is_current {
+            debug!("Ignoring end of a replaced track");
+            return Ok(false);
+        }
+
+        *self.currently_playing.write().unwrap() = None;
+        if let Some(source) = current.take() {
+            self.remove_source(source)?;
+        }
+        if skipped {
+            self.flush_audio_bin();
+        }
+
+        Ok(true)
+    }
+
+    /// Makes the preloaded track that followed `source` the current one,
+    /// returns false if `source` was already replaced.
+    fn continue_track(&self, source: Option<gst::Object>) -> Result<bool, AudioPlayerError> {
+        let mut current = self.source.lock().unwrap();
+        let is_current = match (current.as_ref(), source) {
+            (Some(current), Some(source)) => current.upcast_ref::<gst::Object>() == &source,
+            _ => false,
+        };
+        if !is_current {
+            debug!("Ignoring end of a replaced track");
+            return Ok(false);
+        }
+
+        let next = match self.preloaded.lock().unwrap().take() {
+            Some(next) => next,
+            None => return Ok(false),
+        };
+        if let Some(source) = current.take() {
+            self.remove_source(source)?;
+        }
+        *current = Some(next.source);
+        *self.currently_playing.write().unwrap() = Some(next.metadata);
+        *self.progress.lock().unwrap() = Progress::new();
+        drop(current);
+        self.reset_track_volume()?;
+
+        Ok(true)
+    }
+
     pub fn quit(&self, reason: String) {
         info!("Quitting audio player");
 
@@ -350,7 +1616,18 @@ impl AudioPlayer {
 
                         match (old, current, pending) {
                             (gst::State::Paused, gst::State::Playing, gst::State::VoidPending) => {
-                                self.send_state(State::Playing)
+                                let restarted = self.restart_position.lock().unwrap().take();
+                                match restarted {
+                                    Some(position) => {
+                                        if let Err(e) = self.seek(Seek::Absolute(position)) {
+                                            self.log.warn(format!(
+                                                "Failed to continue the restarted track: {:?}",
+                                                e
+                                            ));
+                                        }
+                                    }
+                                    None => self.send_state(State::Playing),
+                                }
                             }
                             (gst::State::Playing, gst::State::Paused, gst::State::VoidPending) => {
                                 self.send_state(State::Paused)
@@ -370,27 +1647,39 @@ impl AudioPlayer {
                         }
                     }
                     MessageView::Eos(..) => {
-                        info!("End of stream reached");
+                        info!("{} End of stream reached", self.current_request_id());
                         self.reset().unwrap();
 
                         break 'outer;
                     }
                     MessageView::Warning(warn) => {
-                        warn!(
-                            "Warning from {:?}: {} ({:?})",
+                        if self.discard_failed_preload(warn.get_src().as_ref()) {
+                            self.log
+                                .warn(format!("Preloading failed: {}", warn.get_error()));
+                            continue;
+                        }
+                        self.log.warn(format!(
+                            "{} Warning from {:?}: {} ({:?})",
+                            self.current_request_id(),
                             warn.get_src().map(|s| s.get_path_string()),
                             warn.get_error(),
                             warn.get_debug()
-                        );
+                        ));
                         break 'outer;
                     }
                     MessageView::Error(err) => {
-                        error!(
-                            "Error from {:?}: {} ({:?})",
+                        if self.discard_failed_preload(err.get_src().as_ref()) {
+                            self.log
+                                .warn(format!("Preloading failed: {}", err.get_error()));
+                            continue;
+                        }
+                        self.log.error(format!(
+                            "{} Error from {:?}: {} ({:?})",
+                            self.current_request_id(),
                             err.get_src().map(|s| s.get_path_string()),
                             err.get_error(),
                             err.get_debug()
-                        );
+                        ));
                         break 'outer;
                     }
                     MessageView::Application(content) => {
@@ -399,6 +1688,39 @@ impl AudioPlayer {
                                 self.reset().unwrap();
                                 return PollResult::Quit;
                             }
+
+                            if s.get_name() == "track-continued" {
+                                match self.continue_track(msg.get_src()) {
+                                    Ok(true) => {
+                                        info!(
+                                            "{} Continued without a gap",
+                                            self.current_request_id()
+                                        );
+                                        return PollResult::NextTrack;
+                                    }
+                                    Ok(false) => (),
+                                    // The bot starts the next track itself after the end
+                                    Err(e) => {
+                                        self.log.error(format!(
+                                            "{} Failed to continue with the preloaded track: {:?}",
+                                            self.current_request_id(),
+                                            e
+                                        ));
+                                        break 'outer;
+                                    }
+                                }
+                            }
+
+                            if s.get_name() == "track-ended" {
+                                let request_id = self.current_request_id();
+                                let skipped =
+                                    s.get::<bool>("skipped").ok().flatten().unwrap_or(false);
+                                if self.end_track(msg.get_src(), skipped).unwrap() {
+                                    info!("{} End of track reached", request_id);
+
+                                    break 'outer;
+                                }
+                            }
                         }
                     }
                     _ => {
@@ -418,6 +1740,7 @@ pub enum AudioPlayerError {
     GStreamerError(glib::error::BoolError),
     StateChangeFailed,
     SeekError,
+    EffectUnavailable,
 }
 
 impl From<glib::error::BoolError> for AudioPlayerError {
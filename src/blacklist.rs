@@ -0,0 +1,129 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use tracing::{error, info};
+
+/// Stores the blocked tracks of all servers in a single JSON file, keyed by server.
+///
+/// A pattern is a video id or a part of a url, it matches tracks whose page
+/// url contains it. `*` in a pattern matches any text.
+pub struct BlacklistStore {
+    path: PathBuf,
+    server: String,
+    patterns: Arc<Mutex<HashMap<String, Vec<String>>>>,
+    /// Held while saving so an older state can't overwrite a newer one
+    saving: Arc<Mutex<()>>,
+}
+
+impl BlacklistStore {
+    pub fn new(path: PathBuf, server: &str) -> Self {
+        let patterns = match fs::read(&path) {
+            Ok(data) => match serde_json::from_slice(&data) {
+                Ok(patterns) => patterns,
+                Err(e) => {
+                    error!("Failed to parse blacklist in {:?}: {}", path, e);
+                    HashMap::new()
+                }
+            },
+            Err(e) if e.kind() == io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => {
+                error!("Failed to read blacklist from {:?}: {}", path, e);
+                HashMap::new()
+            }
+        };
+
+        Self {
+            path,
+            server: server.to_owned(),
+            patterns: Arc::new(Mutex::new(patterns)),
+            saving: Arc::new(Mutex::new(())),
+        }
+    }
+
+    pub fn patterns(&self) -> Vec<String> {
+        self.patterns
+            .lock()
+            .unwrap()
+            .get(&self.server)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// The first pattern that matches the page url of a track
+    pub fn matching(&self, url: &str) -> Option<String> {
+        let patterns = self.patterns.lock().unwrap();
+
+        patterns
+            .get(&self.server)?
+            .iter()
+            .find(|pattern| matches(pattern, url))
+            .cloned()
+    }
+
+    /// Returns false if the pattern was on the blacklist already
+    pub fn add(&self, pattern: &str) -> bool {
+        self.update(|patterns| {
+            if patterns.iter().any(|p| p == pattern) {
+                return false;
+            }
+            patterns.push(pattern.to_owned());
+
+            true
+        })
+    }
+
+    /// Returns false if the pattern was not on the blacklist
+    pub fn remove(&self, pattern: &str) -> bool {
+        self.update(|patterns| {
+            let before = patterns.len();
+            patterns.retain(|p| p != pattern);
+
+            patterns.len() != before
+        })
+    }
+
+    /// Saves the patterns in the background if `change` changed them
+    fn update(&self, change: impl FnOnce(&mut Vec<String>) -> bool) -> bool {
+        {
+            let mut patterns = self.patterns.lock().unwrap();
+            if !change(patterns.entry(self.server.clone()).or_default()) {
+                return false;
+            }
+            patterns.retain(|_, p| !p.is_empty());
+        }
+
+        let path = self.path.clone();
+        let server = self.server.clone();
+        let patterns = self.patterns.clone();
+        let saving = self.saving.clone();
+        tokio::task::spawn_blocking(move || {
+            let _saving = saving.lock().unwrap();
+            let data = serde_json::to_vec_pretty(&*patterns.lock().unwrap());
+            let result = data
+                .map_err(io::Error::from)
+                .and_then(|data| fs::write(&path, data));
+            match result {
+                Ok(()) => info!("Saved blacklist of {}", server),
+                Err(e) => error!("Failed to save blacklist to {:?}: {}", path, e),
+            }
+        });
+
+        true
+    }
+}
+
+/// If the parts of the pattern between `*` appear in `url` in order
+fn matches(pattern: &str, url: &str) -> bool {
+    let mut rest = url;
+    for part in pattern.split('*').filter(|part| !part.is_empty()) {
+        match rest.find(part) {
+            Some(i) => rest = &rest[i + part.len()..],
+            None => return false,
+        }
+    }
+
+    true
+}
@@ -1,5 +1,13 @@
+mod mailbox;
+mod manager;
 mod master;
 mod music;
+mod registry;
+mod template;
 
+pub use mailbox::*;
+pub use manager::*;
 pub use master::*;
 pub use music::*;
+pub use registry::*;
+pub use template::*;
@@ -0,0 +1,175 @@
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use serde::Serialize;
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tracing::{debug, warn};
+use tsclientlib::{ChannelId, ClientId};
+
+use crate::bot::MusicBotMessage;
+
+/// How many messages may wait before events of the server are dropped
+const EVENT_CAPACITY: usize = 512;
+/// How many messages may wait before anything is dropped, the room above
+/// `EVENT_CAPACITY` is kept for messages that are not events
+const CAPACITY: usize = 4096;
+
+/// Creates the message queue of a bot.
+///
+/// All messages go through one bounded queue, so they are handled in the
+/// order they were sent and a flood of them can not use up memory while the
+/// bot is busy. An event that is already waiting is not queued again and
+/// events are dropped once `EVENT_CAPACITY` messages are waiting. Other
+/// messages, like chat messages, quitting and the messages of the player,
+/// are only dropped once the whole queue is full.
+pub fn mailbox() -> (MessageSender, MessageReceiver) {
+    let (sender, receiver) = mpsc::channel(CAPACITY);
+    let shared = Arc::new(Shared::default());
+
+    (
+        MessageSender {
+            sender: Arc::new(Mutex::new(sender)),
+            shared: shared.clone(),
+        },
+        MessageReceiver { receiver, shared },
+    )
+}
+
+/// Events that are the same as one that is still waiting, the bot looks at
+/// the current state when it handles them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Coalesced {
+    ChannelAdded(ChannelId),
+    ClientChannel(ClientId),
+    ClientTalking,
+    TalkPowerChanged,
+    ListenersChanged,
+}
+
+impl Coalesced {
+    fn of(message: &MusicBotMessage) -> Option<Self> {
+        match message {
+            MusicBotMessage::ChannelAdded(id) => Some(Coalesced::ChannelAdded(*id)),
+            MusicBotMessage::ClientChannel { client, .. } => {
+                Some(Coalesced::ClientChannel(*client))
+            }
+            MusicBotMessage::ClientTalking => Some(Coalesced::ClientTalking),
+            MusicBotMessage::TalkPowerChanged => Some(Coalesced::TalkPowerChanged),
+            MusicBotMessage::ListenersChanged => Some(Coalesced::ListenersChanged),
+            _ => None,
+        }
+    }
+}
+
+/// Messages that come from the server and may be dropped if there are too many.
+///
+/// Only events that the bot can reconstruct from the current state are dropped
+/// early, chat messages with commands and clients joining or leaving only once
+/// the whole queue is full.
+fn is_event(message: &MusicBotMessage) -> bool {
+    Coalesced::of(message).is_some()
+}
+
+#[derive(Default)]
+struct Shared {
+    /// Messages of any kind that are in the queue
+    queued: AtomicUsize,
+    /// Coalesced events that are in the queue
+    waiting: Mutex<HashSet<Coalesced>>,
+    coalesced: AtomicU64,
+    dropped: AtomicU64,
+    /// Set from the first dropped event until one fits again, to warn once per flood
+    overflowing: AtomicBool,
+}
+
+/// How many events a bot did not handle
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct MailboxStats {
+    /// Events that were already waiting
+    pub coalesced: u64,
+    /// Messages that did not fit into the queue
+    pub dropped: u64,
+}
+
+/// The receiver of the bot was dropped
+#[derive(Debug)]
+pub struct Closed;
+
+#[derive(Clone)]
+pub struct MessageSender {
+    sender: Arc<Mutex<mpsc::Sender<MusicBotMessage>>>,
+    shared: Arc<Shared>,
+}
+
+impl MessageSender {
+    /// Only fails if the bot is gone, dropping and coalescing messages is not an error
+    pub fn send(&self, message: MusicBotMessage) -> Result<(), Closed> {
+        let coalesced = Coalesced::of(&message);
+        if let Some(coalesced) = coalesced {
+            if !self.shared.waiting.lock().unwrap().insert(coalesced) {
+                self.shared.coalesced.fetch_add(1, Ordering::Relaxed);
+                return Ok(());
+            }
+        }
+
+        // Counted before it is sent, so the receiver never takes more messages than counted
+        let queued = self.shared.queued.fetch_add(1, Ordering::Relaxed);
+        let result = if is_event(&message) && queued >= EVENT_CAPACITY {
+            Err(TrySendError::Full(message))
+        } else {
+            self.sender.lock().unwrap().try_send(message)
+        };
+        if result.is_ok() {
+            self.shared.overflowing.store(false, Ordering::Relaxed);
+            return Ok(());
+        }
+        self.shared.queued.fetch_sub(1, Ordering::Relaxed);
+
+        if let Some(coalesced) = coalesced {
+            self.shared.waiting.lock().unwrap().remove(&coalesced);
+        }
+        match result {
+            Err(TrySendError::Full(message)) => {
+                self.shared.dropped.fetch_add(1, Ordering::Relaxed);
+                if !self.shared.overflowing.swap(true, Ordering::Relaxed) {
+                    warn!("Too many messages are waiting, dropping new ones");
+                }
+                if is_event(&message) {
+                    debug!("Dropped {:?}", message);
+                } else {
+                    warn!("Dropped {:?}", message);
+                }
+
+                Ok(())
+            }
+            _ => Err(Closed),
+        }
+    }
+
+    pub fn stats(&self) -> MailboxStats {
+        MailboxStats {
+            coalesced: self.shared.coalesced.load(Ordering::Relaxed),
+            dropped: self.shared.dropped.load(Ordering::Relaxed),
+        }
+    }
+}
+
+pub struct MessageReceiver {
+    receiver: mpsc::Receiver<MusicBotMessage>,
+    shared: Arc<Shared>,
+}
+
+impl MessageReceiver {
+    /// The next message, `None` once all senders are gone
+    pub async fn recv(&mut self) -> Option<MusicBotMessage> {
+        let message = self.receiver.recv().await?;
+        self.shared.queued.fetch_sub(1, Ordering::Relaxed);
+
+        if let Some(coalesced) = Coalesced::of(&message) {
+            self.shared.waiting.lock().unwrap().remove(&coalesced);
+        }
+
+        Some(message)
+    }
+}
@@ -0,0 +1,42 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+
+use crate::bot::{MasterActionError, MusicBot};
+use crate::reports::ReportStore;
+use crate::scheduler::ScheduleStore;
+use crate::stats::StatsStore;
+use crate::web_server::{BotDetails, BotSummary, IdentityInfo, MasterData, WebServerStatus};
+use crate::youtube_dl::Downloader;
+
+/// The master of an instance as seen by the web server, one per frontend.
+///
+/// The futures are not `Send` because they are only run by the actix runtime.
+#[async_trait(?Send)]
+pub trait BotManager: Send + Sync {
+    async fn master_data(&self) -> MasterData;
+    async fn set_description(&self, description: String) -> Result<(), MasterActionError>;
+    async fn move_to_channel(&self, channel: String) -> Result<(), MasterActionError>;
+    /// Spawns a bot in a channel, returns the name of the bot
+    async fn spawn_bot_in(&self, channel: &str) -> Result<String, MasterActionError>;
+    /// Disconnects all music bots and the master
+    async fn quit(&self, reason: String);
+    /// Whether the master still handles events, used by the health check
+    async fn ping(&self) -> bool;
+
+    fn set_maintenance(&self, enabled: bool);
+    fn set_web_server_status(&self, web_server: WebServerStatus);
+    fn identities(&self) -> Vec<IdentityInfo>;
+    fn music_bot(&self, name: &str) -> Option<Arc<MusicBot>>;
+    fn bot_names(&self) -> Vec<String>;
+    fn bot_summaries(&self) -> Vec<BotSummary>;
+    fn bot_details(&self, name: String) -> Option<BotDetails>;
+    /// Play statistics of the server, `None` if they are disabled
+    fn stats(&self) -> Option<Arc<StatsStore>>;
+    /// Problems users reported with tracks
+    fn reports(&self) -> Arc<ReportStore>;
+    /// Recurring jobs like playing a stream at a set time
+    fn schedule(&self) -> Arc<ScheduleStore>;
+    /// The yt-dlp or youtube-dl binary used by the bots
+    fn downloader(&self) -> Arc<Downloader>;
+}
@@ -1,44 +1,115 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::future::Future;
-use std::sync::{Arc, RwLock};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
 
-use log::info;
-use rand::{rngs::SmallRng, seq::SliceRandom, SeedableRng};
+use async_trait::async_trait;
+use futures::future::{join, join_all};
 use serde::{Deserialize, Serialize};
-use tokio::sync::mpsc::UnboundedSender;
-use tsclientlib::{ClientId, Connection, Identity, MessageTarget};
+use tracing::{error, info, warn};
+use tsclientlib::{ChannelId, ClientId, Connection, Identity, Invoker, MessageTarget};
 
-use crate::audio_player::AudioPlayerError;
-use crate::teamspeak::TeamSpeakConnection;
+use crate::audio_cache::{self, AudioCache};
+use crate::audio_output::AudioOutputConfig;
+use crate::audio_player::{EncoderSettings, WatchdogConfig};
+use crate::blacklist::BlacklistStore;
+use crate::bridge::BridgeConfig;
+use crate::card::{self, CardRenderer};
+use crate::channel_settings::ChannelSettingsStore;
+use crate::command::{CommandConfig, CommandError, MasterCommand};
+use crate::discord::DiscordConfig;
+use crate::error::Error;
+use crate::file_playback::FilePlaybackConfig;
+use crate::frontend::{FrontendKind, IdleAudio};
+use crate::i18n::{self, I18nConfig, Locale, Locales};
+use crate::logging::LoggingConfig;
+use crate::lyrics::{self, LyricsClient, LyricsConfig};
+use crate::mumble::MumbleConfig;
+use crate::musicbrainz::{self, MetadataConfig, MetadataLookup};
+use crate::rate_limit::{RateLimit, RateLimitConfig, RateLimiter};
+use crate::reports::{ReportConfig, ReportStore};
+use crate::saved_playlists::PlaylistStore;
+use crate::scheduler::{ScheduleConfig, ScheduleStore};
+use crate::scrobble::{self, ScrobbleConfig, Scrobbler};
+use crate::sources::SourceRules;
+use crate::stats::{self, StatsStore};
+use crate::teamspeak::query::{self, ServerQuery, ServerQueryConfig};
+use crate::teamspeak::{
+    identity_uid, ChannelTree, ClientVersion, EventFilter, IdentityStore, TeamSpeakConnection,
+};
+use crate::templates::{self, TemplateVars, TemplatesConfig};
+use crate::tr;
+use crate::user_settings::UserSettingsStore;
+use crate::web_server::{BotDetails, BotSummary, IdentityInfo, MasterData, WebServerStatus};
+use crate::youtube_dl::{self, Downloader, DownloaderConfig};
 
 use crate::Args;
 
-use crate::bot::{MusicBot, MusicBotArgs, MusicBotMessage};
+use crate::bot::{
+    mailbox, ping_message_loop, AutoplayConfig, BotManager, BotRegistry, Ducking, EndOfQueue, Kick,
+    MessageSender, MusicBot, MusicBotArgs, MusicBotMessage, NoDuplicates, PrivateSession,
+    ProgressBarConfig, QueueLimits, Reconnect, Slot,
+};
+
+/// How often and how long to look for a channel the master created
+const CHANNEL_CREATION_CHECKS: usize = 25;
+const CHANNEL_CREATION_INTERVAL: Duration = Duration::from_millis(200);
+/// Longest wait between two attempts to reconnect a music bot
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(5 * 60);
+/// How long an offered bot waits for `!yes` without `auto_confirm`
+const OFFER_TIMEOUT: Duration = Duration::from_secs(2 * 60);
 
 pub struct MasterBot {
     config: Arc<MasterConfig>,
-    music_bots: Arc<RwLock<MusicBots>>,
+    registry: BotRegistry,
+    /// Clients who poked while `max_bots` bots were connected, in order
+    waiting: RwLock<VecDeque<ClientId>>,
     teamspeak: TeamSpeakConnection,
-    sender: Arc<RwLock<UnboundedSender<MusicBotMessage>>>,
+    sender: Arc<RwLock<MessageSender>>,
+    /// Bots waiting for the client they were offered to to confirm them
+    offers: RwLock<HashMap<ClientId, Offer>>,
+    /// When a music bot was last kicked from a channel, by channel path
+    kicks: RwLock<HashMap<String, Instant>>,
+    /// When the master was moved out of its parking channel
+    parking_left: RwLock<Option<Instant>>,
+    started: Instant,
+    status: RwLock<MasterStatus>,
+}
+
+/// A bot that has its name and identity but only connects after `!yes`
+struct Offer {
+    bot_args: MusicBotArgs,
+    offered: Instant,
 }
 
-struct MusicBots {
-    rng: SmallRng,
-    available_names: Vec<usize>,
-    available_ids: Vec<usize>,
-    connected_bots: HashMap<String, Arc<MusicBot>>,
+struct MasterStatus {
+    description: String,
+    connected: bool,
+    maintenance: bool,
+    web_server: WebServerStatus,
 }
 
 impl MasterBot {
-    pub async fn new(args: MasterArgs) -> (Arc<Self>, impl Future) {
-        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+    pub async fn new(args: MasterArgs) -> Result<(Arc<Self>, impl Future<Output = ()>), Error> {
+        let (tx, mut rx) = mailbox();
         let tx = Arc::new(RwLock::new(tx));
         info!("Starting in TeamSpeak mode");
 
+        let identities = Arc::new(IdentityStore::new(args.identity_store));
+        let id = args.id.ok_or(Error::MissingIdentity)?;
+        let ids = args.ids.ok_or(Error::MissingIdentity)?;
+        // Increasing a level can take minutes, so all identities are prepared at once
+        let level = args.identity_level;
+        let (id, ids) = join(
+            identities.prepare(id, level),
+            join_all(ids.into_iter().map(|id| identities.prepare(id, level))),
+        )
+        .await;
         let mut con_config = Connection::build(args.address.clone())
-            .version(tsclientlib::Version::Linux_3_3_2)
+            .version(args.client_version.version())
             .name(args.master_name.clone())
-            .identity(args.id.expect("identity should exist"))
+            .identity(id.clone())
             .log_commands(args.verbose >= 1)
             .log_packets(args.verbose >= 2)
             .log_udp_packets(args.verbose >= 3);
@@ -46,35 +117,136 @@ impl MasterBot {
         if let Some(channel) = args.channel {
             con_config = con_config.channel(channel);
         }
+        if let Some(password) = args.channel_password {
+            con_config = con_config.channel_password(password);
+        }
+        if let Some(password) = args.server_password.clone() {
+            con_config = con_config.password(password);
+        }
 
-        let connection = TeamSpeakConnection::new(tx.clone(), con_config)
-            .await
-            .unwrap();
+        let mut connection = TeamSpeakConnection::new(
+            tx.clone(),
+            &args.address,
+            con_config,
+            EventFilter::Master,
+            Some(identities.clone()),
+        )
+        .await?;
 
+        if let Some(key) = args.privilege_key {
+            connection.use_privilege_key(key).await;
+        }
+        if !args.badges.is_empty() {
+            connection.set_badges(&args.badges).await;
+        }
+
+        let playlists = Arc::new(PlaylistStore::new(args.playlist_dir, &args.address));
+        let channel_settings = Arc::new(ChannelSettingsStore::new(
+            args.channel_settings,
+            &args.address,
+        ));
+        let blacklist = Arc::new(BlacklistStore::new(args.blacklist, &args.address));
+        let bot_uids = Arc::new(
+            std::iter::once(&id)
+                .chain(ids.iter())
+                .map(identity_uid)
+                .collect(),
+        );
+        let downloader = youtube_dl::open(args.downloader).await;
         let config = Arc::new(MasterConfig {
             master_name: args.master_name,
             address: args.address,
             names: args.names,
-            ids: args.ids.expect("identies should exists"),
+            max_bots: args.max_bots,
+            music_channel: args.music_channel,
+            multiple_bots: args.multiple_bots,
+            reconnect_attempts: args.reconnect_attempts,
+            reconnect_delay: args.reconnect_delay,
+            kick_cooldown: args.kick_cooldown,
+            parking: args.parking,
+            confirm_spawn: args.confirm_spawn,
+            id,
+            bot_uids,
+            empty_channel_timeout: args.empty_channel_timeout,
+            ids,
+            identities,
+            server_password: args.server_password,
+            bot_privilege_keys: Mutex::new(args.bot_privilege_keys.into_iter().map(Some).collect()),
             local: args.local,
             verbose: args.verbose,
+            commands: args.commands,
+            thumbnail_avatars: args.thumbnail_avatars,
+            max_playlist_tracks: args.max_playlist_tracks,
+            queue_limits: args.queue_limits,
+            sources: args.sources,
+            blacklist,
+            no_duplicates: args.no_duplicates,
+            file_playback: args.file_playback,
+            ducking: args.ducking,
+            encoder: args.encoder,
+            watchdog: args.watchdog,
+            audio_output: args.audio_output,
+            admins: args.admins,
+            badges: args.badges,
+            client_version: args.client_version,
+            idle_audio: args.idle_audio,
+            end_of_queue: args.end_of_queue,
+            autoplay: args.autoplay,
+            progress_bar: args.progress_bar,
+            channel_settings,
+            user_settings: Arc::new(UserSettingsStore::new(args.user_settings)),
+            reports: Arc::new(ReportStore::new(args.reports)),
+            schedule: Arc::new(ScheduleStore::new(args.schedule)),
+            rate_limiter: Arc::new(RateLimiter::new(args.rate_limit)),
+            web_url: web_url(&args.domain),
+            playlists,
+            audio_cache: audio_cache::open(
+                args.audio_cache_dir,
+                args.audio_cache_max_mb,
+                downloader.clone(),
+            ),
+            stats: stats::open(args.stats_db, &args.address),
+            cards: card::open(args.card_font),
+            scrobbler: scrobble::open(args.scrobble),
+            lyrics: lyrics::open(args.lyrics),
+            musicbrainz: musicbrainz::open(args.musicbrainz),
+            downloader,
+            locales: i18n::open(args.i18n, &args.templates.messages),
+            templates: args.templates,
+            query: query::open(args.query),
         });
 
-        let name_count = config.names.len();
-        let id_count = config.ids.len();
-
-        let music_bots = Arc::new(RwLock::new(MusicBots {
-            rng: SmallRng::from_entropy(),
-            available_names: (0..name_count).collect(),
-            available_ids: (0..id_count).collect(),
-            connected_bots: HashMap::new(),
-        }));
+        let registry = BotRegistry::start(config.names.clone(), config.ids.len(), config.max_bots);
+        let description = match &config.templates.description {
+            Some(template) => {
+                let tree = connection.channel_tree();
+                let channel = tree.own_channel().and_then(|c| tree.channel_path(c));
+                let vars = TemplateVars {
+                    channel: channel.as_deref(),
+                    user: None,
+                    botname: &config.master_name,
+                };
+                templates::fill(template, &vars)
+            }
+            None => String::from("Poke me if you want a music bot!"),
+        };
 
         let bot = Arc::new(Self {
             config,
-            music_bots,
+            registry,
+            waiting: RwLock::new(VecDeque::new()),
             teamspeak: connection,
             sender: tx.clone(),
+            offers: RwLock::new(HashMap::new()),
+            kicks: RwLock::new(HashMap::new()),
+            parking_left: RwLock::new(None),
+            started: Instant::now(),
+            status: RwLock::new(MasterStatus {
+                description,
+                connected: true,
+                maintenance: false,
+                web_server: WebServerStatus::Starting,
+            }),
         });
 
         let cbot = bot.clone();
@@ -85,255 +257,1721 @@ impl MasterBot {
                         MusicBotMessage::Quit(reason) => {
                             let mut cteamspeak = cbot.teamspeak.clone();
                             cteamspeak.disconnect(&reason).await;
+                            cbot.set_connected(false);
                             break 'outer;
                         }
                         MusicBotMessage::ClientDisconnected { id, .. } => {
                             if id == cbot.my_id().await {
                                 // TODO Reconnect since quit was not called
+                                cbot.set_connected(false);
                                 break 'outer;
                             }
+                            cbot.forget_client(id);
+                        }
+                        MusicBotMessage::ConnectionLost => {
+                            error!("Master lost its connection");
+                            cbot.set_connected(false);
+                            break 'outer;
+                        }
+                        _ => {
+                            if let Err(e) = cbot.on_message(msg).await {
+                                error!("Failed to handle message: {}", e);
+                            }
                         }
-                        _ => cbot.on_message(msg).await.unwrap(),
                     }
                 }
             }
         };
 
-        (bot, msg_loop)
+        bot.return_to_parking().await;
+        bot.add_to_server_groups(identity_uid(&bot.config.id));
+
+        Ok((bot, msg_loop))
     }
 
-    async fn build_bot_args_for(&self, id: ClientId) -> Result<MusicBotArgs, BotCreationError> {
-        let mut cteamspeak = self.teamspeak.clone();
-        let channel = match cteamspeak.channel_of_user(id).await {
+    async fn build_bot_args_for(
+        &self,
+        id: ClientId,
+        channel_password: Option<String>,
+    ) -> Result<MusicBotArgs, BotCreationError> {
+        // Resolve everything from one snapshot so moving clients can't mix up the answer
+        let tree = self.teamspeak.channel_tree();
+        let channel = match tree.channel_of(id) {
             Some(channel) => channel,
-            None => return Err(BotCreationError::UnfoundUser),
+            None => self
+                .query_channel_of(id)
+                .await
+                .ok_or(BotCreationError::UnfoundUser)?,
+        };
+
+        self.build_bot_args_in(&tree, channel, Some(id), None, channel_password, false)
+            .await
+    }
+
+    /// Asks the query for the channel of a client the master does not see
+    async fn query_channel_of(&self, id: ClientId) -> Option<ChannelId> {
+        let query = self.config.query.as_ref()?;
+        let clients = match query.clients().await {
+            Ok(clients) => clients,
+            Err(e) => {
+                warn!("Failed to list the clients through the query: {}", e);
+                return None;
+            }
         };
 
-        if channel == cteamspeak.my_channel().await {
+        let client = clients.into_iter().find(|c| c.id == id)?;
+        info!("Found {} through the query", client.name);
+
+        Some(client.channel)
+    }
+
+    /// Takes a slot for a bot in `channel`.
+    ///
+    /// A bot that joins a channel with another bot only whispers to its
+    /// summoner, or to the `requester` if it has none, so both can be heard.
+    async fn build_bot_args_in(
+        &self,
+        tree: &ChannelTree,
+        channel: ChannelId,
+        summoner: Option<ClientId>,
+        requester: Option<ClientId>,
+        channel_password: Option<String>,
+        force: bool,
+    ) -> Result<MusicBotArgs, BotCreationError> {
+        if Some(channel) == tree.own_channel() {
             return Err(BotCreationError::MasterChannel(
                 self.config.master_name.clone(),
             ));
         }
+        let channel_password = if tree.has_password(channel) {
+            Some(channel_password.ok_or(BotCreationError::PasswordRequired)?)
+        } else {
+            None
+        };
 
-        let MusicBots {
-            ref mut rng,
-            ref mut available_names,
-            ref mut available_ids,
-            ref connected_bots,
-        } = &mut *self.music_bots.write().expect("RwLock was not poisoned");
-
-        for bot in connected_bots.values() {
-            if bot.my_channel().await == channel {
-                return Err(BotCreationError::MultipleBots(bot.name().to_owned()));
+        let shared = force || self.config.multiple_bots;
+        let mut occupied = None;
+        for bot in self.registry.list().await {
+            if bot.my_channel().await == Some(channel) {
+                occupied = Some(bot.name().to_owned());
+                break;
             }
         }
+        if let (Some(name), false) = (&occupied, shared) {
+            return Err(BotCreationError::MultipleBots(name.clone()));
+        }
+        let channel_path = match tree.channel_path(channel) {
+            Some(path) => path,
+            None => return Err(BotCreationError::UnfoundChannel),
+        };
+        // Nobody could hear a bot that shares its channel without a listener
+        let listener = summoner.or(requester).and_then(|id| {
+            let name = tree.client_name(id)?;
+            Some(PrivateSession::new(id, name))
+        });
 
-        let channel_path = cteamspeak
-            .channel_path_of_user(id)
-            .await
-            .expect("can find poke sender");
-
-        available_names.shuffle(rng);
-        let name_index = match available_names.pop() {
-            Some(v) => v,
-            None => {
-                return Err(BotCreationError::OutOfNames);
-            }
+        let slot = if shared {
+            let (slot, connecting) = self.registry.spawn_shared(channel).await?;
+            occupied = occupied.or(connecting);
+            slot
+        } else {
+            self.registry.spawn(channel).await?
         };
-        let name = self.config.names[name_index].clone();
 
-        available_ids.shuffle(rng);
-        let id_index = match available_ids.pop() {
-            Some(v) => v,
-            None => {
-                return Err(BotCreationError::OutOfIdentities);
+        // The bots would talk over each other, so the new one only whispers
+        let private_session = match (occupied, listener) {
+            (Some(_), Some(listener)) => Some(listener),
+            (Some(name), None) => {
+                self.registry.despawn(slot);
+                return Err(BotCreationError::MultipleBots(name));
             }
+            (None, _) => None,
         };
 
-        let id = self.config.ids[id_index].clone();
+        let mut bot_args = self.music_bot_args(slot, channel_path, channel_password, summoner);
+        bot_args.private_session = private_session;
+
+        Ok(bot_args)
+    }
 
-        let cmusic_bots = self.music_bots.clone();
-        let disconnect_cb = Box::new(move |n, name_index, id_index| {
-            let mut music_bots = cmusic_bots.write().expect("RwLock was not poisoned");
-            music_bots.connected_bots.remove(&n);
-            music_bots.available_names.push(name_index);
-            music_bots.available_ids.push(id_index);
+    /// Arguments for a bot whose name and identity are already taken
+    fn music_bot_args(
+        &self,
+        slot: Slot,
+        channel_path: String,
+        channel_password: Option<String>,
+        summoner: Option<ClientId>,
+    ) -> MusicBotArgs {
+        let Slot {
+            name,
+            name_index,
+            id_index,
+        } = slot;
+        let id = self.config.identities.current(&self.config.ids[id_index]);
+
+        let cregistry = self.registry.clone();
+        let csender = self.sender.clone();
+        let disconnect_cb = Box::new(move |name: String, name_index, id_index| {
+            cregistry.despawn(Slot {
+                name,
+                name_index,
+                id_index,
+            });
+            let _ = csender.read().unwrap().send(MusicBotMessage::BotSlotFreed);
+        });
+
+        let cregistry = self.registry.clone();
+        let csender = self.sender.clone();
+        let reconnect_cb = Box::new(move |reconnect: Reconnect| {
+            cregistry.disconnected(reconnect.name.clone());
+
+            let message = MusicBotMessage::Reconnect(Box::new(reconnect));
+            let _ = csender.read().unwrap().send(message);
         });
 
         info!("Connecting to {} on {}", channel_path, self.config.address);
 
-        Ok(MusicBotArgs {
+        MusicBotArgs {
             name,
             name_index,
             id_index,
             local: self.config.local,
             address: self.config.address.clone(),
-            id,
+            id: Some(id),
+            identities: Some(self.config.identities.clone()),
+            server_password: self.config.server_password.clone(),
+            privilege_key: self
+                .config
+                .bot_privilege_keys
+                .lock()
+                .unwrap()
+                .get_mut(id_index)
+                .and_then(Option::take),
+            frontend: None,
             channel: channel_path,
+            channel_password,
+            summoner,
+            private_session: None,
+            bot_uids: self.config.bot_uids.clone(),
+            empty_channel_timeout: self.config.empty_channel_timeout,
             verbose: self.config.verbose,
+            commands: self.config.commands.clone(),
+            thumbnail_avatars: self.config.thumbnail_avatars,
+            max_playlist_tracks: self.config.max_playlist_tracks,
+            queue_limits: self.config.queue_limits,
+            sources: self.config.sources.clone(),
+            blacklist: self.config.blacklist.clone(),
+            no_duplicates: self.config.no_duplicates,
+            file_playback: self.config.file_playback.clone(),
+            ducking: self.config.ducking,
+            encoder: self.config.encoder,
+            watchdog: self.config.watchdog,
+            audio_output: self.config.audio_output.clone(),
+            admins: self.config.admins.clone(),
+            badges: self.config.badges.clone(),
+            client_version: self.config.client_version,
+            idle_audio: self.config.idle_audio,
+            end_of_queue: self.config.end_of_queue.clone(),
+            autoplay: self.config.autoplay,
+            progress_bar: self.config.progress_bar,
+            channel_settings: self.config.channel_settings.clone(),
+            user_settings: self.config.user_settings.clone(),
+            reports: self.config.reports.clone(),
+            rate_limiter: self.config.rate_limiter.clone(),
+            web_url: Some(self.config.web_url.clone()),
+            playlists: self.config.playlists.clone(),
+            audio_cache: self.config.audio_cache.clone(),
+            stats: self.config.stats.clone(),
+            cards: self.config.cards.clone(),
+            scrobbler: self.config.scrobbler.clone(),
+            lyrics: self.config.lyrics.clone(),
+            musicbrainz: self.config.musicbrainz.clone(),
+            downloader: self.config.downloader.clone(),
+            locales: self.config.locales.clone(),
+            resume: None,
             disconnect_cb,
-        })
+            reconnect_cb: Some(reconnect_cb),
+        }
     }
 
-    async fn spawn_bot_for(&self, id: ClientId) {
-        match self.build_bot_args_for(id).await {
-            Ok(bot_args) => {
-                let (bot, fut) = MusicBot::new(bot_args).await;
-                tokio::spawn(fut);
-                let mut music_bots = self.music_bots.write().expect("RwLock was not poisoned");
-                music_bots
-                    .connected_bots
-                    .insert(bot.name().to_string(), bot);
+    /// Connects a bot that lost its connection again, gives up after
+    /// `reconnect_attempts` failed attempts.
+    async fn reconnect(&self, mut reconnect: Reconnect) {
+        if let Some(kick) = reconnect.kick.take() {
+            self.on_bot_kicked(&reconnect, kick).await;
+            self.release(&reconnect);
+            return;
+        }
+        if let Some(remaining) = self.kick_cooldown(&reconnect.channel) {
+            info!(
+                "Not reconnecting {}, a bot was kicked from {} and may come back in {}",
+                reconnect.name,
+                reconnect.channel,
+                humantime::format_duration(remaining)
+            );
+            self.release(&reconnect);
+            return;
+        }
+
+        let result = self.try_reconnect(&reconnect).await;
+        let error = match result {
+            Ok(()) => {
+                info!("{} is back in {}", reconnect.name, reconnect.channel);
+                return;
             }
+            Err(e) => e,
+        };
+        reconnect.attempts += 1;
+
+        let retry = self.is_connected()
+            && !self.is_in_maintenance()
+            && reconnect.attempts < self.config.reconnect_attempts;
+        if !retry {
+            error!(
+                "Giving up on reconnecting {} after {} attempts: {}",
+                reconnect.name, reconnect.attempts, error
+            );
+            self.release(&reconnect);
+            return;
+        }
+
+        let delay = self
+            .config
+            .reconnect_delay
+            .checked_mul(2u32.saturating_pow(reconnect.attempts - 1))
+            .unwrap_or(MAX_RECONNECT_DELAY)
+            .min(MAX_RECONNECT_DELAY);
+        warn!(
+            "Failed to reconnect {}, trying again in {}: {}",
+            reconnect.name,
+            humantime::format_duration(delay),
+            error
+        );
+
+        let sender = self.sender.clone();
+        tokio::spawn(async move {
+            tokio::time::delay_for(delay).await;
+            let message = MusicBotMessage::Reconnect(Box::new(reconnect));
+            let _ = sender.read().unwrap().send(message);
+        });
+    }
+
+    /// Frees the name and identity of a bot that is not coming back
+    fn release(&self, reconnect: &Reconnect) {
+        self.release_slot(slot_of(reconnect));
+    }
+
+    async fn on_bot_kicked(&self, reconnect: &Reconnect, kick: Kick) {
+        let by = kick.by.unwrap_or_else(|| String::from("the server"));
+        warn!(
+            "{} was {} in {} by {}",
+            reconnect.name, kick.kind, reconnect.channel, by
+        );
+        let cooldown = self.config.kick_cooldown;
+        if cooldown > Duration::from_secs(0) {
+            self.kicks
+                .write()
+                .unwrap()
+                .insert(reconnect.channel.clone(), Instant::now());
+        }
+
+        let summoner = match reconnect.summoner {
+            Some(summoner) => summoner,
+            None => return,
+        };
+        let mut text = format!("{} was {} by {}.", reconnect.name, kick.kind, by);
+        if cooldown > Duration::from_secs(0) {
+            text.push_str(&format!(
+                " No bot will come back to your channel by itself for {}.",
+                humantime::format_duration(cooldown)
+            ));
+        }
+        self.send_to_user(summoner, text).await;
+    }
+
+    /// How long bots have to stay away from a channel they were kicked from
+    fn kick_cooldown(&self, channel: &str) -> Option<Duration> {
+        let cooldown = self.config.kick_cooldown;
+        let mut kicks = self.kicks.write().unwrap();
+        kicks.retain(|_, kicked| kicked.elapsed() < cooldown);
+
+        kicks
+            .get(channel)
+            .map(|kicked| cooldown.checked_sub(kicked.elapsed()).unwrap_or_default())
+    }
+
+    async fn try_reconnect(&self, reconnect: &Reconnect) -> Result<(), Error> {
+        let tree = self.teamspeak.channel_tree();
+        let channel = match tree.resolve_channel(&reconnect.channel) {
+            Some(channel) => channel,
+            None => return Err(BotCreationError::UnfoundChannel.into()),
+        };
+
+        self.registry.respawn(slot_of(reconnect), channel).await?;
+        // The spot might have been taken by a new bot while this one was gone
+        for bot in self.registry.list().await {
+            if !self.config.multiple_bots && bot.my_channel().await == Some(channel) {
+                self.registry.connected(reconnect.name.clone(), None);
+                return Err(BotCreationError::MultipleBots(bot.name().to_owned()).into());
+            }
+        }
+
+        let mut bot_args = self.music_bot_args(
+            slot_of(reconnect),
+            reconnect.channel.clone(),
+            reconnect.channel_password.clone(),
+            reconnect.summoner,
+        );
+        bot_args.resume = Some(reconnect.resume.clone());
+        self.connect_bot(bot_args).await?;
+
+        Ok(())
+    }
+
+    async fn spawn_bot_for(&self, id: ClientId, password: Option<String>) -> Result<(), Error> {
+        let bot_args = self.build_bot_args_for(id, password).await?;
+        let name = self.spawn_bot(bot_args).await?;
+        self.greet(id, &name).await;
+
+        Ok(())
+    }
+
+    /// Sends the greeting template to the client a bot was spawned for
+    async fn greet(&self, client: ClientId, botname: &str) {
+        let template = match &self.config.templates.greeting {
+            Some(template) => template,
+            None => return,
+        };
+
+        let text = self.fill_template(client, botname, template);
+        self.send_to_user(client, text).await;
+    }
+
+    /// Fills in the variables of a template for `client`
+    fn fill_template(&self, client: ClientId, botname: &str, template: &str) -> String {
+        let tree = self.teamspeak.channel_tree();
+        let user = tree.client_name(client);
+        let channel = tree.channel_of(client).and_then(|c| tree.channel_path(c));
+        let vars = TemplateVars {
+            channel: channel.as_deref(),
+            user: user.as_deref(),
+            botname,
+        };
+
+        templates::fill(template, &vars)
+    }
+
+    /// Spawns a bot in a channel given by id, path or name, returns the name of the bot.
+    ///
+    /// With `force` it joins even if the channel has a bot already, it then
+    /// only whispers to the `requester`.
+    pub async fn spawn_bot_in(
+        &self,
+        channel: &str,
+        force: bool,
+        requester: Option<ClientId>,
+    ) -> Result<String, MasterActionError> {
+        if !self.is_connected() {
+            return Err(MasterActionError::NotConnected);
+        }
+
+        let tree = self.teamspeak.channel_tree();
+        let id = match tree.resolve_channel(channel) {
+            Some(id) => id,
+            None => return Err(MasterActionError::UnknownChannel(channel.to_owned())),
+        };
+
+        let bot_args = self
+            .build_bot_args_in(&tree, id, None, requester, None, force)
+            .await
+            .map_err(MasterActionError::BotCreation)?;
+        info!("Spawning {} in {}", bot_args.name, bot_args.channel);
+
+        self.spawn_bot(bot_args)
+            .await
+            .map_err(MasterActionError::Spawn)
+    }
+
+    /// Connects a new bot, its slot is freed again if it fails to
+    async fn spawn_bot(&self, bot_args: MusicBotArgs) -> Result<String, Error> {
+        let slot = slot_of_args(&bot_args);
+        let result = self.connect_bot(bot_args).await;
+        if result.is_err() {
+            self.release_slot(slot);
+        }
+
+        result
+    }
+
+    /// Connects a bot whose slot stays reserved if it fails, so a reconnect can try again
+    async fn connect_bot(&self, bot_args: MusicBotArgs) -> Result<String, Error> {
+        let name = bot_args.name.clone();
+        let uid = bot_args.id.as_ref().map(identity_uid);
+        let (bot, fut) = match MusicBot::new(bot_args).await {
+            Ok(bot) => bot,
             Err(e) => {
-                let mut cteamspeak = self.teamspeak.clone();
-                cteamspeak.send_message_to_user(id, e.to_string()).await
+                self.registry.connected(name, None);
+                return Err(e);
+            }
+        };
+        tokio::spawn(fut);
+        self.registry.connected(name.clone(), Some(bot));
+
+        if let Some(uid) = uid {
+            self.add_to_server_groups(uid);
+        }
+
+        Ok(name)
+    }
+
+    /// Puts a connected identity in the server groups of the query config
+    fn add_to_server_groups(&self, uid: String) {
+        let query = match &self.config.query {
+            Some(query) => query.clone(),
+            None => return,
+        };
+
+        tokio::spawn(async move {
+            if let Err(e) = query.add_to_server_groups(&uid).await {
+                warn!("Failed to add {} to its server groups: {}", uid, e);
+            }
+        });
+    }
+
+    /// Sends a private message, through the query if it is configured to send them
+    async fn send_to_user(&self, client: ClientId, text: String) {
+        if let Some(query) = self.config.query.as_ref().filter(|q| q.sends_messages()) {
+            match query.send_message(client, &text).await {
+                Ok(()) => return,
+                Err(e) => warn!("Failed to send a message through the query: {}", e),
             }
         }
+
+        let mut cteamspeak = self.teamspeak.clone();
+        cteamspeak.send_message_to_user(client, text).await;
     }
 
-    async fn on_message(&self, message: MusicBotMessage) -> Result<(), AudioPlayerError> {
+    async fn on_message(&self, message: MusicBotMessage) -> Result<(), Error> {
         match message {
             MusicBotMessage::TextMessage(message) => {
                 if let MessageTarget::Poke(who) = message.target {
+                    if let Err(reply) = self.check_summon(&message.invoker) {
+                        if let Some(reply) = reply {
+                            self.send_to_user(who, reply).await;
+                        }
+                        return Ok(());
+                    }
+
                     info!("Poked by {}, creating bot for their channel", who);
-                    self.spawn_bot_for(who).await;
+                    if let Some(reply) = self.summon(&message.invoker, None).await {
+                        self.send_to_user(who, reply).await;
+                    }
+                } else if let MessageTarget::Client(_) = message.target {
+                    if message.invoker.id != self.my_id().await {
+                        self.on_private_text(&message.invoker, &message.text).await;
+                    }
                 }
             }
             MusicBotMessage::ChannelAdded(id) => {
                 let mut cteamspeak = self.teamspeak.clone();
                 cteamspeak.subscribe(id).await;
             }
+            MusicBotMessage::BotSlotFreed => {
+                self.spawn_for_waiting().await;
+            }
+            MusicBotMessage::Reconnect(reconnect) => {
+                self.reconnect(*reconnect).await;
+            }
+            MusicBotMessage::ClientChannel { client, .. } => {
+                if client == self.my_id().await {
+                    self.on_moved();
+                }
+            }
+            MusicBotMessage::OfferTimeout { client, offered } => {
+                self.on_offer_timeout(client, offered).await;
+            }
+            MusicBotMessage::ReturnToParking(left) => {
+                if *self.parking_left.read().unwrap() == Some(left) {
+                    self.return_to_parking().await;
+                }
+            }
             MusicBotMessage::ClientAdded(id) => {
                 let mut cteamspeak = self.teamspeak.clone();
 
                 if id == cteamspeak.my_id().await {
-                    cteamspeak
-                        .set_description(String::from("Poke me if you want a music bot!"))
-                        .await;
+                    let description = self.status.read().unwrap().description.clone();
+                    cteamspeak.set_description(description).await;
                 }
             }
+            MusicBotMessage::Ping(pong) => {
+                let _ = pong.send(());
+            }
             _ => (),
         }
 
         Ok(())
     }
 
-    async fn my_id(&self) -> ClientId {
+    /// Goes back to the parking channel once the master stayed away from it for `return_after`
+    fn on_moved(&self) {
+        let parking = match &self.config.parking {
+            Some(parking) => parking,
+            None => return,
+        };
+        let tree = self.teamspeak.channel_tree();
+        if tree.own_channel() == tree.resolve_channel(&parking.channel) {
+            *self.parking_left.write().unwrap() = None;
+            return;
+        }
+
+        let left = Instant::now();
+        *self.parking_left.write().unwrap() = Some(left);
+        info!(
+            "Moved out of the parking channel, returning in {}",
+            humantime::format_duration(parking.return_after)
+        );
+
+        let sender = self.sender.clone();
+        let delay = parking.return_after;
+        tokio::spawn(async move {
+            tokio::time::delay_for(delay).await;
+            // Ignore the result because the master might have quit in the meantime
+            let _ = sender
+                .read()
+                .unwrap()
+                .send(MusicBotMessage::ReturnToParking(left));
+        });
+    }
+
+    /// Moves the master into its parking channel if it is somewhere else
+    async fn return_to_parking(&self) {
+        let parking = match &self.config.parking {
+            Some(parking) => parking,
+            None => return,
+        };
+        let tree = self.teamspeak.channel_tree();
+        let channel = match tree.resolve_channel(&parking.channel) {
+            Some(channel) => channel,
+            None => {
+                warn!("Failed to find the parking channel {:?}", parking.channel);
+                return;
+            }
+        };
+        if tree.own_channel() == Some(channel) {
+            return;
+        }
+        if tree.has_password(channel) {
+            warn!(
+                "The parking channel {:?} has a password, the master can not join it",
+                parking.channel
+            );
+            return;
+        }
+
+        info!("Moving into the parking channel {:?}", parking.channel);
+        *self.parking_left.write().unwrap() = None;
         let mut cteamspeak = self.teamspeak.clone();
+        cteamspeak.move_to_channel(channel).await;
+    }
 
-        cteamspeak.my_id().await
+    /// Puts the client in line for the next free bot, returns their position
+    fn wait_for_bot(&self, id: ClientId) -> usize {
+        let mut waiting = self.waiting.write().unwrap();
+        if let Some(index) = waiting.iter().position(|w| *w == id) {
+            return index + 1;
+        }
+        waiting.push_back(id);
+
+        waiting.len()
     }
 
-    pub fn bot_data(&self, name: String) -> Option<crate::web_server::BotData> {
-        let music_bots = self.music_bots.read().unwrap();
-        let bot = music_bots.connected_bots.get(&name)?;
+    /// Checks maintenance and the poke rate limit, which apply to every way of
+    /// asking for a bot. Fails with the reply, if there is one.
+    fn check_summon(&self, invoker: &Invoker) -> Result<(), Option<String>> {
+        let who = invoker.id;
+        if self.is_in_maintenance() {
+            info!("Asked for a bot by {} during maintenance, ignoring", who);
+            let locale = self.locale(who, Some(invoker));
+            return Err(Some(MasterActionError::Maintenance.localize(&locale)));
+        }
 
-        Some(crate::web_server::BotData {
-            name,
-            state: bot.state(),
-            volume: bot.volume(),
-            position: bot.position(),
-            currently_playing: bot.currently_playing(),
-            playlist: bot.playlist_to_vec(),
-        })
-    }
-
-    pub fn bot_datas(&self) -> Vec<crate::web_server::BotData> {
-        let music_bots = self.music_bots.read().unwrap();
-
-        let len = music_bots.connected_bots.len();
-        let mut result = Vec::with_capacity(len);
-        for (name, bot) in &music_bots.connected_bots {
-            let bot_data = crate::web_server::BotData {
-                name: name.clone(),
-                state: bot.state(),
-                volume: bot.volume(),
-                position: bot.position(),
-                currently_playing: bot.currently_playing(),
-                playlist: bot.playlist_to_vec(),
+        let user = match &invoker.uid {
+            Some(uid) => uid.0.clone(),
+            None => invoker.name.clone(),
+        };
+        let limit = self.config.rate_limiter.check_poke(&user);
+        if limit != RateLimit::Allowed {
+            info!("Asked for a bot by {} too often, not creating one", who);
+            return Err(limit.reply(&invoker.name));
+        }
+
+        Ok(())
+    }
+
+    /// Spawns a bot for the client or offers one if spawns have to be
+    /// confirmed, returns the reply if there is one
+    async fn summon(&self, invoker: &Invoker, password: Option<String>) -> Option<String> {
+        let who = invoker.id;
+        let locale = self.locale(who, Some(invoker));
+        let result = match self.config.confirm_spawn {
+            Some(_) => self.offer_bot(who, password, &locale).await.map(Some),
+            None => self.spawn_bot_for(who, password).await.map(|()| None),
+        };
+        match result {
+            Ok(reply) => reply,
+            Err(Error::BotCreation(BotCreationError::TooManyBots(_))) => {
+                let position = self.wait_for_bot(who);
+                info!("All bots are busy, {} is waiting at {}", who, position);
+                Some(tr!(
+                    locale,
+                    "waiting-for-bot",
+                    "All bots are busy, you are number {position} in line. \
+                     I will send one to you once a bot is free.",
+                    position = position
+                ))
+            }
+            Err(Error::BotCreation(BotCreationError::PasswordRequired)) => {
+                Some(self.ask_for_password(&locale))
+            }
+            Err(e) => {
+                warn!("Failed to create bot for {}: {}", who, e);
+                Some(e.localize(&locale))
+            }
+        }
+    }
+
+    /// Reserves a bot for the client and returns the question if they want it.
+    ///
+    /// Poking again from the same channel repeats the offer, from another
+    /// channel the old offer is replaced.
+    async fn offer_bot(
+        &self,
+        who: ClientId,
+        password: Option<String>,
+        locale: &Locale,
+    ) -> Result<String, Error> {
+        let channel = {
+            let tree = self.teamspeak.channel_tree();
+            tree.channel_of(who).and_then(|c| tree.channel_path(c))
+        };
+        let old_offer = {
+            let mut offers = self.offers.write().unwrap();
+            match offers.get(&who) {
+                Some(offer) if Some(&offer.bot_args.channel) == channel.as_ref() => {
+                    return Ok(self.offer_text(&offer.bot_args, locale));
+                }
+                Some(_) => offers.remove(&who),
+                None => None,
+            }
+        };
+        if let Some(offer) = old_offer {
+            info!("{} poked from another channel, replacing their offer", who);
+            self.release_offer(offer);
+        }
+
+        let bot_args = self.build_bot_args_for(who, password).await?;
+        let text = self.offer_text(&bot_args, locale);
+        info!(
+            "Offering {} in {} to {}",
+            bot_args.name, bot_args.channel, who
+        );
+
+        let offered = Instant::now();
+        self.offers
+            .write()
+            .unwrap()
+            .insert(who, Offer { bot_args, offered });
+
+        let delay = self
+            .config
+            .confirm_spawn
+            .as_ref()
+            .and_then(|c| c.auto_confirm)
+            .unwrap_or(OFFER_TIMEOUT);
+        let sender = self.sender.clone();
+        tokio::spawn(async move {
+            tokio::time::delay_for(delay).await;
+            let message = MusicBotMessage::OfferTimeout {
+                client: who,
+                offered,
             };
+            let _ = sender.read().unwrap().send(message);
+        });
+
+        Ok(text)
+    }
 
-            result.push(bot_data);
+    fn offer_text(&self, bot_args: &MusicBotArgs, locale: &Locale) -> String {
+        tr!(
+            locale,
+            "offer",
+            "I'll join \"{path}\" as \"{name}\", reply {prefix}yes to confirm.",
+            path = bot_args.channel,
+            name = bot_args.name,
+            prefix = self.config.commands.prefix
+        )
+    }
+
+    /// Spawns the bot offered to the client after `!yes`, returns the reply if something went wrong
+    async fn confirm_offer(&self, who: ClientId, locale: &Locale) -> Option<String> {
+        let offer = match self.offers.write().unwrap().remove(&who) {
+            Some(offer) => offer,
+            None => {
+                return Some(tr!(
+                    locale,
+                    "no-offer",
+                    "There is no bot waiting for you, poke me to get one."
+                ))
+            }
+        };
+
+        self.spawn_offer(who, offer)
+            .await
+            .map(|e| e.localize(locale))
+    }
+
+    /// Spawns the bot for the client unless it timed out without `auto_confirm`
+    async fn on_offer_timeout(&self, who: ClientId, offered: Instant) {
+        let offer = {
+            let mut offers = self.offers.write().unwrap();
+            match offers.get(&who) {
+                Some(offer) if offer.offered == offered => offers.remove(&who).unwrap(),
+                _ => return,
+            }
+        };
+
+        let auto_confirm = self
+            .config
+            .confirm_spawn
+            .as_ref()
+            .map_or(false, |c| c.auto_confirm.is_some());
+        if auto_confirm {
+            info!("{} did not answer, confirming the bot for them", who);
+            if let Some(e) = self.spawn_offer(who, offer).await {
+                let reply = e.localize(&self.locale(who, None));
+                self.send_to_user(who, reply).await;
+            }
+        } else {
+            info!(
+                "{} did not confirm {}, dropping it",
+                who, offer.bot_args.name
+            );
+            self.release_offer(offer);
         }
+    }
 
-        result
+    async fn spawn_offer(&self, who: ClientId, offer: Offer) -> Option<Error> {
+        match self.spawn_bot(offer.bot_args).await {
+            Ok(name) => {
+                self.greet(who, &name).await;
+                None
+            }
+            Err(e) => {
+                warn!("Failed to create the confirmed bot for {}: {}", who, e);
+                Some(e)
+            }
+        }
     }
 
-    pub fn bot_names(&self) -> Vec<String> {
-        let music_bots = self.music_bots.read().unwrap();
+    /// Frees the slot of an offer that was not taken, so a waiting client can get it
+    fn release_offer(&self, offer: Offer) {
+        self.release_slot(slot_of_args(&offer.bot_args));
+    }
+
+    /// Frees the slot reserved for a bot that will not connect
+    fn release_slot(&self, slot: Slot) {
+        self.registry.despawn(slot);
+        let _ = self
+            .sender
+            .read()
+            .unwrap()
+            .send(MusicBotMessage::BotSlotFreed);
+    }
 
-        let len = music_bots.connected_bots.len();
-        let mut result = Vec::with_capacity(len);
-        for name in music_bots.connected_bots.keys() {
-            result.push(name.clone());
+    /// Tells the client how to send the password of their channel
+    fn ask_for_password(&self, locale: &Locale) -> String {
+        let mut reply = BotCreationError::PasswordRequired.localize(locale);
+        reply.push(' ');
+        reply.push_str(&tr!(
+            locale,
+            "password-hint",
+            "Send {prefix}password <password> to me in a private message.",
+            prefix = self.config.commands.prefix
+        ));
+        if self.config.music_channel.is_some() {
+            reply.push(' ');
+            reply.push_str(&tr!(
+                locale,
+                "music-channel-hint",
+                "Or send {prefix}music-channel to get a channel without one.",
+                prefix = self.config.commands.prefix
+            ));
         }
 
-        result
+        reply
+    }
+
+    fn forget_client(&self, id: ClientId) {
+        self.waiting.write().unwrap().retain(|w| *w != id);
+        if let Some(offer) = self.offers.write().unwrap().remove(&id) {
+            self.release_offer(offer);
+        }
     }
 
-    pub fn quit(&self, reason: String) {
-        let music_bots = self.music_bots.read().unwrap();
-        for bot in music_bots.connected_bots.values() {
-            bot.quit(reason.clone())
+    /// Moves the client into the music channel below their channel and spawns a bot there.
+    ///
+    /// The music channel is created if it does not exist yet.
+    async fn spawn_in_music_channel(&self, id: ClientId) -> Result<(), MasterActionError> {
+        if self.is_in_maintenance() {
+            return Err(MasterActionError::Maintenance);
+        }
+        let name = match &self.config.music_channel {
+            Some(name) => name.clone(),
+            None => return Err(MasterActionError::Unsupported),
+        };
+
+        let tree = self.teamspeak.channel_tree();
+        let current = tree.channel_of(id).ok_or(MasterActionError::BotCreation(
+            BotCreationError::UnfoundUser,
+        ))?;
+        let current_path = tree
+            .channel_path(current)
+            .ok_or(MasterActionError::BotCreation(
+                BotCreationError::UnfoundChannel,
+            ))?;
+
+        // Users already in a music channel should not get one inside of it
+        let channel = if current_path.rsplit('/').next() == Some(&name[..]) {
+            current
+        } else {
+            let path = format!("{}/{}", current_path, name);
+            match tree.find_channel(&path) {
+                Some(channel) => channel,
+                None => self.create_channel(name, current, &path).await?,
+            }
+        };
+
+        // The client is only moved once the bot can join, a free slot is reserved until then
+        let tree = self.teamspeak.channel_tree();
+        let bot_args = self
+            .build_bot_args_in(&tree, channel, Some(id), None, None, false)
+            .await
+            .map_err(MasterActionError::BotCreation)?;
+
+        if channel != current {
+            let mut cteamspeak = self.teamspeak.clone();
+            if let Err(e) = cteamspeak.move_client(id, channel).await {
+                self.release_slot(slot_of_args(&bot_args));
+                return Err(MasterActionError::ChannelCreation(e));
+            }
         }
+
+        let name = self
+            .spawn_bot(bot_args)
+            .await
+            .map_err(MasterActionError::Spawn)?;
+        self.greet(id, &name).await;
+
+        Ok(())
+    }
+
+    /// Creates a channel and waits until the server announced it
+    async fn create_channel(
+        &self,
+        name: String,
+        parent: ChannelId,
+        path: &str,
+    ) -> Result<ChannelId, MasterActionError> {
+        info!("Creating channel {}", path);
+        let created = match &self.config.query {
+            Some(query) => query.create_channel(&name, parent).await,
+            None => {
+                let mut cteamspeak = self.teamspeak.clone();
+                cteamspeak.create_channel(name, parent).await
+            }
+        };
+        created.map_err(MasterActionError::ChannelCreation)?;
+
+        for _ in 0..CHANNEL_CREATION_CHECKS {
+            tokio::time::delay_for(CHANNEL_CREATION_INTERVAL).await;
+            if let Some(channel) = self.teamspeak.channel_tree().find_channel(path) {
+                return Ok(channel);
+            }
+        }
+
+        Err(MasterActionError::ChannelCreation(String::from(
+            "The server did not create the channel in time",
+        )))
+    }
+
+    /// Spawns bots for the clients in line until all bots are busy again
+    async fn spawn_for_waiting(&self) {
+        loop {
+            let id = match self.waiting.write().unwrap().pop_front() {
+                Some(id) => id,
+                None => return,
+            };
+
+            match self.spawn_bot_for(id, None).await {
+                Ok(()) => {
+                    info!("Created bot for {} who was waiting", id);
+                    let reply = tr!(
+                        self.locale(id, None),
+                        "bot-free",
+                        "A bot is free now and joins you."
+                    );
+                    self.send_to_user(id, reply).await;
+                }
+                Err(Error::BotCreation(BotCreationError::TooManyBots(_))) => {
+                    self.waiting.write().unwrap().push_front(id);
+                    return;
+                }
+                Err(Error::BotCreation(BotCreationError::PasswordRequired)) => {
+                    let reply = self.ask_for_password(&self.locale(id, None));
+                    self.send_to_user(id, reply).await;
+                }
+                Err(e) => {
+                    warn!("Failed to create bot for {} who was waiting: {}", id, e);
+                    let reply = e.localize(&self.locale(id, None));
+                    self.send_to_user(id, reply).await;
+                }
+            }
+        }
+    }
+
+    /// Spawns a bot in the channel of the client, which has this password
+    async fn on_password(&self, from: &Invoker, password: String) -> Option<String> {
+        if let Err(reply) = self.check_summon(from) {
+            return reply;
+        }
+
+        info!("Got the channel password from {}", from.id);
+        self.summon(from, Some(password)).await
+    }
+
+    async fn on_private_text(&self, from: &Invoker, text: &str) {
+        // Not tokenized, spaces and quotes may be part of the password
+        let password = self.config.commands.raw_arguments(text, "password");
+        if let Some(password) = password.filter(|p| !p.is_empty()) {
+            if let Some(reply) = self.on_password(from, password.to_owned()).await {
+                self.send_to_user(from.id, reply).await;
+            }
+            return;
+        }
+
+        let locale = self.locale(from.id, Some(from));
+        let reply = match self.config.commands.parse::<MasterCommand>(text) {
+            Some(Ok(MasterCommand::Status)) => self.status_text(&locale),
+            Some(Ok(MasterCommand::Yes)) => match self.check_summon(from) {
+                Ok(()) => match self.confirm_offer(from.id, &locale).await {
+                    Some(reply) => reply,
+                    None => return,
+                },
+                Err(Some(reply)) => reply,
+                Err(None) => return,
+            },
+            // Only reached through aliases, the command itself is handled above
+            Some(Ok(MasterCommand::Password { password })) => {
+                match self.on_password(from, password.join(" ")).await {
+                    Some(reply) => reply,
+                    None => return,
+                }
+            }
+            Some(Ok(MasterCommand::MusicChannel)) => {
+                match self.spawn_in_music_channel(from.id).await {
+                    Ok(()) => return,
+                    Err(e) => {
+                        warn!(
+                            "Failed to create bot in music channel for {}: {}",
+                            from.id, e
+                        );
+                        e.localize(&locale)
+                    }
+                }
+            }
+            Some(Ok(MasterCommand::Spawn { force, channel })) => {
+                if self.is_admin(from) {
+                    let channel = channel.join(" ");
+                    match self.spawn_bot_in(&channel, force, Some(from.id)).await {
+                        Ok(name) => tr!(
+                            locale,
+                            "spawned",
+                            "Spawned \"{name}\" in {channel}",
+                            name = name,
+                            channel = channel,
+                        ),
+                        Err(e) => e.localize(&locale),
+                    }
+                } else {
+                    tr!(
+                        locale,
+                        "spawn-admins-only",
+                        "Only admins can spawn bots by command"
+                    )
+                }
+            }
+            Some(Ok(MasterCommand::Schedule { action })) => {
+                if self.is_admin(from) {
+                    self.config.schedule.on_command(action, &from.name)
+                } else {
+                    tr!(
+                        locale,
+                        "schedule-admins-only",
+                        "Only admins can schedule jobs"
+                    )
+                }
+            }
+            Some(Err(CommandError::Help(help))) => {
+                format!("\n{}", self.config.commands.format_help(&help, &locale))
+            }
+            // Music bot commands are sent to the master as well
+            Some(Err(CommandError::UnknownCommand(_))) | None => return,
+            Some(Err(e)) => e.to_string(),
+        };
+
+        self.send_to_user(from.id, reply).await;
+    }
+
+    /// The language of `user` for replies to `client`, the variables of the
+    /// configured templates are filled in for that client
+    fn locale(&self, client: ClientId, user: Option<&Invoker>) -> Arc<Locale> {
+        let language = user
+            .and_then(|user| user.uid.as_ref())
+            .and_then(|uid| self.config.user_settings.get(&uid.0).language);
+        let locale = self
+            .config
+            .locales
+            .for_user(&self.config.address, language.as_deref());
+
+        let templates = &self.config.templates.messages;
+        if templates.is_empty() {
+            return locale;
+        }
+        Arc::new(locale.with_filled(templates.keys(), |template| {
+            self.fill_template(client, &self.config.master_name, template)
+        }))
+    }
+
+    fn is_admin(&self, invoker: &Invoker) -> bool {
+        match &invoker.uid {
+            Some(uid) => self.config.admins.contains(&uid.0),
+            None => false,
+        }
+    }
+
+    fn status_text(&self, locale: &Locale) -> String {
+        let status = self.status.read().unwrap();
+        let registered = self.registry.registered();
+
+        let uptime = Duration::from_secs(self.started.elapsed().as_secs());
+        let maintenance = if status.maintenance {
+            tr!(locale, "status-on", "on")
+        } else {
+            tr!(locale, "status-off", "off")
+        };
+        let text = tr!(
+            locale,
+            "status",
+            "Uptime: {uptime}\nMaintenance: {maintenance}\nMusic bots: {bots}\nWeb server: {web_server}",
+            uptime = humantime::format_duration(uptime),
+            maintenance = maintenance,
+            bots = registered.bots.len(),
+            web_server = status.web_server.localize(locale),
+        );
+
+        format!("\n{}", text)
+    }
+
+    async fn my_id(&self) -> ClientId {
+        let mut cteamspeak = self.teamspeak.clone();
+
+        cteamspeak.my_id().await
+    }
+
+    fn set_connected(&self, connected: bool) {
+        self.status.write().unwrap().connected = connected;
+    }
+
+    fn is_connected(&self) -> bool {
+        self.status.read().unwrap().connected
+    }
+
+    fn is_in_maintenance(&self) -> bool {
+        self.status.read().unwrap().maintenance
+    }
+
+    pub async fn master_data(&self) -> MasterData {
+        let channel = if self.is_connected() {
+            let tree = self.teamspeak.channel_tree();
+            tree.own_channel().and_then(|c| tree.channel_path(c))
+        } else {
+            None
+        };
+
+        let (description, connected, maintenance, web_server) = {
+            let status = self.status.read().unwrap();
+            (
+                status.description.clone(),
+                status.connected,
+                status.maintenance,
+                status.web_server.clone(),
+            )
+        };
+
+        let registered = self.registry.registered();
+
+        MasterData {
+            name: self.config.master_name.clone(),
+            channel,
+            description,
+            uptime: self.started.elapsed(),
+            connected,
+            maintenance,
+            available_names: registered.available_names,
+            available_identities: registered.available_identities,
+            connected_bots: registered.bots.keys().cloned().collect(),
+            web_server,
+            messages: Some(self.sender.read().unwrap().stats()),
+        }
+    }
+
+    pub async fn set_description(&self, description: String) -> Result<(), MasterActionError> {
+        if !self.is_connected() {
+            return Err(MasterActionError::NotConnected);
+        }
+
+        self.status.write().unwrap().description = description.clone();
+
+        let mut cteamspeak = self.teamspeak.clone();
+        cteamspeak.set_description(description).await;
+
+        Ok(())
+    }
+
+    pub async fn move_to_channel(&self, channel: String) -> Result<(), MasterActionError> {
+        if !self.is_connected() {
+            return Err(MasterActionError::NotConnected);
+        }
+
+        let mut cteamspeak = self.teamspeak.clone();
+        let id = match cteamspeak.find_channel(&channel).await {
+            Some(id) => id,
+            None => return Err(MasterActionError::UnknownChannel(channel)),
+        };
+
+        info!("Moving master to {}", channel);
+        cteamspeak.move_to_channel(id).await;
+
+        Ok(())
+    }
+
+    pub fn set_web_server_status(&self, web_server: WebServerStatus) {
+        self.status.write().unwrap().web_server = web_server;
+    }
+
+    pub fn set_maintenance(&self, enabled: bool) {
+        info!("Setting maintenance mode: {}", enabled);
+
+        self.status.write().unwrap().maintenance = enabled;
+    }
+
+    /// Lists the identities of the master and all music bots so they can be whitelisted.
+    pub fn identities(&self) -> Vec<IdentityInfo> {
+        let registered = self.registry.registered();
+        let used_by = |index: usize| {
+            registered
+                .bots
+                .values()
+                .find(|b| b.identity_index() == index)
+                .map(|b| b.name().to_owned())
+        };
+
+        let mut identities = vec![IdentityInfo {
+            uid: identity_uid(&self.config.id),
+            level: self.config.id.level().ok(),
+            used_by: Some(self.config.master_name.clone()),
+        }];
+
+        for (i, id) in self.config.ids.iter().enumerate() {
+            identities.push(IdentityInfo {
+                uid: identity_uid(id),
+                level: self.config.identities.current(id).level().ok(),
+                used_by: used_by(i),
+            });
+        }
+
+        identities
+    }
+
+    pub fn music_bot(&self, name: &str) -> Option<Arc<MusicBot>> {
+        self.registry.registered().bots.get(name).cloned()
+    }
+
+    pub fn bot_details(&self, name: String) -> Option<BotDetails> {
+        let bot = self.music_bot(&name)?;
+
+        Some(bot.details())
+    }
+
+    pub fn bot_summaries(&self) -> Vec<BotSummary> {
+        let registered = self.registry.registered();
+
+        registered.bots.values().map(|bot| bot.summary()).collect()
+    }
+
+    pub fn bot_names(&self) -> Vec<String> {
+        self.registry.registered().bots.keys().cloned().collect()
+    }
+
+    /// Disconnects all music bots at once and the master after them
+    pub async fn quit(&self, reason: String) {
+        self.registry.quit_all(reason.clone()).await;
+
         let sender = self.sender.read().unwrap();
-        sender.send(MusicBotMessage::Quit(reason)).unwrap();
+        if sender.send(MusicBotMessage::Quit(reason)).is_err() {
+            error!("Master message loop already stopped");
+        }
+    }
+}
+
+/// The name and identity a bot that lost its connection still holds
+fn slot_of(reconnect: &Reconnect) -> Slot {
+    Slot {
+        name: reconnect.name.clone(),
+        name_index: reconnect.name_index,
+        id_index: reconnect.id_index,
+    }
+}
+
+fn slot_of_args(bot_args: &MusicBotArgs) -> Slot {
+    Slot {
+        name: bot_args.name.clone(),
+        name_index: bot_args.name_index,
+        id_index: bot_args.id_index,
+    }
+}
+
+// The inherent methods are also used by the master itself
+#[async_trait(?Send)]
+impl BotManager for MasterBot {
+    async fn master_data(&self) -> MasterData {
+        MasterBot::master_data(self).await
+    }
+
+    async fn set_description(&self, description: String) -> Result<(), MasterActionError> {
+        MasterBot::set_description(self, description).await
+    }
+
+    async fn move_to_channel(&self, channel: String) -> Result<(), MasterActionError> {
+        MasterBot::move_to_channel(self, channel).await
+    }
+
+    async fn spawn_bot_in(&self, channel: &str) -> Result<String, MasterActionError> {
+        MasterBot::spawn_bot_in(self, channel, false, None).await
+    }
+
+    async fn quit(&self, reason: String) {
+        MasterBot::quit(self, reason).await
+    }
+
+    async fn ping(&self) -> bool {
+        let sender = self.sender.read().unwrap().clone();
+
+        ping_message_loop(&sender).await
+    }
+
+    fn set_maintenance(&self, enabled: bool) {
+        MasterBot::set_maintenance(self, enabled)
+    }
+
+    fn set_web_server_status(&self, web_server: WebServerStatus) {
+        MasterBot::set_web_server_status(self, web_server)
+    }
+
+    fn identities(&self) -> Vec<IdentityInfo> {
+        MasterBot::identities(self)
+    }
+
+    fn music_bot(&self, name: &str) -> Option<Arc<MusicBot>> {
+        MasterBot::music_bot(self, name)
+    }
+
+    fn bot_names(&self) -> Vec<String> {
+        MasterBot::bot_names(self)
+    }
+
+    fn bot_summaries(&self) -> Vec<BotSummary> {
+        MasterBot::bot_summaries(self)
+    }
+
+    fn bot_details(&self, name: String) -> Option<BotDetails> {
+        MasterBot::bot_details(self, name)
+    }
+
+    fn stats(&self) -> Option<Arc<StatsStore>> {
+        self.config.stats.clone()
+    }
+
+    fn reports(&self) -> Arc<ReportStore> {
+        self.config.reports.clone()
+    }
+
+    fn schedule(&self) -> Arc<ScheduleStore> {
+        self.config.schedule.clone()
+    }
+
+    fn downloader(&self) -> Arc<Downloader> {
+        self.config.downloader.clone()
     }
 }
 
 #[derive(Debug)]
 pub enum BotCreationError {
     UnfoundUser,
+    UnfoundChannel,
     MasterChannel(String),
     MultipleBots(String),
+    StillConnecting(String),
+    TooManyBots(usize),
+    PasswordRequired,
     OutOfNames,
     OutOfIdentities,
 }
 
-impl std::fmt::Display for BotCreationError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+impl BotCreationError {
+    /// The message for the user who wanted the bot
+    pub fn localize(&self, locale: &Locale) -> String {
         use BotCreationError::*;
         match self {
-            UnfoundUser => write!(
-                f,
+            UnfoundUser => tr!(
+                locale,
+                "creation-unfound-user",
                 "I can't find you in the channel list, \
-                    either I am not subscribed to your channel or this is a bug.",
+                    either I am not subscribed to your channel or this is a bug."
+            ),
+            UnfoundChannel => tr!(
+                locale,
+                "creation-unfound-channel",
+                "I can't find the channel, it may have been deleted."
+            ),
+            MasterChannel(name) => tr!(
+                locale,
+                "creation-master-channel",
+                "Joining the channel of \"{name}\" is not allowed",
+                name = name
+            ),
+            MultipleBots(name) => tr!(
+                locale,
+                "creation-multiple-bots",
+                "\"{name}\" is already in this channel. \
+                    Multiple bots in one channel are not allowed.",
+                name = name
+            ),
+            StillConnecting(name) => tr!(
+                locale,
+                "creation-still-connecting",
+                "\"{name}\" is still connecting to this channel, \
+                    please try again once it is there.",
+                name = name
+            ),
+            TooManyBots(max) => tr!(
+                locale,
+                "creation-too-many-bots",
+                "All {max} bots are busy, please try again later.",
+                max = max
             ),
-            MasterChannel(name) => write!(f, "Joining the channel of \"{}\" is not allowed", name),
-            MultipleBots(name) => write!(
-                f,
-                "\"{}\" is already in this channel. \
-                         Multiple bots in one channel are not allowed.",
-                name
+            PasswordRequired => tr!(
+                locale,
+                "creation-password-required",
+                "Your channel has a password."
+            ),
+            OutOfNames => tr!(
+                locale,
+                "creation-out-of-names",
+                "Out of names. Too many bots are already connected!"
+            ),
+            OutOfIdentities => tr!(
+                locale,
+                "creation-out-of-identities",
+                "Out of identities. Too many bots are already connected!"
             ),
-            OutOfNames => write!(f, "Out of names. Too many bots are already connected!"),
-            OutOfIdentities => write!(f, "Out of identities. Too many bots are already connected!"),
         }
     }
 }
 
+impl std::fmt::Display for BotCreationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.localize(&Locale::english()))
+    }
+}
+
+#[derive(Debug)]
+pub enum MasterActionError {
+    NotConnected,
+    UnknownChannel(String),
+    BotCreation(BotCreationError),
+    Spawn(Error),
+    Maintenance,
+    NotInVoice,
+    ChannelCreation(String),
+    Unsupported,
+}
+
+impl MasterActionError {
+    /// The message for the user who asked for the action
+    pub fn localize(&self, locale: &Locale) -> String {
+        use MasterActionError::*;
+        match self {
+            BotCreation(e) => e.localize(locale),
+            Maintenance => tr!(
+                locale,
+                "maintenance",
+                "I am in maintenance mode, please try again later."
+            ),
+            NotInVoice => tr!(
+                locale,
+                "not-in-voice",
+                "Join a voice channel so I know where to play."
+            ),
+            e => e.to_string(),
+        }
+    }
+}
+
+impl std::fmt::Display for MasterActionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use MasterActionError::*;
+        match self {
+            NotConnected => write!(f, "The master bot is not connected"),
+            UnknownChannel(name) => write!(f, "Can't find a channel named \"{}\"", name),
+            BotCreation(_) | Maintenance | NotInVoice => {
+                f.write_str(&self.localize(&Locale::english()))
+            }
+            Spawn(e) => e.fmt(f),
+            ChannelCreation(e) => write!(f, "Failed to set up the music channel: {}", e),
+            Unsupported => write!(f, "This is not supported by the current frontend"),
+        }
+    }
+}
+
+/// A channel the master stays in, e.g. a lobby for bots
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ParkingConfig {
+    /// Path, name or id of the channel
+    pub channel: String,
+    /// How long the master stays where it was moved to, "0s" moves it back right away
+    #[serde(
+        default = "default_return_after",
+        with = "crate::duration::serde_human"
+    )]
+    pub return_after: Duration,
+}
+
+/// Pokes only spawn a bot once the user confirmed it with `!yes`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConfirmSpawnConfig {
+    /// Spawns the bot without `!yes` after this long, offers are dropped after two minutes if not set
+    #[serde(default, with = "crate::duration::serde_human_option")]
+    pub auto_confirm: Option<Duration>,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct MasterArgs {
     #[serde(default = "default_name")]
     pub master_name: String,
     #[serde(default = "default_local")]
     pub local: bool,
+    #[serde(default)]
+    pub frontend: FrontendKind,
     pub address: String,
     pub channel: Option<String>,
+    /// Password of `channel`
+    pub channel_password: Option<String>,
+    pub server_password: Option<String>,
+    /// Redeemed by the master once it connected
+    pub privilege_key: Option<String>,
+    /// Redeemed by the music bots, the first key by the first identity in `ids` and so on
+    #[serde(default)]
+    pub bot_privilege_keys: Vec<String>,
     #[serde(default = "default_verbose")]
     pub verbose: u8,
     pub domain: String,
     pub bind_address: String,
+    pub admin_token: Option<String>,
+    #[serde(default)]
+    pub public_web: bool,
+    #[serde(
+        default = "default_web_retry_interval",
+        with = "crate::duration::serde_human"
+    )]
+    pub web_retry_interval: Duration,
+    #[serde(
+        default = "default_empty_channel_timeout",
+        with = "crate::duration::serde_human"
+    )]
+    pub empty_channel_timeout: Duration,
     pub names: Vec<String>,
+    pub max_bots: Option<usize>,
+    pub music_channel: Option<String>,
+    #[serde(default)]
+    pub multiple_bots: bool,
+    #[serde(default = "default_reconnect_attempts")]
+    pub reconnect_attempts: u32,
+    #[serde(
+        default = "default_reconnect_delay",
+        with = "crate::duration::serde_human"
+    )]
+    pub reconnect_delay: Duration,
+    #[serde(
+        default = "default_kick_cooldown",
+        with = "crate::duration::serde_human"
+    )]
+    pub kick_cooldown: Duration,
+    pub parking: Option<ParkingConfig>,
+    pub confirm_spawn: Option<ConfirmSpawnConfig>,
+    #[serde(default)]
+    pub thumbnail_avatars: bool,
+    #[serde(default = "default_max_playlist_tracks")]
+    pub max_playlist_tracks: usize,
+    #[serde(default)]
+    pub admins: Vec<String>,
+    #[serde(default = "default_playlist_dir")]
+    pub playlist_dir: PathBuf,
+    #[serde(default = "default_channel_settings")]
+    pub channel_settings: PathBuf,
+    #[serde(default = "default_user_settings")]
+    pub user_settings: PathBuf,
+    #[serde(default = "default_blacklist")]
+    pub blacklist: PathBuf,
+    /// Identities are increased to at least this security level before connecting
+    pub identity_level: Option<u8>,
+    #[serde(default = "default_identity_store")]
+    pub identity_store: PathBuf,
+    pub audio_cache_dir: Option<PathBuf>,
+    #[serde(default = "default_audio_cache_max_mb")]
+    pub audio_cache_max_mb: u64,
+    pub stats_db: Option<PathBuf>,
+    pub card_font: Option<PathBuf>,
+    #[serde(default)]
+    pub badges: Vec<String>,
+    #[serde(default)]
+    pub client_version: ClientVersion,
+    #[serde(default)]
+    pub idle_audio: IdleAudio,
+    #[serde(default)]
+    pub end_of_queue: EndOfQueue,
+    #[serde(default)]
+    pub autoplay: AutoplayConfig,
+    #[serde(default)]
+    pub progress_bar: ProgressBarConfig,
+    #[serde(default)]
+    pub queue_limits: QueueLimits,
+    #[serde(default)]
+    pub sources: SourceRules,
+    pub no_duplicates: Option<NoDuplicates>,
+    pub file_playback: Option<FilePlaybackConfig>,
+    pub ducking: Option<Ducking>,
+    #[serde(default)]
+    pub encoder: EncoderSettings,
+    #[serde(default)]
+    pub watchdog: WatchdogConfig,
+    #[serde(default)]
+    pub audio_output: AudioOutputConfig,
+    #[serde(default)]
+    pub rate_limit: RateLimitConfig,
+    #[serde(default)]
+    pub reports: ReportConfig,
+    #[serde(default)]
+    pub schedule: ScheduleConfig,
+    pub discord: Option<DiscordConfig>,
+    #[serde(default)]
+    pub mumble: MumbleConfig,
+    pub scrobble: Option<ScrobbleConfig>,
+    pub lyrics: Option<LyricsConfig>,
+    pub musicbrainz: Option<MetadataConfig>,
+    #[serde(default)]
+    pub logging: LoggingConfig,
+    #[serde(default)]
+    pub downloader: DownloaderConfig,
+    #[serde(default)]
+    pub i18n: I18nConfig,
+    #[serde(default)]
+    pub bridge: BridgeConfig,
+    pub query: Option<ServerQueryConfig>,
+    #[serde(default)]
+    pub templates: TemplatesConfig,
+    #[serde(default)]
+    pub commands: CommandConfig,
     pub id: Option<Identity>,
     pub ids: Option<Vec<Identity>>,
 }
 
+/// `domain` may contain a scheme and port, plain host names are assumed to use http
+pub fn web_url(domain: &str) -> String {
+    let domain = domain.trim_end_matches('/');
+    if domain.contains("://") {
+        domain.to_owned()
+    } else {
+        format!("http://{}", domain)
+    }
+}
+
 fn default_name() -> String {
     String::from("PokeBot")
 }
 
+fn default_web_retry_interval() -> Duration {
+    Duration::from_secs(30)
+}
+
+fn default_empty_channel_timeout() -> Duration {
+    Duration::from_secs(5 * 60)
+}
+
+fn default_reconnect_attempts() -> u32 {
+    5
+}
+
+fn default_reconnect_delay() -> Duration {
+    Duration::from_secs(5)
+}
+
+fn default_kick_cooldown() -> Duration {
+    Duration::from_secs(30 * 60)
+}
+
+fn default_return_after() -> Duration {
+    Duration::from_secs(30)
+}
+
+fn default_max_playlist_tracks() -> usize {
+    100
+}
+
+fn default_playlist_dir() -> PathBuf {
+    PathBuf::from("playlists")
+}
+
+fn default_channel_settings() -> PathBuf {
+    PathBuf::from("channel_settings.json")
+}
+
+fn default_user_settings() -> PathBuf {
+    PathBuf::from("user_settings.json")
+}
+
+fn default_blacklist() -> PathBuf {
+    PathBuf::from("blacklist.json")
+}
+
+fn default_identity_store() -> PathBuf {
+    PathBuf::from("identities.json")
+}
+
+fn default_audio_cache_max_mb() -> u64 {
+    1024
+}
+
 fn default_local() -> bool {
     false
 }
@@ -355,14 +1993,73 @@ impl MasterArgs {
 
         Self {
             master_name: self.master_name,
+            frontend: self.frontend,
+            discord: self.discord,
+            mumble: self.mumble,
+            scrobble: self.scrobble,
+            lyrics: self.lyrics,
+            musicbrainz: self.musicbrainz,
+            logging: self.logging,
+            downloader: self.downloader,
+            i18n: self.i18n,
+            bridge: self.bridge,
+            query: self.query,
+            templates: self.templates,
             names: self.names,
+            max_bots: self.max_bots,
+            music_channel: self.music_channel,
+            multiple_bots: self.multiple_bots,
+            reconnect_attempts: self.reconnect_attempts,
+            reconnect_delay: self.reconnect_delay,
+            kick_cooldown: self.kick_cooldown,
+            parking: self.parking,
+            confirm_spawn: self.confirm_spawn,
+            commands: self.commands,
+            badges: self.badges,
+            client_version: self.client_version,
+            idle_audio: self.idle_audio,
+            end_of_queue: self.end_of_queue,
+            autoplay: self.autoplay,
+            progress_bar: self.progress_bar,
+            rate_limit: self.rate_limit,
+            reports: self.reports,
+            schedule: self.schedule,
+            thumbnail_avatars: self.thumbnail_avatars,
+            max_playlist_tracks: self.max_playlist_tracks,
+            admins: self.admins,
+            queue_limits: self.queue_limits,
+            sources: self.sources,
+            no_duplicates: self.no_duplicates,
+            file_playback: self.file_playback,
+            ducking: self.ducking,
+            encoder: self.encoder,
+            watchdog: self.watchdog,
+            audio_output: self.audio_output,
+            playlist_dir: self.playlist_dir,
+            channel_settings: self.channel_settings,
+            user_settings: self.user_settings,
+            blacklist: self.blacklist,
+            identity_level: self.identity_level,
+            identity_store: self.identity_store,
+            audio_cache_dir: self.audio_cache_dir,
+            audio_cache_max_mb: self.audio_cache_max_mb,
+            stats_db: self.stats_db,
+            card_font: self.card_font,
             ids: self.ids,
             local,
             address,
             domain: self.domain,
             bind_address: self.bind_address,
+            admin_token: self.admin_token,
+            public_web: self.public_web,
+            web_retry_interval: self.web_retry_interval,
+            empty_channel_timeout: self.empty_channel_timeout,
             id: self.id,
             channel,
+            channel_password: self.channel_password,
+            server_password: self.server_password,
+            privilege_key: self.privilege_key,
+            bot_privilege_keys: self.bot_privilege_keys,
             verbose,
         }
     }
@@ -372,7 +2069,66 @@ pub struct MasterConfig {
     pub master_name: String,
     pub address: String,
     pub names: Vec<String>,
+    pub max_bots: Option<usize>,
+    /// Name of the sub-channel created by `!music-channel`, the command is disabled if not set
+    pub music_channel: Option<String>,
+    /// Pokes spawn a bot even if there is one in the channel already
+    pub multiple_bots: bool,
+    /// How often a music bot that lost its connection is reconnected before it is given up
+    pub reconnect_attempts: u32,
+    /// Wait before the first reconnect, doubled after every failed attempt
+    pub reconnect_delay: Duration,
+    /// How long no bot reconnects to a channel after one was kicked from it
+    pub kick_cooldown: Duration,
+    pub parking: Option<ParkingConfig>,
+    /// Pokes are answered with an offer that has to be confirmed if set
+    pub confirm_spawn: Option<ConfirmSpawnConfig>,
+    pub id: Identity,
     pub ids: Vec<Identity>,
+    /// Identities whose security level was increased since they were configured
+    pub identities: Arc<IdentityStore>,
+    pub server_password: Option<String>,
+    /// Privilege keys by identity index, taken by the first bot that uses the identity
+    pub bot_privilege_keys: Mutex<Vec<Option<String>>>,
+    /// Unique ids of the master and all music bot identities
+    pub bot_uids: Arc<Vec<String>>,
+    pub empty_channel_timeout: Duration,
     pub local: bool,
     pub verbose: u8,
+    pub commands: CommandConfig,
+    pub thumbnail_avatars: bool,
+    pub max_playlist_tracks: usize,
+    pub queue_limits: QueueLimits,
+    pub sources: SourceRules,
+    pub blacklist: Arc<BlacklistStore>,
+    pub no_duplicates: Option<NoDuplicates>,
+    pub file_playback: Option<FilePlaybackConfig>,
+    pub ducking: Option<Ducking>,
+    pub encoder: EncoderSettings,
+    pub watchdog: WatchdogConfig,
+    pub audio_output: AudioOutputConfig,
+    pub admins: Vec<String>,
+    pub badges: Vec<String>,
+    pub client_version: ClientVersion,
+    pub idle_audio: IdleAudio,
+    pub end_of_queue: EndOfQueue,
+    pub autoplay: AutoplayConfig,
+    pub progress_bar: ProgressBarConfig,
+    pub channel_settings: Arc<ChannelSettingsStore>,
+    pub user_settings: Arc<UserSettingsStore>,
+    pub reports: Arc<ReportStore>,
+    pub schedule: Arc<ScheduleStore>,
+    pub rate_limiter: Arc<RateLimiter>,
+    pub web_url: String,
+    pub playlists: Arc<PlaylistStore>,
+    pub audio_cache: Option<Arc<AudioCache>>,
+    pub stats: Option<Arc<StatsStore>>,
+    pub cards: Option<Arc<CardRenderer>>,
+    pub scrobbler: Option<Arc<Scrobbler>>,
+    pub lyrics: Option<Arc<LyricsClient>>,
+    pub musicbrainz: Option<Arc<MetadataLookup>>,
+    pub downloader: Arc<Downloader>,
+    pub locales: Arc<Locales>,
+    pub templates: TemplatesConfig,
+    pub query: Option<Arc<ServerQuery>>,
 }
@@ -1,21 +1,52 @@
+use std::collections::{HashMap, VecDeque};
 use std::future::Future;
 use std::io::BufRead;
 use std::sync::{Arc, RwLock};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-use log::{debug, info};
-use serde::Serialize;
-use structopt::StructOpt;
-use tokio::sync::mpsc::UnboundedSender;
+use rand::{distributions::Alphanumeric, seq::SliceRandom, thread_rng, Rng};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{oneshot, watch};
+use tracing::{debug, error, info, info_span, warn, Span};
+use tracing_futures::Instrument;
 use tsclientlib::{data, ChannelId, ClientId, Connection, Identity, Invoker, MessageTarget};
 
-use crate::audio_player::{AudioPlayer, AudioPlayerError, PollResult};
-use crate::command::Command;
-use crate::command::VolumeChange;
+use crate::audio_cache::AudioCache;
+use crate::audio_output::AudioOutputConfig;
+use crate::audio_player::{
+    AudioPlayer, AudioPlayerError, Effects, EncoderSettings, PollResult, Stuck, WatchdogConfig,
+};
+use crate::blacklist::BlacklistStore;
+use crate::bot::{mailbox, MessageSender};
+use crate::bot_log::{BotLog, LogEntry};
+use crate::card::{CardBase, CardRenderer};
+use crate::channel_settings::{ChannelProfile, ChannelSettingsStore};
+use crate::command::{
+    AnnounceTarget, BitrateSetting, BlacklistAction, BroadcastAction, Command, CommandConfig,
+    CommandError, DebugAction, EqBand, PlaylistAction, PreferredVolumeAction, QueueAction,
+    QueueEndPolicy, Seek, SubscriptionAction, Toggle, TopKind, VolumeChange,
+};
+use crate::error::Error;
+use crate::file_playback::{self, FilePlaybackConfig};
+use crate::frontend::{split_message, Frontend, IdleAudio, Whisper, MAX_MESSAGE_LENGTH};
+use crate::i18n::{Locale, Locales};
+use crate::lyrics::LyricsClient;
+use crate::musicbrainz::MetadataLookup;
 use crate::playlist::Playlist;
+use crate::podcast::{self, Subscriptions};
+use crate::queue_text::{QueueText, Target};
+use crate::rate_limit::{RateLimit, RateLimiter};
+use crate::reports::{ReportContext, ReportStore};
+use crate::request_id::RequestId;
+use crate::saved_playlists::PlaylistStore;
+use crate::scrobble::{ListenTime, Scrobble, Scrobbler};
+use crate::sources::SourceRules;
+use crate::stats::StatsStore;
 use crate::teamspeak as ts;
-use crate::youtube_dl::AudioMetadata;
+use crate::tr;
+use crate::user_settings::UserSettingsStore;
+use crate::youtube_dl::{AudioMetadata, Chapter, Downloader};
 use ts::TeamSpeakConnection;
 
 #[derive(Debug)]
@@ -59,15 +90,532 @@ pub enum MusicBotMessage {
         client: Box<data::Client>,
     },
     StateChange(State),
+    /// Sent once the followed client stayed in a channel long enough
+    FollowMove(Instant),
+    /// Sent once the channel stayed empty for the configured timeout
+    EmptyChannelTimeout(Instant),
+    /// Sent by frontends without client events when someone joined or left
+    ListenersChanged,
+    /// Sent to the master once a music bot left while users are waiting for one
+    BotSlotFreed,
+    /// Sent by the connection once it was closed without a quit, e.g. after a kick
+    ConnectionLost,
+    /// Sent to the master when a music bot should connect again
+    Reconnect(Box<Reconnect>),
+    /// Sent by the connection when the bot itself was kicked or banned
+    Kicked(Kick),
+    /// Sent by the connection when a client starts or stops talking
+    ClientTalking,
+    /// Sent by the connection when the talk power of a client or the one a channel needs changed
+    TalkPowerChanged,
+    /// Sent shortly before the current track ends to decode the next one
+    PreloadNext(Instant),
+    /// Sent by the player when the preloaded track followed without a gap
+    TrackContinued,
+    /// Sent by the watchdog when the current track stopped moving or went silent
+    PlaybackStuck(Stuck),
+    /// Sent regularly to move the progress bar in the description
+    UpdateProgressBar,
+    /// Sent by the health check, answered once the message loop gets to it
+    Ping(oneshot::Sender<()>),
+    /// Sent to the master once it was away from its parking channel long enough
+    ReturnToParking(Instant),
+    /// Sent to the master once a client did not confirm the bot offered to them in time
+    OfferTimeout {
+        client: ClientId,
+        offered: Instant,
+    },
     Quit(String),
 }
 
 pub struct MusicBot {
     name: String,
+    identity_index: usize,
+    channel: RwLock<String>,
+    channel_settings: Arc<ChannelSettingsStore>,
+    user_settings: Arc<UserSettingsStore>,
+    reports: Arc<ReportStore>,
+    /// The only listener who was last told about their preferred volume
+    volume_proposed_to: RwLock<Option<String>>,
+    rate_limiter: Arc<RateLimiter>,
+    session_token: String,
+    control_link: Option<String>,
     player: Arc<AudioPlayer>,
+    /// Recent warnings and errors for `!debug` and the web API
+    log: Arc<BotLog>,
+    /// Parent of everything the bot logs, carries its name and channel
+    span: Span,
+    connection: Option<Arc<dyn Frontend>>,
+    /// Only set on TeamSpeak, for features that other frontends do not have
     teamspeak: Option<TeamSpeakConnection>,
     playlist: Arc<RwLock<Playlist>>,
     state: Arc<RwLock<State>>,
+    commands: CommandConfig,
+    announcements: RwLock<Announcements>,
+    thumbnail_avatars: bool,
+    avatar: RwLock<AvatarCache>,
+    max_playlist_tracks: usize,
+    queue_limits: QueueLimits,
+    queue_limited: RwLock<bool>,
+    /// Only the summoner and admins can change the queue while set
+    locked: RwLock<bool>,
+    sources: SourceRules,
+    blacklist: Arc<BlacklistStore>,
+    no_duplicates: Option<NoDuplicates>,
+    /// `!playfile` is turned off if this is not set
+    file_playback: Option<FilePlaybackConfig>,
+    /// Needed to download files from the channel the bot joined
+    channel_password: Option<String>,
+    /// Page urls of the tracks that started within `no_duplicates`, newest first
+    recently_played: RwLock<VecDeque<(String, Instant)>>,
+    admins: Vec<String>,
+    playlists: Arc<PlaylistStore>,
+    audio_cache: Option<Arc<AudioCache>>,
+    stats: Option<Arc<StatsStore>>,
+    cards: Option<Arc<CardRenderer>>,
+    card: RwLock<Option<Arc<CardBase>>>,
+    scrobbler: Option<Arc<Scrobbler>>,
+    scrobbling: RwLock<bool>,
+    lyrics: Option<Arc<LyricsClient>>,
+    musicbrainz: Option<Arc<MetadataLookup>>,
+    downloader: Arc<Downloader>,
+    locales: Arc<Locales>,
+    /// The language of the messages sent to users
+    server_locale: Arc<Locale>,
+    /// The current track and how long it was heard, until it is scrobbled
+    track_started: RwLock<Option<(AudioMetadata, ListenTime)>>,
+    history: RwLock<VecDeque<AudioMetadata>>,
+    searches: RwLock<HashMap<String, PendingSearch>>,
+    /// Restored from and saved to the channel profile
+    podcasts: Subscriptions,
+    disconnected: watch::Receiver<bool>,
+    prefetch: Arc<RwLock<PrefetchStatus>>,
+    end_of_queue: RwLock<EndOfQueue>,
+    /// Plays related tracks once the queue is empty
+    autoplay: RwLock<bool>,
+    progress_bar: bool,
+    max_autoplayed: usize,
+    /// Related tracks played in a row since a track was added by a user
+    autoplayed: RwLock<usize>,
+    idle_since: Arc<RwLock<Option<Instant>>>,
+    broadcast: Arc<RwLock<Vec<BroadcastChannel>>>,
+    private_session: Arc<RwLock<Option<PrivateSession>>>,
+    summoner: Option<ClientId>,
+    /// Set once the summoner was told that the bot can not be heard in its channel
+    talk_power_reported: RwLock<bool>,
+    /// Set while playback is paused because the bot lost the right to talk
+    paused_for_talk_power: RwLock<bool>,
+    following: RwLock<Option<ClientId>>,
+    follow_moved: Arc<RwLock<Option<Instant>>>,
+    bot_uids: Arc<Vec<String>>,
+    sender: Arc<RwLock<MessageSender>>,
+    empty_channel_timeout: Duration,
+    empty_since: RwLock<Option<Instant>>,
+    paused_while_empty: RwLock<bool>,
+    /// Where to continue the first track after reconnecting
+    resume_position: RwLock<Option<Duration>>,
+    /// When the current track was started, to ignore preloads of earlier tracks
+    preload_scheduled: RwLock<Option<Instant>>,
+    kicked: RwLock<Option<Kick>>,
+    /// The track the watchdog restarted last, it is skipped if it gets stuck again
+    watchdog_restarted: RwLock<Option<(RequestId, String)>>,
+    ducking: Option<Ducking>,
+    ducked: Arc<RwLock<bool>>,
+    /// The configured encoder settings, `!bitrate auto` goes back to them
+    encoder: EncoderSettings,
+}
+
+/// A channel the bot whispers to in addition to its own channel
+#[derive(Debug, Clone)]
+struct BroadcastChannel {
+    id: ChannelId,
+    path: String,
+}
+
+/// Clients that hear the bot through whispers while its channel hears nothing
+#[derive(Debug, Clone, Serialize)]
+pub struct PrivateSession {
+    /// Name of the client that started the session, only they can invite others
+    pub owner: String,
+    #[serde(skip)]
+    owner_id: ClientId,
+    /// Everyone who hears the bot, starting with the owner
+    pub listeners: Vec<PrivateListener>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PrivateListener {
+    #[serde(skip)]
+    id: ClientId,
+    pub name: String,
+}
+
+impl PrivateSession {
+    /// A session only the owner hears until they invite others
+    pub fn new(owner_id: ClientId, owner: String) -> Self {
+        Self {
+            listeners: vec![PrivateListener {
+                id: owner_id,
+                name: owner.clone(),
+            }],
+            owner,
+            owner_id,
+        }
+    }
+}
+
+/// What a bot does once the last track of the queue has ended
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "policy")]
+pub enum EndOfQueue {
+    /// Stay in the channel without playing anything
+    Silent,
+    /// Play a random track from the history, `autoplay` is its old name
+    #[serde(alias = "autoplay")]
+    Replay,
+    /// Play a configured playlist or stream
+    Fallback { url: String },
+    /// Leave the channel if nothing was added within the grace period
+    Leave {
+        #[serde(with = "crate::duration::serde_human")]
+        grace_period: Duration,
+    },
+}
+
+impl Default for EndOfQueue {
+    fn default() -> Self {
+        EndOfQueue::Silent
+    }
+}
+
+impl std::fmt::Display for EndOfQueue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EndOfQueue::Silent => write!(f, "stop"),
+            EndOfQueue::Replay => write!(f, "play recent tracks"),
+            EndOfQueue::Fallback { url } => write!(f, "play {}", url),
+            EndOfQueue::Leave { grace_period } => write!(
+                f,
+                "leave after {}",
+                humantime::format_duration(*grace_period)
+            ),
+        }
+    }
+}
+
+/// Everything that decides what the bot plays next and where.
+///
+/// The chat commands, the web interface and the API all read this one
+/// struct so they can't show different states.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PlaybackMode {
+    pub end_of_queue: EndOfQueue,
+    /// Whether related tracks are played before the end of queue policy applies
+    pub autoplay: bool,
+    /// Whether the bot moves along with its summoner
+    pub follow: bool,
+}
+
+impl std::fmt::Display for PlaybackMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "When the queue is empty: {}\nAutoplay: {}\nFollowing: {}",
+            self.end_of_queue,
+            if self.autoplay { "on" } else { "off" },
+            if self.follow { "on" } else { "off" }
+        )
+    }
+}
+
+/// What a bot was playing when it lost its connection
+#[derive(Debug, Clone)]
+pub struct ResumeState {
+    /// The current track followed by the queue
+    pub queue: Vec<AudioMetadata>,
+    pub position: Option<Duration>,
+    pub volume: f64,
+}
+
+/// A music bot that lost its connection, its name and identity stay taken
+/// until it is back or the master gives up.
+#[derive(Debug)]
+pub struct Reconnect {
+    pub name: String,
+    pub name_index: usize,
+    pub id_index: usize,
+    pub channel: String,
+    pub channel_password: Option<String>,
+    pub summoner: Option<ClientId>,
+    pub resume: ResumeState,
+    /// Set if the bot was thrown out, it is not reconnected then
+    pub kick: Option<Kick>,
+    /// Failed attempts so far
+    pub attempts: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KickKind {
+    Channel,
+    Server,
+    Ban,
+}
+
+impl std::fmt::Display for KickKind {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        match self {
+            KickKind::Channel => write!(fmt, "kicked from the channel"),
+            KickKind::Server => write!(fmt, "kicked from the server"),
+            KickKind::Ban => write!(fmt, "banned from the server"),
+        }
+    }
+}
+
+/// A kick or ban of the bot itself
+#[derive(Debug, Clone)]
+pub struct Kick {
+    pub kind: KickKind,
+    /// Name of whoever kicked the bot
+    pub by: Option<String>,
+}
+
+/// Lowers the music while someone in the channel is talking
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Ducking {
+    /// How much quieter the music gets, from 0 for not at all to 1 for silence
+    #[serde(default = "default_ducking_amount")]
+    pub amount: f64,
+    /// Silence after which the volume goes back up
+    #[serde(
+        default = "default_ducking_release",
+        with = "crate::duration::serde_human"
+    )]
+    pub release: Duration,
+}
+
+fn default_ducking_amount() -> f64 {
+    0.6
+}
+
+fn default_ducking_release() -> Duration {
+    Duration::from_secs(1)
+}
+
+/// Limits on the tracks waiting in the queue of a bot
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct QueueLimits {
+    #[serde(default = "default_max_queue_tracks")]
+    pub max_tracks: usize,
+    #[serde(default, with = "crate::duration::serde_human_option")]
+    pub max_duration: Option<Duration>,
+    /// Longest single track, tracks of unknown length are always allowed
+    #[serde(default, with = "crate::duration::serde_human_option")]
+    pub max_track_duration: Option<Duration>,
+}
+
+fn default_max_queue_tracks() -> usize {
+    200
+}
+
+impl Default for QueueLimits {
+    fn default() -> Self {
+        Self {
+            max_tracks: default_max_queue_tracks(),
+            max_duration: None,
+            max_track_duration: None,
+        }
+    }
+}
+
+impl QueueLimits {
+    /// How many of `entries` still fit into a queue that already holds `queue`
+    fn fitting(&self, queue: &[AudioMetadata], entries: &[AudioMetadata]) -> usize {
+        let tracks = self.max_tracks.saturating_sub(queue.len());
+        let mut duration = queue.iter().filter_map(|e| e.duration).sum::<Duration>();
+
+        let mut fitting = 0;
+        for entry in entries.iter().take(tracks) {
+            if let (Some(max), Some(entry)) = (self.max_duration, entry.duration) {
+                duration += entry;
+                if duration > max {
+                    break;
+                }
+            }
+            fitting += 1;
+        }
+
+        fitting
+    }
+}
+
+/// Plays tracks related to the last one once the queue is empty
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct AutoplayConfig {
+    /// Whether bots start with autoplay turned on
+    #[serde(default)]
+    pub enabled: bool,
+    /// Autoplay stops after this many related tracks in a row until a user adds a track
+    #[serde(default = "default_max_autoplayed")]
+    pub max_tracks: usize,
+}
+
+fn default_max_autoplayed() -> usize {
+    25
+}
+
+impl Default for AutoplayConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_tracks: default_max_autoplayed(),
+        }
+    }
+}
+
+/// Keeps a progress bar of the current track in the description
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct ProgressBarConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// How often the description is updated, it is never updated more than every 5 seconds
+    #[serde(
+        default = "default_progress_bar_interval",
+        with = "crate::duration::serde_human"
+    )]
+    pub interval: Duration,
+}
+
+fn default_progress_bar_interval() -> Duration {
+    Duration::from_secs(15)
+}
+
+impl Default for ProgressBarConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            interval: default_progress_bar_interval(),
+        }
+    }
+}
+
+/// Rejects tracks that are queued or playing already
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct NoDuplicates {
+    /// Tracks that started within this time are rejected as well
+    #[serde(default, with = "crate::duration::serde_human")]
+    pub played_within: Duration,
+}
+
+impl std::fmt::Display for QueueLimits {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        write!(fmt, "{} tracks", self.max_tracks)?;
+        if let Some(duration) = self.max_duration {
+            write!(fmt, " and {}", humantime::format_duration(duration))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// How long a followed client has to stay in a channel before the bot moves there
+const FOLLOW_DELAY: Duration = Duration::from_secs(3);
+
+/// Length of one opus frame sent by the player
+const VOICE_PACKET_INTERVAL: Duration = Duration::from_millis(20);
+
+/// How long the player has to stop sending audio until the bot counts as idle
+const IDLE_AUDIO_DELAY: Duration = Duration::from_millis(100);
+
+/// Length of the token in the control link of a bot
+const SESSION_TOKEN_LENGTH: usize = 24;
+
+/// Grace period of `!queue-end leave` if none is given
+const DEFAULT_GRACE_PERIOD: Duration = Duration::from_secs(5 * 60);
+
+/// How long before the end of a track the next one of the same album is decoded
+const PRELOAD_AHEAD: Duration = Duration::from_secs(20);
+
+/// State of resolving the next playlist entry ahead of time
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "snake_case", tag = "state")]
+pub enum PrefetchStatus {
+    Idle,
+    Resolving { title: String },
+    Ready { title: String },
+    Failed { title: String },
+}
+
+/// How many playlist entries are requested from youtube-dl at once
+const PLAYLIST_PAGE_SIZE: usize = 50;
+
+/// How many of the last played tracks are remembered
+const HISTORY_LENGTH: usize = 10;
+
+/// How many results `!search` lists
+const SEARCH_RESULTS: usize = 5;
+
+/// Number of entries shown by `!top`
+const LEADERBOARD_LENGTH: usize = 10;
+
+/// Number of log entries shown by `!debug last` without a count
+const DEFAULT_DEBUG_ENTRIES: usize = 10;
+
+/// Shown as the one who added tracks that autoplay picked
+const AUTOPLAY_USER: &str = "Autoplay";
+
+/// Shown as the one who added tracks the replay policy picked from the history
+const REPLAY_USER: &str = "Replay";
+
+/// How many related tracks are looked at to find one that did not play recently
+const RELATED_TRACKS: usize = 10;
+
+/// How often the watchdog looks at the progress of the current track
+const WATCHDOG_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Servers limit how often a client may change its description
+const MIN_PROGRESS_BAR_INTERVAL: Duration = Duration::from_secs(5);
+const PROGRESS_BAR_LENGTH: usize = 10;
+
+/// How long a user can pick one of their search results
+const SEARCH_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// How long the health check waits for a message loop to answer
+const PING_TIMEOUT: Duration = Duration::from_secs(5);
+
+struct PendingSearch {
+    results: Vec<AudioMetadata>,
+    started: Instant,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SearchResults {
+    pub user: String,
+    pub results: Vec<AudioMetadata>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BotSettings {
+    pub announce_in_chat: bool,
+    pub announce_in_description: bool,
+    pub thumbnail_avatars: bool,
+    pub max_playlist_tracks: usize,
+    pub queue_limits: Option<QueueLimits>,
+    pub effects: Effects,
+    pub encoder: EncoderSettings,
+    pub playback_mode: PlaybackMode,
+}
+
+/// Remembers the last uploaded avatar so identical art is not uploaded again
+#[derive(Debug, Default)]
+struct AvatarCache {
+    url: Option<String>,
+    hash: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Announcements {
+    chat: bool,
+    description: bool,
 }
 
 pub struct MusicBotArgs {
@@ -76,54 +624,204 @@ pub struct MusicBotArgs {
     pub id_index: usize,
     pub local: bool,
     pub address: String,
-    pub id: Identity,
+    /// Identity used to connect to TeamSpeak, not needed if `frontend` is set
+    pub id: Option<Identity>,
+    /// Keeps the identity if the server made the bot increase its security level
+    pub identities: Option<Arc<ts::IdentityStore>>,
+    pub server_password: Option<String>,
+    /// Redeemed once the bot connected to TeamSpeak
+    pub privilege_key: Option<String>,
+    /// A connection set up by the master, the bot connects to TeamSpeak itself if this is not set
+    pub frontend: Option<Arc<dyn Frontend>>,
     pub channel: String,
+    /// Needed to join password protected channels
+    pub channel_password: Option<String>,
+    /// The client that poked the master for this bot
+    pub summoner: Option<ClientId>,
+    /// Set for bots that share their channel with another bot, so only the summoner hears them
+    pub private_session: Option<PrivateSession>,
+    /// Unique ids of the master and all music bot identities
+    pub bot_uids: Arc<Vec<String>>,
+    /// How long a bot stays paused in a channel without listeners before leaving
+    pub empty_channel_timeout: Duration,
     pub verbose: u8,
+    pub commands: CommandConfig,
+    pub thumbnail_avatars: bool,
+    pub max_playlist_tracks: usize,
+    pub queue_limits: QueueLimits,
+    pub sources: SourceRules,
+    pub blacklist: Arc<BlacklistStore>,
+    pub no_duplicates: Option<NoDuplicates>,
+    pub file_playback: Option<FilePlaybackConfig>,
+    /// Only works on TeamSpeak, which tells the bot when someone talks
+    pub ducking: Option<Ducking>,
+    pub encoder: EncoderSettings,
+    pub watchdog: WatchdogConfig,
+    /// Where local mode plays to and where the audio of one bot can be monitored
+    pub audio_output: AudioOutputConfig,
+    /// Unique ids of users that may use admin commands
+    pub admins: Vec<String>,
+    pub badges: Vec<String>,
+    pub client_version: ts::ClientVersion,
+    pub idle_audio: IdleAudio,
+    pub end_of_queue: EndOfQueue,
+    pub autoplay: AutoplayConfig,
+    pub progress_bar: ProgressBarConfig,
+    pub channel_settings: Arc<ChannelSettingsStore>,
+    pub user_settings: Arc<UserSettingsStore>,
+    pub reports: Arc<ReportStore>,
+    pub rate_limiter: Arc<RateLimiter>,
+    /// Base url of the web interface, used to put a control link into the description
+    pub web_url: Option<String>,
+    pub playlists: Arc<PlaylistStore>,
+    pub audio_cache: Option<Arc<AudioCache>>,
+    pub stats: Option<Arc<StatsStore>>,
+    pub cards: Option<Arc<CardRenderer>>,
+    pub scrobbler: Option<Arc<Scrobbler>>,
+    pub lyrics: Option<Arc<LyricsClient>>,
+    pub musicbrainz: Option<Arc<MetadataLookup>>,
+    pub downloader: Arc<Downloader>,
+    pub locales: Arc<Locales>,
+    /// Playback to continue with, set when the bot reconnects
+    pub resume: Option<ResumeState>,
     pub disconnect_cb: Box<dyn FnMut(String, usize, usize) + Send + Sync>,
+    /// Called instead of `disconnect_cb` when the connection was lost,
+    /// the bot leaves for good if this is not set
+    pub reconnect_cb: Option<Box<dyn FnMut(Reconnect) + Send + Sync>>,
 }
 
 impl MusicBot {
-    pub async fn new(args: MusicBotArgs) -> (Arc<Self>, impl Future<Output = ()>) {
-        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+    pub async fn new(args: MusicBotArgs) -> Result<(Arc<Self>, impl Future<Output = ()>), Error> {
+        let span = info_span!("bot", name = %args.name, channel = %args.channel);
+
+        Self::connect(args, span.clone()).instrument(span).await
+    }
+
+    async fn connect(
+        args: MusicBotArgs,
+        span: Span,
+    ) -> Result<(Arc<Self>, impl Future<Output = ()>), Error> {
+        let (tx, mut rx) = mailbox();
         let tx = Arc::new(RwLock::new(tx));
-        let (player, connection) = if args.local {
+        let (disconnected_tx, disconnected) = watch::channel(false);
+        let broadcast = Arc::new(RwLock::new(Vec::<BroadcastChannel>::new()));
+        if let Some(session) = &args.private_session {
+            info!("Sharing the channel, only {} hears the bot", session.owner);
+        }
+        let private_session = Arc::new(RwLock::new(args.private_session.clone()));
+
+        // Anyone who can read the description may control the bot until it leaves
+        let session_token = thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(SESSION_TOKEN_LENGTH)
+            .collect::<String>();
+        let control_link = args
+            .web_url
+            .as_ref()
+            .and_then(|url| control_link(url, &args.name, &session_token));
+        let log = Arc::new(BotLog::new(args.name.clone()));
+        let (player, connection, teamspeak) = if args.local {
             info!("Starting in CLI mode");
-            let audio_player = AudioPlayer::new(tx.clone(), None).unwrap();
+            let audio_player = AudioPlayer::new(
+                tx.clone(),
+                log.clone(),
+                None,
+                args.encoder,
+                &args.audio_output,
+            )?;
 
-            (audio_player, None)
+            (audio_player, None, None)
         } else {
-            info!("Starting in TeamSpeak mode");
-
-            let con_config = Connection::build(args.address)
-                .version(tsclientlib::Version::Linux_3_3_2)
-                .name(format!("🎵 {}", args.name))
-                .identity(args.id)
-                .log_commands(args.verbose >= 1)
-                .log_packets(args.verbose >= 2)
-                .log_udp_packets(args.verbose >= 3)
-                .channel(args.channel);
-
-            let connection = TeamSpeakConnection::new(tx.clone(), con_config)
-                .await
-                .unwrap();
-            let mut cconnection = connection.clone();
+            let (connection, teamspeak): (Arc<dyn Frontend>, _) = match args.frontend {
+                Some(frontend) => (frontend, None),
+                None => {
+                    info!("Starting in TeamSpeak mode");
+
+                    let mut con_config = Connection::build(args.address.clone())
+                        .version(args.client_version.version())
+                        .name(format!("🎵 {}", args.name))
+                        .identity(args.id.ok_or(Error::MissingIdentity)?)
+                        .log_commands(args.verbose >= 1)
+                        .log_packets(args.verbose >= 2)
+                        .log_udp_packets(args.verbose >= 3)
+                        .channel(args.channel.clone());
+                    if let Some(password) = args.channel_password.clone() {
+                        con_config = con_config.channel_password(password);
+                    }
+                    if let Some(password) = args.server_password.clone() {
+                        con_config = con_config.password(password);
+                    }
+
+                    let mut connection = TeamSpeakConnection::new(
+                        tx.clone(),
+                        &args.address,
+                        con_config,
+                        ts::EventFilter::All,
+                        args.identities.clone(),
+                    )
+                    .await?;
+                    if let Some(key) = args.privilege_key.clone() {
+                        connection.use_privilege_key(key).await;
+                    }
+                    if !args.badges.is_empty() {
+                        connection.set_badges(&args.badges).await;
+                    }
+
+                    (Arc::new(connection.clone()), Some(connection))
+                }
+            };
+
+            let last_packet = Arc::new(RwLock::new(None));
+            spawn_idle_audio_task(
+                connection.clone(),
+                broadcast.clone(),
+                private_session.clone(),
+                last_packet.clone(),
+                args.idle_audio,
+                disconnected.clone(),
+            );
+
+            // Only the selected bot plays on the device, the others keep passing opus through
+            let mut monitor_output = args.audio_output.clone();
+            if !monitor_output.monitors(&args.name) {
+                monitor_output.monitor = None;
+            }
+
+            let cconnection = connection.clone();
+            let cbroadcast = broadcast.clone();
+            let cprivate_session = private_session.clone();
             let audio_player = AudioPlayer::new(
                 tx.clone(),
+                log.clone(),
                 Some(Box::new(move |samples| {
+                    *last_packet.write().unwrap() = Some(Instant::now());
+
+                    let whisper = whisper_targets(&cbroadcast, &cprivate_session);
+
                     let mut rt = tokio::runtime::Runtime::new().unwrap();
-                    rt.block_on(cconnection.send_audio_packet(samples));
+                    rt.block_on(cconnection.send_audio_packet(samples, &whisper));
                 })),
-            )
-            .unwrap();
+                args.encoder,
+                &monitor_output,
+            )?;
 
-            (audio_player, Some(connection))
+            (audio_player, Some(connection), teamspeak)
         };
 
-        player.change_volume(VolumeChange::Absolute(0.5)).unwrap();
+        player.change_volume(VolumeChange::Absolute(0.5))?;
         let player = Arc::new(player);
         let playlist = Arc::new(RwLock::new(Playlist::new()));
 
         spawn_gstreamer_thread(player.clone(), tx.clone());
+        spawn_watchdog_task(
+            player.clone(),
+            tx.clone(),
+            args.watchdog,
+            disconnected.clone(),
+        );
+        if args.progress_bar.enabled {
+            spawn_progress_bar_task(tx.clone(), args.progress_bar.interval, disconnected.clone());
+        }
 
         if args.local {
             spawn_stdin_reader(tx);
@@ -131,250 +829,2659 @@ impl MusicBot {
 
         let bot = Arc::new(Self {
             name: args.name.clone(),
+            identity_index: args.id_index,
+            channel: RwLock::new(args.channel),
+            channel_settings: args.channel_settings,
+            user_settings: args.user_settings,
+            reports: args.reports,
+            volume_proposed_to: RwLock::new(None),
+            rate_limiter: args.rate_limiter,
+            session_token,
+            control_link,
             player,
-            teamspeak: connection,
+            log,
+            span: span.clone(),
+            connection,
+            teamspeak,
             playlist,
             state: Arc::new(RwLock::new(State::EndOfStream)),
+            commands: args.commands,
+            announcements: RwLock::new(Announcements {
+                chat: true,
+                description: true,
+            }),
+            thumbnail_avatars: args.thumbnail_avatars,
+            avatar: RwLock::new(AvatarCache::default()),
+            max_playlist_tracks: args.max_playlist_tracks,
+            queue_limits: args.queue_limits,
+            queue_limited: RwLock::new(true),
+            locked: RwLock::new(false),
+            sources: args.sources,
+            blacklist: args.blacklist,
+            no_duplicates: args.no_duplicates,
+            file_playback: args.file_playback,
+            channel_password: args.channel_password.clone(),
+            recently_played: RwLock::new(VecDeque::new()),
+            admins: args.admins,
+            playlists: args.playlists,
+            audio_cache: args.audio_cache,
+            stats: args.stats,
+            cards: args.cards,
+            card: RwLock::new(None),
+            scrobbler: args.scrobbler,
+            scrobbling: RwLock::new(true),
+            lyrics: args.lyrics,
+            musicbrainz: args.musicbrainz,
+            downloader: args.downloader.for_server(&args.address),
+            server_locale: args.locales.for_server(&args.address),
+            locales: args.locales,
+            track_started: RwLock::new(None),
+            history: RwLock::new(VecDeque::with_capacity(HISTORY_LENGTH)),
+            searches: RwLock::new(HashMap::new()),
+            podcasts: Subscriptions::default(),
+            disconnected,
+            prefetch: Arc::new(RwLock::new(PrefetchStatus::Idle)),
+            end_of_queue: RwLock::new(args.end_of_queue),
+            autoplay: RwLock::new(args.autoplay.enabled),
+            max_autoplayed: args.autoplay.max_tracks,
+            autoplayed: RwLock::new(0),
+            progress_bar: args.progress_bar.enabled,
+            idle_since: Arc::new(RwLock::new(None)),
+            broadcast,
+            private_session,
+            summoner: args.summoner,
+            talk_power_reported: RwLock::new(false),
+            paused_for_talk_power: RwLock::new(false),
+            following: RwLock::new(None),
+            follow_moved: Arc::new(RwLock::new(None)),
+            bot_uids: args.bot_uids,
+            sender: tx.clone(),
+            empty_channel_timeout: args.empty_channel_timeout,
+            empty_since: RwLock::new(None),
+            paused_while_empty: RwLock::new(false),
+            resume_position: RwLock::new(None),
+            preload_scheduled: RwLock::new(None),
+            kicked: RwLock::new(None),
+            watchdog_restarted: RwLock::new(None),
+            ducking: args.ducking,
+            ducked: Arc::new(RwLock::new(false)),
+            encoder: args.encoder,
         });
 
         let cbot = bot.clone();
         let mut disconnect_cb = args.disconnect_cb;
+        let mut reconnect_cb = args.reconnect_cb;
         let name = args.name;
         let name_index = args.name_index;
         let id_index = args.id_index;
+        let channel_password = args.channel_password;
+        let summoner = args.summoner;
         let msg_loop = async move {
             'outer: loop {
                 while let Some(msg) = rx.recv().await {
-                    if let MusicBotMessage::Quit(reason) = msg {
-                        if let Some(ts) = &cbot.teamspeak {
-                            let mut ts = ts.clone();
-                            ts.disconnect(&reason).await;
+                    match msg {
+                        MusicBotMessage::Quit(_) | MusicBotMessage::ConnectionLost => {
+                            let resume = cbot.resume_state();
+                            let kick = cbot.kicked.write().unwrap().take();
+                            let lost = match msg {
+                                MusicBotMessage::Quit(reason) => {
+                                    if let Some(connection) = &cbot.connection {
+                                        connection.disconnect(&reason).await;
+                                    }
+                                    // Only the master can tell the summoner about a kick
+                                    kick.is_some()
+                                }
+                                _ => {
+                                    warn!("{} lost its connection", name);
+                                    // The quit message is never read because the loop ends here
+                                    cbot.player.quit(String::from("Connection lost"));
+                                    true
+                                }
+                            };
+
+                            match &mut reconnect_cb {
+                                Some(reconnect_cb) if lost => reconnect_cb(Reconnect {
+                                    channel: cbot.channel.read().unwrap().clone(),
+                                    name,
+                                    name_index,
+                                    id_index,
+                                    channel_password,
+                                    summoner,
+                                    resume,
+                                    kick,
+                                    attempts: 0,
+                                }),
+                                _ => disconnect_cb(name, name_index, id_index),
+                            }
+                            let _ = disconnected_tx.broadcast(true);
+                            break 'outer;
+                        }
+                        msg => {
+                            if let Err(e) = cbot.on_message(msg).await {
+                                cbot.log.error(format!("Failed to handle message: {:?}", e));
+                            }
                         }
-                        disconnect_cb(name, name_index, id_index);
-                        break 'outer;
                     }
-                    cbot.on_message(msg).await.unwrap();
                 }
             }
             debug!("Left message loop");
         };
 
+        bot.apply_channel_profile();
+        bot.check_podcasts_periodically();
         bot.update_name(State::EndOfStream).await;
+        bot.set_description(String::new()).await;
+        // Bots spawned by command might join a channel without listeners
+        bot.update_listeners().await;
+        bot.limit_bitrate().await;
+        if let Some(resume) = args.resume {
+            bot.resume(resume).await;
+        }
 
-        (bot, msg_loop)
+        Ok((bot, msg_loop.instrument(span)))
     }
 
     async fn start_playing_audio(&self, metadata: AudioMetadata) {
-        let duration = if let Some(duration) = metadata.duration {
-            format!("({})", ts::bold(&humantime::format_duration(duration)))
-        } else {
-            format!("")
-        };
-
-        self.send_message(format!(
-            "Playing {} {}",
-            ts::underline(&metadata.title),
-            duration
-        ))
-        .await;
-        self.set_description(format!("Currently playing '{}'", metadata.title))
-            .await;
-        self.player.reset().unwrap();
-        self.player.set_metadata(metadata).unwrap();
-        self.player.play().unwrap();
-    }
+        *self.idle_since.write().unwrap() = None;
 
-    pub async fn add_audio(&self, url: String, user: String) {
-        match crate::youtube_dl::get_audio_download_from_url(url).await {
-            Ok(mut metadata) => {
-                metadata.added_by = user;
-                info!("Found audio url: {}", metadata.url);
+        let mut metadata = match self.resolve_entry(metadata).await {
+            Some(metadata) => metadata,
+            None => {
+                self.update_name(State::EndOfStream).await;
+                self.set_description(String::new()).await;
+                return;
+            }
+        };
 
-                // RWLockGuard can not be kept around or the compiler complains that
-                // it might cross the await boundary
-                self.playlist
-                    .write()
-                    .expect("RwLock was not poisoned")
-                    .push(metadata.clone());
+        // Tracks that were not looked up while queued are only looked up for the next time
+        if let Some(musicbrainz) = &self.musicbrainz {
+            musicbrainz.apply_cached(&mut metadata);
+            tokio::spawn(
+                musicbrainz
+                    .clone()
+                    .look_up(metadata.clone())
+                    .in_current_span(),
+            );
+        }
 
-                if !self.player.is_started() {
-                    let entry = self
-                        .playlist
-                        .write()
-                        .expect("RwLock was not poisoned")
-                        .pop();
-                    if let Some(request) = entry {
-                        self.start_playing_audio(request).await;
-                    }
-                } else {
-                    let duration = if let Some(duration) = metadata.duration {
-                        format!(" ({})", ts::bold(&humantime::format_duration(duration)))
-                    } else {
-                        format!("")
-                    };
+        let thumbnail = metadata.thumbnail.clone();
+        self.on_track_started(&metadata).await;
 
-                    self.send_message(format!(
-                        "Added {}{} to playlist",
-                        ts::underline(&metadata.title),
-                        duration
-                    ))
-                    .await;
+        let request_id = metadata.request_id;
+        // The url was extracted already, the stream only downloads it
+        let stream = if self.downloader.should_stream(&metadata) {
+            match self.downloader.stream(&metadata.url, request_id) {
+                Ok(stream) => Some(stream),
+                Err(e) => {
+                    self.log.warn(format!(
+                        "{} Failed to stream, playing the extracted url instead: {}",
+                        request_id, e
+                    ));
+                    None
                 }
             }
-            Err(e) => {
-                info!("Failed to find audio url: {}", e);
-
-                self.send_message(format!("Failed to find url: {}", e))
-                    .await;
-            }
+        } else {
+            None
+        };
+        let streamed = match stream {
+            Some(stream) => match self.player.set_stream(metadata.clone(), stream) {
+                Ok(()) => true,
+                Err(e) => {
+                    self.log.warn(format!(
+                        "{} Failed to play the stream, playing the extracted url instead: {:?}",
+                        request_id, e
+                    ));
+                    false
+                }
+            },
+            None => false,
+        };
+        if !streamed {
+            self.player.set_metadata(metadata).unwrap();
         }
-    }
+        self.player.play().unwrap();
 
-    pub fn name(&self) -> &str {
-        &self.name
-    }
+        self.prefetch_next();
 
-    pub fn state(&self) -> State {
-        *self.state.read().expect("RwLock was not poisoned")
+        self.update_avatar(thumbnail).await;
     }
 
-    pub fn volume(&self) -> f64 {
-        self.player.volume()
-    }
+    /// Announces and records a track that just started playing
+    async fn on_track_started(&self, metadata: &AudioMetadata) {
+        // Files are stored already, there is nothing to download
+        if let Some(cache) = &self.audio_cache {
+            if !metadata.webpage_url.starts_with("file://") {
+                tokio::spawn(cache.clone().store(metadata.clone()).in_current_span());
+            }
+        }
+        if let Some(stats) = &self.stats {
+            let stats = stats.clone();
+            let metadata = metadata.clone();
+            let channel = self.channel.read().unwrap().clone();
+            tokio::task::spawn_blocking(move || stats.record(&metadata, &channel));
+        }
 
-    pub fn position(&self) -> Option<Duration> {
-        self.player.position()
-    }
+        self.check_talk_power().await;
 
-    pub fn currently_playing(&self) -> Option<AudioMetadata> {
-        self.player.currently_playing()
-    }
+        let announcements = *self.announcements.read().unwrap();
 
-    pub fn playlist_to_vec(&self) -> Vec<AudioMetadata> {
-        self.playlist.read().unwrap().to_vec()
-    }
+        if announcements.chat {
+            let duration = if let Some(duration) = metadata.duration {
+                format!("({})", ts::bold(&humantime::format_duration(duration)))
+            } else {
+                format!("")
+            };
 
-    pub async fn my_channel(&self) -> ChannelId {
-        let ts = self.teamspeak.as_ref().expect("my_channel needs ts");
+            self.send_message(tr!(
+                self.server_locale,
+                "playing",
+                "Playing {title} {duration}",
+                title = ts::underline(&metadata.title),
+                duration = duration
+            ))
+            .await;
+        }
 
-        let mut ts = ts.clone();
-        ts.my_channel().await
-    }
+        if announcements.description {
+            self.set_description(self.now_playing_description(metadata))
+                .await;
+        }
 
-    async fn user_count(&self, channel: ChannelId) -> u32 {
-        let ts = self.teamspeak.as_ref().expect("user_count needs ts");
+        {
+            let mut history = self.history.write().unwrap();
+            if history.len() == HISTORY_LENGTH {
+                history.pop_back();
+            }
+            history.push_front(metadata.clone());
+        }
+        if metadata.added_by != AUTOPLAY_USER {
+            *self.autoplayed.write().unwrap() = 0;
+        }
+        if let Some(no_duplicates) = self.no_duplicates {
+            let mut recently_played = self.recently_played.write().unwrap();
+            recently_played.retain(|(_, started)| started.elapsed() < no_duplicates.played_within);
+            recently_played.push_front((metadata.webpage_url.clone(), Instant::now()));
+        }
+        *self.track_started.write().unwrap() = Some((metadata.clone(), ListenTime::start()));
 
-        let mut ts = ts.clone();
-        ts.user_count(channel).await
+        self.schedule_preload(metadata.duration);
     }
 
-    async fn send_message(&self, text: String) {
-        debug!("Sending message to TeamSpeak: {}", text);
+    /// Preloads the next track shortly before the end of the current one,
+    /// pauses and seeks only make it happen a bit early or not in time.
+    fn schedule_preload(&self, duration: Option<Duration>) {
+        let started = Instant::now();
+        *self.preload_scheduled.write().unwrap() = Some(started);
 
-        if let Some(ts) = &self.teamspeak {
-            let mut ts = ts.clone();
-            ts.send_message_to_channel(text).await;
-        }
+        let delay = match duration {
+            Some(duration) => duration.checked_sub(PRELOAD_AHEAD).unwrap_or_default(),
+            None => return,
+        };
+
+        let sender = self.sender.clone();
+        tokio::spawn(
+            async move {
+                tokio::time::delay_for(delay).await;
+                // Ignore the result because the bot might have quit in the meantime
+                let _ = sender
+                    .read()
+                    .unwrap()
+                    .send(MusicBotMessage::PreloadNext(started));
+            }
+            .in_current_span(),
+        );
     }
 
-    async fn set_nickname(&self, name: String) {
-        info!("Setting TeamSpeak nickname: {}", name);
+    /// Starts decoding the next entry if it continues the album or playlist of the current one
+    fn preload_next(&self) {
+        let current = match self.player.currently_playing() {
+            Some(current) => current,
+            None => return,
+        };
+        let next = match self.playlist.read().unwrap().peek() {
+            Some(next) if !next.needs_resolving() => next.clone(),
+            _ => return,
+        };
+        if !current.continues_into(&next) {
+            return;
+        }
 
-        if let Some(ts) = &self.teamspeak {
-            let mut ts = ts.clone();
-            ts.set_nickname(name).await;
+        let mut next = match self.audio_cache.as_ref().and_then(|c| c.get(&next)) {
+            Some(cached) => cached,
+            None => next,
+        };
+        if let Some(musicbrainz) = &self.musicbrainz {
+            musicbrainz.apply_cached(&mut next);
+        }
+        if let Err(e) = self.player.preload(next) {
+            self.log
+                .error(format!("Failed to preload next track: {:?}", e));
         }
     }
 
-    async fn set_description(&self, desc: String) {
-        info!("Setting TeamSpeak description: {}", desc);
+    /// Restarts a stuck track once, it is skipped if that does not help or is not possible
+    async fn on_playback_stuck(&self, stuck: Stuck) -> Result<(), AudioPlayerError> {
+        let current = match self.player.currently_playing() {
+            Some(current) => current,
+            None => return Ok(()),
+        };
 
-        if let Some(ts) = &self.teamspeak {
-            let mut ts = ts.clone();
-            ts.set_description(desc).await;
-        }
-    }
+        let position = self
+            .player
+            .position()
+            .map(crate::duration::format_timestamp)
+            .unwrap_or_else(|| String::from("an unknown position"));
+        let stream = match self.player.buffer_level() {
+            Some(level) => format!(", the stream buffer is {:.0}% full", level * 100.0),
+            None => String::new(),
+        };
+        let diagnostic = format!(
+            "{} {} is stuck at {}, {}{} and {} bytes are buffered",
+            current.request_id,
+            current.title,
+            position,
+            stuck,
+            stream,
+            self.player.resource_usage().buffered_bytes
+        );
 
-    async fn subscribe(&self, id: ChannelId) {
-        if let Some(ts) = &self.teamspeak {
-            let mut ts = ts.clone();
-            ts.subscribe(id).await;
+        let track = (current.request_id, current.webpage_url.clone());
+        let restarted_before = self
+            .watchdog_restarted
+            .write()
+            .unwrap()
+            .replace(track.clone())
+            == Some(track);
+        if !restarted_before && self.player.restart()? {
+            self.log.warn(format!("{}, restarting it", diagnostic));
+            return Ok(());
         }
+
+        self.log.warn(format!("{}, skipping it", diagnostic));
+        self.send_message(format!(
+            "{} got stuck, skipping it",
+            ts::underline(&current.title)
+        ))
+        .await;
+        self.player.stop_current()
     }
 
-    async fn on_text(&self, message: Message) -> Result<(), AudioPlayerError> {
-        let msg = message.text;
-        if msg.starts_with('!') {
-            let tokens = msg[1..].split_whitespace().collect::<Vec<_>>();
+    /// Catches up with the player after the preloaded track started by itself
+    async fn on_track_continued(&self) {
+        self.scrobble_finished().await;
+
+        let preloaded = self.player.currently_playing();
+        let next_track = self
+            .playlist
+            .write()
+            .expect("RwLock was not poisoned")
+            .pop();
 
-            match Command::from_iter_safe(&tokens) {
-                Ok(args) => self.on_command(args, message.invoker).await?,
-                Err(e) if e.kind == structopt::clap::ErrorKind::HelpDisplayed => {
-                    self.send_message(format!("\n{}", e.message)).await;
+        match (preloaded, next_track) {
+            (Some(preloaded), Some(next))
+                if preloaded.request_id == next.request_id
+                    && preloaded.webpage_url == next.webpage_url =>
+            {
+                info!("Advancing playlist without a gap");
+
+                let thumbnail = preloaded.thumbnail.clone();
+                self.on_track_started(&preloaded).await;
+                self.prefetch_next();
+                self.update_avatar(thumbnail).await;
+            }
+            // The queue changed after preloading, play what is actually next
+            (_, Some(next)) => self.start_playing_audio(next).await,
+            // The preloaded track was removed from the queue in the meantime
+            (_, None) => {
+                if let Err(e) = self.player.reset() {
+                    error!("Failed to stop preloaded track: {:?}", e);
                 }
-                _ => (),
+                self.on_queue_end().await;
             }
         }
-
-        Ok(())
     }
 
-    async fn on_command(&self, command: Command, invoker: Invoker) -> Result<(), AudioPlayerError> {
-        match command {
-            Command::Play => {
-                let playlist = self.playlist.read().expect("RwLock was not poisoned");
+    /// Resolves the next playlist entry in the background so it can start right away
+    fn prefetch_next(&self) {
+        let next = match self.playlist.read().unwrap().peek() {
+            Some(next) => next.clone(),
+            None => {
+                *self.prefetch.write().unwrap() = PrefetchStatus::Idle;
+                return;
+            }
+        };
 
-                if !self.player.is_started() {
-                    if !playlist.is_empty() {
-                        self.player.stop_current()?;
-                    }
+        if !next.needs_resolving() {
+            if let Some(cache) = &self.audio_cache {
+                tokio::spawn(cache.clone().store(next.clone()).in_current_span());
+            }
+            if let Some(musicbrainz) = &self.musicbrainz {
+                tokio::spawn(musicbrainz.clone().look_up(next.clone()).in_current_span());
+            }
+            *self.prefetch.write().unwrap() = PrefetchStatus::Ready { title: next.title };
+            return;
+        }
+
+        {
+            let mut prefetch = self.prefetch.write().unwrap();
+            if let PrefetchStatus::Resolving { title } = &*prefetch {
+                if *title == next.title {
+                    return;
+                }
+            }
+            *prefetch = PrefetchStatus::Resolving {
+                title: next.title.clone(),
+            };
+        }
+
+        tokio::spawn(
+            prefetch(
+                next,
+                self.playlist.clone(),
+                self.prefetch.clone(),
+                self.audio_cache.clone(),
+                self.musicbrainz.clone(),
+                self.downloader.clone(),
+                self.log.clone(),
+            )
+            .in_current_span(),
+        );
+    }
+
+    async fn update_avatar(&self, thumbnail: Option<String>) {
+        if !self.thumbnail_avatars {
+            return;
+        }
+
+        let connection = match &self.connection {
+            Some(connection) => connection.clone(),
+            None => return,
+        };
+
+        let url = match thumbnail {
+            Some(url) => url,
+            None => {
+                if self.avatar.read().unwrap().hash.is_some() {
+                    info!("Removing avatar");
+                    if let Err(e) = connection.remove_avatar().await {
+                        self.log.error(format!("Failed to remove avatar: {}", e));
+                    }
+                    *self.avatar.write().unwrap() = AvatarCache::default();
+                }
+                return;
+            }
+        };
+
+        if self.avatar.read().unwrap().url.as_ref() == Some(&url) {
+            debug!("Avatar is already set to {}", url);
+            return;
+        }
+
+        let data = match download_thumbnail(&url).await {
+            Ok(data) => data,
+            Err(e) => {
+                self.log
+                    .error(format!("Failed to download thumbnail {}: {}", url, e));
+                return;
+            }
+        };
+
+        let hash = format!("{:x}", md5::compute(&data));
+        if self.avatar.read().unwrap().hash.as_ref() == Some(&hash) {
+            debug!("Thumbnail {} is identical to the current avatar", url);
+            self.avatar.write().unwrap().url = Some(url);
+            return;
+        }
+
+        info!("Setting avatar to {}", url);
+        match connection.set_avatar(data).await {
+            Ok(()) => {
+                *self.avatar.write().unwrap() = AvatarCache {
+                    url: Some(url),
+                    hash: Some(hash),
+                };
+            }
+            Err(e) => self.log.error(format!("Failed to set avatar: {}", e)),
+        }
+    }
+
+    /// Resolves lazily added entries, skipping to the next one if that fails
+    async fn resolve_entry(&self, mut metadata: AudioMetadata) -> Option<AudioMetadata> {
+        if let Some(cached) = self.audio_cache.as_ref().and_then(|c| c.get(&metadata)) {
+            return Some(cached);
+        }
+
+        while metadata.needs_resolving() {
+            let request_id = metadata.request_id;
+            info!("{} Resolving {}", request_id, metadata.webpage_url);
+
+            let url = metadata.webpage_url.clone();
+            match self
+                .downloader
+                .get_audio_download_from_url(url, request_id)
+                .await
+            {
+                Ok(mut resolved) => {
+                    resolved.added_by = metadata.added_by;
+                    metadata = resolved;
+                }
+                Err(e) => {
+                    self.log
+                        .warn(format!("{} Failed to resolve entry: {}", request_id, e));
+                    let reason = crate::youtube_dl::error_summary(&e);
+
+                    if !crate::youtube_dl::needs_login(&e) {
+                        if let Some((source, mut replacement)) =
+                            self.downloader.find_replacement(&metadata).await
+                        {
+                            info!("{} Replacing with {}", request_id, replacement.webpage_url);
+                            self.send_message(tr!(
+                                self.server_locale,
+                                "replacing",
+                                "{title} can not be played ({reason}), \
+                                 playing {replacement} from {source} instead",
+                                title = ts::underline(&metadata.title),
+                                reason = reason,
+                                replacement = ts::underline(&replacement.title),
+                                source = source
+                            ))
+                            .await;
+
+                            replacement.added_by = metadata.added_by;
+                            metadata = replacement;
+                            continue;
+                        }
+                    }
+
+                    self.send_message(tr!(
+                        self.server_locale,
+                        "skipping",
+                        "Skipping {title}, it can not be played ({reason})",
+                        title = ts::underline(&metadata.title),
+                        reason = reason
+                    ))
+                    .await;
+
+                    metadata = self
+                        .playlist
+                        .write()
+                        .expect("RwLock was not poisoned")
+                        .pop()?;
+                }
+            }
+        }
+
+        Some(metadata)
+    }
+
+    /// Adds a track or playlist, `privileged` requests by admins skip the
+    /// track and source restrictions. Replies are in the language of the server.
+    pub async fn add_audio(&self, url: String, user: String, privileged: bool) {
+        self.add_audio_at(url, user, privileged, false, &self.server_locale)
+            .await;
+    }
+
+    /// Requests made through the web API are logged under the bot as well
+    fn request_span(&self, request_id: RequestId) -> Span {
+        info_span!(parent: &self.span, "request", id = %request_id)
+    }
+
+    /// Adds a track or playlist, `next` adds a single track to the front of the queue
+    async fn add_audio_at(
+        &self,
+        url: String,
+        user: String,
+        privileged: bool,
+        next: bool,
+        locale: &Locale,
+    ) {
+        let request_id = RequestId::next();
+
+        self.add_audio_request(url, user, privileged, next, request_id, locale)
+            .instrument(self.request_span(request_id))
+            .await
+    }
+
+    async fn add_audio_request(
+        &self,
+        url: String,
+        user: String,
+        privileged: bool,
+        next: bool,
+        request_id: RequestId,
+        locale: &Locale,
+    ) {
+        info!("{} {} requested {}", request_id, user, url);
+
+        if !privileged {
+            if let Some(domain) = self.sources.rejected_domain(&url) {
+                info!("{} Source {} is not allowed", request_id, domain);
+                self.send_message(tr!(
+                    locale,
+                    "source-not-allowed",
+                    "Could not add {url}, tracks from {domain} are not allowed",
+                    url = url,
+                    domain = ts::bold(&domain)
+                ))
+                .await;
+                return;
+            }
+        }
+
+        let is_playlist = crate::youtube_dl::is_playlist_url(&url)
+            || crate::playlist_file::is_playlist_file_url(&url);
+        if next && is_playlist {
+            self.send_message(String::from(
+                "Only single tracks can be played next, use !add for playlists",
+            ))
+            .await;
+            return;
+        }
+
+        if crate::youtube_dl::is_playlist_url(&url) {
+            self.add_playlist(url, user, request_id, privileged, locale)
+                .await;
+            return;
+        }
+
+        if crate::playlist_file::is_playlist_file_url(&url) {
+            self.add_playlist_file(url, user, request_id, privileged, locale)
+                .await;
+            return;
+        }
+
+        match self
+            .downloader
+            .get_audio_download_from_url(url, request_id)
+            .await
+        {
+            Ok(mut metadata) => {
+                metadata.added_by = user;
+                info!("{} Found audio url: {}", request_id, metadata.url);
+
+                self.add_entry(metadata, privileged, next, locale).await;
+            }
+            Err(e) => {
+                self.log
+                    .warn(format!("{} Failed to find audio url: {}", request_id, e));
+
+                self.send_message(tr!(
+                    locale,
+                    "url-failed",
+                    "Failed to find url: {error}",
+                    error = e
+                ))
+                .await;
+            }
+        }
+    }
+
+    /// Downloads an audio file from the file browser of the channel and adds it
+    async fn play_file(&self, name: String, user: String, privileged: bool, locale: &Locale) {
+        let request_id = RequestId::next();
+        let span = self.request_span(request_id);
+
+        let result = self
+            .download_file(&name, request_id)
+            .instrument(span.clone())
+            .await;
+        match result {
+            Ok(url) => {
+                let metadata = AudioMetadata {
+                    webpage_url: url.clone(),
+                    url,
+                    title: name.trim_start_matches('/').to_owned(),
+                    thumbnail: None,
+                    duration: None,
+                    artist: None,
+                    track: None,
+                    album: None,
+                    recording: None,
+                    chapters: Vec::new(),
+                    added_by: user,
+                    request_id,
+                };
+                self.add_entry(metadata, privileged, false, locale)
+                    .instrument(span)
+                    .await;
+            }
+            Err(e) => {
+                self.send_message(format!("Could not play {}, {}", ts::underline(&name), e))
+                    .await;
+            }
+        }
+    }
+
+    /// Stores the file with the given name and returns its `file://` url
+    async fn download_file(&self, name: &str, request_id: RequestId) -> Result<String, String> {
+        let config = self
+            .file_playback
+            .as_ref()
+            .ok_or("playing files is not enabled")?;
+        let mut ts = self
+            .teamspeak
+            .clone()
+            .ok_or("files can only be played on TeamSpeak")?;
+
+        info!("{} Downloading file {}", request_id, name);
+        let path = format!("/{}", name.trim_start_matches('/'));
+        let channel = ts
+            .my_channel()
+            .await
+            .ok_or("I do not know which channel I am in right now")?;
+        let data = ts
+            .download_file(
+                channel,
+                path,
+                self.channel_password.clone(),
+                config.max_size(),
+            )
+            .await
+            .map_err(|e| {
+                self.log
+                    .warn(format!("{} Failed to download file: {}", request_id, e));
+                e
+            })?;
+
+        let extension =
+            file_playback::audio_format(&data).ok_or("the file is not a supported audio file")?;
+        let config = config.clone();
+        let path = tokio::task::spawn_blocking(move || config.store(&data, extension))
+            .await
+            .map_err(|e| e.to_string())
+            .and_then(|r| r.map_err(|e| e.to_string()))
+            .map_err(|e| {
+                self.log
+                    .error(format!("{} Failed to store file: {}", request_id, e));
+                String::from("the file could not be stored")
+            })?;
+
+        Ok(format!("file://{}", path.display()))
+    }
+
+    /// Queues a single track or starts playing it if nothing is playing
+    async fn add_entry(
+        &self,
+        metadata: AudioMetadata,
+        privileged: bool,
+        next: bool,
+        locale: &Locale,
+    ) {
+        let request_id = metadata.request_id;
+        if let Some(reason) = self.rejection(&metadata, privileged) {
+            info!("{} Rejected: {}", request_id, reason);
+            self.send_message(format!(
+                "Could not add {}, {}",
+                ts::underline(&metadata.title),
+                reason
+            ))
+            .await;
+            return;
+        }
+
+        if !self.fits_into_queue(&metadata) {
+            info!("{} Queue is full, not adding", request_id);
+            self.send_message(tr!(
+                locale,
+                "queue-full",
+                "Could not add {title}, the queue is limited to {limits}",
+                title = ts::underline(&metadata.title),
+                limits = self.queue_limits
+            ))
+            .await;
+            return;
+        }
+
+        // RWLockGuard can not be kept around or the compiler complains that
+        // it might cross the await boundary
+        {
+            let mut playlist = self.playlist.write().expect("RwLock was not poisoned");
+            if next {
+                playlist.push_next(metadata.clone());
+            } else {
+                playlist.push(metadata.clone());
+            }
+        }
+
+        if !self.player.is_started() {
+            let entry = self
+                .playlist
+                .write()
+                .expect("RwLock was not poisoned")
+                .pop();
+            if let Some(request) = entry {
+                self.start_playing_audio(request).await;
+            }
+        } else {
+            self.prefetch_next();
+
+            let duration = if let Some(duration) = metadata.duration {
+                format!(" ({})", ts::bold(&humantime::format_duration(duration)))
+            } else {
+                format!("")
+            };
+
+            let title = ts::underline(&metadata.title);
+            let text = if next {
+                tr!(
+                    locale,
+                    "added-next",
+                    "Added {title}{duration} to the front of the playlist",
+                    title = title,
+                    duration = duration
+                )
+            } else {
+                tr!(
+                    locale,
+                    "added",
+                    "Added {title}{duration} to playlist",
+                    title = title,
+                    duration = duration
+                )
+            };
+            self.send_message(text).await;
+        }
+    }
+
+    async fn add_playlist(
+        &self,
+        url: String,
+        user: String,
+        request_id: RequestId,
+        privileged: bool,
+        locale: &Locale,
+    ) {
+        let max = self.max_playlist_tracks;
+        let mut added = 0;
+        let mut skipped = 0;
+        let mut queue_full = false;
+
+        while added < max {
+            let count = PLAYLIST_PAGE_SIZE.min(max - added);
+            let page = match self
+                .downloader
+                .get_playlist_page(&url, added + 1, count, request_id)
+                .await
+            {
+                Ok(page) => page,
+                Err(e) => {
+                    info!("{} Failed to load playlist page: {}", request_id, e);
+                    if added == 0 {
+                        self.send_message(format!("Failed to load playlist: {}", e))
+                            .await;
+                        return;
+                    }
+                    break;
+                }
+            };
+
+            let mut page = page;
+            let loaded = page.len();
+            skipped += self.drop_rejected(&mut page, privileged);
+            let len = page.len();
+            // Starts playing right away instead of waiting for the whole playlist
+            let enqueued = self.enqueue(page, &user).await;
+            added += enqueued;
+            info!("{} Loaded {} playlist entries", request_id, added);
+
+            if enqueued < len {
+                queue_full = true;
+                break;
+            }
+
+            if loaded < count {
+                break;
+            }
+        }
+
+        self.send_message(format!(
+            "{}{}",
+            self.playlist_added_text(added, queue_full, added + skipped >= max, locale),
+            skipped_text(skipped)
+        ))
+        .await;
+    }
+
+    fn playlist_added_text(
+        &self,
+        added: usize,
+        queue_full: bool,
+        truncated: bool,
+        locale: &Locale,
+    ) -> String {
+        let count = ts::bold(&added);
+        if queue_full {
+            tr!(
+                locale,
+                "added-tracks-queue-full",
+                "Added {count} tracks, the queue is limited to {limits}",
+                count = count,
+                limits = self.queue_limits
+            )
+        } else if truncated {
+            tr!(
+                locale,
+                "added-tracks-truncated",
+                "Added {count} tracks, playlists are limited to {max} tracks",
+                count = count,
+                max = self.max_playlist_tracks
+            )
+        } else {
+            tr!(
+                locale,
+                "added-tracks",
+                "Added {count} tracks",
+                count = count
+            )
+        }
+    }
+
+    async fn add_playlist_file(
+        &self,
+        url: String,
+        user: String,
+        request_id: RequestId,
+        privileged: bool,
+        locale: &Locale,
+    ) {
+        let text = match download_text(&url).await {
+            Ok(text) => text,
+            Err(e) => {
+                info!("{} Failed to download playlist file: {}", request_id, e);
+                self.send_message(format!("Failed to download playlist: {}", e))
+                    .await;
+                return;
+            }
+        };
+
+        let mut entries = crate::playlist_file::parse(&text, request_id);
+        let found = entries.len();
+        entries.truncate(self.max_playlist_tracks);
+        let truncated = entries.len() < found;
+        let skipped = self.drop_rejected(&mut entries, privileged);
+        let len = entries.len();
+
+        let added = self.enqueue(entries, &user).await;
+        self.send_message(format!(
+            "{}{}",
+            self.playlist_added_text(added, added < len, truncated, locale),
+            skipped_text(skipped)
+        ))
+        .await;
+    }
+
+    async fn search(&self, query: String, user: String, locale: &Locale) {
+        let request_id = RequestId::next();
+
+        self.search_request(query, user, request_id, locale)
+            .instrument(self.request_span(request_id))
+            .await
+    }
+
+    async fn search_request(
+        &self,
+        query: String,
+        user: String,
+        request_id: RequestId,
+        locale: &Locale,
+    ) {
+        info!("{} {} searched for {}", request_id, user, query);
+
+        let results = match self
+            .downloader
+            .search(&query, SEARCH_RESULTS, request_id)
+            .await
+        {
+            Ok(results) => results,
+            Err(e) => {
+                info!("{} Failed to search: {}", request_id, e);
+                self.send_message(tr!(locale, "search-failed", "Search failed"))
+                    .await;
+                return;
+            }
+        };
+
+        if results.is_empty() {
+            self.send_message(tr!(
+                locale,
+                "nothing-found",
+                "Nothing found for {query}",
+                query = ts::bold(&query)
+            ))
+            .await;
+            return;
+        }
+
+        let mut text = format!("Results for {}:", ts::bold(&query));
+        for (i, result) in results.iter().enumerate() {
+            let duration = if let Some(duration) = result.duration {
+                format!(" ({})", crate::duration::format_timestamp(duration))
+            } else {
+                String::new()
+            };
+            text += &format!("\n{}. {}{}", i + 1, ts::underline(&result.title), duration);
+        }
+        text += &format!("\nUse {}pick <number> to add one", self.commands.prefix);
+
+        {
+            let mut searches = self.searches.write().expect("RwLock was not poisoned");
+            searches.retain(|_, s| s.started.elapsed() < SEARCH_TIMEOUT);
+            searches.insert(
+                user,
+                PendingSearch {
+                    results,
+                    started: Instant::now(),
+                },
+            );
+        }
+
+        self.send_message(text).await;
+    }
+
+    /// Adds the result with the 1-based `number` of the last search done by `user`
+    pub async fn pick(&self, user: &str, number: usize, privileged: bool) -> Result<(), String> {
+        let entry = {
+            let mut searches = self.searches.write().expect("RwLock was not poisoned");
+            let search = match searches.get(user) {
+                Some(s) if s.started.elapsed() < SEARCH_TIMEOUT => s,
+                _ => return Err(String::from("You have no recent search results")),
+            };
+
+            let entry = match number.checked_sub(1).and_then(|i| search.results.get(i)) {
+                Some(entry) => entry.clone(),
+                None => return Err(format!("There is no result number {}", number)),
+            };
+            searches.remove(user);
+
+            entry
+        };
+
+        if entry.needs_resolving() {
+            self.add_audio(entry.webpage_url, user.to_owned(), privileged)
+                .await;
+            return Ok(());
+        }
+
+        // Podcast episodes are played straight from the url in the feed
+        let title = ts::underline(&entry.title);
+        if let Some(reason) = self.rejection(&entry, privileged) {
+            return Err(format!("Could not add {}, {}", title, reason));
+        }
+        if !self.fits_into_queue(&entry) {
+            return Err(format!(
+                "Could not add {}, the queue is limited to {}",
+                title, self.queue_limits
+            ));
+        }
+        self.enqueue(vec![entry], user).await;
+        self.send_message(format!("Added {} to playlist", title))
+            .await;
+
+        Ok(())
+    }
+
+    /// Runs a command sent through the web API like one sent in chat, replies go to the channel
+    pub async fn run_command(&self, text: &str, admin: bool) -> Result<(), String> {
+        let command = self
+            .commands
+            .parse_args::<Command>(text)
+            .map_err(|e| e.to_string())?;
+        if command.edits_queue() && !admin {
+            self.check_unlocked()?;
+        }
+        let invoker = Invoker {
+            name: String::from("Web"),
+            id: ClientId(0),
+            uid: None,
+        };
+
+        self.on_command(command, invoker)
+            .await
+            .map_err(|e| format!("Failed to run the command: {:?}", e))
+    }
+
+    /// Lists the newest episodes of a feed so `user` can pick one,
+    /// lists the subscriptions without a feed.
+    async fn list_podcast(&self, feed: Option<String>, user: String) {
+        let feed = match feed {
+            Some(feed) => feed,
+            None => {
+                self.send_message(self.podcasts.text(&self.commands.prefix))
+                    .await;
+                return;
+            }
+        };
+        // A number refers to a subscription, anything else is a url
+        let url = match feed.parse::<usize>() {
+            Ok(number) => match self.podcasts.find(number) {
+                Some(subscription) => subscription.url,
+                None => {
+                    self.send_message(format!("There is no subscription number {}", number))
+                        .await;
+                    return;
+                }
+            },
+            Err(_) => podcast::feed_url(&feed),
+        };
+
+        let request_id = RequestId::next();
+        info!("{} {} listed podcast {}", request_id, user, url);
+        let feed = match self
+            .downloader
+            .get_podcast(&url, podcast::EPISODES, request_id)
+            .instrument(self.request_span(request_id))
+            .await
+        {
+            Ok(feed) => feed,
+            Err(e) => {
+                info!("{} Failed to read podcast: {}", request_id, e);
+                self.send_message(podcast::read_error(&e)).await;
+                return;
+            }
+        };
+
+        let text = podcast::episodes_text(&feed, &self.commands.prefix);
+        {
+            let mut searches = self.searches.write().expect("RwLock was not poisoned");
+            searches.retain(|_, s| s.started.elapsed() < SEARCH_TIMEOUT);
+            searches.insert(
+                user,
+                PendingSearch {
+                    results: feed.episodes,
+                    started: Instant::now(),
+                },
+            );
+        }
+
+        self.send_message(text).await;
+    }
+
+    /// Anyone can list the subscriptions, only the summoner and admins can change them
+    async fn on_subscription_command(&self, action: SubscriptionAction, invoker: &Invoker) {
+        let may_change = self.is_summoner(invoker) || self.is_admin(invoker);
+        if !may_change && !matches!(action, SubscriptionAction::List) {
+            self.send_message(String::from(
+                "Only the one who summoned me and admins can change the subscriptions",
+            ))
+            .await;
+            return;
+        }
+
+        let reply = match action {
+            SubscriptionAction::Add { url } => {
+                let url = podcast::feed_url(&url);
+                if self.podcasts.contains(&url) {
+                    self.send_message(String::from("This channel is already subscribed to it"))
+                        .await;
+                    return;
+                }
+
+                let request_id = RequestId::next();
+                let feed = match self
+                    .downloader
+                    .get_podcast(&url, 1, request_id)
+                    .instrument(self.request_span(request_id))
+                    .await
+                {
+                    Ok(feed) => feed,
+                    Err(e) => {
+                        info!("{} Failed to read podcast: {}", request_id, e);
+                        self.send_message(podcast::read_error(&e)).await;
+                        return;
+                    }
+                };
+
+                info!("Subscribing to {}", url);
+                let text = format!("Subscribed to {}", ts::bold(&feed.title));
+                self.podcasts.add(url, feed);
+                self.save_channel_profile();
+
+                text
+            }
+            SubscriptionAction::Remove { number } => match self.podcasts.remove(number) {
+                Some(subscription) => {
+                    info!("Unsubscribed from {}", subscription.url);
+                    self.save_channel_profile();
+                    format!("Unsubscribed from {}", ts::bold(&subscription.title))
+                }
+                None => format!("There is no subscription number {}", number),
+            },
+            SubscriptionAction::List => self.podcasts.text(&self.commands.prefix),
+            SubscriptionAction::Announce { number, toggle } => {
+                match self.podcasts.set_announce(number, toggle.is_on()) {
+                    Some(title) => {
+                        self.save_channel_profile();
+                        if toggle.is_on() {
+                            format!("New episodes of {} will be announced", ts::bold(&title))
+                        } else {
+                            format!("New episodes of {} will not be announced", ts::bold(&title))
+                        }
+                    }
+                    None => format!("There is no subscription number {}", number),
+                }
+            }
+        };
+
+        self.send_message(reply).await;
+    }
+
+    /// Reading the feeds can take a while, so they are checked next to the
+    /// message loop instead of in it
+    fn check_podcasts_periodically(self: &Arc<Self>) {
+        let weak = Arc::downgrade(self);
+        tokio::spawn(
+            async move {
+                loop {
+                    tokio::time::delay_for(podcast::CHECK_INTERVAL).await;
+                    let bot = match weak.upgrade() {
+                        Some(bot) if bot.is_connected() => bot,
+                        _ => break,
+                    };
+                    bot.check_podcasts().await;
+                }
+            }
+            .in_current_span(),
+        );
+    }
+
+    /// Announces episodes that came out since the last check
+    async fn check_podcasts(&self) {
+        let subscriptions = self.podcasts.get();
+        let mut changed = false;
+
+        for (i, subscription) in subscriptions.iter().enumerate().filter(|(_, p)| p.announce) {
+            let request_id = RequestId::next();
+            let episode = match podcast::new_episode(&self.downloader, subscription, request_id)
+                .instrument(self.request_span(request_id))
+                .await
+            {
+                Ok(Some(episode)) => episode,
+                Ok(None) => continue,
+                Err(e) => {
+                    self.log.warn(format!(
+                        "Failed to check podcast {}: {}",
+                        subscription.url, e
+                    ));
+                    continue;
+                }
+            };
+
+            info!(
+                "New episode of {}: {}",
+                subscription.url, episode.webpage_url
+            );
+            changed |= self
+                .podcasts
+                .set_latest_episode(&subscription.url, &episode);
+
+            let text =
+                podcast::new_episode_text(subscription, i + 1, &episode, &self.commands.prefix);
+            self.send_message(text).await;
+        }
+
+        if changed {
+            self.save_channel_profile();
+        }
+    }
+
+    pub fn pending_searches(&self) -> Vec<SearchResults> {
+        self.searches
+            .read()
+            .expect("RwLock was not poisoned")
+            .iter()
+            .filter(|(_, s)| s.started.elapsed() < SEARCH_TIMEOUT)
+            .map(|(user, s)| SearchResults {
+                user: user.clone(),
+                results: s.results.clone(),
+            })
+            .collect()
+    }
+
+    /// Adds entries to the playlist and starts playback if nothing is playing.
+    ///
+    /// Entries that do not fit into the queue limits are dropped,
+    /// returns the number of added entries.
+    pub async fn enqueue(&self, mut entries: Vec<AudioMetadata>, user: &str) -> usize {
+        let len = {
+            let mut playlist = self.playlist.write().expect("RwLock was not poisoned");
+            if let Some(limits) = self.active_queue_limits() {
+                let fitting = limits.fitting(&playlist.to_vec(), &entries);
+                if fitting < entries.len() {
+                    info!(
+                        "Queue is full, dropping {} entries",
+                        entries.len() - fitting
+                    );
+                    entries.truncate(fitting);
+                }
+            }
+
+            let len = entries.len();
+            for mut entry in entries {
+                entry.added_by = user.to_owned();
+                playlist.push(entry);
+            }
+
+            len
+        };
+
+        if len != 0 && !self.player.is_started() {
+            let entry = self
+                .playlist
+                .write()
+                .expect("RwLock was not poisoned")
+                .pop();
+            if let Some(request) = entry {
+                self.start_playing_audio(request).await;
+            }
+        } else if len != 0 {
+            self.prefetch_next();
+        }
+
+        len
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn play(&self) -> Result<(), AudioPlayerError> {
+        let playlist = self.playlist.read().expect("RwLock was not poisoned");
+
+        if !self.player.is_started() {
+            if !playlist.is_empty() {
+                self.player.stop_current()?;
+            }
+        } else {
+            self.player.play()?;
+        }
+
+        Ok(())
+    }
+
+    pub fn pause(&self) -> Result<(), AudioPlayerError> {
+        self.player.pause()
+    }
+
+    pub fn stop(&self) -> Result<(), AudioPlayerError> {
+        self.player.reset()
+    }
+
+    pub fn next(&self) -> Result<(), AudioPlayerError> {
+        let playlist = self.playlist.read().expect("RwLock was not poisoned");
+        if !playlist.is_empty() {
+            info!("Skipping to next track");
+            self.player.stop_current()?;
+        } else {
+            info!("Playlist empty, cannot skip");
+            self.player.reset()?;
+        }
+
+        Ok(())
+    }
+
+    pub fn clear(&self) {
+        self.playlist
+            .write()
+            .expect("RwLock was not poisoned")
+            .clear();
+        self.prefetch_next();
+    }
+
+    /// Moves the queue entry at the 1-based position `from` to `to`
+    pub fn move_entry(&self, from: usize, to: usize) -> Result<(), String> {
+        {
+            let mut playlist = self.playlist.write().expect("RwLock was not poisoned");
+            let from = queue_index(&playlist, from)?;
+            let to = queue_index(&playlist, to)?;
+            playlist.move_entry(from, to);
+        }
+        self.prefetch_next();
+
+        Ok(())
+    }
+
+    /// Removes the queue entry at the 1-based `position`
+    pub fn remove_entry(&self, position: usize) -> Result<AudioMetadata, String> {
+        let removed = {
+            let mut playlist = self.playlist.write().expect("RwLock was not poisoned");
+            let index = queue_index(&playlist, position)?;
+            playlist.remove(index).expect("index was checked")
+        };
+        self.prefetch_next();
+
+        Ok(removed)
+    }
+
+    pub async fn change_volume(&self, volume: VolumeChange) -> Result<(), AudioPlayerError> {
+        self.player.change_volume(volume)?;
+        self.save_channel_profile();
+        self.update_name(self.state()).await;
+
+        Ok(())
+    }
+
+    /// Only lasts until the next track starts, so it is not stored
+    pub fn change_track_volume(&self, volume: VolumeChange) -> Result<(), AudioPlayerError> {
+        self.player.change_track_volume(volume)
+    }
+
+    /// Silences the output without pausing, so the track keeps its position
+    pub async fn set_muted(&self, muted: bool) -> Result<(), AudioPlayerError> {
+        self.player.set_muted(muted)?;
+        self.update_name(self.state()).await;
+
+        Ok(())
+    }
+
+    pub fn is_muted(&self) -> bool {
+        self.player.is_muted()
+    }
+
+    /// Paths of the channels the bot whispers to
+    pub fn broadcast_channels(&self) -> Vec<String> {
+        self.broadcast
+            .read()
+            .unwrap()
+            .iter()
+            .map(|c| c.path.clone())
+            .collect()
+    }
+
+    /// Lets the invoker opt in to or out of hearing the bot, while anyone
+    /// listens the bot only whispers to those who did
+    fn on_listen_command(&self, on: bool, invoker: &Invoker) -> String {
+        let connection = match &self.teamspeak {
+            Some(connection) => connection,
+            None => return String::from("Listening needs a TeamSpeak connection"),
+        };
+
+        match (on, connection.set_listening(invoker.id, on)) {
+            (true, Some(1)) => {
+                info!("Starting listen mode for {}", invoker.name);
+                format!(
+                    "Only {} can hear me now, others can join with !listen on",
+                    invoker.name
+                )
+            }
+            (true, Some(_)) => {
+                info!("{} started listening", invoker.name);
+                format!("{} can hear me now", invoker.name)
+            }
+            (false, Some(0)) => {
+                info!("Nobody is listening anymore, ending listen mode");
+                String::from("Nobody is listening anymore, everyone in the channel hears me again")
+            }
+            (false, Some(_)) => {
+                info!("{} stopped listening", invoker.name);
+                format!("{} can not hear me anymore", invoker.name)
+            }
+            (true, None) => String::from("You can hear me already"),
+            (false, None) => String::from("You are not listening"),
+        }
+    }
+
+    pub fn session_token(&self) -> &str {
+        &self.session_token
+    }
+
+    pub fn identity_index(&self) -> usize {
+        self.identity_index
+    }
+
+    pub fn state(&self) -> State {
+        *self.state.read().expect("RwLock was not poisoned")
+    }
+
+    pub fn volume(&self) -> f64 {
+        self.player.volume()
+    }
+
+    pub fn track_volume(&self) -> f64 {
+        self.player.track_volume()
+    }
+
+    pub fn position(&self) -> Option<Duration> {
+        self.player.position()
+    }
+
+    pub fn currently_playing(&self) -> Option<AudioMetadata> {
+        self.player.currently_playing()
+    }
+
+    /// The chapter of the current track at the current position
+    pub fn current_chapter(&self) -> Option<Chapter> {
+        let metadata = self.currently_playing()?;
+        let position = self.position().unwrap_or_default();
+
+        metadata
+            .chapter_at(position)
+            .map(|(_, chapter)| chapter.clone())
+    }
+
+    fn chapters_text(&self) -> String {
+        let metadata = match self.currently_playing() {
+            Some(metadata) => metadata,
+            None => return String::from("Nothing is playing"),
+        };
+        if metadata.chapters.is_empty() {
+            return format!("{} has no chapters", ts::underline(&metadata.title));
+        }
+
+        let current = metadata
+            .chapter_at(self.position().unwrap_or_default())
+            .map(|(number, _)| number);
+        let mut text = format!("Chapters of {}:", ts::underline(&metadata.title));
+        for (i, chapter) in metadata.chapters.iter().enumerate() {
+            let line = format!(
+                "{}. {} {}",
+                i + 1,
+                crate::duration::format_timestamp(chapter.start_time),
+                chapter.title
+            );
+            if current == Some(i + 1) {
+                text.push_str(&format!("\n{}", ts::bold(&line)));
+            } else {
+                text.push_str(&format!("\n{}", line));
+            }
+        }
+
+        text
+    }
+
+    /// Sends the lyrics of the current track privately so they do not flood the channel
+    async fn send_lyrics(&self, invoker: &Invoker) {
+        let locale = self.reply_locale(invoker);
+        let lyrics = match &self.lyrics {
+            Some(lyrics) => lyrics.clone(),
+            None => {
+                self.send_message(String::from("Lyrics are not configured"))
+                    .await;
+                return;
+            }
+        };
+        let metadata = match self.currently_playing() {
+            Some(metadata) => metadata,
+            None => {
+                self.send_message(tr!(locale, "nothing-playing", "Nothing is playing"))
+                    .await;
+                return;
+            }
+        };
+
+        let text = match lyrics.find(&metadata).await {
+            Ok(Some(text)) => format!("Lyrics of {}:\n{}", ts::underline(&metadata.title), text),
+            Ok(None) => format!("Found no lyrics for {}", ts::underline(&metadata.title)),
+            Err(e) => {
+                self.log.error(format!("Failed to look up lyrics: {}", e));
+                format!(
+                    "Failed to look up the lyrics of {}",
+                    ts::underline(&metadata.title)
+                )
+            }
+        };
+
+        for message in split_message(&text, MAX_MESSAGE_LENGTH) {
+            self.send_message_to_user(invoker, message).await;
+        }
+    }
+
+    pub fn seek_to(&self, position: Duration) -> Result<(), AudioPlayerError> {
+        self.player.seek(Seek::Absolute(position)).map(|_| ())
+    }
+
+    /// Seeks to the start of the chapter with the 1-based `number`
+    fn seek_to_chapter(&self, number: usize) -> String {
+        let metadata = match self.currently_playing() {
+            Some(metadata) => metadata,
+            None => return String::from("Nothing is playing"),
+        };
+        let chapter = match number.checked_sub(1).and_then(|i| metadata.chapters.get(i)) {
+            Some(chapter) => chapter,
+            None if metadata.chapters.is_empty() => {
+                return format!("{} has no chapters", ts::underline(&metadata.title))
+            }
+            None => {
+                return format!(
+                    "There is no chapter {}, the track has {}",
+                    number,
+                    metadata.chapters.len()
+                )
+            }
+        };
+
+        match self.player.seek(Seek::Absolute(chapter.start_time)) {
+            Ok(_) => format!("Jumped to chapter {}", ts::underline(&chapter.title)),
+            Err(_) => String::from("Failed to seek"),
+        }
+    }
+
+    pub fn playlist_to_vec(&self) -> Vec<AudioMetadata> {
+        self.playlist.read().unwrap().to_vec()
+    }
+
+    /// Lowers the volume until nobody talked for the release time
+    fn duck(&self) {
+        let (ducking, teamspeak) = match (self.ducking, &self.teamspeak) {
+            (Some(ducking), Some(teamspeak)) => (ducking, teamspeak.clone()),
+            _ => return,
+        };
+        // Other music bots would keep each other quiet
+        let bot_uids = self.bot_uids.clone();
+        if !teamspeak.is_anyone_talking(&bot_uids) {
+            return;
+        }
+
+        {
+            let mut ducked = self.ducked.write().unwrap();
+            if *ducked {
+                return;
+            }
+            if let Err(e) = self.player.set_ducking(1.0 - ducking.amount) {
+                error!("Failed to lower volume: {:?}", e);
+                return;
+            }
+            *ducked = true;
+        }
+
+        let player = self.player.clone();
+        let ducked = self.ducked.clone();
+        tokio::spawn(
+            async move {
+                loop {
+                    // Checked under the lock so nobody starts talking while the volume goes up
+                    let wait = {
+                        let mut ducked = ducked.write().unwrap();
+                        let silence = teamspeak
+                            .last_voice(&bot_uids)
+                            .map_or(ducking.release, |t| t.elapsed());
+                        if silence < ducking.release {
+                            Some(ducking.release - silence)
+                        } else {
+                            if let Err(e) = player.set_ducking(1.0) {
+                                error!("Failed to restore volume: {:?}", e);
+                            }
+                            *ducked = false;
+                            None
+                        }
+                    };
+
+                    match wait {
+                        Some(wait) => tokio::time::delay_for(wait).await,
+                        None => break,
+                    }
+                }
+            }
+            .in_current_span(),
+        );
+    }
+
+    fn resume_state(&self) -> ResumeState {
+        ResumeState {
+            queue: self.queue_with_current(),
+            position: self.position(),
+            volume: self.volume(),
+        }
+    }
+
+    /// Continues the playback of a bot that lost its connection
+    async fn resume(&self, resume: ResumeState) {
+        info!(
+            "Resuming {} tracks at {:?}",
+            resume.queue.len(),
+            resume.position
+        );
+        if let Err(e) = self
+            .player
+            .change_volume(VolumeChange::Absolute(resume.volume))
+        {
+            error!("Failed to restore volume: {:?}", e);
+        }
+
+        let mut queue = resume.queue.into_iter();
+        let current = match queue.next() {
+            Some(current) => current,
+            None => return,
+        };
+        {
+            // Skips the queue limits and keeps who added the tracks
+            let mut playlist = self.playlist.write().expect("RwLock was not poisoned");
+            for entry in queue {
+                playlist.push(entry);
+            }
+        }
+
+        *self.resume_position.write().unwrap() = resume.position;
+        self.start_playing_audio(current).await;
+    }
+
+    /// The current track followed by the queue
+    pub fn queue_with_current(&self) -> Vec<AudioMetadata> {
+        let mut entries = Vec::new();
+        entries.extend(self.currently_playing());
+        entries.extend(self.playlist_to_vec());
+
+        entries
+    }
+
+    pub fn queue_text(&self, target: Target) -> QueueText {
+        let current = self.currently_playing();
+        let queue = self.playlist_to_vec();
+
+        QueueText::new(
+            current.as_ref().map(|c| (c, self.position())),
+            &queue,
+            target,
+        )
+    }
+
+    /// The last played tracks, starting with the most recent one
+    pub fn history(&self) -> Vec<AudioMetadata> {
+        self.history.read().unwrap().iter().cloned().collect()
+    }
+
+    pub fn max_playlist_tracks(&self) -> usize {
+        self.max_playlist_tracks
+    }
+
+    /// The queue limits unless an admin turned them off
+    fn active_queue_limits(&self) -> Option<QueueLimits> {
+        if *self.queue_limited.read().unwrap() {
+            Some(self.queue_limits)
+        } else {
+            None
+        }
+    }
+
+    pub fn is_locked(&self) -> bool {
+        *self.locked.read().unwrap()
+    }
+
+    pub fn set_locked(&self, locked: bool) {
+        *self.locked.write().unwrap() = locked;
+    }
+
+    /// Fails if the queue is locked, for changes from the web that come without the admin token
+    pub fn check_unlocked(&self) -> Result<(), String> {
+        if self.is_locked() {
+            Err(String::from("The queue is locked"))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn may_edit_queue(&self, invoker: &Invoker) -> bool {
+        !self.is_locked() || self.is_summoner(invoker) || self.is_admin(invoker)
+    }
+
+    fn is_summoner(&self, invoker: &Invoker) -> bool {
+        self.summoner == Some(invoker.id)
+    }
+
+    /// Why an entry may not be queued, admins are not restricted
+    fn rejection(&self, entry: &AudioMetadata, privileged: bool) -> Option<String> {
+        if privileged {
+            return None;
+        }
+
+        if let Some(domain) = self.sources.rejected_domain(&entry.webpage_url) {
+            return Some(format!("tracks from {} are not allowed", ts::bold(&domain)));
+        }
+        if let Some(pattern) = self.blacklist.matching(&entry.webpage_url) {
+            return Some(format!("it is blacklisted by {}", ts::bold(&pattern)));
+        }
+        if let Some(reason) = self.duplicate(entry) {
+            return Some(reason);
+        }
+
+        let max = self.active_queue_limits()?.max_track_duration?;
+        match entry.duration {
+            Some(duration) if duration > max => Some(format!(
+                "tracks are limited to {}",
+                ts::bold(&humantime::format_duration(max))
+            )),
+            _ => None,
+        }
+    }
+
+    /// Why an entry counts as a duplicate, `None` if duplicates are allowed
+    fn duplicate(&self, entry: &AudioMetadata) -> Option<String> {
+        let no_duplicates = self.no_duplicates?;
+        let url = &entry.webpage_url;
+
+        let playing = self
+            .currently_playing()
+            .map_or(false, |current| &current.webpage_url == url);
+        if playing || self.playlist_to_vec().iter().any(|e| &e.webpage_url == url) {
+            return Some(String::from("it is in the queue already"));
+        }
+
+        let played = self
+            .recently_played
+            .read()
+            .unwrap()
+            .iter()
+            .any(|(played, started)| {
+                played == url && started.elapsed() < no_duplicates.played_within
+            });
+        if played {
+            return Some(format!(
+                "it was played within the last {}",
+                ts::bold(&humantime::format_duration(no_duplicates.played_within))
+            ));
+        }
+
+        None
+    }
+
+    /// Removes the entries that may not be queued, returns how many were removed
+    pub fn drop_rejected(&self, entries: &mut Vec<AudioMetadata>, privileged: bool) -> usize {
+        let before = entries.len();
+        entries.retain(|entry| match self.rejection(entry, privileged) {
+            Some(reason) => {
+                info!("{} Skipping {}: {}", entry.request_id, entry.title, reason);
+                false
+            }
+            None => true,
+        });
+
+        before - entries.len()
+    }
+
+    fn fits_into_queue(&self, entry: &AudioMetadata) -> bool {
+        match self.active_queue_limits() {
+            Some(limits) => {
+                let queue = self.playlist_to_vec();
+                limits.fitting(&queue, std::slice::from_ref(entry)) == 1
+            }
+            None => true,
+        }
+    }
+
+    fn is_admin(&self, invoker: &Invoker) -> bool {
+        match &invoker.uid {
+            Some(uid) => self.admins.contains(&uid.0),
+            None => false,
+        }
+    }
+
+    /// Renders the now playing card as PNG, `None` if nothing is playing or cards are disabled
+    pub async fn now_playing_card(&self) -> Option<Vec<u8>> {
+        let renderer = self.cards.as_ref()?;
+        let metadata = self.currently_playing()?;
+
+        let cached = self
+            .card
+            .read()
+            .unwrap()
+            .clone()
+            .filter(|base| base.webpage_url == metadata.webpage_url);
+        let base = match cached {
+            Some(base) => base,
+            None => {
+                let cover = match &metadata.thumbnail {
+                    Some(url) => match download_thumbnail(url).await {
+                        Ok(data) => Some(data),
+                        Err(e) => {
+                            self.log
+                                .error(format!("Failed to download thumbnail {}: {}", url, e));
+                            None
+                        }
+                    },
+                    None => None,
+                };
+
+                let base = Arc::new(renderer.render_base(&metadata, cover.as_deref()));
+                *self.card.write().unwrap() = Some(base.clone());
+                base
+            }
+        };
+
+        let chapter = self.current_chapter().map(|c| c.title);
+        match renderer.render(
+            &base,
+            self.position(),
+            metadata.duration,
+            chapter.as_deref(),
+        ) {
+            Ok(png) => Some(png),
+            Err(e) => {
+                self.log
+                    .error(format!("Failed to render now playing card: {}", e));
+                None
+            }
+        }
+    }
+
+    /// The newest `count` warnings and errors, oldest first
+    pub fn recent_log(&self, count: usize) -> Vec<LogEntry> {
+        self.log.last(count)
+    }
+
+    pub fn prefetch_status(&self) -> PrefetchStatus {
+        self.prefetch.read().unwrap().clone()
+    }
+
+    pub fn details(&self) -> crate::web_server::BotDetails {
+        crate::web_server::BotDetails {
+            name: self.name.clone(),
+            state: self.state(),
+            volume: self.volume(),
+            track_volume: self.track_volume(),
+            muted: self.is_muted(),
+            position: self.position(),
+            currently_playing: self.currently_playing(),
+            chapter: self.current_chapter(),
+            playlist: self.playlist_to_vec(),
+            settings: self.settings(),
+            history: self.history(),
+            searches: self.pending_searches(),
+            broadcast: self.broadcast_channels(),
+            private_session: self.private_session.read().unwrap().clone(),
+            locked: self.is_locked(),
+            prefetch: self.prefetch_status(),
+            buffer_level: self.player.buffer_level(),
+            resources: self.player.resource_usage(),
+            messages: self.sender.read().unwrap().stats(),
+        }
+    }
+
+    pub fn summary(&self) -> crate::web_server::BotSummary {
+        crate::web_server::BotSummary {
+            name: self.name.clone(),
+            state: self.state(),
+            current_title: self.currently_playing().map(|m| m.title),
+            position: self.position(),
+            playback_mode: self.playback_mode(),
+            locked: self.is_locked(),
+            resources: self.player.resource_usage(),
+        }
+    }
+
+    pub fn settings(&self) -> BotSettings {
+        let announcements = *self.announcements.read().unwrap();
+
+        BotSettings {
+            announce_in_chat: announcements.chat,
+            announce_in_description: announcements.description,
+            thumbnail_avatars: self.thumbnail_avatars,
+            max_playlist_tracks: self.max_playlist_tracks,
+            queue_limits: self.active_queue_limits(),
+            effects: self.player.effects(),
+            encoder: self.player.encoder_settings(),
+            playback_mode: self.playback_mode(),
+        }
+    }
+
+    pub fn playback_mode(&self) -> PlaybackMode {
+        PlaybackMode {
+            end_of_queue: self.end_of_queue.read().unwrap().clone(),
+            autoplay: *self.autoplay.read().unwrap(),
+            follow: self.following.read().unwrap().is_some(),
+        }
+    }
+
+    /// Stores a report about the current track, returns the reply
+    fn report(&self, reason: String, reporter: &str) -> String {
+        let metadata = match self.currently_playing() {
+            Some(metadata) => metadata,
+            None => return String::from("Nothing is playing that could be reported"),
+        };
+        if reason.trim().is_empty() {
+            return String::from("Please add what is wrong with the track");
+        }
+
+        let channel = self.channel.read().unwrap().clone();
+        let report = self.reports.add(
+            &metadata,
+            reason,
+            ReportContext {
+                reporter,
+                bot: self.name(),
+                channel: &channel,
+                position: self.position(),
+            },
+        );
+
+        format!(
+            "Thanks, reported {} as #{}",
+            ts::bold(&metadata.title),
+            report.id
+        )
+    }
+
+    /// The summary of the play statistics or one of the leaderboards
+    async fn stats_text(&self, top: Option<TopKind>) -> String {
+        let stats = match &self.stats {
+            Some(stats) => stats.clone(),
+            None => return String::from("Statistics are disabled"),
+        };
+
+        // The queries go through the whole table, the bot keeps running meanwhile
+        let result = tokio::task::spawn_blocking(move || match top {
+            None => stats.summary().map(|summary| {
+                if summary.plays == 0 {
+                    return String::new();
+                }
+
+                format!(
+                    "{} plays of {} tracks requested by {} users, {} of audio",
+                    summary.plays,
+                    summary.tracks,
+                    summary.requesters,
+                    humantime::format_duration(Duration::from_secs(
+                        summary.total_duration.as_secs()
+                    ))
+                )
+            }),
+            Some(TopKind::Tracks) => stats.top_tracks(LEADERBOARD_LENGTH).map(|tracks| {
+                tracks
+                    .iter()
+                    .enumerate()
+                    .map(|(i, t)| {
+                        format!(
+                            "\n{}. {} ({} plays)",
+                            i + 1,
+                            ts::underline(&t.title),
+                            t.plays
+                        )
+                    })
+                    .collect::<String>()
+            }),
+            Some(TopKind::Requesters) => {
+                stats.top_requesters(LEADERBOARD_LENGTH).map(|requesters| {
+                    requesters
+                        .iter()
+                        .enumerate()
+                        .map(|(i, r)| {
+                            format!(
+                                "\n{}. {} ({} plays)",
+                                i + 1,
+                                ts::bold(&r.requester),
+                                r.plays
+                            )
+                        })
+                        .collect::<String>()
+                })
+            }
+        })
+        .await
+        .map_err(|e| e.to_string())
+        .and_then(|result| result.map_err(|e| e.to_string()));
+
+        match result {
+            Ok(text) if text.is_empty() => String::from("Nothing was played yet"),
+            Ok(text) => text,
+            Err(e) => {
+                error!("Failed to read statistics: {}", e);
+                String::from("Failed to read statistics")
+            }
+        }
+    }
+
+    fn settings_text(&self) -> String {
+        let settings = self.settings();
+        let on_off = |on| if on { "on" } else { "off" };
+        let queue_limit = match settings.queue_limits {
+            Some(QueueLimits {
+                max_tracks,
+                max_duration: Some(duration),
+                ..
+            }) => format!(
+                "{} tracks, {}",
+                max_tracks,
+                humantime::format_duration(duration)
+            ),
+            Some(QueueLimits { max_tracks, .. }) => format!("{} tracks", max_tracks),
+            None => String::from("off"),
+        };
+        let scrobbling = match &self.scrobbler {
+            Some(_) => on_off(*self.scrobbling.read().unwrap()),
+            None => "not configured",
+        };
+
+        let bitrate = match settings.encoder.bitrate {
+            Some(bitrate) => format!("{} kbit/s", bitrate),
+            None => String::from("auto"),
+        };
+
+        format!(
+            "\nVolume: {:.0}%\nAnnouncements in chat: {}\nAnnouncements in description: {}\n\
+             Queue limit: {}\nQueue locked: {}\nBitrate: {}\nScrobbling: {}\n{}",
+            self.volume() * 100.0,
+            on_off(settings.announce_in_chat),
+            on_off(settings.announce_in_description),
+            queue_limit,
+            on_off(self.is_locked()),
+            bitrate,
+            scrobbling,
+            settings.playback_mode,
+        )
+    }
+
+    async fn version_text(&self) -> String {
+        let downloader = match self.downloader.version().await {
+            Ok(version) => format!("{} {}", self.downloader.kind(), version),
+            Err(e) => {
+                self.log
+                    .warn(format!("Failed to get the downloader version: {}", e));
+                format!("{} which cannot be run", self.downloader.kind())
+            }
+        };
+
+        format!("PokeBot {} with {}", env!("CARGO_PKG_VERSION"), downloader)
+    }
+
+    pub async fn my_channel(&self) -> Option<ChannelId> {
+        let connection = self
+            .connection
+            .as_ref()
+            .expect("my_channel needs a connection");
+
+        connection.my_channel().await
+    }
+
+    /// Passes an event to the bot, for frontends that do not deliver them to the bot directly
+    pub fn post(&self, message: MusicBotMessage) {
+        // Ignore the result because the bot might have quit in the meantime
+        let _ = self.sender.read().unwrap().send(message);
+    }
+
+    async fn send_message(&self, text: String) {
+        debug!("Sending message: {}", text);
+
+        if let Some(connection) = &self.connection {
+            connection.send_message_to_channel(text).await;
+        }
+    }
+
+    async fn send_message_to_user(&self, invoker: &Invoker, text: String) {
+        debug!("Sending message to {}: {}", invoker.name, text);
+
+        if let Some(connection) = &self.connection {
+            connection.send_message_to_user(invoker, text).await;
+        }
+    }
+
+    async fn set_nickname(&self, name: String) {
+        info!("Setting nickname: {}", name);
+
+        if let Some(connection) = &self.connection {
+            connection.set_nickname(name).await;
+        }
+    }
+
+    async fn set_description(&self, mut desc: String) {
+        if let Some(link) = &self.control_link {
+            if !desc.is_empty() {
+                desc.push_str(" - ");
+            }
+            desc.push_str(&format!("Control me at {}", link));
+        }
+        info!("Setting description: {}", desc);
+
+        if let Some(connection) = &self.connection {
+            connection.set_description(desc).await;
+        }
+    }
+
+    fn now_playing_description(&self, metadata: &AudioMetadata) -> String {
+        let position = if self.progress_bar {
+            Some(self.position().unwrap_or_default())
+        } else {
+            None
+        };
+
+        now_playing_description(metadata, position)
+    }
+
+    /// Only moves the bar while a track with a known length plays
+    async fn update_progress_bar(&self) {
+        if !self.announcements.read().unwrap().description || self.state() != State::Playing {
+            return;
+        }
+        let metadata = match self.currently_playing() {
+            Some(metadata) if metadata.duration.is_some() => metadata,
+            _ => return,
+        };
+
+        self.set_description(self.now_playing_description(&metadata))
+            .await;
+    }
+
+    async fn subscribe(&self, id: ChannelId) {
+        if let Some(ts) = &self.teamspeak {
+            let mut ts = ts.clone();
+            ts.subscribe(id).await;
+        }
+    }
+
+    async fn on_text(&self, message: Message) -> Result<(), AudioPlayerError> {
+        let parsed = match self.commands.parse(&message.text) {
+            Some(parsed) => parsed,
+            None => return Ok(()),
+        };
+
+        let user = match &message.invoker.uid {
+            Some(uid) => uid.0.clone(),
+            None => message.invoker.name.clone(),
+        };
+        if !self
+            .allow(&message.invoker, self.rate_limiter.check(&user))
+            .await
+        {
+            return Ok(());
+        }
+
+        let locale = self.reply_locale(&message.invoker);
+        let result = match parsed {
+            Ok(args) => {
+                let limit = self.rate_limiter.check_command(&user, args.name());
+                if !self.allow(&message.invoker, limit).await {
+                    Ok(())
+                } else if args.edits_queue() && !self.may_edit_queue(&message.invoker) {
+                    self.send_message(tr!(
+                        locale,
+                        "queue-locked",
+                        "The queue is locked, only the summoner and admins can change it"
+                    ))
+                    .await;
+                    Ok(())
                 } else {
-                    self.player.play()?;
+                    self.on_command(args, message.invoker).await
                 }
             }
+            Err(CommandError::Help(help)) => {
+                let help = self.commands.format_help(&help, &locale);
+                self.send_message(format!("\n{}", help)).await;
+                Ok(())
+            }
+            // Other bots in the channel might use the same prefix
+            Err(CommandError::UnknownCommand(_)) => Ok(()),
+            Err(e) => {
+                self.send_message(e.to_string()).await;
+                Ok(())
+            }
+        };
+
+        result
+    }
+
+    /// The language the invoker picked with `!lang` if there is a catalog for
+    /// it, the one of the server otherwise
+    fn reply_locale(&self, invoker: &Invoker) -> Arc<Locale> {
+        invoker
+            .uid
+            .as_ref()
+            .and_then(|uid| self.user_settings.get(&uid.0).language)
+            .and_then(|language| self.locales.get(&language))
+            .unwrap_or_else(|| self.server_locale.clone())
+    }
+
+    /// Tells the invoker to slow down if the rate limit does not allow their command
+    async fn allow(&self, invoker: &Invoker, limit: RateLimit) -> bool {
+        if let RateLimit::Allowed = limit {
+            return true;
+        }
+        if let Some(reply) = limit.reply(&invoker.name) {
+            self.send_message(reply).await;
+        }
+
+        false
+    }
+
+    async fn on_command(&self, command: Command, invoker: Invoker) -> Result<(), AudioPlayerError> {
+        let locale = self.reply_locale(&invoker);
+        match command {
+            Command::Play => {
+                self.play()?;
+            }
             Command::Add { url } => {
                 // strip bbcode tags from url
                 let url = url.replace("[URL]", "").replace("[/URL]", "");
 
-                self.add_audio(url.to_string(), invoker.name).await;
+                let privileged = self.is_admin(&invoker);
+                self.add_audio_at(url.to_string(), invoker.name, privileged, false, &locale)
+                    .await;
             }
             Command::Search { query } => {
-                self.add_audio(format!("ytsearch:{}", query.join(" ")), invoker.name)
+                self.search(query.join(" "), invoker.name, &locale).await;
+            }
+            Command::Playfile { name } => {
+                let privileged = self.is_admin(&invoker);
+                self.play_file(name.join(" "), invoker.name, privileged, &locale)
                     .await;
             }
+            Command::Pick { number } => {
+                let privileged = self.is_admin(&invoker);
+                if let Err(e) = self.pick(&invoker.name, number, privileged).await {
+                    self.send_message(e).await;
+                }
+            }
+            Command::Podcast { feed } => {
+                self.list_podcast(feed, invoker.name).await;
+            }
+            Command::Subscription { action } => {
+                self.on_subscription_command(action, &invoker).await;
+            }
             Command::Pause => {
-                self.player.pause()?;
+                self.pause()?;
             }
             Command::Stop => {
-                self.player.reset()?;
+                self.stop()?;
             }
             Command::Seek { amount } => {
                 if let Ok(time) = self.player.seek(amount) {
-                    self.send_message(format!("New position: {}", ts::bold(&time)))
-                        .await;
+                    self.send_message(tr!(
+                        locale,
+                        "seeked",
+                        "New position: {time}",
+                        time = ts::bold(&time)
+                    ))
+                    .await;
                 } else {
-                    self.send_message(String::from("Failed to seek")).await;
+                    self.send_message(tr!(locale, "seek-failed", "Failed to seek"))
+                        .await;
                 }
             }
             Command::Next => {
-                let playlist = self.playlist.read().expect("RwLock was not poisoned");
-                if !playlist.is_empty() {
-                    info!("Skipping to next track");
-                    self.player.stop_current()?;
-                } else {
-                    info!("Playlist empty, cannot skip");
-                    self.player.reset()?;
-                }
+                self.next()?;
+            }
+            Command::Lyrics => {
+                self.send_lyrics(&invoker).await;
+            }
+            Command::Chapters => {
+                let text = self.chapters_text();
+                self.send_message(text).await;
+            }
+            Command::Chapter { number } => {
+                let text = self.seek_to_chapter(number);
+                self.send_message(text).await;
+            }
+            Command::PlayNext { url } => {
+                let url = url.replace("[URL]", "").replace("[/URL]", "");
+
+                let privileged = self.is_admin(&invoker);
+                self.add_audio_at(url, invoker.name, privileged, true, &locale)
+                    .await;
             }
             Command::Clear => {
-                self.playlist
-                    .write()
-                    .expect("RwLock was not poisoned")
-                    .clear();
+                self.clear();
+                self.send_message(tr!(locale, "cleared", "Cleared the queue"))
+                    .await;
+            }
+            Command::Queue { page, action } => {
+                let text = match action {
+                    None => {
+                        let queue = self.queue_text(Target::Chat);
+                        let page = page.unwrap_or(1);
+                        match queue.page(page, MAX_MESSAGE_LENGTH, &locale) {
+                            Some(text) => text,
+                            None if queue.is_empty() => {
+                                tr!(locale, "queue-empty", "The queue is empty")
+                            }
+                            None => tr!(locale, "no-page", "There is no page {page}", page = page),
+                        }
+                    }
+                    Some(QueueAction::Move { from, to }) => match self.move_entry(from, to) {
+                        Ok(()) => format!(
+                            "Moved entry {} to position {}",
+                            ts::bold(&from),
+                            ts::bold(&to)
+                        ),
+                        Err(e) => e,
+                    },
+                    Some(QueueAction::Remove { position }) => match self.remove_entry(position) {
+                        Ok(removed) => format!("Removed {}", ts::underline(&removed.title)),
+                        Err(e) => e,
+                    },
+                };
+                self.send_message(text).await;
             }
             Command::Volume { volume } => {
-                self.player.change_volume(volume)?;
-                self.update_name(self.state()).await;
+                self.change_volume(volume).await?;
+            }
+            Command::TrackVolume { volume } => {
+                self.change_track_volume(volume)?;
+            }
+            Command::Mute => {
+                self.set_muted(true).await?;
+            }
+            Command::Unmute => {
+                self.set_muted(false).await?;
+            }
+            Command::PreferredVolume { action } => {
+                let text = self.on_preferred_volume_command(action, &invoker);
+                self.send_message(text).await;
+            }
+            Command::Lang { language } => {
+                let text = self.on_lang_command(language, &invoker);
+                self.send_message(text).await;
+            }
+            Command::QueueLimit { toggle } => {
+                if !self.is_admin(&invoker) {
+                    self.send_message(String::from("Only admins can change the queue limit"))
+                        .await;
+                    return Ok(());
+                }
+
+                let limited = toggle.is_on();
+                *self.queue_limited.write().unwrap() = limited;
+                info!("{} turned queue limit {:?}", invoker.name, toggle);
+
+                let text = if limited {
+                    format!("The queue is limited to {}", self.queue_limits)
+                } else {
+                    String::from("The queue is not limited anymore")
+                };
+                self.send_message(text).await;
+            }
+            Command::Lock => {
+                let text = self.on_lock_command(true, &invoker);
+                self.send_message(text).await;
+            }
+            Command::Unlock => {
+                let text = self.on_lock_command(false, &invoker);
+                self.send_message(text).await;
+            }
+            Command::Blacklist { action } => {
+                let text = self.on_blacklist_command(action, &invoker);
+                for message in split_message(&text, MAX_MESSAGE_LENGTH) {
+                    self.send_message(message).await;
+                }
+            }
+            Command::Bitrate { bitrate } => {
+                let text = self.on_bitrate_command(bitrate, &invoker).await?;
+                self.send_message(text).await;
+            }
+            Command::Autoplay { toggle } => {
+                let enabled = toggle.is_on();
+                *self.autoplay.write().unwrap() = enabled;
+                *self.autoplayed.write().unwrap() = 0;
+                info!("{} turned autoplay {:?}", invoker.name, toggle);
+
+                let text = if enabled {
+                    format!(
+                        "I will play related tracks once the queue is empty, up to {} in a row",
+                        self.max_autoplayed
+                    )
+                } else {
+                    String::from("I will not play related tracks anymore")
+                };
+                self.send_message(text).await;
+            }
+            Command::QueueEnd { policy } => {
+                let text = match self.set_end_of_queue(policy) {
+                    EndOfQueue::Silent => String::from("I will stop once the queue is empty"),
+                    EndOfQueue::Replay => {
+                        String::from("I will play recent tracks once the queue is empty")
+                    }
+                    EndOfQueue::Fallback { url } => format!(
+                        "I will play {} once the queue is empty",
+                        ts::underline(&url)
+                    ),
+                    EndOfQueue::Leave { grace_period } => format!(
+                        "I will leave {} after the queue is empty",
+                        ts::bold(&humantime::format_duration(grace_period))
+                    ),
+                };
+                self.save_channel_profile();
+                self.send_message(text).await;
+            }
+            Command::Eq { band, gain } => {
+                self.on_eq_command(band, gain).await?;
+            }
+            Command::Speed { speed } => {
+                if !(0.5..=2.0).contains(&speed) {
+                    self.send_message(String::from("Speed has to be between 0.5 and 2"))
+                        .await;
+                } else if let Err(AudioPlayerError::EffectUnavailable) =
+                    self.player.set_speed(speed)
+                {
+                    self.send_message(String::from("Speed effects are not available"))
+                        .await;
+                } else {
+                    self.send_message(format!("Speed set to {}", ts::bold(&speed)))
+                        .await;
+                }
+            }
+            Command::Nightcore => {
+                let enabled = !self.player.effects().nightcore;
+                if let Err(AudioPlayerError::EffectUnavailable) = self.player.set_nightcore(enabled)
+                {
+                    self.send_message(String::from("Speed effects are not available"))
+                        .await;
+                } else if enabled {
+                    self.send_message(String::from("Nightcore mode enabled"))
+                        .await;
+                } else {
+                    self.send_message(String::from("Nightcore mode disabled"))
+                        .await;
+                }
+            }
+            Command::Announce { target, toggle } => {
+                let enabled = toggle.is_on();
+                {
+                    let mut announcements = self.announcements.write().unwrap();
+                    match target {
+                        AnnounceTarget::Chat => announcements.chat = enabled,
+                        AnnounceTarget::Description => announcements.description = enabled,
+                    }
+                }
+
+                if target == AnnounceTarget::Description {
+                    let description = match self.currently_playing() {
+                        Some(metadata) if enabled => self.now_playing_description(&metadata),
+                        _ => String::new(),
+                    };
+                    self.set_description(description).await;
+                }
+
+                let target = match target {
+                    AnnounceTarget::Chat => "chat",
+                    AnnounceTarget::Description => "description",
+                };
+                let state = if enabled { "enabled" } else { "disabled" };
+                self.save_channel_profile();
+                self.send_message(format!("Announcements in {} {}", target, state))
+                    .await;
+            }
+            Command::Broadcast { action } => {
+                self.on_broadcast_command(action).await;
+            }
+            Command::Playlist { action } => {
+                let privileged = self.is_admin(&invoker);
+                self.on_playlist_command(action, invoker.name, privileged)
+                    .await;
+            }
+            Command::Follow { toggle } => {
+                let text = if self.teamspeak.is_none() {
+                    String::from("Following needs a TeamSpeak connection")
+                } else if toggle.is_on() {
+                    let followed = self.summoner.unwrap_or(invoker.id);
+                    *self.following.write().unwrap() = Some(followed);
+                    info!("Following client {}", followed.0);
+
+                    String::from("I will follow when my summoner switches channels")
+                } else {
+                    *self.following.write().unwrap() = None;
+                    *self.follow_moved.write().unwrap() = None;
+
+                    String::from("I will stay in this channel")
+                };
+                self.send_message(text).await;
+            }
+            Command::Scrobble { toggle } => {
+                let text = if self.scrobbler.is_none() {
+                    String::from("Scrobbling is not configured")
+                } else {
+                    *self.scrobbling.write().unwrap() = toggle.is_on();
+
+                    if toggle.is_on() {
+                        String::from("Finished tracks will be scrobbled")
+                    } else {
+                        String::from("Finished tracks will not be scrobbled")
+                    }
+                };
+                self.send_message(text).await;
+            }
+            Command::Private { toggle } => {
+                let on = toggle.map(Toggle::is_on).unwrap_or(true);
+                let text = self.on_private_command(on, &invoker).await;
+                self.send_message(text).await;
+            }
+            Command::Invite { name } => {
+                let text = self.on_invite_command(&name.join(" "), &invoker).await;
+                self.send_message(text).await;
+            }
+            Command::Uninvite { name } => {
+                let text = self.on_uninvite_command(&name.join(" "), &invoker);
+                self.send_message(text).await;
+            }
+            Command::Stats => {
+                let text = self.stats_text(None).await;
+                self.send_message(text).await;
+            }
+            Command::Top { kind } => {
+                let text = self.stats_text(Some(kind)).await;
+                self.send_message(text).await;
+            }
+            Command::Report { reason } => {
+                let text = self.report(reason.join(" "), &invoker.name);
+                self.send_message(text).await;
+            }
+            Command::Settings => {
+                self.send_message(self.settings_text()).await;
+            }
+            Command::Version => {
+                let text = self.version_text().await;
+                self.send_message(text).await;
+            }
+            Command::Debug {
+                action: DebugAction::Last { count },
+            } => {
+                if !self.is_admin(&invoker) {
+                    self.send_message(String::from("Only admins can see the log"))
+                        .await;
+                    return Ok(());
+                }
+
+                let entries = self.recent_log(count.unwrap_or(DEFAULT_DEBUG_ENTRIES));
+                let text = if entries.is_empty() {
+                    String::from("Nothing went wrong recently")
+                } else {
+                    entries
+                        .iter()
+                        .map(ToString::to_string)
+                        .collect::<Vec<_>>()
+                        .join("\n")
+                };
+                for message in split_message(&text, MAX_MESSAGE_LENGTH) {
+                    self.send_message_to_user(&invoker, message).await;
+                }
+            }
+            Command::Listen { toggle } => {
+                let text = self.on_listen_command(toggle.is_on(), &invoker);
+                self.send_message(text).await;
             }
             Command::Leave => {
                 self.quit(String::from("Leaving"));
@@ -384,11 +3491,475 @@ impl MusicBot {
         Ok(())
     }
 
+    fn on_blacklist_command(&self, action: BlacklistAction, invoker: &Invoker) -> String {
+        if let BlacklistAction::List = action {
+            return self.blacklist_text();
+        }
+        if !self.is_admin(invoker) {
+            return String::from("Only admins can change the blacklist");
+        }
+
+        match action {
+            BlacklistAction::Add { pattern } => {
+                let pattern = pattern.replace("[URL]", "").replace("[/URL]", "");
+                if !self.blacklist.add(&pattern) {
+                    return format!("{} is blacklisted already", ts::bold(&pattern));
+                }
+                info!("{} blacklisted {}", invoker.name, pattern);
+
+                format!(
+                    "Tracks matching {} can not be added anymore",
+                    ts::bold(&pattern)
+                )
+            }
+            BlacklistAction::Remove { pattern } => {
+                let pattern = pattern.replace("[URL]", "").replace("[/URL]", "");
+                if !self.blacklist.remove(&pattern) {
+                    return format!("{} is not blacklisted", ts::bold(&pattern));
+                }
+                info!("{} removed {} from the blacklist", invoker.name, pattern);
+
+                format!("Removed {} from the blacklist", ts::bold(&pattern))
+            }
+            BlacklistAction::List => self.blacklist_text(),
+        }
+    }
+
+    fn blacklist_text(&self) -> String {
+        let patterns = self.blacklist.patterns();
+        if patterns.is_empty() {
+            return String::from("Nothing is blacklisted");
+        }
+
+        let mut text = String::from("Blacklisted:");
+        for pattern in patterns {
+            text.push_str(&format!("\n{}", ts::bold(&pattern)));
+        }
+
+        text
+    }
+
+    fn on_lock_command(&self, locked: bool, invoker: &Invoker) -> String {
+        let locale = self.reply_locale(invoker);
+        if !self.is_summoner(invoker) && !self.is_admin(invoker) {
+            return tr!(
+                locale,
+                "lock-not-allowed",
+                "Only the summoner and admins can lock the queue"
+            );
+        }
+
+        self.set_locked(locked);
+        info!("{} set the queue locked to {}", invoker.name, locked);
+
+        if locked {
+            tr!(
+                locale,
+                "locked",
+                "Locked the queue, only the summoner and admins can change it"
+            )
+        } else {
+            tr!(locale, "unlocked", "Everyone can change the queue again")
+        }
+    }
+
+    async fn on_bitrate_command(
+        &self,
+        bitrate: Option<BitrateSetting>,
+        invoker: &Invoker,
+    ) -> Result<String, AudioPlayerError> {
+        let max = self.max_bitrate().await;
+        let bitrate = match bitrate {
+            Some(bitrate) => bitrate,
+            None => return Ok(self.bitrate_text(max)),
+        };
+        if !self.is_admin(invoker) {
+            return Ok(String::from("Only admins can change the bitrate"));
+        }
+
+        let bitrate = match bitrate {
+            BitrateSetting::Auto => self.encoder.bitrate,
+            BitrateSetting::Kbps(kbps) => match max {
+                Some(max) if kbps > max => {
+                    return Ok(format!(
+                        "The codec settings of this channel only allow up to {} kbit/s",
+                        max
+                    ));
+                }
+                _ => Some(kbps),
+            },
+        };
+        info!("{} set the bitrate to {:?}", invoker.name, bitrate);
+        self.player.set_bitrate(bitrate)?;
+        self.save_channel_profile();
+
+        Ok(self.bitrate_text(max))
+    }
+
+    fn bitrate_text(&self, max: Option<u32>) -> String {
+        let settings = self.player.encoder_settings();
+        let mut text = match settings.bitrate {
+            Some(bitrate) => format!("Sending {} kbit/s", bitrate),
+            None => format!(
+                "Sending {} kbit/s, Opus sources are sent as they are",
+                settings.effective_bitrate()
+            ),
+        };
+        if let Some(max) = max {
+            text.push_str(&format!(", this channel allows up to {} kbit/s", max));
+        }
+
+        text
+    }
+
+    async fn max_bitrate(&self) -> Option<u32> {
+        match &self.connection {
+            Some(connection) => connection.max_bitrate().await,
+            None => None,
+        }
+    }
+
+    /// Lowers the bitrate if the codec settings of the channel do not allow it
+    async fn limit_bitrate(&self) {
+        let max = match self.max_bitrate().await {
+            Some(max) => max,
+            None => return,
+        };
+
+        let bitrate = self.player.encoder_settings().bitrate;
+        if let Some(bitrate) = bitrate.filter(|b| *b > max) {
+            self.log.warn(format!(
+                "Bitrate of {} kbit/s is too high for the channel, using {} kbit/s",
+                bitrate, max
+            ));
+            if let Err(e) = self.player.set_bitrate(Some(max)) {
+                self.log.error(format!("Failed to lower bitrate: {:?}", e));
+            }
+        }
+    }
+
+    fn on_preferred_volume_command(
+        &self,
+        action: PreferredVolumeAction,
+        invoker: &Invoker,
+    ) -> String {
+        let uid = match &invoker.uid {
+            Some(uid) => &uid.0,
+            None => return String::from("Preferences need a unique id to be stored"),
+        };
+
+        match action {
+            PreferredVolumeAction::Set { volume } => {
+                let volume = (volume * 0.01).max(0.0).min(1.0);
+                self.user_settings.set_volume(uid, Some(volume));
+                format!(
+                    "I will propose {:.0}% when you are the only listener",
+                    volume * 100.0
+                )
+            }
+            PreferredVolumeAction::Show => match self.user_settings.get(uid).volume {
+                Some(volume) => format!("Your preferred volume is {:.0}%", volume * 100.0),
+                None => String::from("You have no preferred volume"),
+            },
+            PreferredVolumeAction::Clear => {
+                self.user_settings.set_volume(uid, None);
+                String::from("Forgot your preferred volume")
+            }
+        }
+    }
+
+    fn on_lang_command(&self, language: Option<String>, invoker: &Invoker) -> String {
+        let locale = self.reply_locale(invoker);
+        let uid = match &invoker.uid {
+            Some(uid) => &uid.0,
+            None => return String::from("Preferences need a unique id to be stored"),
+        };
+        let languages = self.locales.languages().join(", ");
+
+        let language = match language {
+            Some(language) => language,
+            None => {
+                return tr!(
+                    locale,
+                    "language-show",
+                    "I reply to you in {language}, available are {languages}",
+                    language = locale.language(),
+                    languages = languages
+                )
+            }
+        };
+
+        if language == "reset" {
+            self.user_settings.set_language(uid, None);
+            return tr!(
+                self.server_locale,
+                "language-reset",
+                "I reply to you in the language of the server again"
+            );
+        }
+
+        match self.locales.get(&language) {
+            Some(locale) => {
+                info!("{} picked {} as language", invoker.name, locale.language());
+                self.user_settings
+                    .set_language(uid, Some(locale.language().to_owned()));
+                tr!(locale, "language-set", "I reply to you in English now")
+            }
+            None => tr!(
+                locale,
+                "language-unknown",
+                "There is no {language} translation, available are {languages}",
+                language = ts::bold(&language),
+                languages = languages
+            ),
+        }
+    }
+
+    /// Starts or ends a private session, clients in the channel stop hearing the bot
+    async fn on_private_command(&self, on: bool, invoker: &Invoker) -> String {
+        let connection = match (&self.connection, &self.teamspeak) {
+            (Some(connection), Some(_)) => connection.clone(),
+            _ => return String::from("Private sessions need a TeamSpeak connection"),
+        };
+        // Whoever heard the bot so far would hear it talking forever otherwise
+        let previous = whisper_targets(&self.broadcast, &self.private_session);
+
+        let text = {
+            let mut session = self.private_session.write().unwrap();
+            match &*session {
+                Some(_) if on => return String::from("A private session is running already"),
+                Some(s) if s.owner_id != invoker.id && !self.is_admin(invoker) => {
+                    return format!("Only {} can end this private session", s.owner);
+                }
+                None if !on => return String::from("There is no private session"),
+                Some(_) => {
+                    info!("Ending private session");
+                    *session = None;
+                    String::from("Everyone in the channel can hear me again")
+                }
+                None => {
+                    info!("Starting private session of {}", invoker.name);
+                    *session = Some(PrivateSession::new(invoker.id, invoker.name.clone()));
+                    format!(
+                        "Only {} can hear me now, use !invite to let others listen",
+                        invoker.name
+                    )
+                }
+            }
+        };
+        connection.send_audio_end(&previous).await;
+
+        text
+    }
+
+    async fn on_invite_command(&self, name: &str, invoker: &Invoker) -> String {
+        let mut ts = match &self.teamspeak {
+            Some(ts) => ts.clone(),
+            None => return String::from("Private sessions need a TeamSpeak connection"),
+        };
+        if let Err(e) = self.check_session_owner(invoker) {
+            return e;
+        }
+        let id = match ts.find_client(name).await {
+            Some(id) => id,
+            None => return format!("Could not find {}", name),
+        };
+        let name = ts.client_name(id).await.unwrap_or_else(|| name.to_owned());
+
+        let mut session = self.private_session.write().unwrap();
+        let session = match &mut *session {
+            Some(session) => session,
+            None => return String::from("Start a private session with !private first"),
+        };
+        if session.listeners.iter().any(|l| l.id == id) {
+            return format!("{} can hear me already", name);
+        }
+        info!("Inviting {} to the private session", name);
+        session.listeners.push(PrivateListener {
+            id,
+            name: name.clone(),
+        });
+
+        format!("{} can hear me now", name)
+    }
+
+    fn on_uninvite_command(&self, name: &str, invoker: &Invoker) -> String {
+        if let Err(e) = self.check_session_owner(invoker) {
+            return e;
+        }
+
+        let mut session = self.private_session.write().unwrap();
+        let session = match &mut *session {
+            Some(session) => session,
+            None => return String::from("There is no private session"),
+        };
+        let index = session
+            .listeners
+            .iter()
+            .position(|l| l.id != session.owner_id && l.name.eq_ignore_ascii_case(name.trim()));
+        match index {
+            Some(index) => {
+                let listener = session.listeners.remove(index);
+                info!("Removed {} from the private session", listener.name);
+                format!("{} can not hear me anymore", listener.name)
+            }
+            None => format!("{} is not invited", name),
+        }
+    }
+
+    /// Only the owner of a private session and admins may change who listens
+    fn check_session_owner(&self, invoker: &Invoker) -> Result<(), String> {
+        match &*self.private_session.read().unwrap() {
+            Some(s) if s.owner_id != invoker.id && !self.is_admin(invoker) => {
+                Err(format!("Only {} can invite others", s.owner))
+            }
+            Some(_) => Ok(()),
+            None => Err(String::from("Start a private session with !private first")),
+        }
+    }
+
+    /// Invited clients are forgotten when they leave, the session ends with its owner
+    fn on_private_listener_left(&self, id: ClientId) {
+        let mut session = self.private_session.write().unwrap();
+        let ended = match &mut *session {
+            Some(s) if s.owner_id == id => true,
+            Some(s) => {
+                s.listeners.retain(|l| l.id != id);
+                false
+            }
+            None => false,
+        };
+
+        if ended {
+            info!("Owner of the private session left, ending it");
+            *session = None;
+        }
+    }
+
+    async fn on_broadcast_command(&self, action: BroadcastAction) {
+        let reply = match action {
+            BroadcastAction::Add { channel } => {
+                let path = channel.join(" ");
+                match &self.teamspeak {
+                    Some(connection) => {
+                        let mut connection = connection.clone();
+                        let my_channel = connection.my_channel().await;
+                        match connection.find_channel(&path).await {
+                            Some(id) if Some(id) == my_channel => {
+                                String::from("I am already playing in that channel")
+                            }
+                            Some(id) => {
+                                let mut broadcast = self.broadcast.write().unwrap();
+                                if broadcast.iter().all(|c| c.id != id) {
+                                    info!("Broadcasting to {}", path);
+                                    broadcast.push(BroadcastChannel {
+                                        id,
+                                        path: path.clone(),
+                                    });
+                                }
+
+                                format!("Now also playing in {}", ts::bold(&path))
+                            }
+                            None => format!("Could not find channel {}", ts::bold(&path)),
+                        }
+                    }
+                    None => String::from("Broadcasting needs a TeamSpeak connection"),
+                }
+            }
+            BroadcastAction::Remove { channel } => {
+                let path = channel.join(" ");
+                let mut broadcast = self.broadcast.write().unwrap();
+                let len = broadcast.len();
+                broadcast.retain(|c| c.path != path);
+
+                if broadcast.len() < len {
+                    info!("Stopped broadcasting to {}", path);
+                    format!("Stopped playing in {}", ts::bold(&path))
+                } else {
+                    format!("I am not playing in {}", ts::bold(&path))
+                }
+            }
+            BroadcastAction::List => {
+                let channels = self.broadcast_channels();
+                if channels.is_empty() {
+                    String::from("I am only playing in this channel")
+                } else {
+                    format!("Also playing in: {}", channels.join(", "))
+                }
+            }
+            BroadcastAction::Clear => {
+                self.broadcast.write().unwrap().clear();
+                info!("Stopped broadcasting");
+
+                String::from("Stopped playing in other channels")
+            }
+        };
+
+        self.send_message(reply).await;
+    }
+
+    async fn on_playlist_command(&self, action: PlaylistAction, user: String, privileged: bool) {
+        let reply = match action {
+            PlaylistAction::Save { name } => {
+                let entries = self.queue_with_current();
+
+                self.playlists
+                    .save(&name, &entries)
+                    .map(|_| format!("Saved {} tracks as {}", entries.len(), ts::bold(&name)))
+            }
+            PlaylistAction::Load { name } => {
+                let request_id = RequestId::next();
+                info!("{} {} loads playlist {}", request_id, user, name);
+
+                match self.playlists.load(&name, request_id) {
+                    Ok(mut entries) => {
+                        let skipped = self.drop_rejected(&mut entries, privileged);
+                        let found = entries.len();
+                        let len = self.enqueue(entries, &user).await;
+
+                        let text = if len < found {
+                            format!(
+                                "Added {} tracks from {}, the queue is limited to {}",
+                                len,
+                                ts::bold(&name),
+                                self.queue_limits
+                            )
+                        } else {
+                            format!("Added {} tracks from {}", len, ts::bold(&name))
+                        };
+                        Ok(text + &skipped_text(skipped))
+                    }
+                    Err(e) => Err(e),
+                }
+            }
+            PlaylistAction::List => self.playlists.list().map(|names| {
+                if names.is_empty() {
+                    String::from("There are no saved playlists")
+                } else {
+                    format!("Saved playlists: {}", names.join(", "))
+                }
+            }),
+            PlaylistAction::Delete { name } => self
+                .playlists
+                .delete(&name)
+                .map(|_| format!("Deleted {}", ts::bold(&name))),
+        };
+
+        match reply {
+            Ok(reply) => self.send_message(reply).await,
+            Err(e) => self.send_message(e.to_string()).await,
+        }
+    }
+
     async fn update_name(&self, state: State) {
-        let volume = (self.volume() * 100.0).round();
+        let volume = if self.is_muted() {
+            String::from("muted")
+        } else {
+            format!("{}%", (self.volume() * 100.0).round())
+        };
         let name = match state {
-            State::EndOfStream => format!("🎵 {} ({}%)", self.name, volume),
-            _ => format!("🎵 {} - {} ({}%)", self.name, state, volume),
+            State::EndOfStream => format!("🎵 {} ({})", self.name, volume),
+            _ => format!("🎵 {} - {} ({})", self.name, state, volume),
         };
         self.set_nickname(name).await;
     }
@@ -396,8 +3967,18 @@ impl MusicBot {
     async fn on_state(&self, state: State) -> Result<(), AudioPlayerError> {
         let current_state = *self.state.read().unwrap();
         if current_state != state {
+            if let Some((_, listen_time)) = self.track_started.write().unwrap().as_mut() {
+                match state {
+                    State::Paused => listen_time.pause(),
+                    State::Playing => listen_time.resume(),
+                    _ => (),
+                }
+            }
+
             match state {
                 State::EndOfStream => {
+                    self.scrobble_finished().await;
+
                     let next_track = self
                         .playlist
                         .write()
@@ -408,16 +3989,27 @@ impl MusicBot {
 
                         self.start_playing_audio(request).await;
                     } else {
-                        self.update_name(state).await;
-                        self.set_description(String::new()).await;
+                        self.on_queue_end().await;
                     }
                 }
                 State::Stopped => {
+                    self.scrobble_finished().await;
+
                     if current_state != State::EndOfStream {
                         self.update_name(state).await;
                         self.set_description(String::new()).await;
                     }
                 }
+                State::Playing => {
+                    let resume_position = self.resume_position.write().unwrap().take();
+                    if let Some(position) = resume_position {
+                        if let Err(e) = self.player.seek(Seek::Absolute(position)) {
+                            self.log
+                                .error(format!("Failed to continue where the track was: {:?}", e));
+                        }
+                    }
+                    self.update_name(state).await;
+                }
                 _ => self.update_name(state).await,
             }
         }
@@ -429,6 +4021,44 @@ impl MusicBot {
         Ok(())
     }
 
+    /// Submits the track that just ended if it was listened to long enough
+    async fn scrobble_finished(&self) {
+        let (metadata, listen_time) = match self.track_started.write().unwrap().take() {
+            Some(track) => track,
+            None => return,
+        };
+        let scrobbler = match &self.scrobbler {
+            Some(scrobbler) if *self.scrobbling.read().unwrap() => scrobbler.clone(),
+            _ => return,
+        };
+        let scrobble = match Scrobble::new(&metadata, listen_time) {
+            Some(scrobble) => scrobble,
+            None => {
+                debug!("Not scrobbling {}", metadata.title);
+                return;
+            }
+        };
+
+        // Other frontends have no unique ids, they only scrobble to the bot account
+        let listeners = match &self.teamspeak {
+            Some(ts) => {
+                let mut ts = ts.clone();
+                match ts.my_channel().await {
+                    Some(channel) => ts.uids_in_channel(channel).await,
+                    None => Vec::new(),
+                }
+            }
+            None => Vec::new(),
+        };
+
+        tokio::spawn(
+            async move {
+                scrobbler.submit(scrobble, &listeners).await;
+            }
+            .in_current_span(),
+        );
+    }
+
     async fn on_message(&self, message: MusicBotMessage) -> Result<(), AudioPlayerError> {
         match message {
             MusicBotMessage::TextMessage(message) => {
@@ -436,41 +4066,720 @@ impl MusicBot {
                     self.on_text(message).await?;
                 }
             }
-            MusicBotMessage::ClientChannel {
-                client: _,
-                old_channel,
-            } => {
-                self.on_client_left_channel(old_channel).await;
+            MusicBotMessage::ClientChannel { client, .. } => {
+                if *self.following.read().unwrap() == Some(client) {
+                    self.on_followed_client_moved();
+                } else {
+                    self.update_listeners().await;
+                }
+            }
+            MusicBotMessage::ClientDisconnected { id, client } => {
+                if let Some(connection) = &self.teamspeak {
+                    connection.set_listening(id, false);
+                }
+                self.on_private_listener_left(id);
+                {
+                    let mut following = self.following.write().unwrap();
+                    if *following == Some(id) {
+                        info!("Followed client disconnected, staying");
+                        *following = None;
+                    }
+                }
+
+                if Some(client.channel) == self.my_channel().await {
+                    self.update_listeners().await;
+                }
+            }
+            MusicBotMessage::ClientAdded(_) | MusicBotMessage::ListenersChanged => {
+                self.update_listeners().await;
+            }
+            MusicBotMessage::FollowMove(moved) => {
+                if *self.follow_moved.read().unwrap() == Some(moved) {
+                    self.follow().await;
+                }
             }
-            MusicBotMessage::ClientDisconnected { id: _, client } => {
-                let old_channel = client.channel;
-                self.on_client_left_channel(old_channel).await;
+            MusicBotMessage::EmptyChannelTimeout(since) => {
+                if *self.empty_since.read().unwrap() == Some(since) {
+                    self.quit(String::from("Channel is empty"));
+                }
             }
             MusicBotMessage::ChannelAdded(id) => {
                 self.subscribe(id).await;
             }
+            MusicBotMessage::ClientTalking => {
+                self.duck();
+            }
+            MusicBotMessage::PreloadNext(started) => {
+                if *self.preload_scheduled.read().unwrap() == Some(started) {
+                    self.preload_next();
+                }
+            }
+            MusicBotMessage::TrackContinued => {
+                self.on_track_continued().await;
+            }
+            MusicBotMessage::PlaybackStuck(stuck) => {
+                self.on_playback_stuck(stuck).await?;
+            }
+            MusicBotMessage::Kicked(kick) => {
+                warn!(
+                    "{} was {} by {}",
+                    self.name,
+                    kick.kind,
+                    kick.by.as_deref().unwrap_or("the server")
+                );
+                let kind = kick.kind;
+                *self.kicked.write().unwrap() = Some(kick);
+
+                // The connection closes by itself after a server kick
+                if kind == KickKind::Channel {
+                    self.quit(String::from("Kicked from the channel"));
+                }
+            }
             MusicBotMessage::StateChange(state) => {
                 self.on_state(state).await?;
             }
+            MusicBotMessage::TalkPowerChanged => {
+                self.on_talk_power_changed().await?;
+            }
+            MusicBotMessage::UpdateProgressBar => {
+                self.update_progress_bar().await;
+            }
+            MusicBotMessage::Ping(pong) => {
+                let _ = pong.send(());
+            }
             _ => (),
         }
 
         Ok(())
     }
 
-    async fn on_client_left_channel(&self, old_channel: ChannelId) {
-        let my_channel = self.my_channel().await;
-        if old_channel == my_channel && self.user_count(my_channel).await <= 1 {
-            self.quit(String::from("Channel is empty"));
+    /// Restores the settings used the last time a bot was in this channel
+    fn apply_channel_profile(&self) {
+        let channel = self.channel.read().unwrap().clone();
+        let profile = match self.channel_settings.get(&channel) {
+            Some(profile) => profile,
+            None => return,
+        };
+        info!("Restoring settings of {}: {:?}", channel, profile);
+
+        if let Err(e) = self
+            .player
+            .change_volume(VolumeChange::Absolute(profile.volume))
+        {
+            error!("Failed to restore volume: {:?}", e);
+        }
+        *self.announcements.write().unwrap() = Announcements {
+            chat: profile.announce_in_chat,
+            description: profile.announce_in_description,
+        };
+        *self.end_of_queue.write().unwrap() = profile.end_of_queue;
+        if let Some(bitrate) = profile.bitrate {
+            if let Err(e) = self.player.set_bitrate(Some(bitrate)) {
+                error!("Failed to restore bitrate: {:?}", e);
+            }
+        }
+        self.podcasts.set(profile.podcasts);
+    }
+
+    fn save_channel_profile(&self) {
+        let announcements = *self.announcements.read().unwrap();
+        let profile = ChannelProfile {
+            volume: self.volume(),
+            announce_in_chat: announcements.chat,
+            announce_in_description: announcements.description,
+            end_of_queue: self.end_of_queue.read().unwrap().clone(),
+            bitrate: self.player.encoder_settings().bitrate,
+            podcasts: self.podcasts.get(),
+        };
+
+        let channel = self.channel.read().unwrap().clone();
+        self.channel_settings.set(&channel, profile);
+    }
+
+    async fn on_queue_end(&self) {
+        let policy = self.end_of_queue.read().unwrap().clone();
+        let request_id = RequestId::next();
+        info!("{} Queue ended, policy: {:?}", request_id, policy);
+
+        if let Some(related) = self.related_track(request_id).await {
+            self.start_playing_audio(related).await;
+            return;
+        }
+
+        let next = match policy {
+            EndOfQueue::Silent => None,
+            EndOfQueue::Replay => {
+                let history = self.history.read().unwrap();
+                history
+                    .iter()
+                    .collect::<Vec<_>>()
+                    .choose(&mut thread_rng())
+                    .map(|entry| {
+                        AudioMetadata {
+                            // The stream url has most likely expired, resolve it again
+                            url: String::new(),
+                            added_by: String::from(REPLAY_USER),
+                            request_id,
+                            ..(*entry).clone()
+                        }
+                    })
+            }
+            EndOfQueue::Fallback { url } => Some(AudioMetadata {
+                url: String::new(),
+                title: url.clone(),
+                webpage_url: url,
+                thumbnail: None,
+                duration: None,
+                artist: None,
+                track: None,
+                album: None,
+                recording: None,
+                chapters: Vec::new(),
+                added_by: String::from("Fallback"),
+                request_id,
+            }),
+            EndOfQueue::Leave { grace_period } => {
+                let idle_since = Instant::now();
+                *self.idle_since.write().unwrap() = Some(idle_since);
+
+                let cidle_since = self.idle_since.clone();
+                let player = self.player.clone();
+                tokio::spawn(
+                    async move {
+                        tokio::time::delay_for(grace_period).await;
+                        if *cidle_since.read().unwrap() == Some(idle_since) {
+                            info!("{} Nothing was added, leaving", request_id);
+                            player.quit(String::from("Queue ended"));
+                        }
+                    }
+                    .in_current_span(),
+                );
+
+                None
+            }
+        };
+
+        match next {
+            Some(next) => self.start_playing_audio(next).await,
+            None => {
+                self.update_name(State::EndOfStream).await;
+                self.set_description(String::new()).await;
+            }
+        }
+    }
+
+    /// A track related to the last one if autoplay is on and did not reach its limit
+    async fn related_track(&self, request_id: RequestId) -> Option<AudioMetadata> {
+        if !*self.autoplay.read().unwrap() {
+            return None;
+        }
+        let autoplayed = *self.autoplayed.read().unwrap();
+        if autoplayed >= self.max_autoplayed {
+            if autoplayed == self.max_autoplayed {
+                // Counted once more so this is only said once
+                *self.autoplayed.write().unwrap() += 1;
+                self.send_message(format!(
+                    "Played {} related tracks in a row, add a track to continue autoplay",
+                    self.max_autoplayed
+                ))
+                .await;
+            }
+            return None;
+        }
+
+        let last = self.history.read().unwrap().front().cloned()?;
+        let related = match self
+            .downloader
+            .related(&last, RELATED_TRACKS, request_id)
+            .await
+        {
+            Ok(related) => related,
+            Err(e) => {
+                self.log.warn(format!(
+                    "{} Failed to find tracks related to {}: {}",
+                    request_id, last.title, e
+                ));
+                return None;
+            }
+        };
+
+        // Mixes lead back to tracks that just played, they are skipped
+        let history = self.history();
+        let next = related.into_iter().find(|track| {
+            !history.iter().any(|h| h.webpage_url == track.webpage_url)
+                && self.rejection(track, false).is_none()
+        });
+        let next = match next {
+            Some(next) => next,
+            None => {
+                info!("{} No new related track for {}", request_id, last.title);
+                return None;
+            }
+        };
+        *self.autoplayed.write().unwrap() += 1;
+        info!(
+            "{} Autoplaying {} after {}",
+            request_id, next.title, last.title
+        );
+
+        Some(AudioMetadata {
+            added_by: String::from(AUTOPLAY_USER),
+            ..next
+        })
+    }
+
+    fn set_end_of_queue(&self, policy: QueueEndPolicy) -> EndOfQueue {
+        let end_of_queue = match policy {
+            QueueEndPolicy::Silent => EndOfQueue::Silent,
+            QueueEndPolicy::Replay => EndOfQueue::Replay,
+            QueueEndPolicy::Fallback { url } => EndOfQueue::Fallback { url },
+            QueueEndPolicy::Leave { grace_period } => EndOfQueue::Leave {
+                grace_period: grace_period.unwrap_or(DEFAULT_GRACE_PERIOD),
+            },
+        };
+        info!("Setting end of queue policy: {:?}", end_of_queue);
+
+        *self.end_of_queue.write().unwrap() = end_of_queue.clone();
+        *self.idle_since.write().unwrap() = None;
+
+        end_of_queue
+    }
+
+    /// Asks for talk power and tells the summoner once if the bot is not heard in its channel
+    async fn check_talk_power(&self) {
+        let mut ts = match &self.teamspeak {
+            Some(ts) => ts.clone(),
+            None => return,
+        };
+        let (needed, talk_power) = match ts.channel_tree().missing_talk_power() {
+            Some(missing) => missing,
+            None => {
+                *self.talk_power_reported.write().unwrap() = false;
+                return;
+            }
+        };
+        if std::mem::replace(&mut *self.talk_power_reported.write().unwrap(), true) {
+            return;
+        }
+
+        self.log.warn(format!(
+            "Can not be heard, the channel needs a talk power of {} and the bot has {}",
+            needed, talk_power
+        ));
+        ts.request_talk_power(String::from("Music bot")).await;
+
+        let text = tr!(
+            self.server_locale,
+            "missing-talk-power",
+            "Nobody can hear me, this channel needs a talk power of {needed} and I have {talk_power}. \
+             I asked for talk power, a channel admin has to grant it.",
+            needed = needed,
+            talk_power = talk_power
+        );
+        match self.summoner {
+            Some(summoner) => ts.send_message_to_user(summoner, text).await,
+            None => self.send_message(text).await,
+        }
+    }
+
+    /// Pauses while the bot lost the right to talk, e.g. because an admin took
+    /// its talk power, and continues once it was given back
+    async fn on_talk_power_changed(&self) -> Result<(), AudioPlayerError> {
+        let ts = match &self.teamspeak {
+            Some(ts) => ts,
+            None => return Ok(()),
+        };
+        let silenced = ts.channel_tree().missing_talk_power().is_some();
+        let paused = *self.paused_for_talk_power.read().unwrap();
+
+        if silenced && !paused && self.state() == State::Playing {
+            *self.paused_for_talk_power.write().unwrap() = true;
+            self.log
+                .warn(String::from("Pausing, the bot can not talk anymore"));
+            self.pause()?;
+            self.send_message(tr!(
+                self.server_locale,
+                "paused-for-talk-power",
+                "I was muted, pausing until I can talk again"
+            ))
+            .await;
+        } else if !silenced && paused {
+            *self.paused_for_talk_power.write().unwrap() = false;
+            // Someone may have stopped or skipped in the meantime
+            if self.state() == State::Paused {
+                info!("Continuing, the bot can talk again");
+                self.play()?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Waits a moment before following so clicking through channels doesn't drag the bot along
+    fn on_followed_client_moved(&self) {
+        let moved = Instant::now();
+        *self.follow_moved.write().unwrap() = Some(moved);
+
+        let sender = self.sender.clone();
+        tokio::spawn(
+            async move {
+                tokio::time::delay_for(FOLLOW_DELAY).await;
+                // Ignore the result because the bot might have quit in the meantime
+                let _ = sender
+                    .read()
+                    .unwrap()
+                    .send(MusicBotMessage::FollowMove(moved));
+            }
+            .in_current_span(),
+        );
+    }
+
+    async fn follow(&self) {
+        let followed = match *self.following.read().unwrap() {
+            Some(followed) => followed,
+            None => return,
+        };
+        let mut ts = self.teamspeak.as_ref().expect("follow needs ts").clone();
+
+        let my_channel = ts.my_channel().await;
+        let target = match ts.channel_of_user(followed).await {
+            Some(target) if Some(target) != my_channel => target,
+            _ => return,
+        };
+
+        // Same rules as for pokes, no bots in the master channel and only one bot per channel
+        let occupied = ts
+            .uids_in_channel(target)
+            .await
+            .iter()
+            .any(|uid| self.bot_uids.contains(uid));
+        if occupied {
+            info!("Not following into a channel with another bot");
+            self.update_listeners().await;
+            return;
+        }
+
+        let path = ts.channel_path(target).await.unwrap_or_default();
+        info!("Following into {}", path);
+        ts.move_to_channel(target).await;
+        self.span.record("channel", &path.as_str());
+        *self.channel.write().unwrap() = path;
+    }
+
+    /// Pauses while nobody but bots is in the channel and resumes once someone returns
+    async fn update_listeners(&self) {
+        let connection = match &self.connection {
+            Some(connection) => connection.clone(),
+            None => return,
+        };
+
+        let listeners = connection.listener_count(self.bot_uids.clone()).await;
+        let was_empty = self.empty_since.read().unwrap().is_some();
+
+        if listeners == 0 && !was_empty {
+            self.on_channel_empty();
+        } else if listeners != 0 && was_empty {
+            self.on_channel_occupied();
+        }
+
+        if listeners == 1 {
+            self.propose_preferred_volume().await;
+        } else {
+            *self.volume_proposed_to.write().unwrap() = None;
+        }
+    }
+
+    /// Tells the only listener about their preferred volume once, it is not applied on its own
+    async fn propose_preferred_volume(&self) {
+        // Other frontends have no unique ids to look up preferences
+        let mut ts = match &self.teamspeak {
+            Some(ts) => ts.clone(),
+            None => return,
+        };
+
+        let channel = match ts.my_channel().await {
+            Some(channel) => channel,
+            None => return,
+        };
+        let uid = match ts
+            .uids_in_channel(channel)
+            .await
+            .into_iter()
+            .find(|uid| !self.bot_uids.contains(uid))
+        {
+            Some(uid) => uid,
+            None => return,
+        };
+        if self.volume_proposed_to.read().unwrap().as_ref() == Some(&uid) {
+            return;
+        }
+        *self.volume_proposed_to.write().unwrap() = Some(uid.clone());
+
+        let volume = match self.user_settings.get(&uid).volume {
+            Some(volume) if (volume - self.volume()).abs() >= 0.005 => volume,
+            _ => return,
+        };
+        self.send_message(format!(
+            "Your preferred volume is {:.0}%, use !volume {:.0} to switch to it",
+            volume * 100.0,
+            volume * 100.0
+        ))
+        .await;
+    }
+
+    fn on_channel_empty(&self) {
+        info!(
+            "Channel is empty, leaving in {}",
+            humantime::format_duration(self.empty_channel_timeout)
+        );
+
+        if self.state() == State::Playing {
+            match self.pause() {
+                Ok(()) => *self.paused_while_empty.write().unwrap() = true,
+                Err(e) => error!("Failed to pause in empty channel: {:?}", e),
+            }
+        }
+
+        let since = Instant::now();
+        *self.empty_since.write().unwrap() = Some(since);
+
+        let sender = self.sender.clone();
+        let timeout = self.empty_channel_timeout;
+        tokio::spawn(
+            async move {
+                tokio::time::delay_for(timeout).await;
+                // Ignore the result because the bot might have quit in the meantime
+                let _ = sender
+                    .read()
+                    .unwrap()
+                    .send(MusicBotMessage::EmptyChannelTimeout(since));
+            }
+            .in_current_span(),
+        );
+    }
+
+    fn on_channel_occupied(&self) {
+        info!("Someone joined the channel, staying");
+        *self.empty_since.write().unwrap() = None;
+
+        let paused = std::mem::replace(&mut *self.paused_while_empty.write().unwrap(), false);
+        if paused {
+            if let Err(e) = self.play() {
+                error!("Failed to resume playback: {:?}", e);
+            }
+        }
+    }
+
+    async fn on_eq_command(&self, band: EqBand, gain: Option<f64>) -> Result<(), AudioPlayerError> {
+        if band == EqBand::Reset {
+            for band in band.bands() {
+                self.player.set_equalizer_band(band, 0.0)?;
+            }
+            self.send_message(String::from("Equalizer reset")).await;
+
+            return Ok(());
+        }
+
+        let bands = band.bands();
+        match gain {
+            Some(gain) => {
+                let mut set = gain;
+                for &band in &bands {
+                    set = self.player.set_equalizer_band(band, gain)?;
+                }
+                self.send_message(format!("Equalizer set to {} dB", ts::bold(&set)))
+                    .await;
+            }
+            None => {
+                let current = self.player.effects().bands;
+                let gains = bands
+                    .iter()
+                    .map(|&b| format!("{}: {} dB", b, current[b]))
+                    .collect::<Vec<_>>();
+                self.send_message(gains.join(", ")).await;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Whether the connection to the server is still up, bots that lost it
+    /// are replaced once they reconnected
+    pub fn is_connected(&self) -> bool {
+        !*self.disconnected.borrow()
+    }
+
+    /// Whether the bot is connected and its message loop is not stuck
+    pub async fn ping(&self) -> bool {
+        if !self.is_connected() {
+            return false;
         }
+        let sender = self.sender.read().unwrap().clone();
+
+        ping_message_loop(&sender).await
     }
 
     pub fn quit(&self, reason: String) {
         self.player.quit(reason);
     }
+
+    /// Resolves once the bot has disconnected from the server
+    pub async fn wait_for_disconnect(&self) {
+        let mut disconnected = self.disconnected.clone();
+        while let Some(done) = disconnected.recv().await {
+            if done {
+                return;
+            }
+        }
+    }
+}
+
+async fn prefetch(
+    next: AudioMetadata,
+    playlist: Arc<RwLock<Playlist>>,
+    status: Arc<RwLock<PrefetchStatus>>,
+    audio_cache: Option<Arc<AudioCache>>,
+    musicbrainz: Option<Arc<MetadataLookup>>,
+    downloader: Arc<Downloader>,
+    log: Arc<BotLog>,
+) {
+    let request_id = next.request_id;
+    let title = next.title.clone();
+    info!("{} Prefetching {}", request_id, next.webpage_url);
+
+    let url = next.webpage_url.clone();
+    match downloader
+        .get_audio_download_from_url(url, request_id)
+        .await
+    {
+        Ok(mut resolved) => {
+            resolved.added_by = next.added_by;
+            resolved.request_id = request_id;
+            // The resolved url may differ, so keep the one the entry is known by
+            resolved.webpage_url = next.webpage_url;
+
+            if let Some(cache) = audio_cache {
+                tokio::spawn(cache.store(resolved.clone()).in_current_span());
+            }
+            if let Some(musicbrainz) = musicbrainz {
+                tokio::spawn(musicbrainz.look_up(resolved.clone()).in_current_span());
+            }
+
+            if playlist.write().unwrap().replace_next(resolved) {
+                info!("{} Prefetched {}", request_id, title);
+                *status.write().unwrap() = PrefetchStatus::Ready { title };
+            } else {
+                debug!("{} Playlist changed while prefetching", request_id);
+                *status.write().unwrap() = PrefetchStatus::Idle;
+            }
+        }
+        Err(e) => {
+            log.warn(format!("{} Failed to prefetch: {}", request_id, e));
+            *status.write().unwrap() = PrefetchStatus::Failed { title };
+        }
+    }
+}
+
+async fn download_text(url: &str) -> Result<String, reqwest::Error> {
+    reqwest::get(url).await?.error_for_status()?.text().await
+}
+
+async fn download_thumbnail(url: &str) -> Result<Vec<u8>, reqwest::Error> {
+    let response = reqwest::get(url).await?.error_for_status()?;
+
+    Ok(response.bytes().await?.to_vec())
+}
+
+fn skipped_text(skipped: usize) -> String {
+    if skipped == 0 {
+        String::new()
+    } else {
+        format!(", skipped {} that are not allowed", ts::bold(&skipped))
+    }
+}
+
+/// Turns a 1-based queue position as shown to users into an index of the playlist
+fn queue_index(playlist: &Playlist, position: usize) -> Result<usize, String> {
+    match position.checked_sub(1) {
+        Some(index) if index < playlist.len() => Ok(index),
+        _ if playlist.is_empty() => Err(String::from("The queue is empty")),
+        _ => Err(format!(
+            "There is no position {}, the queue has {} entries",
+            position,
+            playlist.len()
+        )),
+    }
+}
+
+/// Shows a progress bar instead of the duration if `position` is set
+fn now_playing_description(metadata: &AudioMetadata, position: Option<Duration>) -> String {
+    let mut description = format!("Currently playing '{}'", metadata.title);
+
+    match (metadata.duration, position) {
+        (Some(duration), Some(position)) => {
+            description.push_str(&format!(" {}", progress_bar(position, duration)));
+        }
+        (Some(duration), None) => {
+            description.push_str(&format!(
+                " ({})",
+                crate::duration::format_timestamp(duration)
+            ));
+        }
+        (None, _) => (),
+    }
+
+    if !metadata.added_by.is_empty() {
+        description.push_str(&format!(", requested by {}", metadata.added_by));
+    }
+
+    description
+}
+
+/// Like `▰▰▰▰▱▱▱▱▱▱ 2:31/4:05`
+fn progress_bar(position: Duration, duration: Duration) -> String {
+    let position = position.min(duration);
+    let filled = if duration.as_secs() > 0 {
+        (position.as_secs_f64() / duration.as_secs_f64() * PROGRESS_BAR_LENGTH as f64).round()
+            as usize
+    } else {
+        0
+    };
+
+    format!(
+        "{}{} {}/{}",
+        "▰".repeat(filled),
+        "▱".repeat(PROGRESS_BAR_LENGTH - filled),
+        crate::duration::format_timestamp(position),
+        crate::duration::format_timestamp(duration)
+    )
+}
+
+/// The page of `bot` on the web server, the name is escaped since it may contain any character
+fn control_link(web_url: &str, bot: &str, token: &str) -> Option<String> {
+    let mut url = reqwest::Url::parse(web_url).ok()?;
+    url.path_segments_mut()
+        .ok()?
+        .pop_if_empty()
+        .extend(&["bot", bot]);
+    url.query_pairs_mut().append_pair("token", token);
+
+    Some(url.to_string())
+}
+
+/// Waits for a message loop to answer a ping, `false` if it does not in time
+pub async fn ping_message_loop(sender: &MessageSender) -> bool {
+    let (pong, answered) = oneshot::channel();
+    if sender.send(MusicBotMessage::Ping(pong)).is_err() {
+        return false;
+    }
+
+    matches!(
+        tokio::time::timeout(PING_TIMEOUT, answered).await,
+        Ok(Ok(()))
+    )
 }
 
-fn spawn_stdin_reader(tx: Arc<RwLock<UnboundedSender<MusicBotMessage>>>) {
+fn spawn_stdin_reader(tx: Arc<RwLock<MessageSender>>) {
     debug!("Spawning stdin reader thread");
     thread::Builder::new()
         .name(String::from("stdin reader"))
@@ -497,21 +4806,138 @@ fn spawn_stdin_reader(tx: Arc<RwLock<UnboundedSender<MusicBotMessage>>>) {
         .expect("Failed to spawn stdin reader thread");
 }
 
-fn spawn_gstreamer_thread(
+/// Whispers go to the clients of a private session instead of the broadcast channels
+fn whisper_targets(
+    broadcast: &RwLock<Vec<BroadcastChannel>>,
+    private_session: &RwLock<Option<PrivateSession>>,
+) -> Whisper {
+    if let Some(session) = &*private_session.read().unwrap() {
+        return Whisper {
+            channels: Vec::new(),
+            clients: session.listeners.iter().map(|l| l.id).collect(),
+        };
+    }
+
+    Whisper {
+        channels: broadcast.read().unwrap().iter().map(|c| c.id).collect(),
+        clients: Vec::new(),
+    }
+}
+
+/// Watches the audio sent by the player and handles the time in between
+/// as configured, which covers pauses, the end of the queue and track changes.
+fn spawn_idle_audio_task(
+    connection: Arc<dyn Frontend>,
+    broadcast: Arc<RwLock<Vec<BroadcastChannel>>>,
+    private_session: Arc<RwLock<Option<PrivateSession>>>,
+    last_packet: Arc<RwLock<Option<Instant>>>,
+    idle_audio: IdleAudio,
+    disconnected: watch::Receiver<bool>,
+) {
+    tokio::spawn(
+        async move {
+            let mut interval = tokio::time::interval(VOICE_PACKET_INTERVAL);
+            while !*disconnected.borrow() {
+                interval.tick().await;
+
+                let idle = match *last_packet.read().unwrap() {
+                    Some(last) => last.elapsed() >= IDLE_AUDIO_DELAY,
+                    None => true,
+                };
+                if !idle {
+                    continue;
+                }
+
+                let whisper = whisper_targets(&broadcast, &private_session);
+                match idle_audio {
+                    IdleAudio::Nothing => {
+                        if last_packet.write().unwrap().take().is_some() {
+                            debug!("Player stopped sending audio, ending transmission");
+                            connection.send_audio_end(&whisper).await;
+                        }
+                    }
+                    IdleAudio::ComfortNoise => connection.send_comfort_noise(&whisper).await,
+                }
+            }
+        }
+        .in_current_span(),
+    );
+}
+
+/// Reports the current track to the bot once it stopped moving or went silent
+fn spawn_watchdog_task(
     player: Arc<AudioPlayer>,
-    tx: Arc<RwLock<UnboundedSender<MusicBotMessage>>>,
+    tx: Arc<RwLock<MessageSender>>,
+    config: WatchdogConfig,
+    disconnected: watch::Receiver<bool>,
+) {
+    if !config.is_enabled() {
+        return;
+    }
+
+    tokio::spawn(
+        async move {
+            let mut interval = tokio::time::interval(WATCHDOG_INTERVAL);
+            while !*disconnected.borrow() {
+                interval.tick().await;
+
+                if let Some(stuck) = player.check_progress(&config) {
+                    if tx
+                        .read()
+                        .unwrap()
+                        .send(MusicBotMessage::PlaybackStuck(stuck))
+                        .is_err()
+                    {
+                        break;
+                    }
+                }
+            }
+        }
+        .in_current_span(),
+    );
+}
+
+/// Asks the bot to update its progress bar every `interval`
+fn spawn_progress_bar_task(
+    tx: Arc<RwLock<MessageSender>>,
+    interval: Duration,
+    disconnected: watch::Receiver<bool>,
 ) {
+    tokio::spawn(
+        async move {
+            let mut interval = tokio::time::interval(interval.max(MIN_PROGRESS_BAR_INTERVAL));
+            while !*disconnected.borrow() {
+                interval.tick().await;
+
+                if tx
+                    .read()
+                    .unwrap()
+                    .send(MusicBotMessage::UpdateProgressBar)
+                    .is_err()
+                {
+                    break;
+                }
+            }
+        }
+        .in_current_span(),
+    );
+}
+
+fn spawn_gstreamer_thread(player: Arc<AudioPlayer>, tx: Arc<RwLock<MessageSender>>) {
+    let span = Span::current();
     thread::Builder::new()
         .name(String::from("gstreamer polling"))
-        .spawn(move || loop {
-            if player.poll() == PollResult::Quit {
-                break;
-            }
+        .spawn(move || {
+            let _span = span.enter();
+            loop {
+                let message = match player.poll() {
+                    PollResult::Quit => break,
+                    PollResult::NextTrack => MusicBotMessage::TrackContinued,
+                    PollResult::Continue => MusicBotMessage::StateChange(State::EndOfStream),
+                };
 
-            tx.read()
-                .unwrap()
-                .send(MusicBotMessage::StateChange(State::EndOfStream))
-                .unwrap();
+                tx.read().unwrap().send(message).unwrap();
+            }
         })
         .expect("Failed to spawn gstreamer thread");
 }
@@ -0,0 +1,323 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::future::join_all;
+use rand::{rngs::SmallRng, seq::SliceRandom, SeedableRng};
+use tokio::sync::{mpsc, oneshot, watch};
+use tracing::{info, warn};
+use tsclientlib::ChannelId;
+
+use crate::bot::{BotCreationError, MusicBot};
+
+/// How long a music bot may take to disconnect when everything is shutting down
+const BOT_QUIT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The name and identity a bot holds until it is despawned
+#[derive(Debug, Clone)]
+pub struct Slot {
+    pub name: String,
+    pub name_index: usize,
+    pub id_index: usize,
+}
+
+/// The bots at the time the registry last changed
+#[derive(Clone)]
+pub struct Registered {
+    pub bots: HashMap<String, Arc<MusicBot>>,
+    /// Names that are not taken, sorted
+    pub available_names: Vec<String>,
+    pub available_identities: usize,
+}
+
+enum RegistryMessage {
+    /// Takes a free slot, or `slot` again for a bot that is reconnecting
+    Spawn {
+        channel: ChannelId,
+        slot: Option<Slot>,
+        /// Allows another bot to connect to the channel at the same time
+        shared: bool,
+        /// Also returns the name of a bot that is still connecting to the channel
+        reply: oneshot::Sender<Result<(Slot, Option<String>), BotCreationError>>,
+    },
+    /// The bot of a slot connected, `None` if it failed to
+    Connected {
+        name: String,
+        bot: Option<Arc<MusicBot>>,
+    },
+    /// The bot is gone but keeps its slot to reconnect
+    Disconnected { name: String },
+    /// Frees the slot of a bot that is not coming back
+    Despawn(Slot),
+    List {
+        reply: oneshot::Sender<Vec<Arc<MusicBot>>>,
+    },
+}
+
+/// Keeps track of the names, identities and connected music bots.
+///
+/// The state is owned by a task that handles one message at a time, the
+/// handles only send messages to it. Changes are applied in the order they
+/// were sent, so a `list` sees everything sent before it.
+#[derive(Clone)]
+pub struct BotRegistry {
+    sender: mpsc::UnboundedSender<RegistryMessage>,
+    registered: watch::Receiver<Registered>,
+}
+
+impl BotRegistry {
+    /// Starts the task of the registry, which ends once all handles are dropped
+    pub fn start(names: Vec<String>, identities: usize, max_bots: Option<usize>) -> Self {
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        let mut state = RegistryState {
+            rng: SmallRng::from_entropy(),
+            available_names: (0..names.len()).collect(),
+            available_ids: (0..identities).collect(),
+            names,
+            max_bots,
+            connected_bots: HashMap::new(),
+            connecting: HashMap::new(),
+        };
+        let (publish, registered) = watch::channel(state.registered());
+
+        tokio::spawn(async move {
+            while let Some(message) = receiver.recv().await {
+                if state.handle(message) && publish.broadcast(state.registered()).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Self { sender, registered }
+    }
+
+    /// Reserves a name and an identity for a new bot in `channel`
+    pub async fn spawn(&self, channel: ChannelId) -> Result<Slot, BotCreationError> {
+        self.request(|reply| RegistryMessage::Spawn {
+            channel,
+            slot: None,
+            shared: false,
+            reply,
+        })
+        .await
+        .map(|(slot, _)| slot)
+    }
+
+    /// Reserves a slot for a bot that may join a channel with other bots,
+    /// also returns the name of a bot that is still connecting to it
+    pub async fn spawn_shared(
+        &self,
+        channel: ChannelId,
+    ) -> Result<(Slot, Option<String>), BotCreationError> {
+        self.request(|reply| RegistryMessage::Spawn {
+            channel,
+            slot: None,
+            shared: true,
+            reply,
+        })
+        .await
+    }
+
+    /// Marks a bot that keeps its slot as connecting to `channel` again
+    pub async fn respawn(&self, slot: Slot, channel: ChannelId) -> Result<(), BotCreationError> {
+        self.request(|reply| RegistryMessage::Spawn {
+            channel,
+            slot: Some(slot),
+            shared: false,
+            reply,
+        })
+        .await
+        .map(|_| ())
+    }
+
+    /// Ends the connecting of a spawned bot, the bot is `None` if it failed
+    pub fn connected(&self, name: String, bot: Option<Arc<MusicBot>>) {
+        let _ = self.sender.send(RegistryMessage::Connected { name, bot });
+    }
+
+    pub fn disconnected(&self, name: String) {
+        let _ = self.sender.send(RegistryMessage::Disconnected { name });
+    }
+
+    pub fn despawn(&self, slot: Slot) {
+        let _ = self.sender.send(RegistryMessage::Despawn(slot));
+    }
+
+    pub async fn list(&self) -> Vec<Arc<MusicBot>> {
+        self.request(|reply| RegistryMessage::List { reply }).await
+    }
+
+    /// Disconnects all music bots at once.
+    ///
+    /// Bots that take longer than `BOT_QUIT_TIMEOUT` are dropped so the
+    /// master does not wait forever.
+    pub async fn quit_all(&self, reason: String) {
+        let bots = self.list().await;
+
+        info!("Disconnecting {} music bots", bots.len());
+        join_all(bots.into_iter().map(|bot| {
+            let reason = reason.clone();
+            async move {
+                bot.quit(reason);
+
+                let disconnect = bot.wait_for_disconnect();
+                if tokio::time::timeout(BOT_QUIT_TIMEOUT, disconnect)
+                    .await
+                    .is_err()
+                {
+                    warn!("{} did not disconnect in time, dropping it", bot.name());
+                    self.disconnected(bot.name().to_owned());
+                }
+            }
+        }))
+        .await;
+    }
+
+    /// The last published state, for callers that can not wait for the registry.
+    ///
+    /// Messages that are still queued are not applied yet.
+    pub fn registered(&self) -> Registered {
+        self.registered.borrow().clone()
+    }
+
+    async fn request<T>(&self, message: impl FnOnce(oneshot::Sender<T>) -> RegistryMessage) -> T {
+        let (reply, response) = oneshot::channel();
+        // The task only ends once this handle is gone
+        let _ = self.sender.send(message(reply));
+
+        response.await.expect("The registry is running")
+    }
+}
+
+struct RegistryState {
+    rng: SmallRng,
+    names: Vec<String>,
+    max_bots: Option<usize>,
+    available_names: Vec<usize>,
+    available_ids: Vec<usize>,
+    connected_bots: HashMap<String, Arc<MusicBot>>,
+    /// Channels of the bots that are still connecting by name
+    connecting: HashMap<String, ChannelId>,
+}
+
+impl RegistryState {
+    /// Returns if the bots, names or identities changed
+    fn handle(&mut self, message: RegistryMessage) -> bool {
+        match message {
+            RegistryMessage::Spawn {
+                channel,
+                slot,
+                shared,
+                reply,
+            } => {
+                let result = self.spawn(channel, slot, shared);
+                let changed = result.is_ok();
+                let _ = reply.send(result);
+
+                changed
+            }
+            RegistryMessage::Connected { name, bot } => {
+                self.connecting.remove(&name);
+                if let Some(bot) = bot {
+                    self.connected_bots.insert(name, bot);
+                }
+
+                true
+            }
+            RegistryMessage::Disconnected { name } => {
+                self.connected_bots.remove(&name);
+
+                true
+            }
+            RegistryMessage::Despawn(slot) => {
+                self.connected_bots.remove(&slot.name);
+                self.connecting.remove(&slot.name);
+                self.available_names.push(slot.name_index);
+                self.available_ids.push(slot.id_index);
+
+                true
+            }
+            RegistryMessage::List { reply } => {
+                let _ = reply.send(self.connected_bots.values().cloned().collect());
+
+                false
+            }
+        }
+    }
+
+    fn spawn(
+        &mut self,
+        channel: ChannelId,
+        slot: Option<Slot>,
+        shared: bool,
+    ) -> Result<(Slot, Option<String>), BotCreationError> {
+        let connecting = self
+            .connecting
+            .iter()
+            .find(|(_, c)| **c == channel)
+            .map(|(name, _)| name.clone());
+        if let (Some(name), false) = (&connecting, shared) {
+            return Err(BotCreationError::StillConnecting(name.clone()));
+        }
+
+        let slot = match slot {
+            Some(slot) => {
+                // The slot stays taken while its bot is gone, only the others count
+                self.check_max_bots(self.slots_in_use().saturating_sub(1))?;
+                slot
+            }
+            None => self.take_slot()?,
+        };
+        self.connecting.insert(slot.name.clone(), channel);
+
+        Ok((slot, connecting))
+    }
+
+    /// Names that are taken, including those of reconnecting bots
+    fn slots_in_use(&self) -> usize {
+        self.names.len() - self.available_names.len()
+    }
+
+    fn check_max_bots(&self, in_use: usize) -> Result<(), BotCreationError> {
+        match self.max_bots {
+            Some(max_bots) if in_use >= max_bots => Err(BotCreationError::TooManyBots(max_bots)),
+            _ => Ok(()),
+        }
+    }
+
+    fn take_slot(&mut self) -> Result<Slot, BotCreationError> {
+        self.check_max_bots(self.slots_in_use())?;
+        if self.available_names.is_empty() {
+            return Err(BotCreationError::OutOfNames);
+        }
+        if self.available_ids.is_empty() {
+            return Err(BotCreationError::OutOfIdentities);
+        }
+
+        self.available_names.shuffle(&mut self.rng);
+        self.available_ids.shuffle(&mut self.rng);
+        let name_index = self.available_names.pop().unwrap();
+        let id_index = self.available_ids.pop().unwrap();
+
+        Ok(Slot {
+            name: self.names[name_index].clone(),
+            name_index,
+            id_index,
+        })
+    }
+
+    fn registered(&self) -> Registered {
+        let mut available_names = self
+            .available_names
+            .iter()
+            .map(|&i| self.names[i].clone())
+            .collect::<Vec<_>>();
+        available_names.sort();
+
+        Registered {
+            bots: self.connected_bots.clone(),
+            available_names,
+            available_identities: self.available_ids.len(),
+        }
+    }
+}
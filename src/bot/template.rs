@@ -0,0 +1,186 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use tsclientlib::ClientId;
+
+use crate::audio_cache::{self, AudioCache};
+use crate::audio_output::AudioOutputConfig;
+use crate::audio_player::{EncoderSettings, WatchdogConfig};
+use crate::blacklist::BlacklistStore;
+use crate::bot::{
+    AutoplayConfig, EndOfQueue, MasterArgs, MusicBotArgs, NoDuplicates, ProgressBarConfig,
+    QueueLimits, Slot,
+};
+use crate::card::{self, CardRenderer};
+use crate::channel_settings::ChannelSettingsStore;
+use crate::command::CommandConfig;
+use crate::file_playback::FilePlaybackConfig;
+use crate::frontend::{Frontend, IdleAudio};
+use crate::i18n::{self, Locales};
+use crate::lyrics::{self, LyricsClient};
+use crate::musicbrainz::{self, MetadataLookup};
+use crate::rate_limit::RateLimiter;
+use crate::reports::ReportStore;
+use crate::saved_playlists::PlaylistStore;
+use crate::scheduler::ScheduleStore;
+use crate::scrobble::{self, Scrobbler};
+use crate::sources::SourceRules;
+use crate::stats::{self, StatsStore};
+use crate::teamspeak::ClientVersion;
+use crate::user_settings::UserSettingsStore;
+use crate::youtube_dl::{self, Downloader};
+
+/// What the Discord and Mumble masters start their music bots with, the
+/// settings and stores that are the same for every bot.
+pub struct BotTemplate {
+    pub master_name: String,
+    pub empty_channel_timeout: Duration,
+    pub verbose: u8,
+    pub commands: CommandConfig,
+    pub max_playlist_tracks: usize,
+    pub queue_limits: QueueLimits,
+    pub sources: SourceRules,
+    pub blacklist: Arc<BlacklistStore>,
+    pub no_duplicates: Option<NoDuplicates>,
+    pub file_playback: Option<FilePlaybackConfig>,
+    pub encoder: EncoderSettings,
+    pub watchdog: WatchdogConfig,
+    pub audio_output: AudioOutputConfig,
+    pub admins: Vec<String>,
+    pub client_version: ClientVersion,
+    pub idle_audio: IdleAudio,
+    pub end_of_queue: EndOfQueue,
+    pub autoplay: AutoplayConfig,
+    pub progress_bar: ProgressBarConfig,
+    pub channel_settings: Arc<ChannelSettingsStore>,
+    pub user_settings: Arc<UserSettingsStore>,
+    pub reports: Arc<ReportStore>,
+    pub schedule: Arc<ScheduleStore>,
+    pub rate_limiter: Arc<RateLimiter>,
+    pub playlists: Arc<PlaylistStore>,
+    pub audio_cache: Option<Arc<AudioCache>>,
+    pub stats: Option<Arc<StatsStore>>,
+    pub cards: Option<Arc<CardRenderer>>,
+    pub scrobbler: Option<Arc<Scrobbler>>,
+    pub lyrics: Option<Arc<LyricsClient>>,
+    pub musicbrainz: Option<Arc<MetadataLookup>>,
+    pub downloader: Arc<Downloader>,
+    pub locales: Arc<Locales>,
+}
+
+impl BotTemplate {
+    /// Opens the stores, which keep the data of `server` apart from other servers
+    pub async fn open(args: MasterArgs, server: &str) -> Self {
+        let downloader = youtube_dl::open(args.downloader).await;
+
+        Self {
+            master_name: args.master_name,
+            empty_channel_timeout: args.empty_channel_timeout,
+            verbose: args.verbose,
+            commands: args.commands,
+            max_playlist_tracks: args.max_playlist_tracks,
+            queue_limits: args.queue_limits,
+            sources: args.sources,
+            blacklist: Arc::new(BlacklistStore::new(args.blacklist, server)),
+            no_duplicates: args.no_duplicates,
+            file_playback: args.file_playback,
+            encoder: args.encoder,
+            watchdog: args.watchdog,
+            audio_output: args.audio_output,
+            admins: args.admins,
+            client_version: args.client_version,
+            idle_audio: args.idle_audio,
+            end_of_queue: args.end_of_queue,
+            autoplay: args.autoplay,
+            progress_bar: args.progress_bar,
+            channel_settings: Arc::new(ChannelSettingsStore::new(args.channel_settings, server)),
+            user_settings: Arc::new(UserSettingsStore::new(args.user_settings)),
+            reports: Arc::new(ReportStore::new(args.reports)),
+            schedule: Arc::new(ScheduleStore::new(args.schedule)),
+            rate_limiter: Arc::new(RateLimiter::new(args.rate_limit)),
+            playlists: Arc::new(PlaylistStore::new(args.playlist_dir, server)),
+            audio_cache: audio_cache::open(
+                args.audio_cache_dir,
+                args.audio_cache_max_mb,
+                downloader.clone(),
+            ),
+            stats: stats::open(args.stats_db, server),
+            cards: card::open(args.card_font),
+            scrobbler: scrobble::open(args.scrobble),
+            lyrics: lyrics::open(args.lyrics),
+            musicbrainz: musicbrainz::open(args.musicbrainz),
+            downloader,
+            locales: i18n::open(args.i18n, &args.templates.messages),
+        }
+    }
+
+    /// The arguments of a bot in `slot` that plays to `channel` through `frontend`.
+    ///
+    /// Everything only TeamSpeak has is left out, the rest can be changed with
+    /// struct update syntax.
+    pub fn bot_args(
+        &self,
+        slot: &Slot,
+        address: String,
+        frontend: Arc<dyn Frontend>,
+        channel: String,
+        summoner: Option<ClientId>,
+        disconnect_cb: Box<dyn FnMut(String, usize, usize) + Send + Sync>,
+    ) -> MusicBotArgs {
+        MusicBotArgs {
+            name: slot.name.clone(),
+            name_index: slot.name_index,
+            id_index: slot.id_index,
+            local: false,
+            address,
+            id: None,
+            identities: None,
+            server_password: None,
+            privilege_key: None,
+            frontend: Some(frontend),
+            channel,
+            channel_password: None,
+            summoner,
+            private_session: None,
+            bot_uids: Arc::new(Vec::new()),
+            empty_channel_timeout: self.empty_channel_timeout,
+            verbose: self.verbose,
+            commands: self.commands.clone(),
+            thumbnail_avatars: false,
+            max_playlist_tracks: self.max_playlist_tracks,
+            queue_limits: self.queue_limits,
+            sources: self.sources.clone(),
+            blacklist: self.blacklist.clone(),
+            no_duplicates: self.no_duplicates,
+            file_playback: self.file_playback.clone(),
+            ducking: None,
+            encoder: self.encoder,
+            watchdog: self.watchdog,
+            audio_output: self.audio_output.clone(),
+            admins: self.admins.clone(),
+            badges: Vec::new(),
+            client_version: self.client_version,
+            idle_audio: self.idle_audio,
+            end_of_queue: self.end_of_queue.clone(),
+            autoplay: self.autoplay,
+            progress_bar: self.progress_bar,
+            channel_settings: self.channel_settings.clone(),
+            user_settings: self.user_settings.clone(),
+            reports: self.reports.clone(),
+            rate_limiter: self.rate_limiter.clone(),
+            web_url: None,
+            playlists: self.playlists.clone(),
+            audio_cache: self.audio_cache.clone(),
+            stats: self.stats.clone(),
+            cards: self.cards.clone(),
+            scrobbler: self.scrobbler.clone(),
+            lyrics: self.lyrics.clone(),
+            musicbrainz: self.musicbrainz.clone(),
+            downloader: self.downloader.clone(),
+            locales: self.locales.clone(),
+            resume: None,
+            disconnect_cb,
+            reconnect_cb: None,
+        }
+    }
+}
@@ -0,0 +1,95 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+use tracing::{error, warn};
+
+/// How many entries a bot keeps, older ones are dropped
+const CAPACITY: usize = 200;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Warn,
+    Error,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LogEntry {
+    /// Unix time of the entry
+    pub time: u64,
+    pub level: LogLevel,
+    pub message: String,
+}
+
+impl std::fmt::Display for LogEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let time = UNIX_EPOCH + std::time::Duration::from_secs(self.time);
+        let level = match self.level {
+            LogLevel::Warn => "warn",
+            LogLevel::Error => "error",
+        };
+
+        write!(
+            f,
+            "{} [{}] {}",
+            humantime::format_rfc3339_seconds(time),
+            level,
+            self.message
+        )
+    }
+}
+
+/// Recent warnings and errors of a single bot.
+///
+/// Everything is logged as usual as well, the copy lets admins see what went
+/// wrong with a bot without access to the logs of the server.
+pub struct BotLog {
+    name: String,
+    entries: Mutex<VecDeque<LogEntry>>,
+}
+
+impl BotLog {
+    pub fn new(name: String) -> Self {
+        Self {
+            name,
+            entries: Mutex::new(VecDeque::with_capacity(CAPACITY)),
+        }
+    }
+
+    pub fn warn(&self, message: String) {
+        warn!("{}: {}", self.name, message);
+        self.push(LogLevel::Warn, message);
+    }
+
+    pub fn error(&self, message: String) {
+        error!("{}: {}", self.name, message);
+        self.push(LogLevel::Error, message);
+    }
+
+    fn push(&self, level: LogLevel, message: String) {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() == CAPACITY {
+            entries.pop_front();
+        }
+        entries.push_back(LogEntry {
+            time,
+            level,
+            message,
+        });
+    }
+
+    /// The newest `count` entries, oldest first
+    pub fn last(&self, count: usize) -> Vec<LogEntry> {
+        let entries = self.entries.lock().unwrap();
+        let skip = entries.len().saturating_sub(count);
+
+        entries.iter().skip(skip).cloned().collect()
+    }
+}
@@ -0,0 +1,173 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc::UnboundedReceiver;
+use tracing::{debug, error, info};
+
+use crate::bot::BotManager;
+use crate::bridge::{handle_message, BridgeMessage};
+
+/// How long to wait before connecting again after the connection was lost
+const RECONNECT_DELAY: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IrcConfig {
+    pub server: String,
+    #[serde(default = "default_port")]
+    pub port: u16,
+    #[serde(default = "default_tls")]
+    pub tls: bool,
+    #[serde(default = "default_nick")]
+    pub nick: String,
+    pub channel: String,
+}
+
+fn default_port() -> u16 {
+    6697
+}
+
+fn default_tls() -> bool {
+    true
+}
+
+fn default_nick() -> String {
+    String::from("pokebot")
+}
+
+trait IrcStream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> IrcStream for T {}
+
+pub async fn run(
+    config: IrcConfig,
+    bots: Arc<dyn BotManager>,
+    mut now_playing: UnboundedReceiver<String>,
+) {
+    loop {
+        match session(&config, &*bots, &mut now_playing).await {
+            Ok(()) => info!("IRC connection closed"),
+            Err(e) => error!("IRC connection failed: {}", e),
+        }
+
+        tokio::time::delay_for(RECONNECT_DELAY).await;
+    }
+}
+
+async fn connect(config: &IrcConfig) -> std::io::Result<Box<dyn IrcStream>> {
+    let stream = TcpStream::connect((config.server.as_str(), config.port)).await?;
+    if !config.tls {
+        return Ok(Box::new(stream));
+    }
+
+    let to_io = |e| std::io::Error::new(std::io::ErrorKind::Other, e);
+    let connector = native_tls::TlsConnector::new().map_err(to_io)?;
+    let stream = tokio_tls::TlsConnector::from(connector)
+        .connect(&config.server, stream)
+        .await
+        .map_err(to_io)?;
+
+    Ok(Box::new(stream))
+}
+
+/// Stays in the channel until the connection breaks
+async fn session(
+    config: &IrcConfig,
+    bots: &dyn BotManager,
+    now_playing: &mut UnboundedReceiver<String>,
+) -> std::io::Result<()> {
+    let stream = connect(config).await?;
+    let (reader, mut writer) = tokio::io::split(stream);
+    let mut lines = BufReader::new(reader).lines();
+
+    let register = format!(
+        "NICK {}\r\nUSER {} 0 * :PokeBot bridge\r\n",
+        config.nick, config.nick
+    );
+    writer.write_all(register.as_bytes()).await?;
+
+    loop {
+        let line = tokio::select! {
+            line = lines.next_line() => match line? {
+                Some(line) => line,
+                None => return Ok(()),
+            },
+            Some(text) = now_playing.recv() => {
+                send_privmsg(&mut writer, &config.channel, &text).await?;
+                continue;
+            }
+        };
+        debug!("IRC: {}", line);
+
+        let (prefix, command, params) = parse_line(&line);
+        match command {
+            "PING" => {
+                writer
+                    .write_all(format!("PONG :{}\r\n", params.join(" ")).as_bytes())
+                    .await?;
+            }
+            // Registration is done, the channel can be joined now
+            "001" => {
+                writer
+                    .write_all(format!("JOIN {}\r\n", config.channel).as_bytes())
+                    .await?;
+            }
+            "PRIVMSG" if params.len() == 2 && params[0].eq_ignore_ascii_case(&config.channel) => {
+                let nick = prefix.split('!').next().unwrap_or_default();
+                let message = BridgeMessage {
+                    sender: format!("{} (IRC)", nick),
+                    // Nicknames are not verified, so nobody on IRC is an admin
+                    uid: None,
+                    text: params[1].to_owned(),
+                };
+
+                if let Some(reply) = handle_message(bots, message) {
+                    send_privmsg(&mut writer, &config.channel, &reply).await?;
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+async fn send_privmsg(
+    writer: &mut (impl AsyncWrite + Unpin),
+    channel: &str,
+    text: &str,
+) -> std::io::Result<()> {
+    // Messages can't span lines, a lone `\r` would end the command early and
+    // let the rest of a title be sent as a command of its own
+    let lines = text.split(|c| c == '\r' || c == '\n' || c == '\0');
+    for line in lines.filter(|l| !l.trim().is_empty()) {
+        writer
+            .write_all(format!("PRIVMSG {} :{}\r\n", channel, line).as_bytes())
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// Splits a line into its prefix, command and parameters, the trailing
+/// parameter after ` :` may contain spaces.
+fn parse_line(line: &str) -> (&str, &str, Vec<&str>) {
+    let mut rest = line;
+    let mut prefix = "";
+    if rest.starts_with(':') {
+        let end = rest.find(' ').unwrap_or_else(|| rest.len());
+        prefix = &rest[1..end];
+        rest = rest[end..].trim_start();
+    }
+
+    let (middle, trailing) = match rest.find(" :") {
+        Some(i) => (&rest[..i], Some(&rest[i + 2..])),
+        None => (rest, None),
+    };
+    let mut words = middle.split(' ').filter(|w| !w.is_empty());
+    let command = words.next().unwrap_or_default();
+    let mut params = words.collect::<Vec<_>>();
+    params.extend(trailing);
+
+    (prefix, command, params)
+}
@@ -0,0 +1,220 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use reqwest::{Client, Url};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::sync::mpsc::UnboundedReceiver;
+use tracing::{error, info};
+
+use crate::bot::BotManager;
+use crate::bridge::{handle_message, BridgeMessage};
+
+/// How long the homeserver may hold a sync request open
+const SYNC_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How long to wait before syncing again after a failed request
+const RETRY_DELAY: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MatrixConfig {
+    /// Base url of the homeserver, e.g. "https://matrix.org"
+    pub homeserver: String,
+    pub access_token: String,
+    /// Id of the room, the account has to be joined already
+    pub room: String,
+}
+
+struct MatrixClient {
+    http: Client,
+    config: MatrixConfig,
+    user_id: String,
+    transaction: AtomicU64,
+}
+
+pub async fn run(
+    config: MatrixConfig,
+    bots: Arc<dyn BotManager>,
+    mut now_playing: UnboundedReceiver<String>,
+) {
+    let client = loop {
+        match MatrixClient::login(config.clone()).await {
+            Ok(client) => break client,
+            Err(e) => error!("Failed to connect to Matrix: {}", e),
+        }
+        tokio::time::delay_for(RETRY_DELAY).await;
+    };
+    info!("Connected to Matrix as {}", client.user_id);
+
+    // The first sync only skips the history of the room
+    let mut since = None;
+    while since.is_none() {
+        match client.sync(None).await {
+            Ok((next, _)) => since = Some(next),
+            Err(e) => {
+                error!("Failed to sync with Matrix: {}", e);
+                tokio::time::delay_for(RETRY_DELAY).await;
+            }
+        }
+    }
+
+    loop {
+        let messages = tokio::select! {
+            result = client.sync(since.clone()) => match result {
+                Ok((next, messages)) => {
+                    since = Some(next);
+                    messages
+                }
+                Err(e) => {
+                    error!("Failed to sync with Matrix: {}", e);
+                    tokio::time::delay_for(RETRY_DELAY).await;
+                    continue;
+                }
+            },
+            Some(text) = now_playing.recv() => {
+                client.send(&text).await;
+                continue;
+            }
+        };
+
+        for message in messages {
+            if let Some(reply) = handle_message(&*bots, message) {
+                client.send(&reply).await;
+            }
+        }
+    }
+}
+
+impl MatrixClient {
+    async fn login(config: MatrixConfig) -> Result<Self, String> {
+        let http = Client::new();
+        let whoami = get_json(
+            http.get(&api_url(&config, &["account", "whoami"])?)
+                .bearer_auth(&config.access_token),
+        )
+        .await?;
+        let user_id = whoami["user_id"]
+            .as_str()
+            .ok_or_else(|| String::from("The homeserver did not tell who we are"))?
+            .to_owned();
+
+        Ok(Self {
+            http,
+            config,
+            user_id,
+            transaction: AtomicU64::new(0),
+        })
+    }
+
+    /// Waits for new events, returns the token for the next sync and the new messages
+    async fn sync(&self, since: Option<String>) -> Result<(String, Vec<BridgeMessage>), String> {
+        let filter = json!({
+            "room": {
+                "rooms": [self.config.room],
+                "timeline": { "types": ["m.room.message"] }
+            }
+        });
+        let mut url = Url::parse(&api_url(&self.config, &["sync"])?).map_err(|e| e.to_string())?;
+        url.query_pairs_mut()
+            .append_pair("timeout", &SYNC_TIMEOUT.as_millis().to_string())
+            .append_pair("filter", &filter.to_string());
+        if let Some(since) = since {
+            url.query_pairs_mut().append_pair("since", &since);
+        }
+
+        let response = get_json(
+            self.http
+                .get(url)
+                .bearer_auth(&self.config.access_token)
+                .timeout(SYNC_TIMEOUT * 2),
+        )
+        .await?;
+        let next = response["next_batch"]
+            .as_str()
+            .ok_or_else(|| String::from("Sync response has no next_batch"))?
+            .to_owned();
+
+        let events = response["rooms"]["join"][&self.config.room]["timeline"]["events"]
+            .as_array()
+            .cloned()
+            .unwrap_or_default();
+        let messages = events
+            .iter()
+            .filter(|e| e["sender"].as_str() != Some(self.user_id.as_str()))
+            .filter_map(|e| {
+                let sender = e["sender"].as_str()?;
+                let text = e["content"]["body"].as_str()?;
+
+                Some(BridgeMessage {
+                    sender: format!("{} (Matrix)", sender),
+                    // The homeserver verified the sender so it can be listed as admin
+                    uid: Some(sender.to_owned()),
+                    text: text.to_owned(),
+                })
+            })
+            .collect();
+
+        Ok((next, messages))
+    }
+
+    /// Sends a notice, bots don't react to notices so they can't loop
+    async fn send(&self, text: &str) {
+        // Transaction ids only have to be unique for this access token and run
+        let transaction = format!(
+            "pokebot{}-{}",
+            std::process::id(),
+            self.transaction.fetch_add(1, Ordering::Relaxed)
+        );
+        let url = match api_url(
+            &self.config,
+            &[
+                "rooms",
+                &self.config.room,
+                "send",
+                "m.room.message",
+                &transaction,
+            ],
+        ) {
+            Ok(url) => url,
+            Err(e) => {
+                error!("Failed to send Matrix message: {}", e);
+                return;
+            }
+        };
+
+        let body = json!({ "msgtype": "m.notice", "body": text });
+        let request = self
+            .http
+            .put(&url)
+            .bearer_auth(&self.config.access_token)
+            .body(body.to_string());
+        if let Err(e) = get_json(request).await {
+            error!("Failed to send Matrix message: {}", e);
+        }
+    }
+}
+
+/// Builds a client API url, the segments are escaped since room ids contain `!` and `:`
+fn api_url(config: &MatrixConfig, segments: &[&str]) -> Result<String, String> {
+    let mut url = Url::parse(&config.homeserver).map_err(|e| e.to_string())?;
+    url.path_segments_mut()
+        .map_err(|_| String::from("Invalid homeserver url"))?
+        .pop_if_empty()
+        .extend(&["_matrix", "client", "r0"])
+        .extend(segments);
+
+    Ok(url.to_string())
+}
+
+async fn get_json(request: reqwest::RequestBuilder) -> Result<Value, String> {
+    let response = request.send().await.map_err(|e| e.to_string())?;
+    let status = response.status();
+    let body = response.text().await.map_err(|e| e.to_string())?;
+
+    if !status.is_success() {
+        return Err(format!("{}: {}", status, body));
+    }
+
+    serde_json::from_str(&body).map_err(|e| e.to_string())
+}
@@ -0,0 +1,136 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc::{self, UnboundedSender};
+use tracing::info;
+use tsclientlib::{ClientId, Invoker, MessageTarget, Uid};
+
+use crate::bot::{BotManager, Message, MusicBotMessage};
+
+mod irc;
+mod matrix;
+
+pub use irc::IrcConfig;
+pub use matrix::MatrixConfig;
+
+/// How often the bridges check which tracks the bots are playing
+const NOW_PLAYING_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Rooms outside of the voice server where the bots can be controlled.
+///
+/// Messages like `Alice: !play <url>` are passed to the bot named Alice as if
+/// they were sent in its channel, the bots announce new tracks in the rooms.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BridgeConfig {
+    pub irc: Option<IrcConfig>,
+    pub matrix: Option<MatrixConfig>,
+}
+
+/// A message from a bridged room
+pub struct BridgeMessage {
+    /// Name shown to the bots
+    pub sender: String,
+    /// Set if the room verified who sent the message, can be used for admins
+    pub uid: Option<String>,
+    pub text: String,
+}
+
+/// Starts the configured bridges, they run until the program stops
+pub fn spawn(config: BridgeConfig, bots: Arc<dyn BotManager>) {
+    let mut rooms = Vec::new();
+
+    if let Some(irc) = config.irc {
+        info!("Bridging {} on {}", irc.channel, irc.server);
+        let (tx, rx) = mpsc::unbounded_channel();
+        tokio::spawn(irc::run(irc, bots.clone(), rx));
+        rooms.push(tx);
+    }
+
+    if let Some(matrix) = config.matrix {
+        info!("Bridging {} on {}", matrix.room, matrix.homeserver);
+        let (tx, rx) = mpsc::unbounded_channel();
+        tokio::spawn(matrix::run(matrix, bots.clone(), rx));
+        rooms.push(tx);
+    }
+
+    if !rooms.is_empty() {
+        tokio::spawn(announce_now_playing(bots, rooms));
+    }
+}
+
+/// Passes a message to the bot it addresses, returns a reply for the room if there is one
+pub fn handle_message(bots: &dyn BotManager, message: BridgeMessage) -> Option<String> {
+    let text = message.text.trim();
+
+    if text == "!bots" {
+        let mut summaries = bots.bot_summaries();
+        if summaries.is_empty() {
+            return Some(String::from("No bots are connected"));
+        }
+        summaries.sort_by(|a, b| a.name.cmp(&b.name));
+
+        let list = summaries
+            .iter()
+            .map(|s| match &s.current_title {
+                Some(title) => format!("{} ({}: {})", s.name, s.state, title),
+                None => format!("{} ({})", s.name, s.state),
+            })
+            .collect::<Vec<_>>();
+        return Some(list.join(", "));
+    }
+
+    let split = text.find(|c| c == ':' || c == ',')?;
+    let (name, command) = (text[..split].trim(), text[split + 1..].trim());
+    let name = bots
+        .bot_names()
+        .into_iter()
+        .find(|n| n.eq_ignore_ascii_case(name))?;
+    let bot = bots.music_bot(&name)?;
+
+    bot.post(MusicBotMessage::TextMessage(Message {
+        target: MessageTarget::Channel,
+        invoker: Invoker {
+            name: message.sender,
+            id: ClientId(0),
+            uid: message.uid.map(Uid),
+        },
+        text: command.to_owned(),
+    }));
+
+    None
+}
+
+async fn announce_now_playing(bots: Arc<dyn BotManager>, rooms: Vec<UnboundedSender<String>>) {
+    let mut playing = HashMap::new();
+    let mut interval = tokio::time::interval(NOW_PLAYING_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        let summaries = bots.bot_summaries();
+        playing.retain(|name: &String, _| summaries.iter().any(|s| &s.name == name));
+
+        for summary in summaries {
+            let title = match summary.current_title {
+                Some(title) => title,
+                None => {
+                    playing.remove(&summary.name);
+                    continue;
+                }
+            };
+            if playing.get(&summary.name) == Some(&title) {
+                continue;
+            }
+
+            let text = format!("{} is now playing {}", summary.name, title);
+            playing.insert(summary.name, title);
+
+            // Ignore the result because a bridge might have given up
+            for room in &rooms {
+                let _ = room.send(text.clone());
+            }
+        }
+    }
+}
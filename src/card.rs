@@ -0,0 +1,211 @@
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use image::imageops::{self, FilterType};
+use image::{DynamicImage, ImageOutputFormat, Rgba, RgbaImage};
+use imageproc::drawing::{draw_filled_rect_mut, draw_text_mut};
+use imageproc::rect::Rect;
+use rusttype::{point, Font, Scale};
+use tracing::{error, info, warn};
+
+use crate::youtube_dl::AudioMetadata;
+
+const WIDTH: u32 = 600;
+const HEIGHT: u32 = 200;
+const MARGIN: u32 = 20;
+
+const BACKGROUND: Rgba<u8> = Rgba([24, 24, 28, 255]);
+const TEXT: Rgba<u8> = Rgba([240, 240, 240, 255]);
+const SECONDARY_TEXT: Rgba<u8> = Rgba([160, 160, 170, 255]);
+const BAR_BACKGROUND: Rgba<u8> = Rgba([60, 60, 68, 255]);
+const BAR: Rgba<u8> = Rgba([220, 60, 60, 255]);
+
+/// Renders "now playing" cards showing the cover art, title, progress and chapter of a track.
+///
+/// The cover art and title only change with the track, so they are rendered
+/// once into a base image and only the progress is drawn on every request.
+pub struct CardRenderer {
+    font: Font<'static>,
+}
+
+/// The parts of a card that stay the same while a track plays
+pub struct CardBase {
+    pub webpage_url: String,
+    image: RgbaImage,
+    /// Where the text starts, right of the cover art if there is one
+    text_x: u32,
+}
+
+impl CardRenderer {
+    pub fn new(font: PathBuf) -> Result<Self, String> {
+        let data = fs::read(&font).map_err(|e| e.to_string())?;
+        let font = Font::try_from_vec(data).ok_or_else(|| String::from("Invalid font"))?;
+
+        Ok(Self { font })
+    }
+
+    pub fn render_base(&self, metadata: &AudioMetadata, cover: Option<&[u8]>) -> CardBase {
+        let mut card = RgbaImage::from_pixel(WIDTH, HEIGHT, BACKGROUND);
+
+        let cover = cover.and_then(|data| match image::load_from_memory(data) {
+            Ok(cover) => Some(cover),
+            Err(e) => {
+                warn!("Failed to decode cover art: {}", e);
+                None
+            }
+        });
+        let text_x = match cover {
+            Some(cover) => {
+                let cover = cover.resize_to_fill(HEIGHT, HEIGHT, FilterType::Triangle);
+                imageops::overlay(&mut card, &cover.to_rgba(), 0, 0);
+                HEIGHT + MARGIN
+            }
+            None => MARGIN,
+        };
+        let text_width = WIDTH - text_x - MARGIN;
+
+        let title_scale = Scale::uniform(28.0);
+        let title = self.fit_text(&metadata.title, title_scale, text_width);
+        draw_text_mut(
+            &mut card,
+            TEXT,
+            text_x,
+            MARGIN,
+            title_scale,
+            &self.font,
+            &title,
+        );
+
+        if !metadata.added_by.is_empty() {
+            let scale = Scale::uniform(20.0);
+            let added_by = format!("Requested by {}", metadata.added_by);
+            let added_by = self.fit_text(&added_by, scale, text_width);
+            draw_text_mut(
+                &mut card,
+                SECONDARY_TEXT,
+                text_x,
+                MARGIN + 40,
+                scale,
+                &self.font,
+                &added_by,
+            );
+        }
+
+        CardBase {
+            webpage_url: metadata.webpage_url.clone(),
+            image: card,
+            text_x,
+        }
+    }
+
+    /// Draws the progress onto a copy of the base and encodes it as PNG
+    pub fn render(
+        &self,
+        base: &CardBase,
+        position: Option<Duration>,
+        duration: Option<Duration>,
+        chapter: Option<&str>,
+    ) -> Result<Vec<u8>, image::ImageError> {
+        let mut card = base.image.clone();
+        let x = base.text_x;
+        let width = WIDTH - x - MARGIN;
+        let bar_y = HEIGHT - MARGIN - 8;
+
+        draw_filled_rect_mut(
+            &mut card,
+            Rect::at(x as i32, bar_y as i32).of_size(width, 8),
+            BAR_BACKGROUND,
+        );
+
+        let position = position.unwrap_or_default();
+        if let Some(duration) = duration.filter(|d| d.as_secs() > 0) {
+            let progress = (position.as_secs_f64() / duration.as_secs_f64()).min(1.0);
+            let filled = (f64::from(width) * progress) as u32;
+            if filled > 0 {
+                draw_filled_rect_mut(
+                    &mut card,
+                    Rect::at(x as i32, bar_y as i32).of_size(filled, 8),
+                    BAR,
+                );
+            }
+        }
+
+        let time = match duration {
+            Some(duration) => format!(
+                "{} / {}",
+                crate::duration::format_timestamp(position),
+                crate::duration::format_timestamp(duration)
+            ),
+            None => crate::duration::format_timestamp(position),
+        };
+        let time = match chapter {
+            Some(chapter) => {
+                let scale = Scale::uniform(20.0);
+                self.fit_text(&format!("{} · {}", time, chapter), scale, width)
+            }
+            None => time,
+        };
+        draw_text_mut(
+            &mut card,
+            SECONDARY_TEXT,
+            x,
+            bar_y - 28,
+            Scale::uniform(20.0),
+            &self.font,
+            &time,
+        );
+
+        let mut png = Vec::new();
+        DynamicImage::ImageRgba8(card).write_to(&mut png, ImageOutputFormat::Png)?;
+
+        Ok(png)
+    }
+
+    /// Shortens `text` with an ellipsis until it fits into `max_width` pixels
+    fn fit_text(&self, text: &str, scale: Scale, max_width: u32) -> String {
+        if self.text_width(text, scale) <= max_width {
+            return text.to_owned();
+        }
+
+        let mut chars = text.chars().collect::<Vec<_>>();
+        while !chars.is_empty() {
+            chars.pop();
+            let shortened = format!("{}…", chars.iter().collect::<String>().trim_end());
+            if self.text_width(&shortened, scale) <= max_width {
+                return shortened;
+            }
+        }
+
+        String::new()
+    }
+
+    fn text_width(&self, text: &str, scale: Scale) -> u32 {
+        self.font
+            .layout(text, scale, point(0.0, 0.0))
+            .filter_map(|glyph| glyph.pixel_bounding_box())
+            .map(|bb| bb.max.x)
+            .max()
+            .unwrap_or(0) as u32
+    }
+}
+
+/// Loads the font for cards if one is configured, cards are disabled if that fails
+pub fn open(font: Option<PathBuf>) -> Option<Arc<CardRenderer>> {
+    let font = font?;
+
+    match CardRenderer::new(font.clone()) {
+        Ok(renderer) => {
+            info!("Rendering now playing cards with {:?}", font);
+            Some(Arc::new(renderer))
+        }
+        Err(e) => {
+            error!(
+                "Failed to load card font {:?}, cards are disabled: {}",
+                font, e
+            );
+            None
+        }
+    }
+}
@@ -0,0 +1,95 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+use tracing::{error, info};
+
+use crate::bot::EndOfQueue;
+
+/// Settings a music bot restores when it joins a channel it was used in before
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChannelProfile {
+    pub volume: f64,
+    pub announce_in_chat: bool,
+    pub announce_in_description: bool,
+    #[serde(default)]
+    pub end_of_queue: EndOfQueue,
+    /// Set by admins with `!bitrate`, the configured bitrate is used otherwise
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bitrate: Option<u32>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub podcasts: Vec<PodcastSubscription>,
+}
+
+/// A podcast feed the channel subscribed to with `!subscription add`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PodcastSubscription {
+    pub url: String,
+    pub title: String,
+    /// Whether new episodes are announced in chat
+    #[serde(default)]
+    pub announce: bool,
+    /// Url of the newest episode the channel knows about
+    pub latest_episode: Option<String>,
+}
+
+/// Stores channel profiles of all servers in a single JSON file,
+/// keyed by `<server>/<channel path>`.
+pub struct ChannelSettingsStore {
+    path: PathBuf,
+    server: String,
+    profiles: Mutex<HashMap<String, ChannelProfile>>,
+}
+
+impl ChannelSettingsStore {
+    pub fn new(path: PathBuf, server: &str) -> Self {
+        let profiles = match fs::read(&path) {
+            Ok(data) => match serde_json::from_slice(&data) {
+                Ok(profiles) => profiles,
+                Err(e) => {
+                    error!("Failed to parse channel settings in {:?}: {}", path, e);
+                    HashMap::new()
+                }
+            },
+            Err(e) if e.kind() == io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => {
+                error!("Failed to read channel settings from {:?}: {}", path, e);
+                HashMap::new()
+            }
+        };
+
+        Self {
+            path,
+            server: server.to_owned(),
+            profiles: Mutex::new(profiles),
+        }
+    }
+
+    fn key_of(&self, channel: &str) -> String {
+        format!("{}/{}", self.server, channel)
+    }
+
+    pub fn get(&self, channel: &str) -> Option<ChannelProfile> {
+        self.profiles
+            .lock()
+            .unwrap()
+            .get(&self.key_of(channel))
+            .cloned()
+    }
+
+    pub fn set(&self, channel: &str, profile: ChannelProfile) {
+        let mut profiles = self.profiles.lock().unwrap();
+        profiles.insert(self.key_of(channel), profile);
+
+        let result = serde_json::to_vec_pretty(&*profiles)
+            .map_err(io::Error::from)
+            .and_then(|data| fs::write(&self.path, data));
+        match result {
+            Ok(()) => info!("Saved settings of {}", channel),
+            Err(e) => error!("Failed to save channel settings to {:?}: {}", self.path, e),
+        }
+    }
+}
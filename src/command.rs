@@ -1,8 +1,17 @@
+use std::collections::HashMap;
 use std::time::Duration;
 
+use serde::{Deserialize, Serialize};
 use structopt::clap::AppSettings::*;
+use structopt::clap::ErrorKind;
 use structopt::StructOpt;
 
+use crate::audio_player::{MAX_BITRATE, MIN_BITRATE};
+use crate::i18n::Locale;
+use crate::scheduler::{Days, TimeOfDay};
+use crate::teamspeak as ts;
+use crate::tr;
+
 #[derive(StructOpt, Debug)]
 #[structopt(
     rename_all = "kebab-case",
@@ -17,8 +26,26 @@ use structopt::StructOpt;
 pub enum Command {
     /// Adds url to playlist
     Add { url: String },
-    /// Adds the first video found on YouTube
+    /// Lists the top results found on YouTube
     Search { query: Vec<String> },
+    /// Adds url to the front of the playlist so it plays next
+    #[structopt(alias = "playnext")]
+    PlayNext { url: String },
+    /// Adds an audio file from the file browser of the channel, e.g. "song.mp3" (TeamSpeak only)
+    #[structopt(alias = "play-file")]
+    Playfile { name: Vec<String> },
+    /// Adds a result of your last search or podcast listing
+    Pick {
+        #[structopt(parse(try_from_str = parse_number))]
+        number: usize,
+    },
+    /// Lists the newest episodes of a podcast feed, or of a subscribed one by its number
+    Podcast { feed: Option<String> },
+    /// Subscribes the channel to podcast feeds, new episodes can be announced
+    Subscription {
+        #[structopt(subcommand)]
+        action: SubscriptionAction,
+    },
     /// Starts audio playback
     Play,
     /// Pauses audio playback
@@ -27,17 +54,837 @@ pub enum Command {
     Seek { amount: Seek },
     /// Stops audio playback
     Stop,
+    /// Sends you the lyrics of the current track
+    Lyrics,
+    /// Lists the chapters of the current track
+    Chapters,
+    /// Seeks to the start of a chapter of the current track
+    Chapter {
+        #[structopt(parse(try_from_str = parse_number))]
+        number: usize,
+    },
     /// Switches to the next playlist entry
     #[structopt(alias = "skip")]
     Next,
     /// Clears the playback queue
     Clear,
-    /// Changes the volume to the specified value
+    /// Shows a page of the playback queue, moves or removes its entries
+    Queue {
+        #[structopt(parse(try_from_str = parse_number))]
+        page: Option<usize>,
+        #[structopt(subcommand)]
+        action: Option<QueueAction>,
+    },
+    /// Changes the volume to the specified value, "+10" and "-5" change it relatively
     Volume { volume: VolumeChange },
+    /// Changes the volume of the current track only, on top of the volume of the bot
+    TrackVolume { volume: VolumeChange },
+    /// Silences the bot while the track keeps playing
+    Mute,
+    /// Makes the bot audible again after a mute
+    Unmute,
+    /// Stores a volume that is proposed when you are the only listener
+    PreferredVolume {
+        #[structopt(subcommand)]
+        action: PreferredVolumeAction,
+    },
+    /// Replies to your commands in a language like "de", "reset" uses the one of the server
+    Lang { language: Option<String> },
+    /// Changes the gain of an equalizer band in dB, use "reset" to flatten all bands
+    Eq {
+        band: EqBand,
+        #[structopt(parse(try_from_str = parse_decimal))]
+        gain: Option<f64>,
+    },
+    /// Changes the playback speed without changing the pitch
+    Speed {
+        #[structopt(parse(try_from_str = parse_decimal))]
+        speed: f64,
+    },
+    /// Toggles faster playback with a higher pitch
+    Nightcore,
+    /// Turns now playing announcements in chat or description on or off
+    Announce {
+        target: AnnounceTarget,
+        toggle: Toggle,
+    },
+    /// Plays into other channels as well by whispering to them
+    Broadcast {
+        #[structopt(subcommand)]
+        action: BroadcastAction,
+    },
+    /// Saves, loads, lists and deletes named playlists
+    Playlist {
+        #[structopt(subcommand)]
+        action: PlaylistAction,
+    },
+    /// Turns the limit on the queue length on or off, only for admins
+    QueueLimit { toggle: Toggle },
+    /// Lists blocked tracks, admins can add and remove them
+    Blacklist {
+        #[structopt(subcommand)]
+        action: BlacklistAction,
+    },
+    /// Only lets the summoner and admins change the queue
+    Lock,
+    /// Lets everyone change the queue again
+    Unlock,
+    /// Shows or changes the bitrate in kbit/s, only admins can change it
+    Bitrate { bitrate: Option<BitrateSetting> },
+    /// Changes what happens once the queue is empty
+    QueueEnd {
+        #[structopt(subcommand)]
+        policy: QueueEndPolicy,
+    },
+    /// Keeps playing tracks related to the last one once the queue is empty
+    Autoplay { toggle: Toggle },
+    /// Moves along when the person who summoned the bot switches channels
+    Follow { toggle: Toggle },
+    /// Plays only to you and the users you invite, "off" plays to the channel again
+    Private { toggle: Option<Toggle> },
+    /// Lets a user hear your private session
+    Invite { name: Vec<String> },
+    /// Stops a user from hearing your private session
+    Uninvite { name: Vec<String> },
+    /// Turns scrobbling of finished tracks to Last.fm or ListenBrainz on or off
+    Scrobble { toggle: Toggle },
+    /// Shows how much was played on this server
+    Stats,
+    /// Shows the most played tracks or the most active requesters
+    Top {
+        #[structopt(subcommand)]
+        kind: TopKind,
+    },
+    /// Reports a problem with the current track to the admins
+    Report { reason: Vec<String> },
+    /// Shows the volume, announcements, queue limit and playback mode
+    Settings,
+    /// Shows the version of the bot and of yt-dlp or youtube-dl
+    Version,
+    /// Sends you recent warnings and errors of the bot, only for admins
+    Debug {
+        #[structopt(subcommand)]
+        action: DebugAction,
+    },
+    /// Only plays to those who turned listening on, "off" stops playing to you
+    Listen { toggle: Toggle },
     /// Leaves the channel
     Leave,
 }
 
+impl Command {
+    /// The name the command is invoked with, aliases resolve to the same name
+    pub fn name(&self) -> &'static str {
+        match self {
+            Command::Add { .. } => "add",
+            Command::Search { .. } => "search",
+            Command::PlayNext { .. } => "play-next",
+            Command::Playfile { .. } => "playfile",
+            Command::Pick { .. } => "pick",
+            Command::Podcast { .. } => "podcast",
+            Command::Subscription { .. } => "subscription",
+            Command::Play => "play",
+            Command::Pause => "pause",
+            Command::Seek { .. } => "seek",
+            Command::Stop => "stop",
+            Command::Lyrics => "lyrics",
+            Command::Chapters => "chapters",
+            Command::Chapter { .. } => "chapter",
+            Command::Next => "next",
+            Command::Clear => "clear",
+            Command::Queue { .. } => "queue",
+            Command::Volume { .. } => "volume",
+            Command::TrackVolume { .. } => "track-volume",
+            Command::Mute => "mute",
+            Command::Unmute => "unmute",
+            Command::PreferredVolume { .. } => "preferred-volume",
+            Command::Lang { .. } => "lang",
+            Command::Eq { .. } => "eq",
+            Command::Speed { .. } => "speed",
+            Command::Nightcore => "nightcore",
+            Command::Announce { .. } => "announce",
+            Command::Broadcast { .. } => "broadcast",
+            Command::Playlist { .. } => "playlist",
+            Command::QueueLimit { .. } => "queue-limit",
+            Command::Blacklist { .. } => "blacklist",
+            Command::Lock => "lock",
+            Command::Unlock => "unlock",
+            Command::Bitrate { .. } => "bitrate",
+            Command::QueueEnd { .. } => "queue-end",
+            Command::Autoplay { .. } => "autoplay",
+            Command::Follow { .. } => "follow",
+            Command::Private { .. } => "private",
+            Command::Invite { .. } => "invite",
+            Command::Uninvite { .. } => "uninvite",
+            Command::Scrobble { .. } => "scrobble",
+            Command::Stats => "stats",
+            Command::Top { .. } => "top",
+            Command::Report { .. } => "report",
+            Command::Settings => "settings",
+            Command::Version => "version",
+            Command::Debug { .. } => "debug",
+            Command::Listen { .. } => "listen",
+            Command::Leave => "leave",
+        }
+    }
+
+    /// If the command adds, removes, reorders or skips entries of the queue
+    pub fn edits_queue(&self) -> bool {
+        match self {
+            Command::Add { .. }
+            | Command::PlayNext { .. }
+            | Command::Playfile { .. }
+            | Command::Pick { .. }
+            | Command::Next
+            | Command::Clear
+            | Command::Queue {
+                action: Some(_), ..
+            }
+            | Command::Playlist {
+                action: PlaylistAction::Load { .. },
+            } => true,
+            _ => false,
+        }
+    }
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(rename_all = "kebab-case")]
+pub enum DebugAction {
+    /// Shows the newest entries, 10 if no count is given
+    Last {
+        #[structopt(parse(try_from_str = parse_number))]
+        count: Option<usize>,
+    },
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(rename_all = "kebab-case")]
+pub enum QueueAction {
+    /// Moves the entry at a position to another one, e.g. "5 1"
+    Move {
+        #[structopt(parse(try_from_str = parse_number))]
+        from: usize,
+        #[structopt(parse(try_from_str = parse_number))]
+        to: usize,
+    },
+    /// Removes the entry at a position
+    Remove {
+        #[structopt(parse(try_from_str = parse_number))]
+        position: usize,
+    },
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(rename_all = "kebab-case")]
+pub enum BlacklistAction {
+    /// Blocks tracks whose url contains a video id or url part, "*" matches any text
+    Add { pattern: String },
+    /// Unblocks tracks of a pattern
+    Remove { pattern: String },
+    /// Lists the blocked patterns
+    List,
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(rename_all = "kebab-case")]
+pub enum QueueEndPolicy {
+    /// Stops playing
+    Silent,
+    /// Plays a random track from the recently played ones
+    Replay,
+    /// Plays the given url
+    Fallback { url: String },
+    /// Leaves the channel if nothing is added within the grace period
+    Leave {
+        #[structopt(parse(try_from_str = crate::duration::parse))]
+        grace_period: Option<Duration>,
+    },
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(rename_all = "kebab-case")]
+pub enum BroadcastAction {
+    /// Starts playing into a channel, e.g. "Lobby/Music"
+    Add { channel: Vec<String> },
+    /// Stops playing into a channel
+    Remove { channel: Vec<String> },
+    /// Lists the channels the bot plays into
+    List,
+    /// Stops playing into all other channels
+    Clear,
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(rename_all = "kebab-case")]
+pub enum SubscriptionAction {
+    /// Subscribes to the podcast feed at a url
+    Add { url: String },
+    /// Unsubscribes from the feed with the given number
+    Remove {
+        #[structopt(parse(try_from_str = parse_number))]
+        number: usize,
+    },
+    /// Lists the subscribed feeds with their numbers
+    List,
+    /// Turns announcing new episodes of a feed in chat on or off
+    Announce {
+        #[structopt(parse(try_from_str = parse_number))]
+        number: usize,
+        toggle: Toggle,
+    },
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(rename_all = "kebab-case")]
+pub enum TopKind {
+    /// Lists the most played tracks
+    Tracks,
+    /// Lists the users who requested the most tracks
+    Requesters,
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(rename_all = "kebab-case")]
+pub enum PreferredVolumeAction {
+    /// Stores the volume in percent
+    Set {
+        #[structopt(parse(try_from_str = parse_decimal))]
+        volume: f64,
+    },
+    /// Shows the stored volume
+    Show,
+    /// Forgets the stored volume
+    Clear,
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(rename_all = "kebab-case")]
+pub enum PlaylistAction {
+    /// Saves the current track and queue under the given name
+    Save { name: String },
+    /// Adds a saved playlist to the queue
+    Load { name: String },
+    /// Lists all saved playlists
+    List,
+    /// Deletes a saved playlist
+    Delete { name: String },
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(
+    rename_all = "kebab-case",
+    template = "{subcommands}",
+    global_settings = &[VersionlessSubcommands,
+                            DisableHelpFlags,
+                            DisableVersion,
+                            ColorNever,
+                            NoBinaryName,
+                            AllowLeadingHyphen],
+)]
+pub enum MasterCommand {
+    /// Shows the status of the master bot and the web server
+    Status,
+    /// Spawns a music bot in a channel given by name, path or id, only for admins
+    Spawn {
+        /// Joins even if there is a bot in the channel already
+        #[structopt(long)]
+        force: bool,
+        channel: Vec<String>,
+    },
+    /// Moves you to a music channel below your channel and spawns a bot there
+    MusicChannel,
+    /// Spawns a music bot in your channel, which has this password
+    Password {
+        #[structopt(required = true)]
+        password: Vec<String>,
+    },
+    /// Confirms the bot offered to you after a poke
+    Yes,
+    /// Lists, adds and removes recurring jobs, only for admins
+    Schedule {
+        #[structopt(subcommand)]
+        action: ScheduleAction,
+    },
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(rename_all = "kebab-case")]
+pub enum ScheduleAction {
+    /// Lists all jobs with their numbers
+    List,
+    /// Plays a url in a channel, e.g. "fri 20:00 <url> Lobby/Music"
+    Play {
+        /// "daily", "weekdays", "weekends" or days like "mon,fri"
+        days: Days,
+        time: TimeOfDay,
+        url: String,
+        #[structopt(required = true)]
+        channel: Vec<String>,
+    },
+    /// Disconnects all music bots, e.g. "daily 03:00"
+    Despawn { days: Days, time: TimeOfDay },
+    /// Removes the job with the given number
+    Remove {
+        #[structopt(parse(try_from_str = parse_number))]
+        number: usize,
+    },
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AnnounceTarget {
+    Chat,
+    Description,
+}
+
+impl std::str::FromStr for AnnounceTarget {
+    type Err = String;
+
+    fn from_str(target: &str) -> std::result::Result<Self, Self::Err> {
+        match target {
+            "chat" => Ok(AnnounceTarget::Chat),
+            "description" | "desc" => Ok(AnnounceTarget::Description),
+            _ => Err(format!(
+                "Unknown target \"{}\", expected \"chat\" or \"description\"",
+                target
+            )),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum EqBand {
+    Bass,
+    Mid,
+    Treble,
+    Band(usize),
+    Reset,
+}
+
+impl EqBand {
+    /// The bands of the 10 band equalizer this refers to
+    pub fn bands(self) -> Vec<usize> {
+        match self {
+            EqBand::Bass => vec![0, 1],
+            EqBand::Mid => vec![4, 5],
+            EqBand::Treble => vec![8, 9],
+            EqBand::Band(band) => vec![band],
+            EqBand::Reset => (0..10).collect(),
+        }
+    }
+}
+
+impl std::str::FromStr for EqBand {
+    type Err = String;
+
+    fn from_str(band: &str) -> std::result::Result<Self, Self::Err> {
+        match band {
+            "bass" => Ok(EqBand::Bass),
+            "mid" => Ok(EqBand::Mid),
+            "treble" => Ok(EqBand::Treble),
+            "reset" => Ok(EqBand::Reset),
+            _ => match band.parse::<usize>() {
+                Ok(band) if band < 10 => Ok(EqBand::Band(band)),
+                _ => Err(format!(
+                    "Unknown band \"{}\", expected \"bass\", \"mid\", \"treble\", \"reset\" or 0-9",
+                    band
+                )),
+            },
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BitrateSetting {
+    Auto,
+    Kbps(u32),
+}
+
+impl std::str::FromStr for BitrateSetting {
+    type Err = String;
+
+    fn from_str(bitrate: &str) -> std::result::Result<Self, Self::Err> {
+        let bitrate = bitrate.trim_end_matches("kbps").trim_end_matches('k');
+        match bitrate {
+            "auto" => Ok(BitrateSetting::Auto),
+            _ => match bitrate.parse::<u32>() {
+                Ok(kbps) if (MIN_BITRATE..=MAX_BITRATE).contains(&kbps) => {
+                    Ok(BitrateSetting::Kbps(kbps))
+                }
+                _ => Err(format!(
+                    "Unknown bitrate \"{}\", expected \"auto\" or {}-{} kbit/s",
+                    bitrate, MIN_BITRATE, MAX_BITRATE
+                )),
+            },
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Toggle {
+    On,
+    Off,
+}
+
+impl Toggle {
+    pub fn is_on(self) -> bool {
+        self == Toggle::On
+    }
+}
+
+impl std::str::FromStr for Toggle {
+    type Err = String;
+
+    fn from_str(toggle: &str) -> std::result::Result<Self, Self::Err> {
+        match toggle {
+            "on" | "true" | "yes" => Ok(Toggle::On),
+            "off" | "false" | "no" => Ok(Toggle::Off),
+            _ => Err(format!("Expected \"on\" or \"off\", got \"{}\"", toggle)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CommandConfig {
+    #[serde(default = "default_prefix")]
+    pub prefix: String,
+    #[serde(default)]
+    pub aliases: HashMap<String, String>,
+}
+
+fn default_prefix() -> String {
+    String::from("!")
+}
+
+impl Default for CommandConfig {
+    fn default() -> Self {
+        Self {
+            prefix: default_prefix(),
+            aliases: HashMap::new(),
+        }
+    }
+}
+
+impl CommandConfig {
+    /// Parses a chat message into a command.
+    ///
+    /// Returns `None` if the message does not start with the configured prefix.
+    pub fn parse<T: StructOpt>(&self, text: &str) -> Option<Result<T, CommandError>> {
+        if self.prefix.is_empty() || !text.starts_with(&self.prefix) {
+            return None;
+        }
+
+        Some(self.parse_args(&text[self.prefix.len()..]))
+    }
+
+    /// The arguments of `command` as they were written, without splitting them.
+    ///
+    /// Returns `None` if the message is not that command, aliases are not expanded.
+    pub fn raw_arguments<'a>(&self, text: &'a str, command: &str) -> Option<&'a str> {
+        if self.prefix.is_empty() || !text.starts_with(&self.prefix) {
+            return None;
+        }
+
+        let text = &text[self.prefix.len()..];
+        let (name, rest) = match text.find(char::is_whitespace) {
+            Some(i) => text.split_at(i),
+            None => (text, ""),
+        };
+        if name != command {
+            return None;
+        }
+
+        // Only the separator is dropped, the arguments may start with more whitespace
+        let mut rest = rest.chars();
+        rest.next();
+        Some(rest.as_str())
+    }
+
+    /// Parses a command without the prefix, e.g. one sent through the web API.
+    ///
+    /// Aliases are only expanded for the first token and may expand to
+    /// multiple tokens, e.g. `mute = "volume 0"`.
+    pub fn parse_args<T: StructOpt>(&self, text: &str) -> Result<T, CommandError> {
+        let mut tokens = tokenize(text)?;
+
+        if let Some(first) = tokens.first() {
+            if let Some(alias) = self.aliases.get(first) {
+                tokens.splice(0..1, tokenize(alias)?);
+            }
+        }
+
+        T::from_iter_safe(&tokens).map_err(|e| self.convert_error(e, tokens.first()))
+    }
+
+    /// Shortens the errors of clap to a single line that fits into a chat message
+    fn convert_error(
+        &self,
+        error: structopt::clap::Error,
+        command: Option<&String>,
+    ) -> CommandError {
+        let first_line = error
+            .message
+            .lines()
+            .next()
+            .unwrap_or_default()
+            .trim_start_matches("error:")
+            .trim();
+        let word = error
+            .info
+            .as_ref()
+            .and_then(|info| info.first())
+            .map(String::as_str)
+            .or_else(|| quoted(first_line));
+
+        match error.kind {
+            ErrorKind::HelpDisplayed => CommandError::Help(error.message.clone()),
+            ErrorKind::UnrecognizedSubcommand
+            | ErrorKind::InvalidSubcommand
+            | ErrorKind::UnknownArgument
+                if word.is_some() && word == command.map(String::as_str) =>
+            {
+                CommandError::UnknownCommand(word.unwrap_or_default().to_owned())
+            }
+            ErrorKind::UnrecognizedSubcommand
+            | ErrorKind::InvalidSubcommand
+            | ErrorKind::UnknownArgument
+            | ErrorKind::TooManyValues
+            | ErrorKind::WrongNumberOfValues => CommandError::Usage {
+                problem: match word {
+                    Some(word) => format!("Unexpected \"{}\"", word),
+                    None => first_line.to_owned(),
+                },
+                usage: self.usage(&error.message),
+            },
+            ErrorKind::MissingRequiredArgument
+            | ErrorKind::MissingSubcommand
+            | ErrorKind::MissingArgumentOrSubcommand
+            | ErrorKind::TooFewValues
+            | ErrorKind::EmptyValue => CommandError::Usage {
+                problem: missing_arguments(&error.message),
+                usage: self.usage(&error.message),
+            },
+            ErrorKind::ValueValidation => {
+                // "Invalid value for '<amount>': expected ..."
+                let problem = first_line
+                    .splitn(2, "': ")
+                    .nth(1)
+                    .unwrap_or(first_line)
+                    .to_owned();
+                CommandError::InvalidValue(capitalize(&problem))
+            }
+            _ => CommandError::InvalidValue(first_line.to_owned()),
+        }
+    }
+
+    /// The usage line of the command in a clap error, with the prefix in front
+    fn usage(&self, message: &str) -> String {
+        message
+            .lines()
+            .skip_while(|line| !line.starts_with("USAGE:"))
+            .nth(1)
+            .map(|line| format!("{}{}", self.prefix, line.trim()))
+            .unwrap_or_default()
+    }
+
+    /// Turns the help text generated by clap into BBCode.
+    ///
+    /// Works for both the command overview and the help of a single command.
+    /// The descriptions in the overview are translated by the ids `help-<command>`.
+    pub fn format_help(&self, help: &str, locale: &Locale) -> String {
+        let is_overview = !help.contains("USAGE:");
+        let mut section = if is_overview {
+            Some("SUBCOMMANDS")
+        } else {
+            None
+        };
+        let mut lines = Vec::new();
+
+        for line in help.lines() {
+            let trimmed = line.trim();
+            if trimmed.is_empty() {
+                continue;
+            }
+
+            if !line.starts_with(' ') && trimmed.ends_with(':') {
+                section = Some(trimmed.trim_end_matches(':'));
+                lines.push(ts::bold(&trimmed).to_string());
+                continue;
+            }
+
+            if !line.starts_with(' ') {
+                lines.push(trimmed.to_owned());
+                continue;
+            }
+
+            let formatted = match section {
+                Some("SUBCOMMANDS") => {
+                    let mut split = trimmed.splitn(2, char::is_whitespace);
+                    let name = split.next().unwrap_or_default();
+                    let about = split.next().unwrap_or_default().trim();
+                    let about = locale.lookup(&format!("help-{}", name)).unwrap_or(about);
+                    let name = format!("{}{}", self.prefix, name);
+
+                    format!("  {} - {}", ts::bold(&name), about)
+                }
+                Some("USAGE") => format!("  {}{}", self.prefix, trimmed),
+                _ => format!("  {}", trimmed),
+            };
+            lines.push(formatted);
+        }
+
+        if is_overview {
+            if !self.aliases.is_empty() {
+                lines.push(ts::bold(&"ALIASES:").to_string());
+
+                let mut aliases = self.aliases.iter().collect::<Vec<_>>();
+                aliases.sort();
+                for (alias, command) in aliases {
+                    let alias = format!("{}{}", self.prefix, alias);
+                    lines.push(format!(
+                        "  {} → {}{}",
+                        ts::bold(&alias),
+                        self.prefix,
+                        command
+                    ));
+                }
+            }
+
+            let hint = format!("{}help <command>", self.prefix);
+            lines.push(tr!(
+                locale,
+                "help-details",
+                "Use {command} for details.",
+                command = ts::italic(&hint)
+            ));
+        }
+
+        lines.join("\n")
+    }
+}
+
+/// Why a message could not be parsed into a command
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CommandError {
+    /// Not an error, the help was asked for, `format_help` makes it fit into chat
+    Help(String),
+    /// The first word is no command, it might be meant for another bot with the same prefix
+    UnknownCommand(String),
+    UnclosedQuote,
+    /// An argument could not be parsed, e.g. `Expected a number, got "abc"`
+    InvalidValue(String),
+    /// Arguments are missing or left over
+    Usage {
+        problem: String,
+        usage: String,
+    },
+}
+
+impl std::fmt::Display for CommandError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CommandError::Help(help) => f.write_str(help),
+            CommandError::UnknownCommand(command) => write!(f, "Unknown command \"{}\"", command),
+            CommandError::UnclosedQuote => {
+                f.write_str("A quote was not closed, use \\\" for quotes inside of words")
+            }
+            CommandError::InvalidValue(problem) => f.write_str(problem),
+            CommandError::Usage { problem, usage } if usage.is_empty() => f.write_str(problem),
+            CommandError::Usage { problem, usage } => write!(f, "{}, usage: {}", problem, usage),
+        }
+    }
+}
+
+impl std::error::Error for CommandError {}
+
+/// Splits a command into its arguments at whitespace.
+///
+/// Text in double quotes is kept together, e.g. `"Lobby/Music Room"`, and a
+/// backslash keeps the next character as it is. Single quotes are left alone
+/// because they are more often part of a search than meant as quotes.
+pub fn tokenize(text: &str) -> Result<Vec<String>, CommandError> {
+    let mut tokens = Vec::new();
+    let mut current = String::new();
+    let mut in_token = false;
+    let mut in_quotes = false;
+    let mut chars = text.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                current.extend(chars.next());
+                in_token = true;
+            }
+            '"' => {
+                in_quotes = !in_quotes;
+                in_token = true;
+            }
+            c if c.is_whitespace() && !in_quotes => {
+                if in_token {
+                    tokens.push(std::mem::take(&mut current));
+                    in_token = false;
+                }
+            }
+            c => {
+                current.push(c);
+                in_token = true;
+            }
+        }
+    }
+
+    if in_quotes {
+        return Err(CommandError::UnclosedQuote);
+    }
+    if in_token {
+        tokens.push(current);
+    }
+
+    Ok(tokens)
+}
+
+fn parse_number(number: &str) -> Result<usize, String> {
+    number
+        .parse()
+        .map_err(|_| format!("expected a number, got \"{}\"", number))
+}
+
+fn parse_decimal(number: &str) -> Result<f64, String> {
+    number
+        .parse::<f64>()
+        .ok()
+        .filter(|n| n.is_finite())
+        .ok_or_else(|| format!("expected a number like \"1.5\", got \"{}\"", number))
+}
+
+/// The first text in single quotes, clap puts the arguments it complains about in them
+fn quoted(message: &str) -> Option<&str> {
+    message.split('\'').nth(1)
+}
+
+/// Turns the list of missing arguments of clap into a single line like `Missing <url>`
+fn missing_arguments(message: &str) -> String {
+    let missing = message
+        .lines()
+        .skip(1)
+        .map(str::trim)
+        .take_while(|line| !line.is_empty())
+        .collect::<Vec<_>>();
+
+    if missing.is_empty() {
+        String::from("Missing an argument")
+    } else {
+        format!("Missing {}", missing.join(" "))
+    }
+}
+
+fn capitalize(text: &str) -> String {
+    let mut chars = text.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
 #[derive(Copy, Clone, Debug)]
 pub enum Seek {
     Positive(Duration),
@@ -46,7 +893,7 @@ pub enum Seek {
 }
 
 impl std::str::FromStr for Seek {
-    type Err = humantime::DurationError;
+    type Err = crate::duration::DurationError;
 
     fn from_str(mut amount: &str) -> std::result::Result<Self, Self::Err> {
         let sign = match amount.chars().next() {
@@ -60,7 +907,7 @@ impl std::str::FromStr for Seek {
             amount = &amount[1..];
         }
 
-        let duration = humantime::parse_duration(amount)?;
+        let duration = crate::duration::parse(amount)?;
 
         match sign {
             1 => Ok(Seek::Positive(duration)),
@@ -77,11 +924,25 @@ pub enum VolumeChange {
     Absolute(f64),
 }
 
+impl VolumeChange {
+    /// The volume after the change, kept between 0 and 1
+    pub fn apply(self, volume: f64) -> f64 {
+        let new_volume = match self {
+            VolumeChange::Positive(vol) => volume + vol,
+            VolumeChange::Negative(vol) => volume - vol,
+            VolumeChange::Absolute(vol) => vol,
+        };
+
+        new_volume.max(0.0).min(1.0)
+    }
+}
+
 // TODO This runs twice, report to clap?
 impl std::str::FromStr for VolumeChange {
-    type Err = std::num::ParseFloatError;
+    type Err = String;
 
-    fn from_str(mut amount: &str) -> std::result::Result<Self, Self::Err> {
+    fn from_str(text: &str) -> std::result::Result<Self, Self::Err> {
+        let mut amount = text;
         let sign = match amount.chars().next() {
             Some('+') => 1,
             Some('-') => -1,
@@ -93,7 +954,16 @@ impl std::str::FromStr for VolumeChange {
             amount = &amount[1..];
         }
 
-        let amount = f64::from_str(amount)? * 0.01;
+        let amount = f64::from_str(amount)
+            .ok()
+            .filter(|a| a.is_finite())
+            .ok_or_else(|| {
+                format!(
+                    "expected a volume like \"50\", \"+10\" or \"-5\", got \"{}\"",
+                    text
+                )
+            })?
+            * 0.01;
 
         match sign {
             1 => Ok(VolumeChange::Positive(amount)),
@@ -102,3 +972,184 @@ impl std::str::FromStr for VolumeChange {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> CommandConfig {
+        let mut aliases = HashMap::new();
+        aliases.insert(String::from("v"), String::from("volume"));
+        aliases.insert(String::from("loud"), String::from("volume 100"));
+        aliases.insert(
+            String::from("radio"),
+            String::from("add \"http://radio/a b\""),
+        );
+
+        CommandConfig {
+            prefix: String::from("!"),
+            aliases,
+        }
+    }
+
+    fn command(text: &str) -> Result<Command, CommandError> {
+        config().parse_args(text)
+    }
+
+    fn master_command(text: &str) -> Result<MasterCommand, CommandError> {
+        config().parse_args(text)
+    }
+
+    #[test]
+    fn tokenizes_quotes_and_escapes() {
+        assert_eq!(
+            tokenize("  spawn   \"Lobby/Music Room\" \\\"x\\\" a\\ b").unwrap(),
+            vec!["spawn", "Lobby/Music Room", "\"x\"", "a b"]
+        );
+        // Quotes may be part of a word and empty quotes are an empty argument
+        assert_eq!(
+            tokenize("a\"b c\"d \"\" it's").unwrap(),
+            vec!["ab cd", "", "it's"]
+        );
+        assert_eq!(tokenize(" \t ").unwrap(), Vec::<String>::new());
+        assert_eq!(tokenize("add \"url"), Err(CommandError::UnclosedQuote));
+    }
+
+    #[test]
+    fn parses_only_messages_with_the_prefix() {
+        let config = config();
+
+        assert!(config.parse::<Command>("play").is_none());
+        assert!(matches!(
+            config.parse::<Command>("!play"),
+            Some(Ok(Command::Play))
+        ));
+
+        let no_prefix = CommandConfig {
+            prefix: String::new(),
+            aliases: HashMap::new(),
+        };
+        assert!(no_prefix.parse::<Command>("play").is_none());
+    }
+
+    #[test]
+    fn parses_arguments() {
+        assert!(matches!(command("pick 3"), Ok(Command::Pick { number: 3 })));
+        assert!(matches!(command("skip"), Ok(Command::Next)));
+        assert!(matches!(
+            command("playnext \"http://a b\""),
+            Ok(Command::PlayNext { url }) if url == "http://a b"
+        ));
+        assert!(matches!(
+            command("seek -1:30"),
+            Ok(Command::Seek { amount: Seek::Negative(d) }) if d == Duration::from_secs(90)
+        ));
+        assert!(matches!(
+            command("queue move 5 1"),
+            Ok(Command::Queue {
+                page: None,
+                action: Some(QueueAction::Move { from: 5, to: 1 }),
+            })
+        ));
+        assert!(matches!(
+            command("subscription announce 2 on"),
+            Ok(Command::Subscription {
+                action: SubscriptionAction::Announce {
+                    number: 2,
+                    toggle: Toggle::On,
+                },
+            })
+        ));
+        assert!(matches!(
+            command("eq bass -3.5"),
+            Ok(Command::Eq { band: EqBand::Bass, gain: Some(g) }) if (g + 3.5).abs() < 1e-9
+        ));
+    }
+
+    #[test]
+    fn parses_master_commands() {
+        assert!(matches!(
+            master_command("spawn --force \"Lobby/Music Room\""),
+            Ok(MasterCommand::Spawn { force: true, channel }) if channel == vec!["Lobby/Music Room"]
+        ));
+        assert!(matches!(
+            master_command("spawn Lobby Music"),
+            Ok(MasterCommand::Spawn { force: false, channel }) if channel == vec!["Lobby", "Music"]
+        ));
+        assert!(matches!(
+            master_command("password"),
+            Err(CommandError::Usage { .. })
+        ));
+    }
+
+    #[test]
+    fn expands_aliases_of_the_first_word() {
+        assert!(matches!(
+            command("loud"),
+            Ok(Command::Volume { volume: VolumeChange::Absolute(v) }) if (v - 1.0).abs() < 1e-9
+        ));
+        assert!(matches!(
+            command("v +10"),
+            Ok(Command::Volume { volume: VolumeChange::Positive(v) }) if (v - 0.1).abs() < 1e-9
+        ));
+        // Quotes in the alias are kept together
+        assert!(matches!(
+            command("radio"),
+            Ok(Command::Add { url }) if url == "http://radio/a b"
+        ));
+        assert!(matches!(
+            command("add v"),
+            Ok(Command::Add { url }) if url == "v"
+        ));
+    }
+
+    #[test]
+    fn reports_bad_arguments() {
+        assert_eq!(
+            command("pick abc").unwrap_err(),
+            CommandError::InvalidValue(String::from("Expected a number, got \"abc\""))
+        );
+        assert_eq!(
+            command("announce chat maybe").unwrap_err(),
+            CommandError::InvalidValue(String::from("Expected \"on\" or \"off\", got \"maybe\""))
+        );
+        match command("seek soon") {
+            Err(CommandError::InvalidValue(problem)) => {
+                assert!(problem.starts_with("Expected a duration"), "{}", problem)
+            }
+            other => panic!("{:?}", other),
+        }
+        match command("add") {
+            Err(CommandError::Usage { problem, usage }) => {
+                assert_eq!(problem, "Missing <url>");
+                assert!(usage.starts_with('!'), "{}", usage);
+            }
+            other => panic!("{:?}", other),
+        }
+        match command("pick 1 2") {
+            Err(CommandError::Usage { problem, .. }) => assert_eq!(problem, "Unexpected \"2\""),
+            other => panic!("{:?}", other),
+        }
+        assert_eq!(
+            command("dance").unwrap_err(),
+            CommandError::UnknownCommand(String::from("dance"))
+        );
+        assert_eq!(
+            command("add \"url").unwrap_err(),
+            CommandError::UnclosedQuote
+        );
+    }
+
+    #[test]
+    fn keeps_raw_arguments() {
+        let config = config();
+
+        assert_eq!(
+            config.raw_arguments("!password  my \"pass word\"", "password"),
+            Some(" my \"pass word\"")
+        );
+        assert_eq!(config.raw_arguments("!password", "password"), Some(""));
+        assert_eq!(config.raw_arguments("!passwords x", "password"), None);
+        assert_eq!(config.raw_arguments("password x", "password"), None);
+    }
+}
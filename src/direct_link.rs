@@ -0,0 +1,540 @@
+//! Audio files that are linked directly and played without asking youtube-dl.
+//!
+//! Only the start of a file is fetched to check that it is audio and to read
+//! its tags, GStreamer requests the ranges it needs itself while playing and seeking.
+
+use std::time::Duration;
+
+use reqwest::header::{ACCEPT_RANGES, CONTENT_TYPE, RANGE};
+use reqwest::StatusCode;
+use tracing::{debug, info};
+
+use crate::youtube_dl::AudioMetadata;
+
+/// Files with other extensions are left to youtube-dl
+const EXTENSIONS: &[&str] = &["mp3", "ogg", "oga", "opus", "flac"];
+
+/// How much of the start of a file is read to find its tags
+const PROBE_BYTES: usize = 128 * 1024;
+
+/// How long connecting to the server of a link may take
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long the start of a file may take to arrive, slow servers are given up
+const PROBE_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// A client for probing links that gives up on servers that do not answer
+pub fn http_client() -> reqwest::Client {
+    reqwest::Client::builder()
+        .connect_timeout(CONNECT_TIMEOUT)
+        .build()
+        .expect("can build http client")
+}
+
+/// Whether the url looks like a link to an audio file
+pub fn is_candidate(url: &str) -> bool {
+    if !url.starts_with("http://") && !url.starts_with("https://") {
+        return false;
+    }
+
+    let path = url.split(|c| c == '?' || c == '#').next().unwrap_or(url);
+    let file = path.rsplit('/').next().unwrap_or(path);
+    match file.rfind('.') {
+        Some(i) => EXTENSIONS.contains(&file[i + 1..].to_lowercase().as_str()),
+        None => false,
+    }
+}
+
+/// Fetches the start of the file, returns `None` if the server does not send audio
+pub async fn probe(http: &reqwest::Client, url: &str) -> Result<Option<AudioMetadata>, String> {
+    let mut response = http
+        .get(url)
+        .header(RANGE, format!("bytes=0-{}", PROBE_BYTES - 1))
+        .timeout(PROBE_TIMEOUT)
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+    if !response.status().is_success() {
+        return Err(format!("the server answered {}", response.status()));
+    }
+
+    let content_type = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|t| t.to_str().ok())
+        .unwrap_or_default()
+        .to_lowercase();
+    if content_type.starts_with("text/") {
+        return Ok(None);
+    }
+    let ranges = response.status() == StatusCode::PARTIAL_CONTENT
+        || response
+            .headers()
+            .get(ACCEPT_RANGES)
+            .map_or(false, |r| r.as_bytes() == b"bytes");
+
+    // Servers that ignore the range send everything, the rest is not read
+    let mut data = Vec::new();
+    while let Some(chunk) = response.chunk().await.map_err(|e| e.to_string())? {
+        data.extend_from_slice(&chunk);
+        if data.len() >= PROBE_BYTES {
+            break;
+        }
+    }
+
+    let is_audio = content_type.starts_with("audio/")
+        || content_type == "application/ogg"
+        || crate::file_playback::audio_format(&data).is_some();
+    if !is_audio {
+        debug!("{} is not audio ({:?})", url, content_type);
+        return Ok(None);
+    }
+    if !ranges {
+        info!(
+            "{} does not support range requests, it can not be seeked",
+            url
+        );
+    }
+
+    let tags = read_tags(&data);
+    let title = match (&tags.artist, &tags.title) {
+        (Some(artist), Some(title)) => format!("{} - {}", artist, title),
+        (None, Some(title)) => title.clone(),
+        _ => file_name(url),
+    };
+
+    Ok(Some(AudioMetadata {
+        url: url.to_owned(),
+        webpage_url: url.to_owned(),
+        title,
+        thumbnail: None,
+        duration: tags.duration,
+        artist: tags.artist,
+        track: tags.title,
+        album: tags.album,
+        recording: None,
+        chapters: Vec::new(),
+        added_by: String::new(),
+        request_id: Default::default(),
+    }))
+}
+
+/// The last part of the path of the url, without query and percent encoding
+fn file_name(url: &str) -> String {
+    let path = url.split(|c| c == '?' || c == '#').next().unwrap_or(url);
+    let name = path.rsplit('/').next().unwrap_or(path);
+
+    percent_decode(name)
+}
+
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        // `from_str_radix` also accepts a sign, which is not a hex digit
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .filter(|h| h.iter().all(|b| b.is_ascii_hexdigit()))
+            .and_then(|h| std::str::from_utf8(h).ok())
+            .and_then(|h| u8::from_str_radix(h, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+#[derive(Debug, Default)]
+struct Tags {
+    title: Option<String>,
+    artist: Option<String>,
+    album: Option<String>,
+    duration: Option<Duration>,
+}
+
+/// Reads ID3v2 tags of MP3 files and the Vorbis comments of Ogg and FLAC files
+fn read_tags(data: &[u8]) -> Tags {
+    let mut tags = Tags::default();
+
+    if data.starts_with(b"ID3") {
+        read_id3(data, &mut tags);
+    } else if data.starts_with(b"fLaC") {
+        read_flac(data, &mut tags);
+    } else if data.starts_with(b"OggS") {
+        let comment = find(data, b"\x03vorbis")
+            .map(|i| i + 7)
+            .or_else(|| find(data, b"OpusTags").map(|i| i + 8));
+        if let Some(start) = comment {
+            read_vorbis_comment(&data[start..], &mut tags);
+        }
+    }
+
+    tags
+}
+
+fn find(data: &[u8], needle: &[u8]) -> Option<usize> {
+    data.windows(needle.len()).position(|w| w == needle)
+}
+
+fn be_u32(bytes: &[u8]) -> usize {
+    bytes.iter().fold(0, |n, &b| n << 8 | b as usize)
+}
+
+fn le_u32(bytes: &[u8]) -> Option<usize> {
+    let bytes = bytes.get(..4)?;
+
+    Some(bytes.iter().rev().fold(0, |n, &b| n << 8 | b as usize))
+}
+
+/// ID3v2 sizes only use the lower 7 bits of every byte
+fn syncsafe(bytes: &[u8]) -> usize {
+    bytes.iter().fold(0, |n, &b| n << 7 | (b & 0x7f) as usize)
+}
+
+fn read_id3(data: &[u8], tags: &mut Tags) {
+    if data.len() < 10 {
+        return;
+    }
+    // Version 2 uses shorter frame headers and is rare enough to be left out
+    let version = data[3];
+    if version < 3 {
+        return;
+    }
+
+    let end = (syncsafe(&data[6..10]) + 10).min(data.len());
+    let mut pos = 10;
+    if data[5] & 0x40 != 0 && data.len() >= 14 {
+        pos += match version {
+            3 => be_u32(&data[10..14]) + 4,
+            _ => syncsafe(&data[10..14]),
+        };
+    }
+
+    while pos + 10 <= end {
+        let id = &data[pos..pos + 4];
+        // The frames are followed by padding
+        if id[0] == 0 {
+            break;
+        }
+        let size = match version {
+            3 => be_u32(&data[pos + 4..pos + 8]),
+            _ => syncsafe(&data[pos + 4..pos + 8]),
+        };
+        let body = match data.get(pos + 10..pos + 10 + size) {
+            Some(body) => body,
+            None => break,
+        };
+
+        match id {
+            b"TIT2" => tags.title = id3_text(body),
+            b"TPE1" => tags.artist = id3_text(body),
+            b"TALB" => tags.album = id3_text(body),
+            b"TLEN" => {
+                tags.duration = id3_text(body)
+                    .and_then(|ms| ms.parse().ok())
+                    .map(Duration::from_millis)
+            }
+            _ => (),
+        }
+        pos += 10 + size;
+    }
+}
+
+/// The first value of a text frame, which starts with its encoding
+fn id3_text(body: &[u8]) -> Option<String> {
+    let (&encoding, text) = body.split_first()?;
+    let text = match encoding {
+        0 => text.iter().map(|&b| b as char).collect(),
+        1 | 2 => {
+            let little_endian = encoding == 1 && text.starts_with(&[0xff, 0xfe]);
+            let text = if encoding == 1 { text.get(2..)? } else { text };
+            let units = text
+                .chunks_exact(2)
+                .map(|c| {
+                    if little_endian {
+                        u16::from_le_bytes([c[0], c[1]])
+                    } else {
+                        u16::from_be_bytes([c[0], c[1]])
+                    }
+                })
+                .collect::<Vec<_>>();
+            String::from_utf16_lossy(&units)
+        }
+        _ => String::from_utf8_lossy(text).into_owned(),
+    };
+
+    let value = text.split('\0').next()?.trim();
+    if value.is_empty() {
+        None
+    } else {
+        Some(value.to_owned())
+    }
+}
+
+fn read_flac(data: &[u8], tags: &mut Tags) {
+    let mut pos = 4;
+    while let Some(header) = data.get(pos..pos + 4) {
+        let last = header[0] & 0x80 != 0;
+        let kind = header[0] & 0x7f;
+        let size = be_u32(&header[1..4]);
+        let block = match data.get(pos + 4..pos + 4 + size) {
+            Some(block) => block,
+            None => break,
+        };
+
+        match kind {
+            // STREAMINFO, the sample rate and sample count follow the block and frame sizes
+            0 if block.len() >= 18 => {
+                let info = block[10..18].iter().fold(0u64, |n, &b| n << 8 | b as u64);
+                let sample_rate = info >> 44;
+                let samples = info & 0xf_ffff_ffff;
+                if sample_rate > 0 && samples > 0 {
+                    tags.duration =
+                        Some(Duration::from_secs_f64(samples as f64 / sample_rate as f64));
+                }
+            }
+            4 => read_vorbis_comment(block, tags),
+            _ => (),
+        }
+
+        if last {
+            break;
+        }
+        pos += 4 + size;
+    }
+}
+
+fn read_vorbis_comment(data: &[u8], tags: &mut Tags) {
+    let vendor = match le_u32(data) {
+        Some(vendor) => vendor,
+        None => return,
+    };
+    let mut pos = 4 + vendor;
+    let count = match data.get(pos..).and_then(le_u32) {
+        Some(count) => count,
+        None => return,
+    };
+    pos += 4;
+
+    for _ in 0..count {
+        let length = match data.get(pos..).and_then(le_u32) {
+            Some(length) => length,
+            None => return,
+        };
+        let comment = match data.get(pos + 4..pos + 4 + length) {
+            Some(comment) => String::from_utf8_lossy(comment),
+            None => return,
+        };
+        pos += 4 + length;
+
+        let mut parts = comment.splitn(2, '=');
+        let (key, value) = match (parts.next(), parts.next()) {
+            (Some(key), Some(value)) if !value.trim().is_empty() => {
+                (key.to_uppercase(), value.trim().to_owned())
+            }
+            _ => continue,
+        };
+        match key.as_str() {
+            "TITLE" => tags.title = Some(value),
+            "ARTIST" => tags.artist = Some(value),
+            "ALBUM" => tags.album = Some(value),
+            _ => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn syncsafe_bytes(n: usize) -> [u8; 4] {
+        [
+            (n >> 21 & 0x7f) as u8,
+            (n >> 14 & 0x7f) as u8,
+            (n >> 7 & 0x7f) as u8,
+            (n & 0x7f) as u8,
+        ]
+    }
+
+    fn id3(version: u8, frames: &[(&[u8], Vec<u8>)]) -> Vec<u8> {
+        let mut body = Vec::new();
+        for (id, frame) in frames {
+            body.extend_from_slice(id);
+            match version {
+                3 => body.extend_from_slice(&(frame.len() as u32).to_be_bytes()),
+                _ => body.extend_from_slice(&syncsafe_bytes(frame.len())),
+            }
+            body.extend_from_slice(&[0, 0]);
+            body.extend_from_slice(frame);
+        }
+        // Padding
+        body.extend_from_slice(&[0; 16]);
+
+        let mut data = vec![b'I', b'D', b'3', version, 0, 0];
+        data.extend_from_slice(&syncsafe_bytes(body.len()));
+        data.extend_from_slice(&body);
+        data
+    }
+
+    fn latin1(text: &str) -> Vec<u8> {
+        let mut frame = vec![0];
+        frame.extend_from_slice(text.as_bytes());
+        frame
+    }
+
+    fn vorbis_comment(comments: &[&str]) -> Vec<u8> {
+        let vendor = b"test";
+        let mut data = (vendor.len() as u32).to_le_bytes().to_vec();
+        data.extend_from_slice(vendor);
+        data.extend_from_slice(&(comments.len() as u32).to_le_bytes());
+        for comment in comments {
+            data.extend_from_slice(&(comment.len() as u32).to_le_bytes());
+            data.extend_from_slice(comment.as_bytes());
+        }
+        data
+    }
+
+    #[test]
+    fn reads_id3v23_frames() {
+        let mut artist = vec![1, 0xff, 0xfe];
+        for unit in "Artist".encode_utf16() {
+            artist.extend_from_slice(&unit.to_le_bytes());
+        }
+        let data = id3(
+            3,
+            &[
+                (b"TIT2", latin1("Title")),
+                (b"TPE1", artist),
+                (b"TALB", latin1("Album\0")),
+                (b"TLEN", latin1("180000")),
+            ],
+        );
+
+        let tags = read_tags(&data);
+        assert_eq!(tags.title.as_deref(), Some("Title"));
+        assert_eq!(tags.artist.as_deref(), Some("Artist"));
+        assert_eq!(tags.album.as_deref(), Some("Album"));
+        assert_eq!(tags.duration, Some(Duration::from_secs(180)));
+    }
+
+    #[test]
+    fn reads_id3v24_syncsafe_frames() {
+        let title = "T".repeat(200);
+        let mut frame = vec![3];
+        frame.extend_from_slice(title.as_bytes());
+        let data = id3(4, &[(b"TIT2", frame), (b"TPE1", latin1("Artist"))]);
+
+        let tags = read_tags(&data);
+        assert_eq!(tags.title, Some(title));
+        assert_eq!(tags.artist.as_deref(), Some("Artist"));
+    }
+
+    #[test]
+    fn ignores_truncated_id3() {
+        let data = id3(3, &[(b"TIT2", latin1("Title"))]);
+        for end in 0..data.len() {
+            read_tags(&data[..end]);
+        }
+
+        // A frame claiming to be larger than the tag
+        let mut data = id3(3, &[(b"TIT2", latin1("Title"))]);
+        data[14..18].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert_eq!(read_tags(&data).title, None);
+
+        // An extended header larger than the tag
+        let mut data = id3(3, &[(b"TIT2", latin1("Title"))]);
+        data[5] = 0x40;
+        data[10..14].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert_eq!(read_tags(&data).title, None);
+    }
+
+    #[test]
+    fn reads_flac_stream_info_and_comments() {
+        let sample_rate = 44100u64;
+        let info = sample_rate << 44 | 1 << 41 | 15 << 36 | sample_rate * 3;
+        let mut stream_info = vec![0; 34];
+        stream_info[10..18].copy_from_slice(&info.to_be_bytes());
+        let comment = vorbis_comment(&["title=Title", "ARTIST=Artist", "ALBUM= ", "broken"]);
+
+        let mut data = b"fLaC".to_vec();
+        data.push(0);
+        data.extend_from_slice(&(stream_info.len() as u32).to_be_bytes()[1..]);
+        data.extend_from_slice(&stream_info);
+        data.push(0x84);
+        data.extend_from_slice(&(comment.len() as u32).to_be_bytes()[1..]);
+        data.extend_from_slice(&comment);
+
+        let tags = read_tags(&data);
+        assert_eq!(tags.title.as_deref(), Some("Title"));
+        assert_eq!(tags.artist.as_deref(), Some("Artist"));
+        assert_eq!(tags.album, None);
+        assert_eq!(tags.duration, Some(Duration::from_secs(3)));
+
+        for end in 0..data.len() {
+            read_tags(&data[..end]);
+        }
+    }
+
+    #[test]
+    fn reads_ogg_comments() {
+        let mut data = b"OggS".to_vec();
+        data.extend_from_slice(&[0; 24]);
+        data.extend_from_slice(b"\x03vorbis");
+        data.extend_from_slice(&vorbis_comment(&["TITLE=Title", "ALBUM=Album"]));
+
+        let tags = read_tags(&data);
+        assert_eq!(tags.title.as_deref(), Some("Title"));
+        assert_eq!(tags.album.as_deref(), Some("Album"));
+        assert_eq!(tags.artist, None);
+
+        for end in 0..data.len() {
+            read_tags(&data[..end]);
+        }
+    }
+
+    #[test]
+    fn ignores_oversized_vorbis_comments() {
+        let mut data = vorbis_comment(&["TITLE=Title"]);
+        data[8..12].copy_from_slice(&u32::MAX.to_le_bytes());
+        let mut tags = Tags::default();
+        read_vorbis_comment(&data, &mut tags);
+        assert_eq!(tags.title.as_deref(), Some("Title"));
+
+        let mut data = vorbis_comment(&["TITLE=Title"]);
+        data[12..16].copy_from_slice(&u32::MAX.to_le_bytes());
+        let mut tags = Tags::default();
+        read_vorbis_comment(&data, &mut tags);
+        assert_eq!(tags.title, None);
+    }
+
+    #[test]
+    fn decodes_percent_encoding() {
+        assert_eq!(percent_decode("My%20Song.mp3"), "My Song.mp3");
+        assert_eq!(percent_decode("%41%62"), "Ab");
+        assert_eq!(percent_decode("caf%C3%A9"), "café");
+        assert_eq!(percent_decode("100%"), "100%");
+        assert_eq!(percent_decode("%4"), "%4");
+        assert_eq!(percent_decode("%zz"), "%zz");
+        assert_eq!(percent_decode("%+1"), "%+1");
+        assert_eq!(percent_decode("%-1"), "%-1");
+        assert_eq!(percent_decode("%%41"), "%A");
+        assert_eq!(percent_decode("%é"), "%é");
+    }
+
+    #[test]
+    fn takes_file_name_from_url() {
+        assert_eq!(
+            file_name("https://example.com/music/My%20Song.mp3?download=1#t=10"),
+            "My Song.mp3"
+        );
+    }
+}
@@ -0,0 +1,600 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use serenity::async_trait as serenity_async_trait;
+use serenity::cache::Cache;
+use serenity::client::bridge::gateway::ShardManager;
+use serenity::client::{Client, Context, EventHandler};
+use serenity::gateway::ConnectionStage;
+use serenity::http::Http;
+use serenity::model::channel::Message as DiscordMessage;
+use serenity::model::gateway::Ready;
+use serenity::model::id::{ChannelId as DiscordChannelId, GuildId};
+use serenity::model::voice::VoiceState;
+use songbird::{SerenityInit, Songbird};
+use tokio::sync::Mutex;
+use tracing::{error, info, warn};
+use tsclientlib::{ChannelId, ClientId, Invoker, MessageTarget, Uid};
+
+use crate::bot::{
+    BotCreationError, BotManager, BotRegistry, BotTemplate, MasterActionError, MasterArgs, Message,
+    MusicBot, MusicBotMessage, Slot,
+};
+use crate::command::Command;
+use crate::discord::DiscordConnection;
+use crate::error::Error;
+use crate::i18n::Locale;
+use crate::rate_limit::RateLimit;
+use crate::reports::ReportStore;
+use crate::scheduler::ScheduleStore;
+use crate::stats::StatsStore;
+use crate::tr;
+use crate::web_server::{BotDetails, BotSummary, IdentityInfo, MasterData, WebServerStatus};
+use crate::youtube_dl::Downloader;
+
+/// How long the shard manager may take to report the state of the shards
+const PING_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Spawns a music bot for every server where somebody sends a command from
+/// a voice channel.
+///
+/// All bots share one gateway connection, messages and voice state changes
+/// are forwarded to the bot of the server they happened on.
+pub struct DiscordMaster {
+    config: BotTemplate,
+    registry: BotRegistry,
+    /// The music bot on each server
+    guilds: Arc<RwLock<HashMap<GuildId, GuildBot>>>,
+    gateway: RwLock<Option<Gateway>>,
+    songbird: Arc<Songbird>,
+    shard_manager: Mutex<Option<Arc<Mutex<ShardManager>>>>,
+    started: Instant,
+    status: RwLock<DiscordStatus>,
+}
+
+#[derive(Clone)]
+struct Gateway {
+    http: Arc<Http>,
+    cache: Arc<Cache>,
+}
+
+struct DiscordStatus {
+    description: String,
+    connected: bool,
+    maintenance: bool,
+    web_server: WebServerStatus,
+}
+
+enum GuildBot {
+    /// Keeps the messages for the bot until it is there
+    Connecting {
+        name: String,
+        pending: Vec<Message>,
+    },
+    Connected(Arc<MusicBot>),
+}
+
+struct Handler {
+    master: Arc<DiscordMaster>,
+}
+
+impl DiscordMaster {
+    pub async fn new(args: MasterArgs) -> Result<(Arc<Self>, impl Future<Output = ()>), Error> {
+        info!("Starting in Discord mode");
+
+        let token = match &args.discord {
+            Some(discord) => discord.token.clone(),
+            None => {
+                return Err(Error::Discord(String::from(
+                    "No [discord] token configured",
+                )))
+            }
+        };
+
+        // Discord bots have no identities, every name can connect
+        let registry = BotRegistry::start(args.names.clone(), args.names.len(), args.max_bots);
+        // Discord has no servers to connect to, the playlists and settings are shared
+        let config = BotTemplate::open(args, "discord").await;
+
+        let songbird = Songbird::serenity();
+        let master = Arc::new(Self {
+            config,
+            registry,
+            guilds: Arc::new(RwLock::new(HashMap::new())),
+            gateway: RwLock::new(None),
+            songbird: songbird.clone(),
+            shard_manager: Mutex::new(None),
+            started: Instant::now(),
+            status: RwLock::new(DiscordStatus {
+                description: String::new(),
+                connected: false,
+                maintenance: false,
+                web_server: WebServerStatus::Starting,
+            }),
+        });
+
+        let mut client = Client::builder(&token)
+            .event_handler(Handler {
+                master: master.clone(),
+            })
+            .register_songbird_with(songbird)
+            .await
+            .map_err(|e| Error::Discord(e.to_string()))?;
+        *master.shard_manager.lock().await = Some(client.shard_manager.clone());
+
+        let cmaster = master.clone();
+        let gateway = async move {
+            if let Err(e) = client.start().await {
+                error!("Discord gateway failed: {}", e);
+            }
+            cmaster.set_connected(false);
+        };
+
+        Ok((master, gateway))
+    }
+
+    fn gateway(&self) -> Option<Gateway> {
+        self.gateway.read().unwrap().clone()
+    }
+
+    fn set_connected(&self, connected: bool) {
+        self.status.write().unwrap().connected = connected;
+    }
+
+    fn is_connected(&self) -> bool {
+        self.status.read().unwrap().connected
+    }
+
+    fn is_in_maintenance(&self) -> bool {
+        self.status.read().unwrap().maintenance
+    }
+
+    fn bot_in(&self, guild: GuildId) -> Option<Arc<MusicBot>> {
+        match self.guilds.read().unwrap().get(&guild)? {
+            GuildBot::Connected(bot) => Some(bot.clone()),
+            GuildBot::Connecting { .. } => None,
+        }
+    }
+
+    /// The language the user picked with `!lang`, the one of the server otherwise
+    fn locale(&self, guild: GuildId, user: &str) -> Arc<Locale> {
+        let language = self.config.user_settings.get(user).language;
+
+        self.config
+            .locales
+            .for_user(&guild.to_string(), language.as_deref())
+    }
+
+    async fn on_message(&self, ctx: &Context, msg: DiscordMessage) {
+        if msg.author.bot {
+            return;
+        }
+        let guild = match msg.guild_id {
+            Some(guild) => guild,
+            None => return,
+        };
+
+        let message = Message {
+            target: MessageTarget::Channel,
+            invoker: Invoker {
+                name: msg.author.name.clone(),
+                id: ClientId(0),
+                uid: Some(Uid(msg.author.id.to_string())),
+            },
+            text: msg.content.clone(),
+        };
+        let message = match self.post_to_bot(guild, message) {
+            Ok(None) => return,
+            Ok(Some(name)) => {
+                let locale = self.locale(guild, &msg.author.id.to_string());
+                // Only Discord keeps the commands until the bot is there
+                let reply = tr!(
+                    locale,
+                    "command-waits-for-bot",
+                    "\"{name}\" is still connecting, your command is handled once it is there.",
+                    name = name
+                );
+                if let Err(e) = msg.channel_id.say(&ctx.http, reply).await {
+                    error!("Failed to send message: {}", e);
+                }
+                return;
+            }
+            Err(message) => message,
+        };
+
+        // Only spawn bots for messages that are meant for them
+        match self.config.commands.parse::<Command>(&msg.content) {
+            Some(Ok(_)) => (),
+            _ => return,
+        }
+
+        let limit = self
+            .config
+            .rate_limiter
+            .check_poke(&msg.author.id.to_string());
+        if limit != RateLimit::Allowed {
+            if let Some(reply) = limit.reply(&msg.author.name) {
+                if let Err(e) = msg.channel_id.say(&ctx.http, reply).await {
+                    error!("Failed to send message: {}", e);
+                }
+            }
+            return;
+        }
+
+        if let Err(e) = self.spawn_bot_for(ctx, guild, &msg, message).await {
+            warn!("Failed to create bot for {}: {}", msg.author.name, e);
+            let locale = self.locale(guild, &msg.author.id.to_string());
+            let reply = e.localize(&locale);
+            if let Err(e) = msg.channel_id.say(&ctx.http, reply).await {
+                error!("Failed to send message: {}", e);
+            }
+        }
+    }
+
+    /// Passes the message on to the bot on the server.
+    ///
+    /// Returns the name of the bot if it is still connecting, the message is
+    /// handled once it is there. The message is returned if there is no bot.
+    fn post_to_bot(&self, guild: GuildId, message: Message) -> Result<Option<String>, Message> {
+        match self.guilds.write().unwrap().get_mut(&guild) {
+            Some(GuildBot::Connecting { name, pending }) => {
+                pending.push(message);
+                Ok(Some(name.clone()))
+            }
+            Some(GuildBot::Connected(bot)) => {
+                bot.post(MusicBotMessage::TextMessage(message));
+                Ok(None)
+            }
+            None => Err(message),
+        }
+    }
+
+    async fn spawn_bot_for(
+        &self,
+        ctx: &Context,
+        guild: GuildId,
+        msg: &DiscordMessage,
+        message: Message,
+    ) -> Result<Arc<MusicBot>, MasterActionError> {
+        if self.is_in_maintenance() {
+            return Err(MasterActionError::Maintenance);
+        }
+
+        let voice_channel = ctx.cache.guild(guild).await.and_then(|g| {
+            g.voice_states
+                .get(&msg.author.id)
+                .and_then(|s| s.channel_id)
+        });
+        let voice_channel = match voice_channel {
+            Some(channel) => channel,
+            None => return Err(MasterActionError::NotInVoice),
+        };
+
+        self.spawn_bot(guild, voice_channel, msg.channel_id, vec![message])
+            .await
+    }
+
+    async fn spawn_bot(
+        &self,
+        guild: GuildId,
+        voice_channel: DiscordChannelId,
+        text_channel: DiscordChannelId,
+        queued: Vec<Message>,
+    ) -> Result<Arc<MusicBot>, MasterActionError> {
+        let gateway = self.gateway().ok_or(MasterActionError::NotConnected)?;
+
+        if let Some(GuildBot::Connected(bot)) = self.guilds.read().unwrap().get(&guild) {
+            return Err(MasterActionError::BotCreation(
+                BotCreationError::MultipleBots(bot.name().to_owned()),
+            ));
+        }
+        // The registry only lets one bot connect per server at a time
+        let slot = self
+            .registry
+            .spawn(ChannelId(guild.0))
+            .await
+            .map_err(MasterActionError::BotCreation)?;
+        let name = slot.name.clone();
+        // Claim the server now so a second command is kept for the bot
+        self.guilds.write().unwrap().insert(
+            guild,
+            GuildBot::Connecting {
+                name: name.clone(),
+                pending: queued,
+            },
+        );
+
+        let connection = DiscordConnection::join(
+            gateway.http,
+            gateway.cache,
+            self.songbird.clone(),
+            guild,
+            voice_channel,
+            text_channel,
+        )
+        .await;
+        let connection = match connection {
+            Ok(connection) => Arc::new(connection),
+            Err(e) => {
+                self.release(guild, slot);
+                return Err(MasterActionError::Spawn(e));
+            }
+        };
+
+        let cregistry = self.registry.clone();
+        let cguilds = self.guilds.clone();
+        let disconnect_cb = Box::new(move |name: String, name_index, id_index| {
+            cguilds.write().unwrap().remove(&guild);
+            cregistry.despawn(Slot {
+                name,
+                name_index,
+                id_index,
+            });
+        });
+
+        info!("Joining {} on {}", voice_channel, guild);
+
+        // Anyone on the server can read the messages, so there is no control link
+        let bot_args = self.config.bot_args(
+            &slot,
+            guild.to_string(),
+            connection,
+            voice_channel.to_string(),
+            None,
+            disconnect_cb,
+        );
+
+        let (bot, fut) = match MusicBot::new(bot_args).await {
+            Ok(bot) => bot,
+            Err(e) => {
+                if let Err(e) = self.songbird.remove(guild).await {
+                    error!("Failed to leave voice channel: {}", e);
+                }
+                self.release(guild, slot);
+                return Err(MasterActionError::Spawn(e));
+            }
+        };
+        tokio::spawn(fut);
+
+        self.registry.connected(name, Some(bot.clone()));
+        let previous = self
+            .guilds
+            .write()
+            .unwrap()
+            .insert(guild, GuildBot::Connected(bot.clone()));
+        // Commands that arrived while connecting, starting with the one that spawned the bot
+        if let Some(GuildBot::Connecting { pending, .. }) = previous {
+            for message in pending {
+                bot.post(MusicBotMessage::TextMessage(message));
+            }
+        }
+
+        Ok(bot)
+    }
+
+    fn release(&self, guild: GuildId, slot: Slot) {
+        self.guilds.write().unwrap().remove(&guild);
+        self.registry.despawn(slot);
+    }
+
+    async fn on_voice_state_update(&self, guild: Option<GuildId>, state: VoiceState) {
+        let guild = match guild {
+            Some(guild) => guild,
+            None => return,
+        };
+        let bot = match self.bot_in(guild) {
+            Some(bot) => bot,
+            None => return,
+        };
+
+        let me = match self.gateway() {
+            Some(gateway) => gateway.cache.current_user_id().await,
+            None => return,
+        };
+
+        if state.user_id == me && state.channel_id.is_none() {
+            info!("{} was disconnected from the voice channel", bot.name());
+            bot.quit(String::from("Disconnected"));
+        } else {
+            bot.post(MusicBotMessage::ListenersChanged);
+        }
+    }
+
+    pub fn set_web_server_status(&self, web_server: WebServerStatus) {
+        self.status.write().unwrap().web_server = web_server;
+    }
+}
+
+#[serenity_async_trait]
+impl EventHandler for Handler {
+    async fn ready(&self, ctx: Context, ready: Ready) {
+        info!(
+            "Connected to Discord as {} on {} servers",
+            ready.user.name,
+            ready.guilds.len()
+        );
+
+        *self.master.gateway.write().unwrap() = Some(Gateway {
+            http: ctx.http.clone(),
+            cache: ctx.cache.clone(),
+        });
+        self.master.set_connected(true);
+    }
+
+    async fn message(&self, ctx: Context, msg: DiscordMessage) {
+        self.master.on_message(&ctx, msg).await;
+    }
+
+    async fn voice_state_update(
+        &self,
+        _ctx: Context,
+        guild: Option<GuildId>,
+        _old: Option<VoiceState>,
+        new: VoiceState,
+    ) {
+        self.master.on_voice_state_update(guild, new).await;
+    }
+}
+
+#[async_trait(?Send)]
+impl BotManager for DiscordMaster {
+    async fn master_data(&self) -> MasterData {
+        let (description, connected, maintenance, web_server) = {
+            let status = self.status.read().unwrap();
+            (
+                status.description.clone(),
+                status.connected,
+                status.maintenance,
+                status.web_server.clone(),
+            )
+        };
+
+        let registered = self.registry.registered();
+
+        MasterData {
+            name: self.config.master_name.clone(),
+            channel: None,
+            description,
+            uptime: self.started.elapsed(),
+            connected,
+            maintenance,
+            available_names: registered.available_names,
+            available_identities: 0,
+            connected_bots: registered.bots.keys().cloned().collect(),
+            web_server,
+            messages: None,
+        }
+    }
+
+    async fn set_description(&self, description: String) -> Result<(), MasterActionError> {
+        // Only shown on the web interface, bot accounts have no description
+        self.status.write().unwrap().description = description;
+
+        Ok(())
+    }
+
+    async fn move_to_channel(&self, _channel: String) -> Result<(), MasterActionError> {
+        Err(MasterActionError::Unsupported)
+    }
+
+    /// Spawns a bot in a voice channel given by id, replies go to the
+    /// system channel of the server
+    async fn spawn_bot_in(&self, channel: &str) -> Result<String, MasterActionError> {
+        if !self.is_connected() {
+            return Err(MasterActionError::NotConnected);
+        }
+        let gateway = self.gateway().ok_or(MasterActionError::NotConnected)?;
+
+        let unknown = || MasterActionError::UnknownChannel(channel.to_owned());
+        let id = channel
+            .trim()
+            .parse::<u64>()
+            .map(DiscordChannelId)
+            .map_err(|_| unknown())?;
+        let guild_channel = match gateway.cache.guild_channel(id).await {
+            Some(channel) => channel,
+            None => return Err(unknown()),
+        };
+        let text_channel = gateway
+            .cache
+            .guild(guild_channel.guild_id)
+            .await
+            .and_then(|g| g.system_channel_id)
+            .ok_or(MasterActionError::BotCreation(
+                BotCreationError::UnfoundChannel,
+            ))?;
+
+        let bot = self
+            .spawn_bot(guild_channel.guild_id, id, text_channel, Vec::new())
+            .await?;
+
+        Ok(bot.name().to_owned())
+    }
+
+    /// Serenity runs the event handlers, they work while a shard is connected
+    /// or reconnecting and its runner answers
+    async fn ping(&self) -> bool {
+        let shard_manager = match self.shard_manager.lock().await.as_ref() {
+            Some(shard_manager) => shard_manager.clone(),
+            None => return false,
+        };
+        let stages = async {
+            let runners = shard_manager.lock().await.runners.clone();
+            let runners = runners.lock().await;
+            runners
+                .values()
+                .map(|runner| runner.stage)
+                .collect::<Vec<_>>()
+        };
+
+        match tokio::time::timeout(PING_TIMEOUT, stages).await {
+            Ok(stages) => stages
+                .iter()
+                .any(|stage| *stage == ConnectionStage::Connected || stage.is_connecting()),
+            Err(_) => false,
+        }
+    }
+
+    async fn quit(&self, reason: String) {
+        self.registry.quit_all(reason.clone()).await;
+
+        if let Some(shard_manager) = self.shard_manager.lock().await.as_ref() {
+            shard_manager.lock().await.shutdown_all().await;
+        }
+        self.set_connected(false);
+    }
+
+    fn set_maintenance(&self, enabled: bool) {
+        info!("Setting maintenance mode: {}", enabled);
+
+        self.status.write().unwrap().maintenance = enabled;
+    }
+
+    fn set_web_server_status(&self, web_server: WebServerStatus) {
+        DiscordMaster::set_web_server_status(self, web_server)
+    }
+
+    // Discord bots log in with a token, there are no identities
+    fn identities(&self) -> Vec<IdentityInfo> {
+        Vec::new()
+    }
+
+    fn music_bot(&self, name: &str) -> Option<Arc<MusicBot>> {
+        self.registry.registered().bots.get(name).cloned()
+    }
+
+    fn bot_names(&self) -> Vec<String> {
+        self.registry.registered().bots.keys().cloned().collect()
+    }
+
+    fn bot_summaries(&self) -> Vec<BotSummary> {
+        let registered = self.registry.registered();
+
+        registered.bots.values().map(|bot| bot.summary()).collect()
+    }
+
+    fn bot_details(&self, name: String) -> Option<BotDetails> {
+        let bot = self.music_bot(&name)?;
+
+        Some(bot.details())
+    }
+
+    fn stats(&self) -> Option<Arc<StatsStore>> {
+        self.config.stats.clone()
+    }
+
+    fn reports(&self) -> Arc<ReportStore> {
+        self.config.reports.clone()
+    }
+
+    fn schedule(&self) -> Arc<ScheduleStore> {
+        self.config.schedule.clone()
+    }
+
+    fn downloader(&self) -> Arc<Downloader> {
+        self.config.downloader.clone()
+    }
+}
@@ -0,0 +1,289 @@
+use std::io::{self, Read};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TryRecvError, TrySendError};
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serenity::cache::Cache;
+use serenity::http::Http;
+use serenity::model::id::{ChannelId as DiscordChannelId, GuildId, UserId};
+use songbird::input::codec::OpusDecoderState;
+use songbird::input::{Codec, Container, Input, Reader};
+use songbird::tracks::TrackHandle;
+use songbird::Songbird;
+use tracing::{debug, error};
+use tsclientlib::{ChannelId, Invoker};
+
+use crate::error::Error;
+use crate::frontend::{Frontend, Whisper, COMFORT_NOISE_PACKET};
+
+mod master;
+
+pub use master::DiscordMaster;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DiscordConfig {
+    /// Token of the bot account, all music bots share it and join one voice channel per server
+    pub token: String,
+}
+
+/// How many opus frames may wait for the voice connection, 1 second of audio
+const AUDIO_BUFFER_FRAMES: usize = 50;
+
+/// The frame Discord expects when a client goes silent
+const SILENCE_FRAME: &[u8] = &[0xF8, 0xFF, 0xFE];
+
+/// A music bot in a Discord voice channel.
+///
+/// The opus frames of the audio player are passed through to songbird, so
+/// the audio is encoded only once just like on TeamSpeak.
+pub struct DiscordConnection {
+    http: Arc<Http>,
+    cache: Arc<Cache>,
+    songbird: Arc<Songbird>,
+    guild: GuildId,
+    voice_channel: DiscordChannelId,
+    /// Where the bot was summoned, replies are sent there
+    text_channel: DiscordChannelId,
+    frames: Mutex<SyncSender<Vec<u8>>>,
+    track: TrackHandle,
+    paused: AtomicBool,
+}
+
+impl DiscordConnection {
+    pub async fn join(
+        http: Arc<Http>,
+        cache: Arc<Cache>,
+        songbird: Arc<Songbird>,
+        guild: GuildId,
+        voice_channel: DiscordChannelId,
+        text_channel: DiscordChannelId,
+    ) -> Result<Self, Error> {
+        let (call, result) = songbird.join(guild, voice_channel).await;
+        result.map_err(|e| Error::Discord(e.to_string()))?;
+
+        let (frames, receiver) = mpsc::sync_channel(AUDIO_BUFFER_FRAMES);
+        let decoder = OpusDecoderState::new().map_err(|e| Error::Discord(e.to_string()))?;
+        let input = Input::new(
+            true,
+            Reader::Extension(Box::new(DcaPipe::new(receiver))),
+            Codec::Opus(decoder),
+            Container::Dca { first_frame: 0 },
+            None,
+        );
+        let track = call.lock().await.play_source(input);
+
+        Ok(Self {
+            http,
+            cache,
+            songbird,
+            guild,
+            voice_channel,
+            text_channel,
+            frames: Mutex::new(frames),
+            track,
+            paused: AtomicBool::new(false),
+        })
+    }
+
+    fn push_frame(&self, frame: &[u8]) {
+        if self.paused.swap(false, Ordering::SeqCst) {
+            if let Err(e) = self.track.play() {
+                error!("Failed to resume voice track: {}", e);
+            }
+        }
+
+        match self.frames.lock().unwrap().try_send(frame.to_vec()) {
+            Ok(()) => (),
+            Err(TrySendError::Full(_)) => debug!("Voice connection is behind, dropping frame"),
+            Err(TrySendError::Disconnected(_)) => debug!("Voice track ended, dropping frame"),
+        }
+    }
+
+    /// The voice channel the bot is in, members can move the bot around
+    async fn current_channel(&self) -> DiscordChannelId {
+        let me = self.cache.current_user_id().await;
+
+        self.cache
+            .guild(self.guild)
+            .await
+            .and_then(|guild| guild.voice_states.get(&me).and_then(|s| s.channel_id))
+            .unwrap_or(self.voice_channel)
+    }
+}
+
+#[async_trait]
+impl Frontend for DiscordConnection {
+    async fn send_audio_packet(&self, samples: &[u8], _whisper: &Whisper) {
+        self.push_frame(samples);
+    }
+
+    async fn send_audio_end(&self, _whisper: &Whisper) {
+        // Songbird stops sending and clears the speaking state while no track plays
+        self.paused.store(true, Ordering::SeqCst);
+        if let Err(e) = self.track.pause() {
+            error!("Failed to pause voice track: {}", e);
+        }
+    }
+
+    async fn send_comfort_noise(&self, _whisper: &Whisper) {
+        self.push_frame(COMFORT_NOISE_PACKET);
+    }
+
+    async fn send_message_to_channel(&self, text: String) {
+        if let Err(e) = self.text_channel.say(&self.http, markdown(&text)).await {
+            error!("Failed to send message: {}", e);
+        }
+    }
+
+    /// The unique id of Discord invokers is their user id
+    async fn send_message_to_user(&self, invoker: &Invoker, text: String) {
+        let user = match invoker.uid.as_ref().and_then(|uid| uid.0.parse().ok()) {
+            Some(id) => UserId(id),
+            None => return self.send_message_to_channel(text).await,
+        };
+
+        let result = match user.create_dm_channel(&self.http).await {
+            Ok(dm) => dm.say(&self.http, markdown(&text)).await.map(|_| ()),
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            error!("Failed to send direct message: {}", e);
+        }
+    }
+
+    async fn set_nickname(&self, name: String) {
+        if let Err(e) = self.guild.edit_nickname(&self.http, Some(&name)).await {
+            error!("Failed to set nickname: {}", e);
+        }
+    }
+
+    async fn set_description(&self, desc: String) {
+        debug!("Discord has no descriptions, not setting {:?}", desc);
+    }
+
+    // The avatar belongs to the bot account and is shared by all servers
+    async fn set_avatar(&self, _data: Vec<u8>) -> Result<(), String> {
+        Ok(())
+    }
+
+    async fn remove_avatar(&self) -> Result<(), String> {
+        Ok(())
+    }
+
+    async fn my_channel(&self) -> Option<ChannelId> {
+        Some(ChannelId(self.current_channel().await.0))
+    }
+
+    async fn listener_count(&self, ignored: Arc<Vec<String>>) -> u32 {
+        let channel = self.current_channel().await;
+        let guild = match self.cache.guild(self.guild).await {
+            Some(guild) => guild,
+            None => return 0,
+        };
+
+        guild
+            .voice_states
+            .iter()
+            .filter(|(_, state)| state.channel_id == Some(channel))
+            .filter(|(user, _)| !ignored.contains(&user.to_string()))
+            .filter(|(user, _)| match guild.members.get(user) {
+                Some(member) => !member.user.bot,
+                None => true,
+            })
+            .count() as u32
+    }
+
+    async fn disconnect(&self, _reason: &str) {
+        if let Err(e) = self.songbird.remove(self.guild).await {
+            error!("Failed to leave voice channel: {}", e);
+        }
+    }
+}
+
+/// Serves opus frames in the DCA format songbird reads, which prefixes
+/// every frame with its length.
+///
+/// The mixer must never wait for data, so silence is served if the player
+/// did not send anything.
+struct DcaPipe {
+    frames: Receiver<Vec<u8>>,
+    buffer: Vec<u8>,
+    position: usize,
+}
+
+impl DcaPipe {
+    fn new(frames: Receiver<Vec<u8>>) -> Self {
+        Self {
+            frames,
+            buffer: Vec::new(),
+            position: 0,
+        }
+    }
+}
+
+impl Read for DcaPipe {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.position == self.buffer.len() {
+            let frame = match self.frames.try_recv() {
+                Ok(frame) => frame,
+                Err(TryRecvError::Empty) => SILENCE_FRAME.to_vec(),
+                Err(TryRecvError::Disconnected) => return Ok(0),
+            };
+
+            self.buffer.clear();
+            self.buffer
+                .extend_from_slice(&(frame.len() as i16).to_le_bytes());
+            self.buffer.extend_from_slice(&frame);
+            self.position = 0;
+        }
+
+        let len = buf.len().min(self.buffer.len() - self.position);
+        buf[..len].copy_from_slice(&self.buffer[self.position..self.position + len]);
+        self.position += len;
+
+        Ok(len)
+    }
+}
+
+/// Converts the BBCode the bots write for TeamSpeak into Discord markdown
+fn markdown(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = rest.find('[') {
+        result.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let end = match rest.find(']') {
+            Some(end) => end,
+            None => break,
+        };
+        let tag = &rest[1..end];
+        rest = &rest[end + 1..];
+
+        match tag {
+            "B" | "/B" => result.push_str("**"),
+            "I" | "/I" => result.push('*'),
+            "U" | "/U" => result.push_str("__"),
+            "/URL" => (),
+            _ => match tag.strip_prefix("URL=") {
+                Some(url) => {
+                    // Discord does not support named links for bots, put the url after the text
+                    let text_end = rest.find("[/URL]").unwrap_or_else(|| rest.len());
+                    result.push_str(&format!("{} (<{}>)", &rest[..text_end], url));
+                    rest = &rest[text_end..];
+                }
+                None => {
+                    result.push('[');
+                    result.push_str(tag);
+                    result.push(']');
+                }
+            },
+        }
+    }
+    result.push_str(rest);
+
+    result
+}
@@ -0,0 +1,240 @@
+use std::time::Duration;
+
+use serde::{Deserialize, Deserializer, Serializer};
+
+#[derive(Debug, Clone)]
+pub enum DurationError {
+    Empty,
+    InvalidTimestamp(String),
+    InvalidDuration(String, humantime::DurationError),
+}
+
+impl std::fmt::Display for DurationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use DurationError::*;
+        match self {
+            Empty => write!(f, "expected a duration, got nothing"),
+            InvalidTimestamp(s) => write!(
+                f,
+                "expected a timestamp like \"2:15\" or \"1:02:15\", got \"{}\"",
+                s
+            ),
+            InvalidDuration(s, e) => write!(
+                f,
+                "expected a duration like \"90s\" or \"1h30m\", got \"{}\" ({})",
+                s, e
+            ),
+        }
+    }
+}
+
+impl std::error::Error for DurationError {}
+
+/// Parses a human-friendly duration.
+///
+/// Accepts plain seconds (`90`), humantime durations (`90s`, `1h30m`)
+/// and timestamps (`2:15`, `1:02:15`).
+pub fn parse(text: &str) -> Result<Duration, DurationError> {
+    let text = text.trim();
+
+    if text.is_empty() {
+        return Err(DurationError::Empty);
+    }
+
+    if text.contains(':') {
+        return parse_timestamp(text);
+    }
+
+    if let Ok(secs) = text.parse::<u64>() {
+        return Ok(Duration::from_secs(secs));
+    }
+
+    humantime::parse_duration(text).map_err(|e| DurationError::InvalidDuration(text.to_owned(), e))
+}
+
+fn parse_timestamp(text: &str) -> Result<Duration, DurationError> {
+    let invalid = || DurationError::InvalidTimestamp(text.to_owned());
+
+    let parts = text.split(':').collect::<Vec<_>>();
+    if parts.len() > 3 {
+        return Err(invalid());
+    }
+
+    let mut secs: u64 = 0;
+    for (i, part) in parts.iter().enumerate() {
+        let value = part.parse::<u64>().map_err(|_| invalid())?;
+
+        // Everything but the most significant part has to fit into a minute
+        if i != 0 && value >= 60 {
+            return Err(invalid());
+        }
+
+        secs = secs
+            .checked_mul(60)
+            .and_then(|s| s.checked_add(value))
+            .ok_or_else(invalid)?;
+    }
+
+    Ok(Duration::from_secs(secs))
+}
+
+/// Formats a duration as a timestamp like `3:07` or `1:02:15`.
+pub fn format_timestamp(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    let hours = secs / 3600;
+    let mins = (secs / 60) % 60;
+    let submin_secs = secs % 60;
+
+    if hours > 0 {
+        format!("{}:{:02}:{:02}", hours, mins, submin_secs)
+    } else {
+        format!("{}:{:02}", mins, submin_secs)
+    }
+}
+
+/// Serde helper for config values, use with `#[serde(with = "crate::duration::serde_human")]`.
+pub mod serde_human {
+    use super::*;
+
+    pub fn serialize<S>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&humantime::format_duration(*duration).to_string())
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Duration, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let text = String::deserialize(deserializer)?;
+
+        parse(&text).map_err(serde::de::Error::custom)
+    }
+}
+
+/// Same as [`serde_human`] for optional values.
+pub mod serde_human_option {
+    use super::*;
+
+    pub fn serialize<S>(duration: &Option<Duration>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match duration {
+            Some(d) => serde_human::serialize(d, serializer),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Option<Duration>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let text: Option<String> = Deserialize::deserialize(deserializer)?;
+
+        text.map(|t| parse(&t).map_err(serde::de::Error::custom))
+            .transpose()
+    }
+}
+
+/// Same as [`serde_human`] for maps with durations as values.
+pub mod serde_human_map {
+    use std::collections::HashMap;
+
+    use serde::ser::SerializeMap;
+
+    use super::*;
+
+    pub fn serialize<S>(
+        durations: &HashMap<String, Duration>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut map = serializer.serialize_map(Some(durations.len()))?;
+        for (key, duration) in durations {
+            map.serialize_entry(key, &humantime::format_duration(*duration).to_string())?;
+        }
+        map.end()
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<HashMap<String, Duration>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let texts: HashMap<String, String> = Deserialize::deserialize(deserializer)?;
+
+        texts
+            .into_iter()
+            .map(|(key, text)| Ok((key, parse(&text).map_err(serde::de::Error::custom)?)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_seconds_durations_and_timestamps() {
+        assert_eq!(parse("90").unwrap(), Duration::from_secs(90));
+        assert_eq!(parse(" 1h30m ").unwrap(), Duration::from_secs(5400));
+        assert_eq!(parse("2:15").unwrap(), Duration::from_secs(135));
+        assert_eq!(parse("1:02:15").unwrap(), Duration::from_secs(3735));
+        // The most significant part may be larger than a minute
+        assert_eq!(parse("90:00").unwrap(), Duration::from_secs(5400));
+    }
+
+    #[test]
+    fn rejects_invalid_durations() {
+        assert!(matches!(parse("  "), Err(DurationError::Empty)));
+        assert!(matches!(
+            parse("soon"),
+            Err(DurationError::InvalidDuration(..))
+        ));
+        for text in &["1:60", "1:2:3:4", "1::2", "-1:00", "1:30s"] {
+            assert!(
+                matches!(parse(text), Err(DurationError::InvalidTimestamp(_))),
+                "{}",
+                text
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_overflowing_timestamps() {
+        let max = u64::MAX.to_string();
+        for text in &[format!("{}:00", max), format!("{}:00:00", u64::MAX / 60)] {
+            assert!(
+                matches!(
+                    parse_timestamp(text),
+                    Err(DurationError::InvalidTimestamp(_))
+                ),
+                "{}",
+                text
+            );
+        }
+        assert_eq!(
+            parse_timestamp(&format!("{}:00", u64::MAX / 60)).unwrap(),
+            Duration::from_secs(u64::MAX / 60 * 60)
+        );
+    }
+
+    #[test]
+    fn formats_timestamps() {
+        assert_eq!(format_timestamp(Duration::from_secs(0)), "0:00");
+        assert_eq!(format_timestamp(Duration::from_millis(187_900)), "3:07");
+        assert_eq!(format_timestamp(Duration::from_secs(3735)), "1:02:15");
+        assert_eq!(format_timestamp(Duration::from_secs(36_000)), "10:00:00");
+    }
+
+    #[test]
+    fn round_trips_timestamps() {
+        for secs in &[0, 59, 60, 3599, 3600, 3735, 360_000] {
+            let duration = Duration::from_secs(*secs);
+            assert_eq!(parse(&format_timestamp(duration)).unwrap(), duration);
+        }
+    }
+}
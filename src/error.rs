@@ -0,0 +1,48 @@
+use thiserror::Error;
+
+use crate::audio_player::AudioPlayerError;
+use crate::bot::BotCreationError;
+use crate::i18n::Locale;
+
+/// Errors that keep a bot from starting or from handling an event
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("No identity found, try running with `-g`")]
+    MissingIdentity,
+    #[error("Failed to connect to TeamSpeak: {0}")]
+    Connection(#[from] tsclientlib::Error),
+    #[error("Audio player failed: {0:?}")]
+    AudioPlayer(AudioPlayerError),
+    #[error("{0}")]
+    BotCreation(BotCreationError),
+    #[error("Discord failed: {0}")]
+    Discord(String),
+    #[error("Mumble failed: {0}")]
+    Mumble(String),
+    #[error("Soak test failed: {0}")]
+    Soak(String),
+}
+
+// `AudioPlayerError` and `BotCreationError` do not implement `std::error::Error`
+// so `#[from]` can not be used for them.
+impl From<AudioPlayerError> for Error {
+    fn from(err: AudioPlayerError) -> Self {
+        Error::AudioPlayer(err)
+    }
+}
+
+impl From<BotCreationError> for Error {
+    fn from(err: BotCreationError) -> Self {
+        Error::BotCreation(err)
+    }
+}
+
+impl Error {
+    /// The message for the user who caused the error
+    pub fn localize(&self, locale: &Locale) -> String {
+        match self {
+            Error::BotCreation(e) => e.localize(locale),
+            e => e.to_string(),
+        }
+    }
+}
@@ -0,0 +1,123 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use serde::{Deserialize, Serialize};
+use tracing::{error, info};
+
+/// Where files played with `!playfile` are kept and how large they may be
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FilePlaybackConfig {
+    #[serde(default = "default_dir")]
+    pub dir: PathBuf,
+    #[serde(default = "default_max_size_mb")]
+    pub max_size_mb: u64,
+    /// The oldest files are removed once `dir` grows larger than this
+    #[serde(default = "default_max_total_mb")]
+    pub max_total_mb: u64,
+}
+
+fn default_dir() -> PathBuf {
+    PathBuf::from("files")
+}
+
+fn default_max_size_mb() -> u64 {
+    50
+}
+
+fn default_max_total_mb() -> u64 {
+    500
+}
+
+impl FilePlaybackConfig {
+    pub fn max_size(&self) -> u64 {
+        self.max_size_mb * 1024 * 1024
+    }
+
+    /// Saves a downloaded file under its content hash, so files that were
+    /// uploaded again are only stored once.
+    ///
+    /// This blocks while the file is written and older files are removed.
+    pub fn store(&self, data: &[u8], extension: &str) -> io::Result<PathBuf> {
+        fs::create_dir_all(&self.dir)?;
+
+        let path = self
+            .dir
+            .join(format!("{:x}.{}", md5::compute(data), extension));
+        if !path.exists() {
+            fs::write(&path, data)?;
+            self.prune(&path);
+        }
+
+        // GStreamer is given a file url, which needs an absolute path
+        path.canonicalize()
+    }
+
+    /// Removes the oldest files until `dir` fits into `max_total_mb`, except for `keep`
+    fn prune(&self, keep: &Path) {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) => {
+                error!("Failed to list files in {:?}: {}", self.dir, e);
+                return;
+            }
+        };
+
+        let mut files: Vec<(PathBuf, u64, SystemTime)> = entries
+            .filter_map(Result::ok)
+            .filter_map(|entry| {
+                let metadata = entry.metadata().ok()?;
+                if !metadata.is_file() {
+                    return None;
+                }
+                let modified = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+                Some((entry.path(), metadata.len(), modified))
+            })
+            .collect();
+        files.sort_by_key(|(_, _, modified)| *modified);
+
+        let max_total = self.max_total_mb * 1024 * 1024;
+        let mut total: u64 = files.iter().map(|(_, size, _)| size).sum();
+        for (path, size, _) in files {
+            if total <= max_total {
+                break;
+            }
+            if path == keep {
+                continue;
+            }
+
+            info!("Removing {:?} to keep the played files small", path);
+            match fs::remove_file(&path) {
+                Ok(()) => total -= size,
+                Err(e) => error!("Failed to remove {:?}: {}", path, e),
+            }
+        }
+    }
+}
+
+/// The extension of an audio file, told by its first bytes instead of its name
+pub fn audio_format(data: &[u8]) -> Option<&'static str> {
+    let starts_with = |magic: &[u8]| data.starts_with(magic);
+
+    if starts_with(b"ID3") || (data.len() > 1 && data[0] == 0xff && data[1] & 0xe0 == 0xe0) {
+        // Frame sync of MPEG audio, ADTS AAC has the layer bits set to zero
+        if data.len() > 1 && data[1] & 0x06 == 0 && !starts_with(b"ID3") {
+            Some("aac")
+        } else {
+            Some("mp3")
+        }
+    } else if starts_with(b"OggS") {
+        Some("ogg")
+    } else if starts_with(b"fLaC") {
+        Some("flac")
+    } else if starts_with(b"RIFF") && data.get(8..12) == Some(b"WAVE") {
+        Some("wav")
+    } else if data.get(4..8) == Some(b"ftyp") {
+        Some("m4a")
+    } else if starts_with(&[0x1a, 0x45, 0xdf, 0xa3]) {
+        Some("webm")
+    } else {
+        None
+    }
+}
@@ -0,0 +1,132 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use tsclientlib::{ChannelId, ClientId, Invoker};
+
+/// TeamSpeak cuts off longer text messages, the other services allow more
+pub const MAX_MESSAGE_LENGTH: usize = 1000;
+
+/// The chat service an instance connects to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FrontendKind {
+    TeamSpeak,
+    Discord,
+    Mumble,
+}
+
+impl Default for FrontendKind {
+    fn default() -> Self {
+        FrontendKind::TeamSpeak
+    }
+}
+
+/// What a bot sends while it is not playing anything
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum IdleAudio {
+    /// Stop transmitting so clients stop showing the bot as talking
+    Nothing,
+    /// Keep transmitting empty frames, clients keep showing the bot as talking
+    ComfortNoise,
+}
+
+impl Default for IdleAudio {
+    fn default() -> Self {
+        IdleAudio::Nothing
+    }
+}
+
+/// An opus packet without any frame data (CELT, fullband, 20 ms, stereo),
+/// decoders fill the gap with comfort noise.
+pub const COMFORT_NOISE_PACKET: &[u8] = &[0xFC];
+
+/// Who hears a bot besides the clients in its channel
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Whisper {
+    /// Channels that hear the bot as well
+    pub channels: Vec<ChannelId>,
+    /// Clients of a private session, if there are any only they hear the bot
+    pub clients: Vec<ClientId>,
+}
+
+impl Whisper {
+    pub fn is_private(&self) -> bool {
+        !self.clients.is_empty()
+    }
+}
+
+/// Everything a music bot needs from the service it plays on.
+///
+/// Features that only exist on TeamSpeak like following clients or
+/// whispering use the `TeamSpeakConnection` directly.
+#[async_trait]
+pub trait Frontend: Send + Sync {
+    /// Sends an opus frame to the channel of the bot and whispers it to `whisper`
+    /// if the service supports that, private sessions replace the channel.
+    async fn send_audio_packet(&self, samples: &[u8], whisper: &Whisper);
+    /// Tells clients that the bot stopped talking
+    async fn send_audio_end(&self, whisper: &Whisper);
+    async fn send_comfort_noise(&self, whisper: &Whisper);
+
+    async fn send_message_to_channel(&self, text: String);
+    /// Sends a private message, services without them send it to the channel
+    async fn send_message_to_user(&self, _invoker: &Invoker, text: String) {
+        self.send_message_to_channel(text).await
+    }
+    async fn set_nickname(&self, name: String);
+    async fn set_description(&self, desc: String);
+    async fn set_avatar(&self, data: Vec<u8>) -> Result<(), String>;
+    async fn remove_avatar(&self) -> Result<(), String>;
+
+    /// `None` while the bot does not know where it is, e.g. during a reconnect
+    async fn my_channel(&self) -> Option<ChannelId>;
+    /// Counts the users in the channel of the bot, except for bots and `ignored` unique ids
+    async fn listener_count(&self, ignored: Arc<Vec<String>>) -> u32;
+    /// The highest bitrate in kbit/s the channel of the bot is meant for,
+    /// `None` if the service does not say
+    async fn max_bitrate(&self) -> Option<u32> {
+        None
+    }
+
+    async fn disconnect(&self, reason: &str);
+}
+
+/// Splits `text` into messages of at most `max` characters, preferably between lines
+pub fn split_message(text: &str, max: usize) -> Vec<String> {
+    let mut messages = Vec::new();
+    let mut current = String::new();
+
+    for line in text.lines() {
+        let mut line = line;
+        // Lines that are too long on their own are cut wherever they hit the limit
+        while line.chars().count() > max {
+            if !current.is_empty() {
+                messages.push(std::mem::take(&mut current));
+            }
+            let split = line
+                .char_indices()
+                .nth(max)
+                .map(|(i, _)| i)
+                .expect("line is longer than max");
+            messages.push(line[..split].to_owned());
+            line = &line[split..];
+        }
+
+        let length = current.chars().count() + line.chars().count() + 1;
+        if !current.is_empty() && length > max {
+            messages.push(std::mem::take(&mut current));
+        }
+        if !current.is_empty() {
+            current.push('\n');
+        }
+        current.push_str(line);
+    }
+
+    if !current.is_empty() {
+        messages.push(current);
+    }
+
+    messages
+}
@@ -0,0 +1,254 @@
+//! Translations of the messages the bots send to users.
+//!
+//! The English text of a message lives in the code next to its id, catalogs of
+//! other languages map the ids to translations like `id = "Text with {name}"`.
+//! Messages missing from a catalog stay English, so a catalog can be
+//! translated bit by bit.
+
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+const ENGLISH: &str = "en";
+
+/// Catalogs that are part of the binary, files in `locale_dir` replace them
+const BUILTIN: &[(&str, &str)] = &[("de", include_str!("../locales/de.toml"))];
+
+/// Looks up the message `id` in a `Locale`, replacing `{name}` with `name = value`
+#[macro_export]
+macro_rules! tr {
+    ($locale:expr, $id:expr, $english:expr) => {
+        $locale.tr($id, $english, &[])
+    };
+    ($locale:expr, $id:expr, $english:expr, $($name:ident = $value:expr),+ $(,)?) => {
+        $locale.tr($id, $english, &[$((stringify!($name), &$value as &dyn std::fmt::Display)),+])
+    };
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct I18nConfig {
+    #[serde(default = "default_language")]
+    pub language: String,
+    /// Languages of single servers by address, or by id for Discord servers
+    #[serde(default)]
+    pub servers: HashMap<String, String>,
+    /// Catalogs named like `de.toml`, used instead of the built-in ones
+    pub locale_dir: Option<PathBuf>,
+}
+
+fn default_language() -> String {
+    String::from(ENGLISH)
+}
+
+impl Default for I18nConfig {
+    fn default() -> Self {
+        Self {
+            language: default_language(),
+            servers: HashMap::new(),
+            locale_dir: None,
+        }
+    }
+}
+
+/// The messages of one language
+#[derive(Debug)]
+pub struct Locale {
+    language: String,
+    messages: HashMap<String, String>,
+}
+
+impl Locale {
+    pub fn english() -> Self {
+        Self {
+            language: default_language(),
+            messages: HashMap::new(),
+        }
+    }
+
+    pub fn language(&self) -> &str {
+        &self.language
+    }
+
+    /// The translation of `id` if this language has one
+    pub fn lookup(&self, id: &str) -> Option<&str> {
+        self.messages.get(id).map(String::as_str)
+    }
+
+    /// A copy in which `fill` was applied to the messages `ids`, before `tr`
+    /// fills in the placeholders of the message itself
+    pub fn with_filled<'a>(
+        &self,
+        ids: impl IntoIterator<Item = &'a String>,
+        fill: impl Fn(&str) -> String,
+    ) -> Self {
+        let mut messages = self.messages.clone();
+        for id in ids {
+            if let Some(text) = messages.get_mut(id) {
+                *text = fill(text);
+            }
+        }
+
+        Self {
+            language: self.language.clone(),
+            messages,
+        }
+    }
+
+    /// Use the `tr!` macro instead of calling this directly
+    pub fn tr(&self, id: &str, english: &str, args: &[(&str, &dyn Display)]) -> String {
+        let text = self.lookup(id).unwrap_or(english);
+
+        fill_placeholders(text, |name| {
+            args.iter()
+                .find(|(arg, _)| *arg == name)
+                .map(|(_, value)| value.to_string())
+        })
+    }
+}
+
+/// Replaces each `{name}` with its value in one pass, so placeholders in the
+/// values are kept as they are. Unknown placeholders are left in the text.
+pub fn fill_placeholders(text: &str, value: impl Fn(&str) -> Option<String>) -> String {
+    let mut filled = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('{') {
+        filled.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let replaced = after
+            .find('}')
+            .and_then(|end| Some((end, value(&after[..end])?)));
+        match replaced {
+            Some((end, replaced)) => {
+                filled.push_str(&replaced);
+                rest = &after[end + 1..];
+            }
+            None => {
+                filled.push('{');
+                rest = after;
+            }
+        }
+    }
+    filled.push_str(rest);
+
+    filled
+}
+
+/// All languages that can be used and which servers use them
+pub struct Locales {
+    default: Arc<Locale>,
+    servers: HashMap<String, Arc<Locale>>,
+    languages: HashMap<String, Arc<Locale>>,
+}
+
+/// Loads the built-in catalogs and those in `locale_dir`, `templates` replace
+/// messages in all of them
+pub fn open(config: I18nConfig, templates: &HashMap<String, String>) -> Arc<Locales> {
+    let mut catalogs: HashMap<String, String> = BUILTIN
+        .iter()
+        .map(|(language, catalog)| (language.to_string(), catalog.to_string()))
+        .collect();
+    if let Some(dir) = &config.locale_dir {
+        catalogs.extend(read_dir(dir));
+    }
+
+    let mut languages = HashMap::new();
+    let english = Locale {
+        language: default_language(),
+        messages: templates.clone(),
+    };
+    languages.insert(default_language(), Arc::new(english));
+    for (language, catalog) in catalogs {
+        match toml::from_str::<HashMap<String, String>>(&catalog) {
+            Ok(mut messages) => {
+                messages.extend(templates.clone());
+                let locale = Locale {
+                    language: language.clone(),
+                    messages,
+                };
+                languages.insert(language, Arc::new(locale));
+            }
+            Err(e) => warn!("Failed to parse the {} catalog: {}", language, e),
+        }
+    }
+
+    let find = |language: &str| match languages.get(language) {
+        Some(locale) => locale.clone(),
+        None => {
+            warn!("There is no {} catalog, using English", language);
+            languages[ENGLISH].clone()
+        }
+    };
+    let default = find(&config.language);
+    let servers = config
+        .servers
+        .iter()
+        .map(|(server, language)| (server.clone(), find(language)))
+        .collect();
+    if default.language() != ENGLISH {
+        info!("Replying in {}", default.language());
+    }
+
+    Arc::new(Locales {
+        default,
+        servers,
+        languages,
+    })
+}
+
+fn read_dir(dir: &Path) -> Vec<(String, String)> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            warn!("Failed to read locale_dir {}: {}", dir.display(), e);
+            return Vec::new();
+        }
+    };
+
+    let mut catalogs = Vec::new();
+    for entry in entries.filter_map(Result::ok) {
+        let path = entry.path();
+        if path.extension().map_or(true, |e| e != "toml") {
+            continue;
+        }
+        let language = match path.file_stem().and_then(|s| s.to_str()) {
+            Some(language) => language.to_owned(),
+            None => continue,
+        };
+        match std::fs::read_to_string(&path) {
+            Ok(catalog) => catalogs.push((language, catalog)),
+            Err(e) => warn!("Failed to read {}: {}", path.display(), e),
+        }
+    }
+
+    catalogs
+}
+
+impl Locales {
+    /// The language of a server, the default language if it has none of its own
+    pub fn for_server(&self, server: &str) -> Arc<Locale> {
+        self.servers.get(server).unwrap_or(&self.default).clone()
+    }
+
+    /// The language a user picked, the one of the server if there is no catalog for it
+    pub fn for_user(&self, server: &str, language: Option<&str>) -> Arc<Locale> {
+        language
+            .and_then(|language| self.get(language))
+            .unwrap_or_else(|| self.for_server(server))
+    }
+
+    pub fn get(&self, language: &str) -> Option<Arc<Locale>> {
+        self.languages.get(&language.to_lowercase()).cloned()
+    }
+
+    /// Codes of all languages with a catalog, sorted
+    pub fn languages(&self) -> Vec<&str> {
+        let mut languages: Vec<_> = self.languages.keys().map(String::as_str).collect();
+        languages.sort_unstable();
+
+        languages
+    }
+}
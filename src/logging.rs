@@ -0,0 +1,110 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{fmt, reload, EnvFilter, Registry};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Human readable lines with the spans of each event in front
+    Text,
+    /// One JSON object per line, with the spans as fields
+    Json,
+}
+
+impl Default for LogFormat {
+    fn default() -> Self {
+        LogFormat::Text
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LoggingConfig {
+    /// Which events are logged, in the syntax of `RUST_LOG` which overrides it
+    #[serde(default = "default_filter")]
+    pub filter: String,
+    #[serde(default)]
+    pub format: LogFormat,
+    /// Everything that is printed is also written to this file
+    #[serde(default = "default_file")]
+    pub file: PathBuf,
+}
+
+fn default_filter() -> String {
+    String::from("debug,tokio_reactor=warn,actix_web=trace")
+}
+
+fn default_file() -> PathBuf {
+    PathBuf::from("log/pokebot.log")
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self {
+            filter: default_filter(),
+            format: LogFormat::default(),
+            file: default_file(),
+        }
+    }
+}
+
+/// Changes which events are logged while the bots are running
+pub struct LogFilter {
+    handle: reload::Handle<EnvFilter, Registry>,
+}
+
+impl LogFilter {
+    /// The directives that are currently in use
+    pub fn get(&self) -> Result<String, String> {
+        self.handle
+            .with_current(|filter| filter.to_string())
+            .map_err(|e| e.to_string())
+    }
+
+    pub fn set(&self, directives: &str) -> Result<(), String> {
+        let filter = EnvFilter::try_new(directives).map_err(|e| e.to_string())?;
+
+        self.handle.reload(filter).map_err(|e| e.to_string())
+    }
+}
+
+/// Has to be kept alive for the log file to be written
+pub struct Logging {
+    pub filter: Arc<LogFilter>,
+    _guard: WorkerGuard,
+}
+
+/// Sets up the global subscriber, events of crates using `log` are included as well
+pub fn init(config: &LoggingConfig) -> Result<Logging, Box<dyn std::error::Error>> {
+    let filter = match std::env::var(EnvFilter::DEFAULT_ENV) {
+        Ok(directives) => EnvFilter::try_new(directives)?,
+        Err(_) => EnvFilter::try_new(&config.filter)?,
+    };
+    let (filter, handle) = reload::Layer::new(filter);
+
+    let directory = config.file.parent().unwrap_or_else(|| Path::new("."));
+    let file_name = config.file.file_name().ok_or("Invalid log file path")?;
+    let appender = tracing_appender::rolling::never(directory, file_name);
+    let (file, guard) = tracing_appender::non_blocking(appender);
+
+    let registry = tracing_subscriber::registry().with(filter);
+    match config.format {
+        LogFormat::Text => registry
+            .with(fmt::layer().with_writer(std::io::stderr))
+            .with(fmt::layer().with_ansi(false).with_writer(file))
+            .try_init()?,
+        LogFormat::Json => registry
+            .with(fmt::layer().json().with_writer(std::io::stderr))
+            .with(fmt::layer().json().with_writer(file))
+            .try_init()?,
+    }
+
+    Ok(Logging {
+        filter: Arc::new(LogFilter { handle }),
+        _guard: guard,
+    })
+}
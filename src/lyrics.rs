@@ -0,0 +1,140 @@
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::{info, warn};
+
+use crate::scrobble::parse_title;
+use crate::youtube_dl::AudioMetadata;
+
+const LRCLIB_API: &str = "https://lrclib.net/api/get";
+const MUSIXMATCH_API: &str = "https://api.musixmatch.com/ws/1.1/matcher.lyrics.get";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LyricsProvider {
+    /// Free and without an account
+    Lrclib,
+    /// Needs an API key, free keys only get part of the lyrics
+    Musixmatch,
+}
+
+impl Default for LyricsProvider {
+    fn default() -> Self {
+        LyricsProvider::Lrclib
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LyricsConfig {
+    #[serde(default)]
+    pub provider: LyricsProvider,
+    pub api_key: Option<String>,
+}
+
+pub struct LyricsClient {
+    config: LyricsConfig,
+    http: reqwest::Client,
+}
+
+/// Sets up lyrics lookups if they are configured
+pub fn open(config: Option<LyricsConfig>) -> Option<Arc<LyricsClient>> {
+    let config = config?;
+
+    if config.provider == LyricsProvider::Musixmatch && config.api_key.is_none() {
+        warn!("Musixmatch needs an api_key, lyrics are disabled");
+        return None;
+    }
+
+    info!("Looking up lyrics with {:?}", config.provider);
+    Some(Arc::new(LyricsClient {
+        config,
+        http: reqwest::Client::new(),
+    }))
+}
+
+impl LyricsClient {
+    /// Returns `Ok(None)` if the provider does not know the track
+    pub async fn find(&self, metadata: &AudioMetadata) -> Result<Option<String>, String> {
+        let (artist, track) = match (&metadata.artist, &metadata.track) {
+            (Some(artist), Some(track)) => (artist.clone(), track.clone()),
+            _ => match parse_title(&metadata.title) {
+                Some(parsed) => parsed,
+                None => {
+                    info!("No artist in {:?}, not looking up lyrics", metadata.title);
+                    return Ok(None);
+                }
+            },
+        };
+        info!("Looking up lyrics of {} - {}", artist, track);
+
+        let lyrics = match self.config.provider {
+            LyricsProvider::Lrclib => self.find_lrclib(&artist, &track, metadata).await?,
+            LyricsProvider::Musixmatch => self.find_musixmatch(&artist, &track).await?,
+        };
+
+        Ok(lyrics
+            .map(|l| l.trim().to_owned())
+            .filter(|l| !l.is_empty()))
+    }
+
+    async fn find_lrclib(
+        &self,
+        artist: &str,
+        track: &str,
+        metadata: &AudioMetadata,
+    ) -> Result<Option<String>, String> {
+        let mut params = vec![
+            ("artist_name", artist.to_owned()),
+            ("track_name", track.to_owned()),
+        ];
+        if let Some(duration) = metadata.duration {
+            params.push(("duration", duration.as_secs().to_string()));
+        }
+
+        let request = self.http.get(LRCLIB_API).query(&params);
+        let response = request.send().await.map_err(|e| e.to_string())?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let body = body(response).await?;
+
+        Ok(body["plainLyrics"].as_str().map(str::to_owned))
+    }
+
+    async fn find_musixmatch(&self, artist: &str, track: &str) -> Result<Option<String>, String> {
+        let api_key = self.config.api_key.clone().unwrap_or_default();
+        let params = [
+            ("q_artist", artist.to_owned()),
+            ("q_track", track.to_owned()),
+            ("apikey", api_key),
+        ];
+
+        let request = self.http.get(MUSIXMATCH_API).query(&params);
+        let response = request.send().await.map_err(|e| e.to_string())?;
+        let body = body(response).await?;
+
+        // Errors are reported in the body with a 200 response
+        match body["message"]["header"]["status_code"].as_u64() {
+            Some(200) => (),
+            Some(404) => return Ok(None),
+            Some(code) => return Err(format!("Musixmatch returned {}", code)),
+            None => return Err(String::from("Invalid Musixmatch response")),
+        }
+
+        Ok(body["message"]["body"]["lyrics"]["lyrics_body"]
+            .as_str()
+            .map(str::to_owned))
+    }
+}
+
+async fn body(response: reqwest::Response) -> Result<Value, String> {
+    let status = response.status();
+    let body = response.text().await.map_err(|e| e.to_string())?;
+
+    if !status.is_success() {
+        return Err(format!("{}: {}", status, body));
+    }
+
+    serde_json::from_str(&body).map_err(|e| e.to_string())
+}
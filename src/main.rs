@@ -1,23 +1,63 @@
 use std::fs::File;
+use std::future::Future;
 use std::io::{Read, Write};
 use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::Arc;
 use std::thread;
 use std::time::Duration;
 
-use log::{debug, error, info};
 use structopt::clap::AppSettings;
 use structopt::StructOpt;
+use tracing::{debug, error, info};
 use tsclientlib::Identity;
 
+mod audio_cache;
+mod audio_output;
 mod audio_player;
+mod blacklist;
 mod bot;
+mod bot_log;
+mod bridge;
+mod card;
+mod channel_settings;
 mod command;
+mod direct_link;
+mod discord;
+mod duration;
+mod error;
+mod file_playback;
+mod frontend;
+mod i18n;
+mod logging;
+mod lyrics;
+mod mumble;
+mod musicbrainz;
 mod playlist;
+mod playlist_file;
+mod podcast;
+mod queue_text;
+mod rate_limit;
+mod reports;
+mod request_id;
+mod resource_usage;
+mod saved_playlists;
+mod scheduler;
+mod scrobble;
+mod soak;
+mod sources;
+mod stats;
 mod teamspeak;
+mod templates;
+mod user_settings;
 mod web_server;
 mod youtube_dl;
 
-use bot::{MasterArgs, MasterBot, MusicBot, MusicBotArgs};
+use bot::{BotManager, MasterArgs, MasterBot, MusicBot, MusicBotArgs};
+use discord::DiscordMaster;
+use frontend::FrontendKind;
+use mumble::MumbleMaster;
+use saved_playlists::PlaylistStore;
 
 #[derive(StructOpt, Debug)]
 #[structopt(global_settings = &[AppSettings::ColoredHelp])]
@@ -58,6 +98,12 @@ pub struct Args {
         parse(from_occurrences)
     )]
     verbose: u8,
+    /// Plays synthetic audio on 'count' simulated bots and logs resource usage
+    #[structopt(long = "soak")]
+    soak: Option<usize>,
+    /// Lists the audio devices local mode and monitoring can play to
+    #[structopt(long = "list-devices")]
+    list_devices: bool,
     // 0. Print nothing
     // 1. Print command string
     // 2. Print packets
@@ -72,17 +118,34 @@ async fn main() {
 }
 
 async fn run() -> Result<(), Box<dyn std::error::Error>> {
-    log4rs::init_file("log4rs.yml", Default::default()).unwrap();
-
     // Parse command line options
     let args = Args::from_args();
 
+    if args.list_devices {
+        let devices = audio_output::list_devices().map_err(error::Error::from)?;
+        for device in devices {
+            match (device.backend, device.device) {
+                (Some(backend), Some(id)) => {
+                    println!(
+                        "{} (backend = \"{}\", device = {:?})",
+                        device.name, backend, id
+                    )
+                }
+                _ => println!("{}", device.name),
+            }
+        }
+
+        return Ok(());
+    }
+
     let mut file = File::open(&args.config_path)?;
     let mut toml = String::new();
     file.read_to_string(&mut toml)?;
 
     let mut config: MasterArgs = toml::from_str(&toml)?;
 
+    let logging = logging::init(&config.logging)?;
+
     if config.id.is_none() {
         let id = Identity::create().expect("Failed to create id");
         config.id = Some(id);
@@ -126,7 +189,13 @@ async fn run() -> Result<(), Box<dyn std::error::Error>> {
         return Ok(());
     }
 
-    if config.id.is_none() || config.ids.is_none() {
+    if let Some(count) = args.soak {
+        soak::run(config.merge(args), count).await?;
+
+        return Ok(());
+    }
+
+    if config.frontend == FrontendKind::TeamSpeak && (config.id.is_none() || config.ids.is_none()) {
         error!("Failed to find required identites, try running with `-g`");
         return Ok(());
     }
@@ -138,9 +207,9 @@ async fn run() -> Result<(), Box<dyn std::error::Error>> {
 
     if bot_args.local {
         let name = bot_args.names[0].clone();
-        let id = bot_args.ids.expect("identies should exists")[0].clone();
 
         let disconnect_cb = Box::new(move |_, _, _| {});
+        let downloader = youtube_dl::open(bot_args.downloader.clone()).await;
 
         let bot_args = MusicBotArgs {
             name,
@@ -148,29 +217,124 @@ async fn run() -> Result<(), Box<dyn std::error::Error>> {
             id_index: 0,
             local: true,
             address: bot_args.address.clone(),
-            id,
+            id: None,
+            identities: None,
+            server_password: None,
+            privilege_key: None,
+            frontend: None,
             channel: String::from("local"),
+            channel_password: None,
+            summoner: None,
+            private_session: None,
+            bot_uids: Arc::new(Vec::new()),
+            empty_channel_timeout: bot_args.empty_channel_timeout,
             verbose: bot_args.verbose,
+            commands: bot_args.commands.clone(),
+            thumbnail_avatars: false,
+            max_playlist_tracks: bot_args.max_playlist_tracks,
+            queue_limits: bot_args.queue_limits,
+            sources: bot_args.sources.clone(),
+            blacklist: Arc::new(blacklist::BlacklistStore::new(
+                bot_args.blacklist.clone(),
+                "local",
+            )),
+            no_duplicates: bot_args.no_duplicates,
+            file_playback: bot_args.file_playback.clone(),
+            ducking: None,
+            encoder: bot_args.encoder,
+            watchdog: bot_args.watchdog,
+            audio_output: bot_args.audio_output.clone(),
+            admins: bot_args.admins.clone(),
+            badges: Vec::new(),
+            client_version: bot_args.client_version,
+            idle_audio: bot_args.idle_audio,
+            end_of_queue: bot_args.end_of_queue.clone(),
+            autoplay: bot_args.autoplay,
+            progress_bar: bot_args.progress_bar,
+            channel_settings: Arc::new(channel_settings::ChannelSettingsStore::new(
+                bot_args.channel_settings.clone(),
+                "local",
+            )),
+            user_settings: Arc::new(user_settings::UserSettingsStore::new(
+                bot_args.user_settings.clone(),
+            )),
+            rate_limiter: Arc::new(rate_limit::RateLimiter::new(bot_args.rate_limit.clone())),
+            reports: Arc::new(reports::ReportStore::new(bot_args.reports.clone())),
+            web_url: None,
+            cards: None,
+            scrobbler: scrobble::open(bot_args.scrobble.clone()),
+            lyrics: lyrics::open(bot_args.lyrics.clone()),
+            musicbrainz: musicbrainz::open(bot_args.musicbrainz.clone()),
+            playlists: Arc::new(PlaylistStore::new(bot_args.playlist_dir.clone(), "local")),
+            audio_cache: audio_cache::open(
+                bot_args.audio_cache_dir.clone(),
+                bot_args.audio_cache_max_mb,
+                downloader.clone(),
+            ),
+            stats: stats::open(bot_args.stats_db.clone(), "local"),
+            downloader,
+            locales: i18n::open(bot_args.i18n.clone(), &bot_args.templates.messages),
+            resume: None,
             disconnect_cb,
+            reconnect_cb: None,
         };
-        MusicBot::new(bot_args).await.1.await;
+        MusicBot::new(bot_args).await?.1.await;
     } else {
         let domain = bot_args.domain.clone();
         let bind_address = bot_args.bind_address.clone();
-        let (bot, fut) = MasterBot::new(bot_args).await;
+        let admin_token = bot_args.admin_token.clone();
+        let public = bot_args.public_web;
+        let retry_interval = bot_args.web_retry_interval;
+        let bridge = bot_args.bridge.clone();
+        let log_filter = logging.filter.clone();
+        let (bot, fut): (Arc<dyn BotManager>, Pin<Box<dyn Future<Output = ()>>>) =
+            match bot_args.frontend {
+                FrontendKind::TeamSpeak => {
+                    let (bot, fut) = MasterBot::new(bot_args).await?;
+                    (bot, Box::pin(fut))
+                }
+                FrontendKind::Discord => {
+                    let (bot, fut) = DiscordMaster::new(bot_args).await?;
+                    (bot, Box::pin(fut))
+                }
+                FrontendKind::Mumble => {
+                    let (bot, fut) = MumbleMaster::new(bot_args).await?;
+                    (bot, Box::pin(fut))
+                }
+            };
+        bridge::spawn(bridge, bot.clone());
+        let scheduler = Box::pin(scheduler::run(bot.clone()));
 
-        thread::spawn(|| {
+        thread::spawn(move || {
             let web_args = web_server::WebServerArgs {
                 domain,
                 bind_address,
+                admin_token,
+                public,
                 bot,
+                log_filter,
             };
-            if let Err(e) = web_server::start(web_args) {
-                error!("Error in web server: {}", e);
+
+            // The bots keep running without the web server, so keep trying to bring it up
+            while let Err(e) = web_server::start(web_args.clone()) {
+                error!(
+                    "Error in web server, retrying in {}: {}",
+                    humantime::format_duration(retry_interval),
+                    e
+                );
+                web_args
+                    .bot
+                    .set_web_server_status(web_server::WebServerStatus::Failed {
+                        error: e.to_string(),
+                        retry_in: retry_interval,
+                    });
+
+                thread::sleep(retry_interval);
             }
         });
 
-        fut.await;
+        // The scheduler only stops with the master
+        futures::future::select(fut, scheduler).await;
         // Keep tokio running while the bot disconnects
         tokio::time::delay_for(Duration::from_secs(1)).await;
     }
@@ -0,0 +1,580 @@
+use std::collections::VecDeque;
+use std::future::Future;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use tracing::{error, info, warn};
+use tsclientlib::{ChannelId, ClientId, Invoker, MessageTarget};
+
+use crate::bot::{
+    mailbox, ping_message_loop, BotCreationError, BotManager, BotRegistry, BotTemplate,
+    MasterActionError, MasterArgs, MessageSender, MusicBot, MusicBotArgs, MusicBotMessage, Slot,
+};
+use crate::command::{CommandError, MasterCommand};
+use crate::error::Error;
+use crate::frontend::Frontend;
+use crate::i18n::Locale;
+use crate::mumble::{MumbleConfig, MumbleConnection};
+use crate::rate_limit::RateLimit;
+use crate::reports::ReportStore;
+use crate::scheduler::ScheduleStore;
+use crate::stats::StatsStore;
+use crate::tr;
+use crate::web_server::{BotDetails, BotSummary, IdentityInfo, MasterData, WebServerStatus};
+use crate::youtube_dl::Downloader;
+
+/// The master on a Mumble server, sending it a private message works like
+/// poking the master on TeamSpeak.
+pub struct MumbleMaster {
+    config: BotTemplate,
+    mumble: MumbleConfig,
+    address: String,
+    /// Names of the master and all music bots, Mumble bots have no certificates
+    bot_names: Arc<Vec<String>>,
+    thumbnail_avatars: bool,
+    web_url: String,
+    registry: BotRegistry,
+    /// Users who messaged while `max_bots` bots were connected, in order
+    waiting: RwLock<VecDeque<ClientId>>,
+    connection: Arc<MumbleConnection>,
+    sender: MessageSender,
+    started: Instant,
+    status: RwLock<MumbleStatus>,
+}
+
+struct MumbleStatus {
+    description: String,
+    connected: bool,
+    maintenance: bool,
+    web_server: WebServerStatus,
+}
+
+impl MumbleMaster {
+    pub async fn new(args: MasterArgs) -> Result<(Arc<Self>, impl Future<Output = ()>), Error> {
+        let (tx, mut rx) = mailbox();
+        info!("Starting in Mumble mode");
+
+        let mumble = args.mumble.clone();
+        let connection = MumbleConnection::connect(
+            tx.clone(),
+            &args.address,
+            &args.master_name,
+            args.channel.as_deref(),
+            &mumble,
+        )
+        .await?;
+
+        // Mumble bots have no identities, every name can connect
+        let registry = BotRegistry::start(args.names.clone(), args.names.len(), args.max_bots);
+        let bot_names = Arc::new(
+            std::iter::once(&args.master_name)
+                .chain(args.names.iter())
+                .cloned()
+                .collect(),
+        );
+        let address = args.address.clone();
+        let thumbnail_avatars = args.thumbnail_avatars;
+        let web_url = crate::bot::web_url(&args.domain);
+        let config = BotTemplate::open(args, &address).await;
+
+        let description = String::from("Message me if you want a music bot!");
+        let connection = Arc::new(connection);
+        connection.set_description(description.clone()).await;
+
+        let master = Arc::new(Self {
+            config,
+            mumble,
+            address,
+            bot_names,
+            thumbnail_avatars,
+            web_url,
+            registry,
+            waiting: RwLock::new(VecDeque::new()),
+            connection,
+            sender: tx,
+            started: Instant::now(),
+            status: RwLock::new(MumbleStatus {
+                description,
+                connected: true,
+                maintenance: false,
+                web_server: WebServerStatus::Starting,
+            }),
+        });
+
+        let cmaster = master.clone();
+        let msg_loop = async move {
+            while let Some(msg) = rx.recv().await {
+                match msg {
+                    MusicBotMessage::Quit(reason) => {
+                        cmaster.connection.disconnect(&reason).await;
+                        cmaster.set_connected(false);
+                        break;
+                    }
+                    MusicBotMessage::TextMessage(message) => {
+                        if let MessageTarget::Client(_) = message.target {
+                            cmaster
+                                .on_private_text(&message.invoker, &message.text)
+                                .await;
+                        }
+                    }
+                    MusicBotMessage::BotSlotFreed => cmaster.spawn_for_waiting().await,
+                    MusicBotMessage::Ping(pong) => {
+                        let _ = pong.send(());
+                    }
+                    _ => (),
+                }
+            }
+        };
+
+        Ok((master, msg_loop))
+    }
+
+    async fn on_private_text(&self, from: &Invoker, text: &str) {
+        let locale = self.locale(Some(from));
+        let reply = match self.config.commands.parse::<MasterCommand>(text) {
+            Some(Ok(MasterCommand::Status)) => self.status_text(&locale),
+            // Mumble bots only play to their channel, so they can not share one
+            Some(Ok(MasterCommand::Spawn { force: true, .. })) => {
+                MasterActionError::Unsupported.to_string()
+            }
+            Some(Ok(MasterCommand::Spawn { channel, .. })) => {
+                if self.is_admin(from) {
+                    let channel = channel.join(" ");
+                    match self.spawn_bot_in(&channel).await {
+                        Ok(name) => tr!(
+                            locale,
+                            "spawned",
+                            "Spawned \"{name}\" in {channel}",
+                            name = name,
+                            channel = channel,
+                        ),
+                        Err(e) => e.localize(&locale),
+                    }
+                } else {
+                    tr!(
+                        locale,
+                        "spawn-admins-only",
+                        "Only admins can spawn bots by command"
+                    )
+                }
+            }
+            Some(Ok(MasterCommand::MusicChannel))
+            | Some(Ok(MasterCommand::Yes))
+            | Some(Ok(MasterCommand::Password { .. })) => {
+                MasterActionError::Unsupported.to_string()
+            }
+            Some(Ok(MasterCommand::Schedule { action })) => {
+                if self.is_admin(from) {
+                    self.config.schedule.on_command(action, &from.name)
+                } else {
+                    tr!(
+                        locale,
+                        "schedule-admins-only",
+                        "Only admins can schedule jobs"
+                    )
+                }
+            }
+            Some(Err(CommandError::Help(help))) => {
+                format!("\n{}", self.config.commands.format_help(&help, &locale))
+            }
+            // Anything else is treated like a poke
+            Some(Err(CommandError::UnknownCommand(_))) | None => match self.on_poke(from).await {
+                Some(reply) => reply,
+                None => return,
+            },
+            Some(Err(e)) => e.to_string(),
+        };
+
+        self.connection.send_message_to_user(from.id, reply);
+    }
+
+    /// Spawns a bot for the sender, returns the reply if there is one
+    async fn on_poke(&self, from: &Invoker) -> Option<String> {
+        let user = match &from.uid {
+            Some(uid) => uid.0.clone(),
+            None => from.name.clone(),
+        };
+        let limit = self.config.rate_limiter.check_poke(&user);
+        if limit != RateLimit::Allowed {
+            return limit.reply(&from.name);
+        }
+
+        match self.spawn_bot_for(from.id).await {
+            Ok(()) => None,
+            Err(MasterActionError::BotCreation(BotCreationError::TooManyBots(_))) => {
+                let position = self.wait_for_bot(from.id);
+                info!(
+                    "All bots are busy, {} is waiting at {}",
+                    from.name, position
+                );
+                Some(tr!(
+                    self.locale(Some(from)),
+                    "waiting-for-bot",
+                    "All bots are busy, you are number {position} in line. \
+                     I will send one to you once a bot is free.",
+                    position = position
+                ))
+            }
+            Err(e) => {
+                warn!("Failed to create bot for {}: {}", from.name, e);
+                Some(e.localize(&self.locale(Some(from))))
+            }
+        }
+    }
+
+    /// Puts the user in line for the next free bot, returns their position
+    fn wait_for_bot(&self, id: ClientId) -> usize {
+        let mut waiting = self.waiting.write().unwrap();
+        if let Some(index) = waiting.iter().position(|w| *w == id) {
+            return index + 1;
+        }
+        waiting.push_back(id);
+
+        waiting.len()
+    }
+
+    /// Spawns bots for the users in line until all bots are busy again
+    async fn spawn_for_waiting(&self) {
+        loop {
+            let id = match self.waiting.write().unwrap().pop_front() {
+                Some(id) => id,
+                None => return,
+            };
+
+            match self.spawn_bot_for(id).await {
+                Ok(()) => {
+                    info!("Created bot for {} who was waiting", id);
+                    let reply = tr!(
+                        self.locale(None),
+                        "bot-free",
+                        "A bot is free now and joins you."
+                    );
+                    self.connection.send_message_to_user(id, reply);
+                }
+                Err(MasterActionError::BotCreation(BotCreationError::TooManyBots(_))) => {
+                    self.waiting.write().unwrap().push_front(id);
+                    return;
+                }
+                // Users who left in the meantime can not be found anymore
+                Err(MasterActionError::BotCreation(BotCreationError::UnfoundUser)) => (),
+                Err(e) => {
+                    warn!("Failed to create bot for {} who was waiting: {}", id, e);
+                    self.connection
+                        .send_message_to_user(id, e.localize(&self.locale(None)));
+                }
+            }
+        }
+    }
+
+    async fn spawn_bot_for(&self, id: ClientId) -> Result<(), MasterActionError> {
+        if self.is_in_maintenance() {
+            return Err(MasterActionError::Maintenance);
+        }
+
+        let channel = self
+            .connection
+            .channel_of_user(id)
+            .ok_or(MasterActionError::BotCreation(
+                BotCreationError::UnfoundUser,
+            ))?;
+        info!("Messaged by {}, creating bot for their channel", id);
+        self.spawn_bot(channel, Some(id)).await?;
+
+        Ok(())
+    }
+
+    async fn spawn_bot(
+        &self,
+        channel: ChannelId,
+        summoner: Option<ClientId>,
+    ) -> Result<String, MasterActionError> {
+        if Some(channel) == self.connection.my_channel().await {
+            return Err(MasterActionError::BotCreation(
+                BotCreationError::MasterChannel(self.config.master_name.clone()),
+            ));
+        }
+
+        let channel_path =
+            self.connection
+                .channel_path(channel)
+                .ok_or(MasterActionError::BotCreation(
+                    BotCreationError::UnfoundChannel,
+                ))?;
+
+        for bot in self.registry.list().await {
+            if bot.my_channel().await == Some(channel) {
+                return Err(MasterActionError::BotCreation(
+                    BotCreationError::MultipleBots(bot.name().to_owned()),
+                ));
+            }
+        }
+
+        let slot = self
+            .registry
+            .spawn(channel)
+            .await
+            .map_err(MasterActionError::BotCreation)?;
+        let name = slot.name.clone();
+
+        info!("Connecting to {} on {}", channel_path, self.address);
+        let (tx, mut rx) = mailbox();
+        let connection =
+            MumbleConnection::connect(tx, &self.address, &name, Some(&channel_path), &self.mumble)
+                .await;
+        let connection = match connection {
+            Ok(connection) => Arc::new(connection),
+            Err(e) => {
+                self.release(slot);
+                return Err(MasterActionError::Spawn(e));
+            }
+        };
+
+        let cregistry = self.registry.clone();
+        let csender = self.sender.clone();
+        let disconnect_cb = Box::new(move |name: String, name_index, id_index| {
+            cregistry.despawn(Slot {
+                name,
+                name_index,
+                id_index,
+            });
+            let _ = csender.send(MusicBotMessage::BotSlotFreed);
+        });
+
+        let bot_args = MusicBotArgs {
+            bot_uids: self.bot_names.clone(),
+            thumbnail_avatars: self.thumbnail_avatars,
+            web_url: Some(self.web_url.clone()),
+            ..self.config.bot_args(
+                &slot,
+                self.address.clone(),
+                connection.clone(),
+                channel_path,
+                summoner,
+                disconnect_cb,
+            )
+        };
+
+        let (bot, fut) = match MusicBot::new(bot_args).await {
+            Ok(bot) => bot,
+            Err(e) => {
+                connection.disconnect("Failed to start").await;
+                self.release(slot);
+                return Err(MasterActionError::Spawn(e));
+            }
+        };
+        tokio::spawn(fut);
+
+        // The bot has its own connection, pass its events on until it closes
+        let cbot = bot.clone();
+        tokio::spawn(async move {
+            while let Some(msg) = rx.recv().await {
+                cbot.post(msg);
+            }
+        });
+
+        self.registry.connected(name.clone(), Some(bot));
+
+        Ok(name)
+    }
+
+    /// Frees the slot of a bot that failed to start, so a waiting user can get it
+    fn release(&self, slot: Slot) {
+        self.registry.despawn(slot);
+        let _ = self.sender.send(MusicBotMessage::BotSlotFreed);
+    }
+
+    /// The language the user picked with `!lang`, the one of the server without a user
+    fn locale(&self, user: Option<&Invoker>) -> Arc<Locale> {
+        let language = user
+            .and_then(|user| user.uid.as_ref())
+            .and_then(|uid| self.config.user_settings.get(&uid.0).language);
+
+        self.config
+            .locales
+            .for_user(&self.address, language.as_deref())
+    }
+
+    fn is_admin(&self, invoker: &Invoker) -> bool {
+        match &invoker.uid {
+            Some(uid) => self.config.admins.contains(&uid.0),
+            None => false,
+        }
+    }
+
+    fn status_text(&self, locale: &Locale) -> String {
+        let status = self.status.read().unwrap();
+        let registered = self.registry.registered();
+
+        let uptime = Duration::from_secs(self.started.elapsed().as_secs());
+        let maintenance = if status.maintenance {
+            tr!(locale, "status-on", "on")
+        } else {
+            tr!(locale, "status-off", "off")
+        };
+        let text = tr!(
+            locale,
+            "status",
+            "Uptime: {uptime}\nMaintenance: {maintenance}\nMusic bots: {bots}\nWeb server: {web_server}",
+            uptime = humantime::format_duration(uptime),
+            maintenance = maintenance,
+            bots = registered.bots.len(),
+            web_server = status.web_server.localize(locale),
+        );
+
+        format!("\n{}", text)
+    }
+
+    fn set_connected(&self, connected: bool) {
+        self.status.write().unwrap().connected = connected;
+    }
+
+    fn is_connected(&self) -> bool {
+        self.status.read().unwrap().connected
+    }
+
+    fn is_in_maintenance(&self) -> bool {
+        self.status.read().unwrap().maintenance
+    }
+}
+
+#[async_trait(?Send)]
+impl BotManager for MumbleMaster {
+    async fn master_data(&self) -> MasterData {
+        let channel = if self.is_connected() {
+            let channel = self.connection.my_channel().await;
+            channel.and_then(|channel| self.connection.channel_path(channel))
+        } else {
+            None
+        };
+
+        let (description, connected, maintenance, web_server) = {
+            let status = self.status.read().unwrap();
+            (
+                status.description.clone(),
+                status.connected,
+                status.maintenance,
+                status.web_server.clone(),
+            )
+        };
+
+        let registered = self.registry.registered();
+
+        MasterData {
+            name: self.config.master_name.clone(),
+            channel,
+            description,
+            uptime: self.started.elapsed(),
+            connected,
+            maintenance,
+            available_names: registered.available_names,
+            available_identities: 0,
+            connected_bots: registered.bots.keys().cloned().collect(),
+            web_server,
+            messages: Some(self.sender.stats()),
+        }
+    }
+
+    async fn set_description(&self, description: String) -> Result<(), MasterActionError> {
+        if !self.is_connected() {
+            return Err(MasterActionError::NotConnected);
+        }
+
+        self.status.write().unwrap().description = description.clone();
+        self.connection.set_description(description).await;
+
+        Ok(())
+    }
+
+    async fn move_to_channel(&self, channel: String) -> Result<(), MasterActionError> {
+        if !self.is_connected() {
+            return Err(MasterActionError::NotConnected);
+        }
+
+        let id = match self.connection.resolve_channel(&channel) {
+            Some(id) => id,
+            None => return Err(MasterActionError::UnknownChannel(channel)),
+        };
+
+        info!("Moving master to {}", channel);
+        self.connection.move_to_channel(id);
+
+        Ok(())
+    }
+
+    async fn spawn_bot_in(&self, channel: &str) -> Result<String, MasterActionError> {
+        if !self.is_connected() {
+            return Err(MasterActionError::NotConnected);
+        }
+
+        let id = match self.connection.resolve_channel(channel) {
+            Some(id) => id,
+            None => return Err(MasterActionError::UnknownChannel(channel.to_owned())),
+        };
+
+        self.spawn_bot(id, None).await
+    }
+
+    async fn ping(&self) -> bool {
+        ping_message_loop(&self.sender).await
+    }
+
+    async fn quit(&self, reason: String) {
+        self.registry.quit_all(reason.clone()).await;
+
+        if self.sender.send(MusicBotMessage::Quit(reason)).is_err() {
+            error!("Master message loop already stopped");
+        }
+    }
+
+    fn set_maintenance(&self, enabled: bool) {
+        info!("Setting maintenance mode: {}", enabled);
+
+        self.status.write().unwrap().maintenance = enabled;
+    }
+
+    fn set_web_server_status(&self, web_server: WebServerStatus) {
+        self.status.write().unwrap().web_server = web_server;
+    }
+
+    // Mumble users are only identified by name and an optional certificate
+    fn identities(&self) -> Vec<IdentityInfo> {
+        Vec::new()
+    }
+
+    fn music_bot(&self, name: &str) -> Option<Arc<MusicBot>> {
+        self.registry.registered().bots.get(name).cloned()
+    }
+
+    fn bot_names(&self) -> Vec<String> {
+        self.registry.registered().bots.keys().cloned().collect()
+    }
+
+    fn bot_summaries(&self) -> Vec<BotSummary> {
+        let registered = self.registry.registered();
+
+        registered.bots.values().map(|bot| bot.summary()).collect()
+    }
+
+    fn bot_details(&self, name: String) -> Option<BotDetails> {
+        let bot = self.music_bot(&name)?;
+
+        Some(bot.details())
+    }
+
+    fn stats(&self) -> Option<Arc<StatsStore>> {
+        self.config.stats.clone()
+    }
+
+    fn reports(&self) -> Arc<ReportStore> {
+        self.config.reports.clone()
+    }
+
+    fn schedule(&self) -> Arc<ScheduleStore> {
+        self.config.schedule.clone()
+    }
+
+    fn downloader(&self) -> Arc<Downloader> {
+        self.config.downloader.clone()
+    }
+}
@@ -0,0 +1,536 @@
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::{SinkExt, StreamExt};
+use mumble_protocol::control::{msgs, ClientControlCodec, ControlPacket};
+use mumble_protocol::voice::{VoicePacket, VoicePacketPayload};
+use mumble_protocol::{Clientbound, Serverbound};
+use serde::{Deserialize, Serialize};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc::{self, UnboundedSender};
+use tokio_util::codec::Framed;
+use tracing::{debug, error, info};
+use tsclientlib::{ChannelId, ClientId, Invoker, MessageTarget, Uid};
+
+use crate::bot::{Message, MessageSender, MusicBotMessage};
+use crate::error::Error;
+use crate::frontend::{Frontend, Whisper, COMFORT_NOISE_PACKET};
+
+mod master;
+mod text;
+
+pub use master::MumbleMaster;
+
+const DEFAULT_PORT: u16 = 64738;
+
+/// The server drops clients that did not ping for 30 seconds
+const PING_INTERVAL: Duration = Duration::from_secs(15);
+
+/// The version reported to the server, 1.3.0
+const CLIENT_VERSION: u32 = 0x0001_0300;
+
+/// The voice target the bots register for whispering, 0 is the channel
+const WHISPER_TARGET: u32 = 1;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MumbleConfig {
+    /// Accepts any server certificate, for servers with a self signed one
+    pub accept_invalid_certs: bool,
+}
+
+/// A client connection to a Mumble server.
+///
+/// Voice is sent through the control connection so no UDP encryption is
+/// needed. Mumble has no pokes, the master treats private messages as pokes.
+pub struct MumbleConnection {
+    outgoing: UnboundedSender<Option<ControlPacket<Serverbound>>>,
+    state: Arc<RwLock<ServerState>>,
+    /// Sequence number of the next voice packet, counted in 10 ms steps
+    sequence: AtomicU64,
+    /// What the whisper target was last registered for and the channel of the bot then
+    whisper: Mutex<Option<(Whisper, u32)>>,
+}
+
+#[derive(Debug, Default)]
+struct ServerState {
+    own_session: u32,
+    channels: HashMap<u32, MumbleChannel>,
+    users: HashMap<u32, MumbleUser>,
+}
+
+#[derive(Debug, Default)]
+struct MumbleChannel {
+    name: String,
+    parent: Option<u32>,
+}
+
+#[derive(Debug, Default)]
+struct MumbleUser {
+    name: String,
+    channel: u32,
+    /// Hash of the certificate, only users with a certificate have one
+    hash: Option<String>,
+}
+
+/// Mumble sessions are 32 bit, they are counted up from 1 so they fit
+/// into the ids used by the bots.
+fn client_id(session: u32) -> ClientId {
+    ClientId(session as u16)
+}
+
+impl MumbleConnection {
+    /// Connects and joins `channel`, events are sent to `tx` until the connection closes.
+    pub async fn connect(
+        tx: MessageSender,
+        address: &str,
+        name: &str,
+        channel: Option<&str>,
+        config: &MumbleConfig,
+    ) -> Result<Self, Error> {
+        let (host, port) = match address.rfind(':') {
+            Some(i) => match address[i + 1..].parse() {
+                Ok(port) => (&address[..i], port),
+                Err(_) => (address, DEFAULT_PORT),
+            },
+            None => (address, DEFAULT_PORT),
+        };
+
+        let stream = TcpStream::connect((host, port))
+            .await
+            .map_err(|e| Error::Mumble(e.to_string()))?;
+        // Many servers use self signed certificates, accepting them is opt-in
+        let connector = native_tls::TlsConnector::builder()
+            .danger_accept_invalid_certs(config.accept_invalid_certs)
+            .build()
+            .map_err(|e| Error::Mumble(e.to_string()))?;
+        let stream = tokio_tls::TlsConnector::from(connector)
+            .connect(host, stream)
+            .await
+            .map_err(|e| Error::Mumble(e.to_string()))?;
+
+        let (mut sink, mut stream) = Framed::new(stream, ClientControlCodec::new()).split();
+
+        let mut version = msgs::Version::new();
+        version.set_version(CLIENT_VERSION);
+        version.set_release(format!("pokebot {}", env!("CARGO_PKG_VERSION")));
+        sink.send(version.into())
+            .await
+            .map_err(|e| Error::Mumble(e.to_string()))?;
+
+        let mut auth = msgs::Authenticate::new();
+        auth.set_username(name.to_owned());
+        auth.set_opus(true);
+        sink.send(auth.into())
+            .await
+            .map_err(|e| Error::Mumble(e.to_string()))?;
+
+        // The server sends all channels and users before the sync
+        let mut state = ServerState::default();
+        loop {
+            let packet = match stream.next().await {
+                Some(Ok(packet)) => packet,
+                Some(Err(e)) => return Err(Error::Mumble(e.to_string())),
+                None => return Err(Error::Mumble(String::from("Connection closed"))),
+            };
+
+            match packet {
+                ControlPacket::ServerSync(sync) => {
+                    state.own_session = sync.get_session();
+                    break;
+                }
+                ControlPacket::Reject(reject) => {
+                    return Err(Error::Mumble(reject.get_reason().to_owned()))
+                }
+                packet => {
+                    state.apply(packet);
+                }
+            }
+        }
+        info!("Connected to Mumble server {}", address);
+
+        let (outgoing, mut outgoing_rx) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            while let Some(Some(packet)) = outgoing_rx.recv().await {
+                if let Err(e) = sink.send(packet).await {
+                    error!("Failed to send to Mumble server: {}", e);
+                    break;
+                }
+            }
+            let _ = sink.close().await;
+        });
+
+        let pings = outgoing.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(PING_INTERVAL);
+            loop {
+                interval.tick().await;
+                if pings.send(Some(msgs::Ping::new().into())).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let state = Arc::new(RwLock::new(state));
+        let cstate = state.clone();
+        tokio::spawn(async move {
+            while let Some(packet) = stream.next().await {
+                let packet = match packet {
+                    Ok(packet) => packet,
+                    Err(e) => {
+                        error!("Error occured during event reading: {}", e);
+                        break;
+                    }
+                };
+
+                let message = cstate.write().unwrap().apply(packet);
+                if let Some(message) = message {
+                    // Ignore the result because the receiver might get dropped first.
+                    let _ = tx.send(message);
+                }
+            }
+
+            debug!("Mumble connection closed");
+            let _ = tx.send(MusicBotMessage::Quit(String::from("Disconnected")));
+        });
+
+        let connection = Self {
+            outgoing,
+            state,
+            sequence: AtomicU64::new(0),
+            whisper: Mutex::new(None),
+        };
+
+        if let Some(channel) = channel {
+            match connection.resolve_channel(channel) {
+                Some(id) => connection.move_to_channel(id),
+                None => error!("Can't find channel {}, staying in the default one", channel),
+            }
+        }
+
+        Ok(connection)
+    }
+
+    fn send(&self, packet: ControlPacket<Serverbound>) {
+        // Ignore the result because the connection might be closed already
+        let _ = self.outgoing.send(Some(packet));
+    }
+
+    /// The voice target to talk to, registers the whisper target when it changed.
+    ///
+    /// Whispering to channels includes the own channel, private sessions
+    /// only reach their clients.
+    fn voice_target(&self, whisper: &Whisper) -> u32 {
+        if whisper.channels.is_empty() && whisper.clients.is_empty() {
+            return 0;
+        }
+
+        let state = self.state.read().unwrap();
+        let own_channel = state.own_channel();
+        let mut registered = self.whisper.lock().unwrap();
+        if registered.as_ref() == Some(&(whisper.clone(), own_channel)) {
+            return WHISPER_TARGET;
+        }
+
+        let mut voice_target = msgs::VoiceTarget::new();
+        voice_target.set_id(WHISPER_TARGET);
+        if whisper.is_private() {
+            for session in state
+                .users
+                .keys()
+                .filter(|s| whisper.clients.contains(&client_id(**s)))
+            {
+                let mut target = msgs::VoiceTarget_Target::new();
+                target.mut_session().push(*session);
+                voice_target.mut_targets().push(target);
+            }
+        } else {
+            let channels = std::iter::once(own_channel)
+                .chain(whisper.channels.iter().map(|c| c.0 as u32))
+                .filter(|c| state.channels.contains_key(c));
+            for channel in channels {
+                let mut target = msgs::VoiceTarget_Target::new();
+                target.set_channel_id(channel);
+                voice_target.mut_targets().push(target);
+            }
+        }
+        self.send(voice_target.into());
+        *registered = Some((whisper.clone(), own_channel));
+
+        WHISPER_TARGET
+    }
+
+    fn send_voice(&self, data: &[u8], end: bool, target: u32) {
+        // A packet holds 20 ms of audio
+        let seq_num = self.sequence.fetch_add(2, Ordering::Relaxed);
+
+        self.send(ControlPacket::UDPTunnel(Box::new(VoicePacket::Audio {
+            _dst: PhantomData,
+            target: target as u8,
+            session_id: (),
+            seq_num,
+            payload: VoicePacketPayload::Opus(Bytes::copy_from_slice(data), end),
+            position_info: None,
+        })));
+    }
+
+    fn update_self(&self, edit: impl FnOnce(&mut msgs::UserState)) {
+        let mut user_state = msgs::UserState::new();
+        user_state.set_session(self.state.read().unwrap().own_session);
+        edit(&mut user_state);
+
+        self.send(user_state.into());
+    }
+
+    pub fn my_id(&self) -> ClientId {
+        client_id(self.state.read().unwrap().own_session)
+    }
+
+    pub fn channel_of_user(&self, id: ClientId) -> Option<ChannelId> {
+        let state = self.state.read().unwrap();
+
+        state
+            .users
+            .iter()
+            .find(|(session, _)| client_id(**session) == id)
+            .map(|(_, user)| ChannelId(user.channel as u64))
+    }
+
+    /// The path of a channel without the root channel, e.g. `Lobby/Music`.
+    pub fn channel_path(&self, id: ChannelId) -> Option<String> {
+        let state = self.state.read().unwrap();
+
+        let mut channel = state.channels.get(&(id.0 as u32))?;
+        let mut names = Vec::new();
+        while let Some(parent) = channel.parent {
+            names.push(&channel.name[..]);
+            channel = state.channels.get(&parent)?;
+        }
+        names.reverse();
+
+        Some(names.join("/"))
+    }
+
+    /// Finds a channel by its id, its path or its name if no other channel has the same name.
+    pub fn resolve_channel(&self, channel: &str) -> Option<ChannelId> {
+        let state = self.state.read().unwrap();
+        let channel = channel.trim();
+
+        if let Ok(id) = channel.parse::<u32>() {
+            if state.channels.contains_key(&id) {
+                return Some(ChannelId(id as u64));
+            }
+        }
+
+        // The root channel has id 0 and is not part of paths
+        let mut path = None;
+        for name in channel.split('/').map(str::trim).filter(|s| !s.is_empty()) {
+            let parent = path.unwrap_or(0);
+            path = state
+                .channels
+                .iter()
+                .find(|(_, c)| c.parent == Some(parent) && c.name == name)
+                .map(|(id, _)| *id);
+            if path.is_none() {
+                break;
+            }
+        }
+        if let Some(id) = path {
+            return Some(ChannelId(id as u64));
+        }
+
+        let mut matching = state.channels.iter().filter(|(_, c)| c.name == channel);
+        match (matching.next(), matching.next()) {
+            (Some((id, _)), None) => Some(ChannelId(*id as u64)),
+            _ => None,
+        }
+    }
+
+    pub fn move_to_channel(&self, channel: ChannelId) {
+        self.update_self(|user_state| user_state.set_channel_id(channel.0 as u32));
+    }
+
+    pub fn send_message_to_user(&self, client: ClientId, text: String) {
+        let session = {
+            let state = self.state.read().unwrap();
+            match state.users.keys().find(|s| client_id(**s) == client) {
+                Some(session) => *session,
+                None => return,
+            }
+        };
+
+        let mut message = msgs::TextMessage::new();
+        message.mut_session().push(session);
+        message.set_message(text::html(&text));
+
+        self.send(message.into());
+    }
+}
+
+impl ServerState {
+    /// Applies a packet from the server, returns what the bot has to know about it
+    fn apply(&mut self, packet: ControlPacket<Clientbound>) -> Option<MusicBotMessage> {
+        match packet {
+            ControlPacket::ChannelState(channel_state) => {
+                let channel = self
+                    .channels
+                    .entry(channel_state.get_channel_id())
+                    .or_default();
+                if channel_state.has_name() {
+                    channel.name = channel_state.get_name().to_owned();
+                }
+                if channel_state.has_parent() {
+                    channel.parent = Some(channel_state.get_parent());
+                }
+
+                None
+            }
+            ControlPacket::ChannelRemove(remove) => {
+                self.channels.remove(&remove.get_channel_id());
+
+                None
+            }
+            ControlPacket::UserState(user_state) => {
+                let user = self.users.entry(user_state.get_session()).or_default();
+                if user_state.has_name() {
+                    user.name = user_state.get_name().to_owned();
+                }
+                if user_state.has_channel_id() {
+                    user.channel = user_state.get_channel_id();
+                }
+                if user_state.has_hash() {
+                    user.hash = Some(user_state.get_hash().to_owned());
+                }
+
+                Some(MusicBotMessage::ListenersChanged)
+            }
+            ControlPacket::UserRemove(remove) => {
+                if remove.get_session() == self.own_session {
+                    let reason = remove.get_reason();
+                    return Some(MusicBotMessage::Quit(format!("Removed: {}", reason)));
+                }
+                self.users.remove(&remove.get_session());
+
+                Some(MusicBotMessage::ListenersChanged)
+            }
+            ControlPacket::TextMessage(message) => {
+                let actor = message.get_actor();
+                let user = self.users.get(&actor)?;
+
+                let target = if message.get_session().contains(&self.own_session) {
+                    MessageTarget::Client(client_id(self.own_session))
+                } else if !message.get_channel_id().is_empty() {
+                    MessageTarget::Channel
+                } else {
+                    return None;
+                };
+
+                Some(MusicBotMessage::TextMessage(Message {
+                    target,
+                    invoker: Invoker {
+                        name: user.name.clone(),
+                        id: client_id(actor),
+                        uid: user.hash.clone().map(Uid),
+                    },
+                    text: text::plain_text(message.get_message()),
+                }))
+            }
+            _ => None,
+        }
+    }
+
+    fn own_channel(&self) -> u32 {
+        self.users
+            .get(&self.own_session)
+            .map(|u| u.channel)
+            .unwrap_or(0)
+    }
+}
+
+#[async_trait]
+impl Frontend for MumbleConnection {
+    async fn send_audio_packet(&self, samples: &[u8], whisper: &Whisper) {
+        self.send_voice(samples, false, self.voice_target(whisper));
+    }
+
+    async fn send_audio_end(&self, whisper: &Whisper) {
+        self.send_voice(&[], true, self.voice_target(whisper));
+    }
+
+    async fn send_comfort_noise(&self, whisper: &Whisper) {
+        self.send_voice(COMFORT_NOISE_PACKET, false, self.voice_target(whisper));
+    }
+
+    async fn send_message_to_channel(&self, text: String) {
+        let mut message = msgs::TextMessage::new();
+        message
+            .mut_channel_id()
+            .push(self.state.read().unwrap().own_channel());
+        message.set_message(text::html(&text));
+
+        self.send(message.into());
+    }
+
+    async fn send_message_to_user(&self, invoker: &Invoker, text: String) {
+        // Users of a bridge are not on the server
+        if invoker.id == ClientId(0) {
+            return self.send_message_to_channel(text).await;
+        }
+
+        // Resolves the session the id was made from
+        MumbleConnection::send_message_to_user(self, invoker.id, text);
+    }
+
+    async fn set_nickname(&self, name: String) {
+        debug!(
+            "Mumble names can't change while connected, not setting {:?}",
+            name
+        );
+    }
+
+    async fn set_description(&self, desc: String) {
+        self.update_self(|user_state| user_state.set_comment(text::html(&desc)));
+    }
+
+    async fn set_avatar(&self, data: Vec<u8>) -> Result<(), String> {
+        self.update_self(|user_state| user_state.set_texture(data));
+
+        Ok(())
+    }
+
+    async fn remove_avatar(&self) -> Result<(), String> {
+        self.update_self(|user_state| user_state.set_texture(Vec::new()));
+
+        Ok(())
+    }
+
+    async fn my_channel(&self) -> Option<ChannelId> {
+        Some(ChannelId(self.state.read().unwrap().own_channel() as u64))
+    }
+
+    /// Bots on Mumble have no certificates, they are recognized by name
+    async fn listener_count(&self, ignored: Arc<Vec<String>>) -> u32 {
+        let state = self.state.read().unwrap();
+        let channel = state.own_channel();
+
+        state
+            .users
+            .iter()
+            .filter(|(session, user)| **session != state.own_session && user.channel == channel)
+            .filter(|(_, user)| !ignored.contains(&user.name))
+            .filter(|(_, user)| match &user.hash {
+                Some(hash) => !ignored.contains(hash),
+                None => true,
+            })
+            .count() as u32
+    }
+
+    async fn disconnect(&self, _reason: &str) {
+        let _ = self.outgoing.send(None);
+    }
+}
@@ -0,0 +1,77 @@
+/// Converts the BBCode the bots write for TeamSpeak into the HTML Mumble shows
+pub fn html(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = rest.find('[') {
+        result.push_str(&escape(&rest[..start]));
+        rest = &rest[start..];
+
+        let end = match rest.find(']') {
+            Some(end) => end,
+            None => break,
+        };
+        let tag = &rest[1..end];
+        rest = &rest[end + 1..];
+
+        match tag {
+            "B" => result.push_str("<b>"),
+            "/B" => result.push_str("</b>"),
+            "I" => result.push_str("<i>"),
+            "/I" => result.push_str("</i>"),
+            "U" => result.push_str("<u>"),
+            "/U" => result.push_str("</u>"),
+            "/URL" => result.push_str("</a>"),
+            _ => match tag.strip_prefix("URL=") {
+                Some(url) => result.push_str(&format!("<a href=\"{}\">", escape(url))),
+                None => result.push_str(&escape(&format!("[{}]", tag))),
+            },
+        }
+    }
+    result.push_str(&escape(rest));
+
+    result
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\n', "<br>")
+}
+
+/// Turns a message from a Mumble client back into the text the user typed.
+///
+/// Clients wrap links in anchors and escape special characters, the tags
+/// are dropped so commands see the plain link.
+pub fn plain_text(html: &str) -> String {
+    let mut result = String::with_capacity(html.len());
+    let mut rest = html;
+
+    while let Some(start) = rest.find('<') {
+        result.push_str(&rest[..start]);
+        let end = match rest[start..].find('>') {
+            Some(end) => start + end,
+            None => {
+                rest = &rest[start..];
+                break;
+            }
+        };
+
+        let tag = rest[start + 1..end].trim().to_lowercase();
+        if tag == "br" || tag == "br/" || tag == "br /" {
+            result.push('\n');
+        }
+        rest = &rest[end + 1..];
+    }
+    result.push_str(rest);
+
+    result
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&nbsp;", " ")
+        .replace("&amp;", "&")
+}
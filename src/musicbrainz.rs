@@ -0,0 +1,425 @@
+//! Finds the artist, title and album of tracks on MusicBrainz.
+//!
+//! Tracks are searched by the artist and title youtube-dl found or that are
+//! parsed from the title. Local files are identified by their audio with
+//! AcoustID if an API key is set and `fpcalc` from Chromaprint is installed.
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::process::Command;
+use tracing::{debug, error, info, warn};
+
+use crate::scrobble::parse_title;
+use crate::youtube_dl::AudioMetadata;
+
+const MUSICBRAINZ_API: &str = "https://musicbrainz.org/ws/2/recording";
+const ACOUSTID_API: &str = "https://api.acoustid.org/v2/lookup";
+
+/// MusicBrainz blocks clients that send more than one request per second
+const REQUEST_INTERVAL: Duration = Duration::from_secs(1);
+/// Lookups that take longer are given up and tried again the next time
+const LOOKUP_TIMEOUT: Duration = Duration::from_secs(30);
+/// Tracks nothing was found for are looked up again after this long
+const MISS_EXPIRY: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+/// Search results with a lower score from 0 to 100 are not trusted
+const MIN_SEARCH_SCORE: u64 = 90;
+/// AcoustID matches with a lower score from 0 to 1 are not trusted
+const MIN_ACOUSTID_SCORE: f64 = 0.8;
+/// How much longer or shorter a recording may be than the track
+const MAX_LENGTH_DIFFERENCE: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MetadataConfig {
+    /// Identifies local files by their audio, needs `fpcalc` to be installed
+    pub acoustid_key: Option<String>,
+    /// Lookups are kept here so tracks are only looked up once
+    #[serde(default = "default_cache_file")]
+    pub cache_file: PathBuf,
+}
+
+fn default_cache_file() -> PathBuf {
+    PathBuf::from("musicbrainz.json")
+}
+
+/// What MusicBrainz knows about a track
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Recording {
+    pub id: String,
+    pub title: String,
+    pub artist: String,
+    pub album: Option<String>,
+}
+
+impl Recording {
+    fn apply(&self, metadata: &mut AudioMetadata) {
+        metadata.title = format!("{} - {}", self.artist, self.title);
+        metadata.artist = Some(self.artist.clone());
+        metadata.track = Some(self.title.clone());
+        if self.album.is_some() {
+            metadata.album = self.album.clone();
+        }
+        metadata.recording = Some(self.id.clone());
+    }
+}
+
+/// The result of looking up a track
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Lookup {
+    /// `None` if nothing was found
+    recording: Option<Recording>,
+    time: SystemTime,
+}
+
+impl Lookup {
+    /// Found recordings are kept, misses are tried again after `MISS_EXPIRY`
+    fn is_fresh(&self) -> bool {
+        self.recording.is_some()
+            || self
+                .time
+                .elapsed()
+                .map_or(true, |elapsed| elapsed < MISS_EXPIRY)
+    }
+}
+
+pub struct MetadataLookup {
+    config: MetadataConfig,
+    http: reqwest::Client,
+    /// Lookups by the webpage url of the track
+    cache: Mutex<HashMap<String, Lookup>>,
+    /// Tracks that are being looked up right now
+    pending: Mutex<HashSet<String>>,
+    /// Held while the cache is written, so an older copy can not win
+    saving: tokio::sync::Mutex<()>,
+    /// Held while waiting for the rate limit, so requests go out one after another
+    last_request: tokio::sync::Mutex<Option<Instant>>,
+}
+
+/// Sets up metadata lookups if they are configured
+pub fn open(config: Option<MetadataConfig>) -> Option<Arc<MetadataLookup>> {
+    let config = config?;
+
+    let cache = match fs::read(&config.cache_file) {
+        Ok(data) => match serde_json::from_slice(&data) {
+            Ok(cache) => cache,
+            Err(e) => {
+                error!(
+                    "Failed to parse metadata cache in {:?}: {}",
+                    config.cache_file, e
+                );
+                HashMap::new()
+            }
+        },
+        Err(e) if e.kind() == io::ErrorKind::NotFound => HashMap::new(),
+        Err(e) => {
+            error!(
+                "Failed to read metadata cache from {:?}: {}",
+                config.cache_file, e
+            );
+            HashMap::new()
+        }
+    };
+
+    let user_agent = format!(
+        "PokeBot/{} ( https://github.com/kilmanio/pokebot )",
+        env!("CARGO_PKG_VERSION")
+    );
+    let http = match reqwest::Client::builder().user_agent(user_agent).build() {
+        Ok(http) => http,
+        Err(e) => {
+            warn!("Failed to set up metadata lookups: {}", e);
+            return None;
+        }
+    };
+
+    info!(
+        "Looking up track metadata on MusicBrainz{}",
+        if config.acoustid_key.is_some() {
+            " and AcoustID"
+        } else {
+            ""
+        }
+    );
+    Some(Arc::new(MetadataLookup {
+        config,
+        http,
+        cache: Mutex::new(cache),
+        pending: Mutex::new(HashSet::new()),
+        saving: tokio::sync::Mutex::new(()),
+        last_request: tokio::sync::Mutex::new(None),
+    }))
+}
+
+impl MetadataLookup {
+    /// Applies an earlier lookup, tracks never wait for one to start
+    pub fn apply_cached(&self, metadata: &mut AudioMetadata) {
+        let cache = self.cache.lock().unwrap();
+        if let Some(Lookup {
+            recording: Some(recording),
+            ..
+        }) = cache.get(&metadata.webpage_url)
+        {
+            recording.apply(metadata);
+        }
+    }
+
+    /// Looks the track up unless that was done before, the result is applied
+    /// once the track starts
+    pub async fn look_up(self: Arc<Self>, metadata: AudioMetadata) {
+        let url = metadata.webpage_url.clone();
+        let fresh = self
+            .cache
+            .lock()
+            .unwrap()
+            .get(&url)
+            .map_or(false, Lookup::is_fresh);
+        if fresh || !self.pending.lock().unwrap().insert(url.clone()) {
+            return;
+        }
+
+        let result = tokio::time::timeout(LOOKUP_TIMEOUT, self.lookup(&metadata)).await;
+        self.pending.lock().unwrap().remove(&url);
+        match result {
+            Ok(Ok(recording)) => {
+                if let Some(recording) = &recording {
+                    debug!(
+                        "{} is {} - {} ({})",
+                        url, recording.artist, recording.title, recording.id
+                    );
+                }
+                self.store(url, recording).await;
+            }
+            Ok(Err(e)) => warn!("Failed to look up {}: {}", url, e),
+            Err(_) => debug!("Looking up {} took too long", url),
+        }
+    }
+
+    async fn lookup(&self, metadata: &AudioMetadata) -> Result<Option<Recording>, String> {
+        if let (Some(key), Some(path)) = (
+            &self.config.acoustid_key,
+            metadata.url.strip_prefix("file://"),
+        ) {
+            match self.identify(key, Path::new(path)).await {
+                Ok(Some(recording)) => return Ok(Some(recording)),
+                Ok(None) => (),
+                Err(e) => warn!("Failed to identify {}: {}", path, e),
+            }
+        }
+
+        let (artist, track) = match (&metadata.artist, &metadata.track) {
+            (Some(artist), Some(track)) => (artist.clone(), track.clone()),
+            _ => match parse_title(&metadata.title) {
+                Some(parsed) => parsed,
+                None => return Ok(None),
+            },
+        };
+
+        self.search(&artist, &track, metadata.duration).await
+    }
+
+    /// Searches recordings by artist and title
+    async fn search(
+        &self,
+        artist: &str,
+        track: &str,
+        duration: Option<Duration>,
+    ) -> Result<Option<Recording>, String> {
+        let query = format!(
+            "recording:\"{}\" AND artist:\"{}\"",
+            escape(track),
+            escape(artist)
+        );
+        let params = [("query", query.as_str()), ("fmt", "json"), ("limit", "5")];
+
+        self.wait_for_rate_limit().await;
+        let request = self.http.get(MUSICBRAINZ_API).query(&params);
+        let response = request.send().await.map_err(|e| e.to_string())?;
+        let body = body(response).await?;
+
+        let recordings = body["recordings"].as_array().cloned().unwrap_or_default();
+        Ok(recordings
+            .iter()
+            .filter(|r| r["score"].as_u64().unwrap_or(0) >= MIN_SEARCH_SCORE)
+            .find(|r| fits_length(r["length"].as_u64(), duration))
+            .and_then(parse_recording))
+    }
+
+    /// Identifies a local file by its audio fingerprint
+    async fn identify(&self, key: &str, path: &Path) -> Result<Option<Recording>, String> {
+        let output = Command::new("fpcalc")
+            .arg("-json")
+            .arg(path)
+            // The lookup is dropped once it takes too long
+            .kill_on_drop(true)
+            .output()
+            .await
+            .map_err(|e| format!("failed to run fpcalc: {}", e))?;
+        if !output.status.success() {
+            return Err(String::from_utf8_lossy(&output.stderr).trim().to_owned());
+        }
+        let fingerprint: Value =
+            serde_json::from_slice(&output.stdout).map_err(|e| e.to_string())?;
+        let (duration, fingerprint) = match (
+            fingerprint["duration"].as_f64(),
+            fingerprint["fingerprint"].as_str(),
+        ) {
+            (Some(duration), Some(fingerprint)) => (duration, fingerprint),
+            _ => return Err(String::from("fpcalc found no fingerprint")),
+        };
+
+        let params = [
+            ("client", key.to_owned()),
+            ("meta", String::from("recordings releasegroups")),
+            ("duration", (duration.round() as u64).to_string()),
+            ("fingerprint", fingerprint.to_owned()),
+        ];
+        let request = self.http.post(ACOUSTID_API).form(&params);
+        let response = request.send().await.map_err(|e| e.to_string())?;
+        let body = body(response).await?;
+        if body["status"].as_str() != Some("ok") {
+            return Err(format!(
+                "AcoustID returned {}",
+                body["error"]["message"].as_str().unwrap_or("an error")
+            ));
+        }
+
+        let results = body["results"].as_array().cloned().unwrap_or_default();
+        Ok(results
+            .iter()
+            .filter(|r| r["score"].as_f64().unwrap_or(0.0) >= MIN_ACOUSTID_SCORE)
+            .filter_map(|r| r["recordings"].as_array()?.first().cloned())
+            .find_map(|r| parse_acoustid_recording(&r)))
+    }
+
+    async fn wait_for_rate_limit(&self) {
+        let mut last_request = self.last_request.lock().await;
+        if let Some(last) = *last_request {
+            let next = last + REQUEST_INTERVAL;
+            if next > Instant::now() {
+                tokio::time::delay_until(next.into()).await;
+            }
+        }
+        *last_request = Some(Instant::now());
+    }
+
+    async fn store(&self, url: String, recording: Option<Recording>) {
+        let _saving = self.saving.lock().await;
+        let data = {
+            let mut cache = self.cache.lock().unwrap();
+            cache.insert(
+                url,
+                Lookup {
+                    recording,
+                    time: SystemTime::now(),
+                },
+            );
+            serde_json::to_vec(&*cache)
+        };
+
+        let path = self.config.cache_file.clone();
+        let result = match data {
+            Ok(data) => tokio::task::spawn_blocking(move || fs::write(path, data))
+                .await
+                .unwrap_or_else(|e| Err(io::Error::new(io::ErrorKind::Other, e))),
+            Err(e) => Err(io::Error::from(e)),
+        };
+        if let Err(e) = result {
+            error!(
+                "Failed to save metadata cache to {:?}: {}",
+                self.config.cache_file, e
+            );
+        }
+    }
+}
+
+/// Quotes and backslashes would end the phrase of the Lucene query early
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// `length` is in milliseconds, recordings or tracks without one always fit
+fn fits_length(length: Option<u64>, duration: Option<Duration>) -> bool {
+    match (length, duration) {
+        (Some(length), Some(duration)) => {
+            let length = Duration::from_millis(length);
+            let difference = if length > duration {
+                length - duration
+            } else {
+                duration - length
+            };
+            difference <= MAX_LENGTH_DIFFERENCE
+        }
+        _ => true,
+    }
+}
+
+/// Joins the names of all credited artists like "Artist feat. Other"
+fn artist_credit(credits: &Value) -> Option<String> {
+    let credits = credits.as_array()?;
+    let artist = credits
+        .iter()
+        .map(|c| {
+            format!(
+                "{}{}",
+                c["name"].as_str().unwrap_or_default(),
+                c["joinphrase"].as_str().unwrap_or_default()
+            )
+        })
+        .collect::<String>();
+
+    if artist.is_empty() {
+        None
+    } else {
+        Some(artist)
+    }
+}
+
+fn parse_recording(recording: &Value) -> Option<Recording> {
+    Some(Recording {
+        id: recording["id"].as_str()?.to_owned(),
+        title: recording["title"].as_str()?.to_owned(),
+        artist: artist_credit(&recording["artist-credit"])?,
+        album: recording["releases"][0]["title"]
+            .as_str()
+            .map(str::to_owned),
+    })
+}
+
+/// AcoustID lists artists without the phrases between them
+fn parse_acoustid_recording(recording: &Value) -> Option<Recording> {
+    let artist = recording["artists"]
+        .as_array()?
+        .iter()
+        .filter_map(|a| a["name"].as_str())
+        .collect::<Vec<_>>()
+        .join(", ");
+    if artist.is_empty() {
+        return None;
+    }
+
+    Some(Recording {
+        id: recording["id"].as_str()?.to_owned(),
+        title: recording["title"].as_str()?.to_owned(),
+        artist,
+        album: recording["releasegroups"][0]["title"]
+            .as_str()
+            .map(str::to_owned),
+    })
+}
+
+async fn body(response: reqwest::Response) -> Result<Value, String> {
+    let status = response.status();
+    let body = response.text().await.map_err(|e| e.to_string())?;
+
+    if !status.is_success() {
+        return Err(format!("{}: {}", status, body));
+    }
+
+    serde_json::from_str(&body).map_err(|e| e.to_string())
+}
@@ -1,6 +1,6 @@
 use std::collections::VecDeque;
 
-use log::info;
+use tracing::info;
 
 use crate::youtube_dl::AudioMetadata;
 
@@ -16,18 +16,88 @@ impl Playlist {
     }
 
     pub fn push(&mut self, data: AudioMetadata) {
-        info!("Adding {:?} to playlist", &data.title);
+        info!("{} Adding {:?} to playlist", data.request_id, &data.title);
 
         self.data.push_front(data)
     }
 
+    /// Adds an entry that will be popped before all others
+    pub fn push_next(&mut self, data: AudioMetadata) {
+        info!(
+            "{} Adding {:?} to the front of the playlist",
+            data.request_id, &data.title
+        );
+
+        self.data.push_back(data)
+    }
+
     pub fn pop(&mut self) -> Option<AudioMetadata> {
         let res = self.data.pop_back();
-        info!("Popping {:?} from playlist", res.as_ref().map(|r| &r.title));
+        if let Some(r) = &res {
+            info!("{} Popping {:?} from playlist", r.request_id, &r.title);
+        } else {
+            info!("Popping None from playlist");
+        }
 
         res
     }
 
+    /// The entry that will be popped next
+    pub fn peek(&self) -> Option<&AudioMetadata> {
+        self.data.back()
+    }
+
+    /// Replaces the next entry if it is still the one for `webpage_url`
+    pub fn replace_next(&mut self, data: AudioMetadata) -> bool {
+        match self.data.back_mut() {
+            Some(next) if next.webpage_url == data.webpage_url => {
+                *next = data;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Removes the entry at `index` in the order of `to_vec`
+    pub fn remove(&mut self, index: usize) -> Option<AudioMetadata> {
+        let index = self.data_index(index)?;
+        let res = self.data.remove(index);
+        if let Some(r) = &res {
+            info!("{} Removing {:?} from playlist", r.request_id, &r.title);
+        }
+
+        res
+    }
+
+    /// Moves the entry at `from` to `to`, both in the order of `to_vec`.
+    ///
+    /// Returns false if either index is out of bounds.
+    pub fn move_entry(&mut self, from: usize, to: usize) -> bool {
+        let (from, to) = match (self.data_index(from), self.data_index(to)) {
+            (Some(from), Some(to)) => (from, to),
+            _ => return false,
+        };
+
+        let entry = self.data.remove(from).expect("index was checked");
+        info!("{} Moving {:?} in playlist", entry.request_id, &entry.title);
+        self.data.insert(to, entry);
+
+        true
+    }
+
+    /// Translates an index of `to_vec` into an index of `data`
+    fn data_index(&self, index: usize) -> Option<usize> {
+        if index < self.data.len() {
+            Some(self.data.len() - 1 - index)
+        } else {
+            None
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
     pub fn to_vec(&self) -> Vec<AudioMetadata> {
         let (a, b) = self.data.as_slices();
 
@@ -0,0 +1,149 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use tracing::debug;
+
+use crate::request_id::RequestId;
+use crate::youtube_dl::AudioMetadata;
+
+/// Checks if the url points to an M3U or PLS playlist file
+pub fn is_playlist_file_url(url: &str) -> bool {
+    let path = url.split(|c| c == '?' || c == '#').next().unwrap_or(url);
+    let path = path.to_lowercase();
+
+    path.ends_with(".m3u") || path.ends_with(".pls")
+}
+
+/// Parses an M3U or PLS playlist, the format is detected from the content.
+///
+/// Only absolute http(s) urls are kept, the entries still have to be resolved.
+pub fn parse(text: &str, request_id: RequestId) -> Vec<AudioMetadata> {
+    let is_pls = text
+        .lines()
+        .map(str::trim)
+        .find(|l| !l.is_empty())
+        .map_or(false, |l| l.eq_ignore_ascii_case("[playlist]"));
+
+    let entries = if is_pls {
+        parse_pls(text)
+    } else {
+        parse_m3u(text)
+    };
+
+    entries
+        .into_iter()
+        .filter(|(url, _, _)| {
+            let keep = url.starts_with("http://") || url.starts_with("https://");
+            if !keep {
+                debug!("{} Ignoring playlist entry {}", request_id, url);
+            }
+            keep
+        })
+        .map(|(url, title, duration)| AudioMetadata {
+            url: String::new(),
+            title: title.unwrap_or_else(|| url.clone()),
+            webpage_url: url,
+            thumbnail: None,
+            duration,
+            artist: None,
+            track: None,
+            album: None,
+            recording: None,
+            chapters: Vec::new(),
+            added_by: String::new(),
+            request_id,
+        })
+        .collect()
+}
+
+type Entry = (String, Option<String>, Option<Duration>);
+
+fn parse_length(length: &str) -> Option<Duration> {
+    // Unknown lengths are usually written as -1
+    length
+        .trim()
+        .parse::<i64>()
+        .ok()
+        .filter(|&l| l > 0)
+        .map(|l| Duration::from_secs(l as u64))
+}
+
+fn parse_m3u(text: &str) -> Vec<Entry> {
+    let mut entries = Vec::new();
+    let mut info = None;
+
+    for line in text.lines().map(str::trim) {
+        if line.is_empty() {
+            continue;
+        }
+
+        if line.starts_with("#EXTINF:") {
+            let mut split = line["#EXTINF:".len()..].splitn(2, ',');
+            let duration = split.next().and_then(parse_length);
+            let title = split
+                .next()
+                .map(|t| t.trim().to_owned())
+                .filter(|t| !t.is_empty());
+            info = Some((title, duration));
+        } else if !line.starts_with('#') {
+            let (title, duration) = info.take().unwrap_or((None, None));
+            entries.push((line.to_owned(), title, duration));
+        }
+    }
+
+    entries
+}
+
+fn parse_pls(text: &str) -> Vec<Entry> {
+    let mut entries: BTreeMap<usize, Entry> = BTreeMap::new();
+
+    for line in text.lines().map(str::trim) {
+        let mut split = line.splitn(2, '=');
+        let (key, value) = match (split.next(), split.next()) {
+            (Some(key), Some(value)) => (key.trim().to_lowercase(), value.trim()),
+            _ => continue,
+        };
+
+        let (field, index) = match key.find(|c: char| c.is_ascii_digit()) {
+            Some(i) => (&key[..i], &key[i..]),
+            None => continue,
+        };
+        let index = match index.parse::<usize>() {
+            Ok(index) => index,
+            Err(_) => continue,
+        };
+
+        let entry = entries
+            .entry(index)
+            .or_insert_with(|| (String::new(), None, None));
+        match field {
+            "file" => entry.0 = value.to_owned(),
+            "title" if !value.is_empty() => entry.1 = Some(value.to_owned()),
+            "length" => entry.2 = parse_length(value),
+            _ => (),
+        }
+    }
+
+    entries
+        .into_iter()
+        .map(|(_, e)| e)
+        .filter(|(url, _, _)| !url.is_empty())
+        .collect()
+}
+
+/// Renders tracks as an extended M3U playlist
+pub fn to_m3u(entries: &[AudioMetadata]) -> String {
+    let mut m3u = String::from("#EXTM3U\n");
+
+    for entry in entries {
+        let length = entry.duration.map_or(-1, |d| d.as_secs() as i64);
+        // Line breaks would end the entry early
+        let title = entry.title.replace(|c| c == '\n' || c == '\r', " ");
+
+        m3u.push_str(&format!("#EXTINF:{},{}\n", length, title));
+        m3u.push_str(&entry.webpage_url);
+        m3u.push('\n');
+    }
+
+    m3u
+}
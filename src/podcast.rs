@@ -0,0 +1,170 @@
+//! Podcast feeds and the subscriptions of a channel to them.
+//!
+//! The feeds are read by youtube-dl, this keeps track of the subscriptions and
+//! of the newest episode each of them has seen.
+
+use std::sync::RwLock;
+use std::time::Duration;
+
+use crate::channel_settings::PodcastSubscription;
+use crate::request_id::RequestId;
+use crate::teamspeak as ts;
+use crate::youtube_dl::{self, AudioMetadata, Downloader, Podcast};
+
+/// How many episodes `!podcast` lists
+pub const EPISODES: usize = 5;
+
+/// How often subscribed podcasts are checked for new episodes
+pub const CHECK_INTERVAL: Duration = Duration::from_secs(30 * 60);
+
+/// Strips the BBCode TeamSpeak puts around links
+pub fn feed_url(text: &str) -> String {
+    text.replace("[URL]", "").replace("[/URL]", "")
+}
+
+pub fn read_error(error: &str) -> String {
+    format!(
+        "Failed to read podcast: {}",
+        youtube_dl::error_summary(error)
+    )
+}
+
+/// Lists the episodes numbered for `!pick`
+pub fn episodes_text(podcast: &Podcast, prefix: &str) -> String {
+    let mut text = format!("Newest episodes of {}:", ts::bold(&podcast.title));
+    for (i, episode) in podcast.episodes.iter().enumerate() {
+        let duration = if let Some(duration) = episode.duration {
+            format!(" ({})", crate::duration::format_timestamp(duration))
+        } else {
+            String::new()
+        };
+        text += &format!("\n{}. {}{}", i + 1, ts::underline(&episode.title), duration);
+    }
+    text += &format!("\nUse {}pick <number> to add one", prefix);
+
+    text
+}
+
+/// Returns the newest episode of the feed if the subscription has not seen it yet
+pub async fn new_episode(
+    downloader: &Downloader,
+    subscription: &PodcastSubscription,
+    request_id: RequestId,
+) -> Result<Option<AudioMetadata>, String> {
+    let feed = downloader
+        .get_podcast(&subscription.url, 1, request_id)
+        .await?;
+
+    Ok(feed
+        .episodes
+        .into_iter()
+        .next()
+        .filter(|newest| subscription.latest_episode.as_ref() != Some(&newest.webpage_url)))
+}
+
+pub fn new_episode_text(
+    subscription: &PodcastSubscription,
+    number: usize,
+    episode: &AudioMetadata,
+    prefix: &str,
+) -> String {
+    format!(
+        "New episode of {}: {}, use {}podcast {} to list it",
+        ts::bold(&subscription.title),
+        ts::underline(&episode.title),
+        prefix,
+        number
+    )
+}
+
+/// The podcasts of a channel, restored from and saved to its channel profile.
+///
+/// Subscriptions are numbered from 1 in the order they were added.
+#[derive(Default)]
+pub struct Subscriptions {
+    podcasts: RwLock<Vec<PodcastSubscription>>,
+}
+
+impl Subscriptions {
+    pub fn get(&self) -> Vec<PodcastSubscription> {
+        self.podcasts.read().unwrap().clone()
+    }
+
+    pub fn set(&self, podcasts: Vec<PodcastSubscription>) {
+        *self.podcasts.write().unwrap() = podcasts;
+    }
+
+    /// The subscription with the 1-based `number`
+    pub fn find(&self, number: usize) -> Option<PodcastSubscription> {
+        let podcasts = self.podcasts.read().unwrap();
+
+        number.checked_sub(1).and_then(|i| podcasts.get(i)).cloned()
+    }
+
+    pub fn contains(&self, url: &str) -> bool {
+        self.podcasts.read().unwrap().iter().any(|p| p.url == url)
+    }
+
+    /// Subscribes to the feed, the episodes it has now are not announced
+    pub fn add(&self, url: String, podcast: Podcast) {
+        self.podcasts.write().unwrap().push(PodcastSubscription {
+            url,
+            title: podcast.title,
+            announce: false,
+            latest_episode: podcast.episodes.into_iter().next().map(|e| e.webpage_url),
+        });
+    }
+
+    pub fn remove(&self, number: usize) -> Option<PodcastSubscription> {
+        let mut podcasts = self.podcasts.write().unwrap();
+
+        match number.checked_sub(1).filter(|i| *i < podcasts.len()) {
+            Some(i) => Some(podcasts.remove(i)),
+            None => None,
+        }
+    }
+
+    /// Returns the title of the subscription if there is one with the `number`
+    pub fn set_announce(&self, number: usize, announce: bool) -> Option<String> {
+        let mut podcasts = self.podcasts.write().unwrap();
+        let podcast = number.checked_sub(1).and_then(|i| podcasts.get_mut(i))?;
+        podcast.announce = announce;
+
+        Some(podcast.title.clone())
+    }
+
+    /// Remembers the episode as the newest one of the feed at `url`
+    pub fn set_latest_episode(&self, url: &str, episode: &AudioMetadata) -> bool {
+        let mut podcasts = self.podcasts.write().unwrap();
+
+        match podcasts.iter_mut().find(|p| p.url == url) {
+            Some(podcast) => {
+                podcast.latest_episode = Some(episode.webpage_url.clone());
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn text(&self, prefix: &str) -> String {
+        let podcasts = self.podcasts.read().unwrap();
+        if podcasts.is_empty() {
+            return format!(
+                "This channel has no podcast subscriptions, add one with {}subscription add <url>",
+                prefix
+            );
+        }
+
+        let mut text = String::from("Podcasts of this channel:");
+        for (i, podcast) in podcasts.iter().enumerate() {
+            let announced = if podcast.announce {
+                " (new episodes are announced)"
+            } else {
+                ""
+            };
+            text += &format!("\n{}. {}{}", i + 1, ts::bold(&podcast.title), announced);
+        }
+
+        text
+    }
+}
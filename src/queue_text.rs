@@ -0,0 +1,201 @@
+//! The queue as text, paginated for `!queue` in chat and as a whole for the
+//! text export of the web API.
+
+use std::fmt;
+use std::time::Duration;
+
+use crate::duration::format_timestamp;
+use crate::i18n::Locale;
+use crate::teamspeak as ts;
+use crate::tr;
+use crate::youtube_dl::AudioMetadata;
+
+/// Characters of a page kept free for its header
+const HEADER_LENGTH: usize = 100;
+/// Longer titles are cut in chat so a page holds more than a few entries
+const MAX_CHAT_TITLE_LENGTH: usize = 80;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    /// BBCode and shortened titles
+    Chat,
+    /// Plain text with full titles
+    Text,
+}
+
+/// The current track and the queue, one line per entry
+#[derive(Debug, Clone)]
+pub struct QueueText {
+    lines: Vec<String>,
+    tracks: usize,
+    remaining: Duration,
+    /// Livestreams and tracks whose duration is not known yet are not part of `remaining`
+    unknown: bool,
+}
+
+impl QueueText {
+    pub fn new(
+        current: Option<(&AudioMetadata, Option<Duration>)>,
+        queue: &[AudioMetadata],
+        target: Target,
+    ) -> Self {
+        let mut lines = Vec::with_capacity(queue.len() + 1);
+        let mut remaining = Duration::from_secs(0);
+        let mut unknown = false;
+
+        if let Some((current, position)) = current {
+            let title = title(current, target);
+            let title = match target {
+                Target::Chat => ts::bold(&title).to_string(),
+                Target::Text => title,
+            };
+            let progress = match (position, current.duration) {
+                (Some(position), Some(duration)) => {
+                    remaining += duration.checked_sub(position).unwrap_or_default();
+                    format!(
+                        " ({} / {})",
+                        format_timestamp(position),
+                        format_timestamp(duration)
+                    )
+                }
+                (None, Some(duration)) => {
+                    remaining += duration;
+                    format!(" ({})", format_timestamp(duration))
+                }
+                (_, None) => {
+                    unknown = true;
+                    String::new()
+                }
+            };
+            lines.push(format!("▶ {}{}", title, progress));
+        }
+
+        for (i, entry) in queue.iter().enumerate() {
+            let duration = match entry.duration {
+                Some(duration) => {
+                    remaining += duration;
+                    format!(" ({})", format_timestamp(duration))
+                }
+                None => {
+                    unknown = true;
+                    String::new()
+                }
+            };
+            lines.push(format!("{}. {}{}", i + 1, title(entry, target), duration));
+        }
+
+        Self {
+            lines,
+            tracks: queue.len() + current.map_or(0, |_| 1),
+            remaining,
+            unknown,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lines.is_empty()
+    }
+
+    fn summary(&self, locale: &Locale) -> String {
+        let tracks = match self.tracks {
+            1 => tr!(locale, "queue-one-track", "1 track"),
+            n => tr!(locale, "queue-tracks", "{count} tracks", count = n),
+        };
+        let remaining = format_timestamp(self.remaining);
+
+        if self.unknown {
+            tr!(
+                locale,
+                "queue-summary-unknown",
+                "{tracks}, more than {remaining} left",
+                tracks = tracks,
+                remaining = remaining
+            )
+        } else {
+            tr!(
+                locale,
+                "queue-summary",
+                "{tracks}, {remaining} left",
+                tracks = tracks,
+                remaining = remaining
+            )
+        }
+    }
+
+    /// Groups the lines so every page fits into a message of `max` characters
+    fn pages(&self, max: usize) -> Vec<&[String]> {
+        let budget = max.saturating_sub(HEADER_LENGTH);
+        let mut pages = Vec::new();
+        let mut start = 0;
+        let mut length = 0;
+
+        for (i, line) in self.lines.iter().enumerate() {
+            let line_length = line.chars().count() + 1;
+            if i > start && length + line_length > budget {
+                pages.push(&self.lines[start..i]);
+                start = i;
+                length = 0;
+            }
+            length += line_length;
+        }
+        if start < self.lines.len() {
+            pages.push(&self.lines[start..]);
+        }
+
+        pages
+    }
+
+    /// The 1-based `page` with a header, `None` if there are fewer pages
+    pub fn page(&self, page: usize, max: usize, locale: &Locale) -> Option<String> {
+        let pages = self.pages(max);
+        let lines = pages.get(page.checked_sub(1)?)?;
+
+        let mut text = tr!(
+            locale,
+            "queue-page",
+            "Page {page}/{pages}: {summary}",
+            page = page,
+            pages = pages.len(),
+            summary = self.summary(locale)
+        );
+        if page < pages.len() {
+            text.push_str(&tr!(
+                locale,
+                "queue-more",
+                ", use {command} for more",
+                command = format!("!queue {}", page + 1)
+            ));
+        }
+        for line in lines.iter() {
+            text.push('\n');
+            text.push_str(line);
+        }
+
+        Some(text)
+    }
+}
+
+/// All entries without pages, the export is always English
+impl fmt::Display for QueueText {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{}", self.summary(&Locale::english()))?;
+        for line in &self.lines {
+            writeln!(f, "{}", line)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn title(entry: &AudioMetadata, target: Target) -> String {
+    // Line breaks would split an entry into several
+    let title = entry.title.replace(|c| c == '\n' || c == '\r', " ");
+    if target == Target::Text || title.chars().count() <= MAX_CHAT_TITLE_LENGTH {
+        return title;
+    }
+
+    let mut short: String = title.chars().take(MAX_CHAT_TITLE_LENGTH - 1).collect();
+    short.push('…');
+
+    short
+}
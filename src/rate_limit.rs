@@ -0,0 +1,352 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use serde::{Deserialize, Serialize};
+use tracing::{error, info};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RateLimitConfig {
+    /// How many commands a user can send in a row, 0 disables the limit
+    #[serde(default = "default_burst")]
+    pub burst: u32,
+    /// How long it takes until a user can send one more command
+    #[serde(default = "default_decay", with = "crate::duration::serde_human")]
+    pub decay: Duration,
+    /// File in which the counters are kept across restarts
+    #[serde(default = "default_path")]
+    pub path: PathBuf,
+    /// How long a user has to wait between two pokes that spawn a bot, 0 disables it
+    #[serde(
+        default = "default_poke_cooldown",
+        with = "crate::duration::serde_human"
+    )]
+    pub poke_cooldown: Duration,
+    /// Minimum time between two uses of a command by the same user, by command name
+    #[serde(default, with = "crate::duration::serde_human_map")]
+    pub cooldowns: HashMap<String, Duration>,
+    /// How often a user can be told to slow down before being muted, 0 disables muting
+    #[serde(default = "default_mute_after")]
+    pub mute_after: u32,
+    /// How long muted users are ignored
+    #[serde(
+        default = "default_mute_duration",
+        with = "crate::duration::serde_human"
+    )]
+    pub mute_duration: Duration,
+}
+
+fn default_burst() -> u32 {
+    10
+}
+
+fn default_decay() -> Duration {
+    Duration::from_secs(6)
+}
+
+fn default_path() -> PathBuf {
+    PathBuf::from("rate_limits.json")
+}
+
+fn default_poke_cooldown() -> Duration {
+    Duration::from_secs(30)
+}
+
+fn default_mute_after() -> u32 {
+    3
+}
+
+fn default_mute_duration() -> Duration {
+    Duration::from_secs(10 * 60)
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            burst: default_burst(),
+            decay: default_decay(),
+            path: default_path(),
+            poke_cooldown: default_poke_cooldown(),
+            cooldowns: HashMap::new(),
+            mute_after: default_mute_after(),
+            mute_duration: default_mute_duration(),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum RateLimit {
+    Allowed,
+    /// The user went over the limit and should be told so
+    Throttled {
+        retry_in: Duration,
+    },
+    /// The user has to wait before doing `action` again and should be told so
+    Cooldown {
+        action: &'static str,
+        retry_in: Duration,
+    },
+    /// The user was told to slow down too often and should be told once that
+    /// they are ignored for a while
+    Muted {
+        duration: Duration,
+    },
+    /// The user was told about the limit recently or is muted
+    Ignored,
+}
+
+impl RateLimit {
+    /// A polite reply to `name` if they should be told about the limit
+    pub fn reply(&self, name: &str) -> Option<String> {
+        // Sub-second waits are shown as a second instead of as milliseconds
+        let format =
+            |d: &Duration| humantime::format_duration(Duration::from_secs(d.as_secs().max(1)));
+
+        match self {
+            RateLimit::Allowed | RateLimit::Ignored => None,
+            RateLimit::Throttled { retry_in } => Some(format!(
+                "{}, you are sending too many commands, please slow down and try again in {}",
+                name,
+                format(retry_in)
+            )),
+            RateLimit::Cooldown { action, retry_in } => Some(format!(
+                "{}, please wait {} before using {} again",
+                name,
+                format(retry_in),
+                action
+            )),
+            RateLimit::Muted { duration } => Some(format!(
+                "{}, you kept sending commands, so I will ignore you for {}",
+                name,
+                format(duration)
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+struct Counter {
+    value: f64,
+    updated: SystemTime,
+}
+
+/// Users are reminded of the limit at most this often
+const REMINDER_INTERVAL: Duration = Duration::from_secs(30);
+/// How long after a change the counters are saved, along with the changes since
+const SAVE_DELAY: Duration = Duration::from_secs(10);
+
+/// Limits how many commands each user can send.
+///
+/// Every command adds one to the counter of the user, which goes down by one
+/// every `decay`. Commands are rejected while the counter is above `burst`.
+/// Rejected commands still count, so spamming only extends the cooldown.
+///
+/// Pokes and configured commands additionally have a cooldown per user.
+/// Every reminder about a limit is a strike, and users that collect
+/// `mute_after` strikes within `mute_duration` are muted for `mute_duration`.
+/// Only the counters are stored, cooldowns and mutes are forgotten on restart.
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    counters: Arc<Mutex<HashMap<String, Counter>>>,
+    /// Set while a save of the counters is waiting
+    save_scheduled: Arc<AtomicBool>,
+    /// When each user can use an action again
+    cooldowns: Mutex<HashMap<(String, &'static str), SystemTime>>,
+    /// When each user was reminded of a limit within the last `mute_duration`
+    reminders: Mutex<HashMap<String, Vec<SystemTime>>>,
+    /// Until when each muted user is ignored
+    mutes: Mutex<HashMap<String, SystemTime>>,
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig) -> Self {
+        let counters = match fs::read(&config.path) {
+            Ok(data) => match serde_json::from_slice(&data) {
+                Ok(counters) => counters,
+                Err(e) => {
+                    error!("Failed to parse rate limits in {:?}: {}", config.path, e);
+                    HashMap::new()
+                }
+            },
+            Err(e) if e.kind() == io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => {
+                error!("Failed to read rate limits from {:?}: {}", config.path, e);
+                HashMap::new()
+            }
+        };
+
+        Self {
+            config,
+            counters: Arc::new(Mutex::new(counters)),
+            save_scheduled: Arc::new(AtomicBool::new(false)),
+            cooldowns: Mutex::new(HashMap::new()),
+            reminders: Mutex::new(HashMap::new()),
+            mutes: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Counts a command sent by `user`, which should be the unique id if there is one
+    pub fn check(&self, user: &str) -> RateLimit {
+        if self.is_muted(user) {
+            return RateLimit::Ignored;
+        }
+        if self.config.burst == 0 {
+            return RateLimit::Allowed;
+        }
+
+        let now = SystemTime::now();
+        let burst = f64::from(self.config.burst);
+        let decay = self.config.decay.as_secs_f64().max(f64::EPSILON);
+
+        let mut counters = self.counters.lock().unwrap();
+        let counter = counters.entry(user.to_owned()).or_insert(Counter {
+            value: 0.0,
+            updated: now,
+        });
+
+        let elapsed = now
+            .duration_since(counter.updated)
+            .unwrap_or_default()
+            .as_secs_f64();
+        let previous = (counter.value - elapsed / decay).max(0.0);
+        counter.value = previous + 1.0;
+        counter.updated = now;
+        let value = counter.value;
+
+        // Counters that decayed completely are not worth keeping
+        counters.retain(|_, c| {
+            let elapsed = now
+                .duration_since(c.updated)
+                .unwrap_or_default()
+                .as_secs_f64();
+            c.value > elapsed / decay
+        });
+        drop(counters);
+        self.schedule_save();
+
+        if value <= burst {
+            return RateLimit::Allowed;
+        }
+        if previous <= burst {
+            info!("Throttling commands of {}", user);
+        }
+        self.remind(
+            user,
+            RateLimit::Throttled {
+                retry_in: Duration::from_secs_f64((value - burst) * decay),
+            },
+        )
+    }
+
+    /// Checks the cooldown of a command, `check` should be called for it first
+    pub fn check_command(&self, user: &str, command: &'static str) -> RateLimit {
+        match self.config.cooldowns.get(command) {
+            Some(cooldown) => self.cooldown(user, command, *cooldown),
+            None => RateLimit::Allowed,
+        }
+    }
+
+    /// Checks whether a poke of `user` should spawn a bot
+    pub fn check_poke(&self, user: &str) -> RateLimit {
+        if self.is_muted(user) {
+            return RateLimit::Ignored;
+        }
+
+        self.cooldown(user, "poke", self.config.poke_cooldown)
+    }
+
+    fn cooldown(&self, user: &str, action: &'static str, cooldown: Duration) -> RateLimit {
+        if cooldown == Duration::from_secs(0) {
+            return RateLimit::Allowed;
+        }
+
+        let now = SystemTime::now();
+        let mut cooldowns = self.cooldowns.lock().unwrap();
+        cooldowns.retain(|_, ready| *ready > now);
+
+        let key = (user.to_owned(), action);
+        if let Some(ready) = cooldowns.get(&key) {
+            let retry_in = ready.duration_since(now).unwrap_or_default();
+            drop(cooldowns);
+
+            return self.remind(user, RateLimit::Cooldown { action, retry_in });
+        }
+        cooldowns.insert(key, now + cooldown);
+
+        RateLimit::Allowed
+    }
+
+    /// Decides whether the user is told about `limit`, ignored or muted
+    fn remind(&self, user: &str, limit: RateLimit) -> RateLimit {
+        let now = SystemTime::now();
+        let since = |t: &SystemTime| now.duration_since(*t).unwrap_or_default();
+
+        let mut reminders = self.reminders.lock().unwrap();
+        reminders.retain(|_, times| {
+            times.retain(|t| since(t) < self.config.mute_duration);
+            !times.is_empty()
+        });
+
+        let times = reminders.entry(user.to_owned()).or_default();
+        if times.last().map_or(false, |t| since(t) < REMINDER_INTERVAL) {
+            return RateLimit::Ignored;
+        }
+        times.push(now);
+
+        let mute_after = self.config.mute_after as usize;
+        if mute_after != 0 && times.len() > mute_after {
+            reminders.remove(user);
+            drop(reminders);
+
+            info!("Muting {} for {:?}", user, self.config.mute_duration);
+            let until = now + self.config.mute_duration;
+            self.mutes.lock().unwrap().insert(user.to_owned(), until);
+
+            return RateLimit::Muted {
+                duration: self.config.mute_duration,
+            };
+        }
+
+        limit
+    }
+
+    fn is_muted(&self, user: &str) -> bool {
+        let now = SystemTime::now();
+        let mut mutes = self.mutes.lock().unwrap();
+        mutes.retain(|_, until| *until > now);
+
+        mutes.contains_key(user)
+    }
+
+    /// Saves the counters a while after they changed, so a burst of commands
+    /// is written once and not by the threads handling them
+    fn schedule_save(&self) {
+        if self.save_scheduled.swap(true, Ordering::AcqRel) {
+            return;
+        }
+
+        let counters = self.counters.clone();
+        let save_scheduled = self.save_scheduled.clone();
+        let path = self.config.path.clone();
+        tokio::spawn(async move {
+            tokio::time::delay_for(SAVE_DELAY).await;
+            save_scheduled.store(false, Ordering::Release);
+
+            let data = serde_json::to_vec(&*counters.lock().unwrap());
+            let target = path.clone();
+            let result = match data {
+                Ok(data) => tokio::task::spawn_blocking(move || fs::write(target, data))
+                    .await
+                    .unwrap_or_else(|e| Err(io::Error::new(io::ErrorKind::Other, e))),
+                Err(e) => Err(io::Error::from(e)),
+            };
+            if let Err(e) = result {
+                error!("Failed to save rate limits to {:?}: {}", path, e);
+            }
+        });
+    }
+}
@@ -0,0 +1,189 @@
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tracing::{error, info};
+
+use crate::youtube_dl::AudioMetadata;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReportConfig {
+    /// File in which the reports are kept
+    #[serde(default = "default_path")]
+    pub path: PathBuf,
+    /// Receives every new report as a JSON POST, Discord and Slack webhooks work as is
+    pub webhook: Option<String>,
+}
+
+fn default_path() -> PathBuf {
+    PathBuf::from("reports.json")
+}
+
+impl Default for ReportConfig {
+    fn default() -> Self {
+        Self {
+            path: default_path(),
+            webhook: None,
+        }
+    }
+}
+
+/// A problem with a track that a user reported while it was playing
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Report {
+    pub id: u64,
+    pub title: String,
+    pub url: String,
+    /// Whoever added the track, empty if it was not added by a user
+    pub added_by: String,
+    pub reason: String,
+    pub reporter: String,
+    pub bot: String,
+    pub channel: String,
+    /// How far the track had played when it was reported
+    #[serde(default, with = "crate::duration::serde_human_option")]
+    pub position: Option<Duration>,
+    /// Unix time of the report
+    pub reported_at: u64,
+    #[serde(default)]
+    pub resolved: bool,
+}
+
+/// Where a report was made from
+pub struct ReportContext<'a> {
+    pub reporter: &'a str,
+    pub bot: &'a str,
+    pub channel: &'a str,
+    pub position: Option<Duration>,
+}
+
+/// Stores the reports of all bots in a single JSON file and forwards new
+/// ones to the configured webhook.
+pub struct ReportStore {
+    config: ReportConfig,
+    reports: Mutex<Vec<Report>>,
+    http: reqwest::Client,
+}
+
+impl ReportStore {
+    pub fn new(config: ReportConfig) -> Self {
+        let reports = match fs::read(&config.path) {
+            Ok(data) => match serde_json::from_slice(&data) {
+                Ok(reports) => reports,
+                Err(e) => {
+                    error!("Failed to parse reports in {:?}: {}", config.path, e);
+                    Vec::new()
+                }
+            },
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => {
+                error!("Failed to read reports from {:?}: {}", config.path, e);
+                Vec::new()
+            }
+        };
+
+        Self {
+            config,
+            reports: Mutex::new(reports),
+            http: reqwest::Client::new(),
+        }
+    }
+
+    /// Stores a report about `metadata` and returns it
+    pub fn add(&self, metadata: &AudioMetadata, reason: String, context: ReportContext) -> Report {
+        let reported_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+
+        let mut reports = self.reports.lock().unwrap();
+        let report = Report {
+            id: reports.last().map_or(1, |r| r.id + 1),
+            title: metadata.title.clone(),
+            url: metadata.webpage_url.clone(),
+            added_by: metadata.added_by.clone(),
+            reason,
+            reporter: context.reporter.to_owned(),
+            bot: context.bot.to_owned(),
+            channel: context.channel.to_owned(),
+            position: context.position,
+            reported_at,
+            resolved: false,
+        };
+        info!(
+            "{} reported {} as #{}: {}",
+            report.reporter, report.url, report.id, report.reason
+        );
+        reports.push(report.clone());
+        self.save(&reports);
+        drop(reports);
+
+        if let Some(webhook) = &self.config.webhook {
+            tokio::spawn(notify(self.http.clone(), webhook.clone(), report.clone()));
+        }
+
+        report
+    }
+
+    /// All reports, the newest first
+    pub fn list(&self, include_resolved: bool) -> Vec<Report> {
+        self.reports
+            .lock()
+            .unwrap()
+            .iter()
+            .rev()
+            .filter(|r| include_resolved || !r.resolved)
+            .cloned()
+            .collect()
+    }
+
+    /// Marks a report as dealt with, returns `false` if there is no such report
+    pub fn resolve(&self, id: u64) -> bool {
+        let mut reports = self.reports.lock().unwrap();
+        let report = match reports.iter_mut().find(|r| r.id == id) {
+            Some(report) => report,
+            None => return false,
+        };
+        report.resolved = true;
+        self.save(&reports);
+
+        true
+    }
+
+    fn save(&self, reports: &[Report]) {
+        let result = serde_json::to_vec_pretty(reports)
+            .map_err(io::Error::from)
+            .and_then(|data| fs::write(&self.config.path, data));
+        if let Err(e) = result {
+            error!("Failed to save reports to {:?}: {}", self.config.path, e);
+        }
+    }
+}
+
+async fn notify(http: reqwest::Client, webhook: String, report: Report) {
+    let summary = format!(
+        "Report #{} by {} in {}: \"{}\" <{}>: {}",
+        report.id, report.reporter, report.channel, report.title, report.url, report.reason
+    );
+    // Discord reads `content` and Slack reads `text`, anything else can use `report`
+    let body = json!({
+        "content": summary,
+        "text": summary,
+        "report": report,
+    });
+
+    let result = http
+        .post(&webhook)
+        .header("Content-Type", "application/json")
+        .body(body.to_string())
+        .send()
+        .await
+        .and_then(|r| r.error_for_status());
+    if let Err(e) = result {
+        error!("Failed to send report #{} to webhook: {}", report.id, e);
+    }
+}
@@ -0,0 +1,26 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use serde::{Deserialize, Serialize};
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Identifies a single enqueue request through resolving, downloading and playback.
+///
+/// Log lines belonging to a request are prefixed with its id, e.g. `[req-42]`,
+/// so the lifecycle of one track can be followed when many bots are running.
+/// The default id `0` is used for tracks that were not enqueued by a request.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct RequestId(u64);
+
+impl RequestId {
+    pub fn next() -> Self {
+        RequestId(NEXT_ID.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+impl std::fmt::Display for RequestId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "[req-{}]", self.0)
+    }
+}
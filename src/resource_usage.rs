@@ -0,0 +1,156 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Duration;
+
+use serde::Serialize;
+
+/// Clock ticks per second of the times in `/proc`, which is 100 on every common Linux
+const TICKS_PER_SECOND: u64 = 100;
+
+thread_local! {
+    /// The player whose audio the current streaming thread handled last
+    static THREAD_OWNER: RefCell<Option<Weak<ResourceCounters>>> = RefCell::new(None);
+}
+
+/// What a bot costs, gathered by its audio player
+#[derive(Debug, Clone, Serialize)]
+pub struct ResourceUsage {
+    /// CPU time of the threads that read, decode and encode the audio, only measured on Linux
+    pub cpu_time: Option<Duration>,
+    /// Audio waiting in the stream buffer and in front of the encoder
+    pub buffered_bytes: u64,
+    /// Undecoded audio read from the sources
+    pub received_bytes: u64,
+    /// Encoded audio handed to the frontend
+    pub sent_bytes: u64,
+}
+
+/// CPU time of a thread in ticks, when it started working for a player and when it was last read
+#[derive(Clone, Copy)]
+struct ThreadTimes {
+    claimed: u64,
+    last: u64,
+}
+
+#[derive(Default)]
+struct Threads {
+    working: HashMap<u32, ThreadTimes>,
+    /// Ticks of threads that ended or work for another player now
+    finished: u64,
+}
+
+/// Shared with the streaming threads of a pipeline, which count what passes through them.
+///
+/// GStreamer reuses its threads for other pipelines, so a thread is only
+/// accounted to the player whose audio it handled last.
+#[derive(Default)]
+pub struct ResourceCounters {
+    received: AtomicU64,
+    sent: AtomicU64,
+    threads: Mutex<Threads>,
+}
+
+impl ResourceCounters {
+    pub fn add_received(&self, bytes: usize) {
+        self.received.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub fn add_sent(&self, bytes: usize) {
+        self.sent.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    /// Accounts the CPU time of the calling thread to this player from now on
+    pub fn claim_thread(self: &Arc<Self>) {
+        if !cfg!(target_os = "linux") {
+            return;
+        }
+
+        THREAD_OWNER.with(|owner| {
+            let mut owner = owner.borrow_mut();
+            let previous = owner.as_ref().and_then(Weak::upgrade);
+            if previous.as_ref().map_or(false, |p| Arc::ptr_eq(p, self)) {
+                return;
+            }
+
+            let id = match thread_id() {
+                Some(id) => id,
+                None => return,
+            };
+            let ticks = thread_ticks(id).unwrap_or(0);
+            if let Some(previous) = previous {
+                previous.release_thread(id, ticks);
+            }
+            self.threads.lock().unwrap().working.insert(
+                id,
+                ThreadTimes {
+                    claimed: ticks,
+                    last: ticks,
+                },
+            );
+            *owner = Some(Arc::downgrade(self));
+        });
+    }
+
+    fn release_thread(&self, id: u32, ticks: u64) {
+        let mut threads = self.threads.lock().unwrap();
+        if let Some(times) = threads.working.remove(&id) {
+            threads.finished += ticks.saturating_sub(times.claimed);
+        }
+    }
+
+    pub fn usage(&self, buffered_bytes: u64) -> ResourceUsage {
+        ResourceUsage {
+            cpu_time: self.cpu_time(),
+            buffered_bytes,
+            received_bytes: self.received.load(Ordering::Relaxed),
+            sent_bytes: self.sent.load(Ordering::Relaxed),
+        }
+    }
+
+    fn cpu_time(&self) -> Option<Duration> {
+        if !cfg!(target_os = "linux") {
+            return None;
+        }
+
+        let mut threads = self.threads.lock().unwrap();
+        let Threads { working, finished } = &mut *threads;
+        working.retain(|id, times| match thread_ticks(*id) {
+            Some(ticks) => {
+                times.last = ticks;
+                true
+            }
+            // The thread ended, what it used since the last read is lost
+            None => {
+                *finished += times.last.saturating_sub(times.claimed);
+                false
+            }
+        });
+        let ticks = *finished
+            + working
+                .values()
+                .map(|t| t.last.saturating_sub(t.claimed))
+                .sum::<u64>();
+
+        Some(Duration::from_millis(ticks * 1000 / TICKS_PER_SECOND))
+    }
+}
+
+/// The id of the calling thread as the kernel knows it, `/proc/thread-self` is `<pid>/task/<tid>`
+fn thread_id() -> Option<u32> {
+    let path = std::fs::read_link("/proc/thread-self").ok()?;
+
+    path.file_name()?.to_str()?.parse().ok()
+}
+
+/// User and system CPU time of a thread of this process
+fn thread_ticks(id: u32) -> Option<u64> {
+    let stat = std::fs::read_to_string(format!("/proc/self/task/{}/stat", id)).ok()?;
+    // The name in parentheses may contain spaces, the fields after it start with the state
+    let fields: Vec<_> = stat[stat.rfind(')')? + 1..].split_whitespace().collect();
+    let user: u64 = fields.get(11)?.parse().ok()?;
+    let system: u64 = fields.get(12)?.parse().ok()?;
+
+    Some(user + system)
+}
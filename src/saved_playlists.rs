@@ -0,0 +1,181 @@
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::request_id::RequestId;
+use crate::youtube_dl::AudioMetadata;
+
+/// Stores named playlists as JSON files in `<dir>/<server>/<name>.json`
+pub struct PlaylistStore {
+    dir: PathBuf,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SavedEntry {
+    webpage_url: String,
+    title: String,
+    duration: Option<f64>,
+}
+
+impl PlaylistStore {
+    pub fn new(base_dir: PathBuf, server: &str) -> Self {
+        Self {
+            dir: base_dir.join(sanitize(server)),
+        }
+    }
+
+    fn path_of(&self, name: &str) -> Result<PathBuf, PlaylistStoreError> {
+        if name.is_empty()
+            || !name
+                .chars()
+                .all(|c| c.is_alphanumeric() || c == '-' || c == '_')
+        {
+            return Err(PlaylistStoreError::InvalidName(name.to_owned()));
+        }
+
+        Ok(self.dir.join(format!("{}.json", name)))
+    }
+
+    pub fn save(&self, name: &str, entries: &[AudioMetadata]) -> Result<(), PlaylistStoreError> {
+        let path = self.path_of(name)?;
+        let entries = entries
+            .iter()
+            .map(|e| SavedEntry {
+                webpage_url: e.webpage_url.clone(),
+                title: e.title.clone(),
+                duration: e.duration.map(|d| d.as_secs_f64()),
+            })
+            .collect::<Vec<_>>();
+
+        fs::create_dir_all(&self.dir)?;
+        fs::write(&path, serde_json::to_vec_pretty(&entries)?)?;
+        info!("Saved {} entries to {:?}", entries.len(), path);
+
+        Ok(())
+    }
+
+    /// Loads a playlist, the entries still have to be resolved before playback
+    pub fn load(
+        &self,
+        name: &str,
+        request_id: RequestId,
+    ) -> Result<Vec<AudioMetadata>, PlaylistStoreError> {
+        let path = self.path_of(name)?;
+        let data = match fs::read(&path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                return Err(PlaylistStoreError::NotFound(name.to_owned()))
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let entries: Vec<SavedEntry> = serde_json::from_slice(&data)?;
+        info!(
+            "{} Loaded {} entries from {:?}",
+            request_id,
+            entries.len(),
+            path
+        );
+
+        Ok(entries
+            .into_iter()
+            .map(|e| AudioMetadata {
+                url: String::new(),
+                webpage_url: e.webpage_url,
+                title: e.title,
+                thumbnail: None,
+                duration: e.duration.map(Duration::from_secs_f64),
+                artist: None,
+                track: None,
+                album: None,
+                recording: None,
+                chapters: Vec::new(),
+                added_by: String::new(),
+                request_id,
+            })
+            .collect())
+    }
+
+    pub fn delete(&self, name: &str) -> Result<(), PlaylistStoreError> {
+        let path = self.path_of(name)?;
+        match fs::remove_file(&path) {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                Err(PlaylistStoreError::NotFound(name.to_owned()))
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn list(&self) -> Result<Vec<String>, PlaylistStoreError> {
+        let dir = match fs::read_dir(&self.dir) {
+            Ok(dir) => dir,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut names = Vec::new();
+        for entry in dir {
+            let path = entry?.path();
+            if path.extension().map_or(false, |e| e == "json") {
+                if let Some(name) = path.file_stem().and_then(|s| s.to_str()) {
+                    names.push(name.to_owned());
+                }
+            }
+        }
+        names.sort();
+
+        Ok(names)
+    }
+}
+
+fn sanitize(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_alphanumeric() || c == '.' || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+#[derive(Debug)]
+pub enum PlaylistStoreError {
+    InvalidName(String),
+    NotFound(String),
+    Io(io::Error),
+    Json(serde_json::Error),
+}
+
+impl From<io::Error> for PlaylistStoreError {
+    fn from(err: io::Error) -> Self {
+        PlaylistStoreError::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for PlaylistStoreError {
+    fn from(err: serde_json::Error) -> Self {
+        PlaylistStoreError::Json(err)
+    }
+}
+
+impl std::fmt::Display for PlaylistStoreError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use PlaylistStoreError::*;
+        match self {
+            InvalidName(name) => write!(
+                f,
+                "\"{}\" is not a valid name, only letters, numbers, '-' and '_' are allowed",
+                name
+            ),
+            NotFound(name) => write!(f, "There is no playlist named \"{}\"", name),
+            Io(e) => write!(f, "Failed to access playlist storage: {}", e),
+            Json(e) => write!(f, "Playlist file is broken: {}", e),
+        }
+    }
+}
@@ -0,0 +1,474 @@
+use std::convert::TryFrom;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use tracing::{error, info, warn};
+
+use crate::bot::{BotCreationError, BotManager, MasterActionError};
+use crate::command::ScheduleAction;
+use crate::teamspeak as ts;
+
+/// How often the scheduler looks for jobs that are due
+const CHECK_INTERVAL: Duration = Duration::from_secs(15);
+/// Missed minutes are only caught up on for this long, e.g. after the clock jumped
+const MAX_CATCH_UP_MINUTES: i64 = 5;
+const MINUTES_PER_DAY: i64 = 24 * 60;
+const DAY_NAMES: [&str; 7] = ["mon", "tue", "wed", "thu", "fri", "sat", "sun"];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScheduleConfig {
+    /// File in which the jobs added with `!schedule` are kept
+    #[serde(default = "default_path")]
+    pub path: PathBuf,
+    /// Offset of the times from UTC, e.g. "+02:00"
+    #[serde(default)]
+    pub utc_offset: UtcOffset,
+    #[serde(default)]
+    pub jobs: Vec<Job>,
+}
+
+fn default_path() -> PathBuf {
+    PathBuf::from("schedule.json")
+}
+
+impl Default for ScheduleConfig {
+    fn default() -> Self {
+        Self {
+            path: default_path(),
+            utc_offset: UtcOffset::default(),
+            jobs: Vec::new(),
+        }
+    }
+}
+
+/// A recurring job, e.g. playing a radio stream in the lobby every Friday
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Job {
+    pub when: When,
+    #[serde(flatten)]
+    pub action: JobAction,
+    /// Whoever added the job by command, not set for jobs from the config
+    #[serde(default)]
+    pub added_by: Option<String>,
+}
+
+impl std::fmt::Display for Job {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: ", self.when)?;
+        match &self.action {
+            JobAction::Play { channel, url } => write!(f, "play {} in {}", url, channel),
+            JobAction::Despawn => write!(f, "despawn all bots"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "kebab-case")]
+pub enum JobAction {
+    /// Adds `url` to the bot in `channel`, a bot is spawned there if needed
+    Play { channel: String, url: String },
+    /// Disconnects all music bots
+    Despawn,
+}
+
+/// Days of the week and the time of day a job runs at, e.g. "fri 20:00"
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct When {
+    pub days: Days,
+    pub time: TimeOfDay,
+}
+
+impl When {
+    /// If the job runs in the minute given in minutes since the epoch in local time
+    fn matches(self, minute: i64) -> bool {
+        let day = minute.div_euclid(MINUTES_PER_DAY);
+        // The epoch was a Thursday
+        let weekday = (day + 3).rem_euclid(7) as u8;
+
+        self.days.0 & (1 << weekday) != 0
+            && minute.rem_euclid(MINUTES_PER_DAY) == i64::from(self.time.0)
+    }
+}
+
+impl std::str::FromStr for When {
+    type Err = String;
+
+    fn from_str(when: &str) -> Result<Self, Self::Err> {
+        let mut split = when.split_whitespace();
+        match (split.next(), split.next(), split.next()) {
+            (Some(days), Some(time), None) => Ok(When {
+                days: days.parse()?,
+                time: time.parse()?,
+            }),
+            _ => Err(format!(
+                "Unknown time \"{}\", expected something like \"fri 20:00\"",
+                when
+            )),
+        }
+    }
+}
+
+impl TryFrom<String> for When {
+    type Error = String;
+
+    fn try_from(when: String) -> Result<Self, Self::Error> {
+        when.parse()
+    }
+}
+
+impl From<When> for String {
+    fn from(when: When) -> String {
+        when.to_string()
+    }
+}
+
+impl std::fmt::Display for When {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.days, self.time)
+    }
+}
+
+/// Bit `n` is set if the job runs on the `n`th day of the week, starting with Monday
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Days(u8);
+
+impl std::str::FromStr for Days {
+    type Err = String;
+
+    fn from_str(days: &str) -> Result<Self, Self::Err> {
+        match days {
+            "daily" => return Ok(Days(0b111_1111)),
+            "weekdays" => return Ok(Days(0b001_1111)),
+            "weekends" => return Ok(Days(0b110_0000)),
+            _ => (),
+        }
+
+        let mut mask = 0;
+        for day in days.split(',') {
+            let day = day.trim().to_lowercase();
+            match DAY_NAMES.iter().position(|name| day.starts_with(name)) {
+                Some(index) => mask |= 1 << index,
+                None => {
+                    return Err(format!(
+                        "Unknown day \"{}\", expected \"daily\", \"weekdays\", \"weekends\" \
+                         or days like \"mon,fri\"",
+                        day
+                    ))
+                }
+            }
+        }
+
+        Ok(Days(mask))
+    }
+}
+
+impl std::fmt::Display for Days {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.0 {
+            0b111_1111 => write!(f, "daily"),
+            0b001_1111 => write!(f, "weekdays"),
+            0b110_0000 => write!(f, "weekends"),
+            mask => {
+                let days = DAY_NAMES
+                    .iter()
+                    .enumerate()
+                    .filter(|(i, _)| mask & (1 << *i) != 0)
+                    .map(|(_, name)| *name)
+                    .collect::<Vec<_>>();
+                write!(f, "{}", days.join(","))
+            }
+        }
+    }
+}
+
+/// Minutes after midnight
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct TimeOfDay(u16);
+
+impl std::str::FromStr for TimeOfDay {
+    type Err = String;
+
+    fn from_str(time: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            format!(
+                "Unknown time \"{}\", expected something like \"20:00\"",
+                time
+            )
+        };
+
+        let mut split = time.splitn(2, ':');
+        let hours = split.next().and_then(|h| h.parse::<u16>().ok());
+        let minutes = split.next().and_then(|m| m.parse::<u16>().ok());
+        match (hours, minutes) {
+            (Some(hours), Some(minutes)) if hours < 24 && minutes < 60 => {
+                Ok(TimeOfDay(hours * 60 + minutes))
+            }
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl std::fmt::Display for TimeOfDay {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:02}:{:02}", self.0 / 60, self.0 % 60)
+    }
+}
+
+/// Offset of local time from UTC in minutes
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct UtcOffset(i64);
+
+impl TryFrom<String> for UtcOffset {
+    type Error = String;
+
+    fn try_from(offset: String) -> Result<Self, Self::Error> {
+        let invalid = || {
+            format!(
+                "Unknown UTC offset \"{}\", expected something like \"+02:00\"",
+                offset
+            )
+        };
+
+        let (sign, rest) = match offset.chars().next() {
+            Some('+') => (1, &offset[1..]),
+            Some('-') => (-1, &offset[1..]),
+            _ => return Err(invalid()),
+        };
+        let time = rest.parse::<TimeOfDay>().map_err(|_| invalid())?;
+
+        Ok(UtcOffset(sign * i64::from(time.0)))
+    }
+}
+
+impl From<UtcOffset> for String {
+    fn from(offset: UtcOffset) -> String {
+        let sign = if offset.0 < 0 { '-' } else { '+' };
+        let minutes = offset.0.abs();
+
+        format!("{}{:02}:{:02}", sign, minutes / 60, minutes % 60)
+    }
+}
+
+/// Keeps the jobs from the config and the ones added by command, the latter
+/// are stored in a JSON file so they survive restarts.
+pub struct ScheduleStore {
+    path: PathBuf,
+    utc_offset: UtcOffset,
+    config_jobs: Vec<Job>,
+    jobs: Mutex<Vec<Job>>,
+}
+
+impl ScheduleStore {
+    pub fn new(config: ScheduleConfig) -> Self {
+        let jobs = match fs::read(&config.path) {
+            Ok(data) => match serde_json::from_slice(&data) {
+                Ok(jobs) => jobs,
+                Err(e) => {
+                    error!("Failed to parse schedule in {:?}: {}", config.path, e);
+                    Vec::new()
+                }
+            },
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => {
+                error!("Failed to read schedule from {:?}: {}", config.path, e);
+                Vec::new()
+            }
+        };
+
+        Self {
+            path: config.path,
+            utc_offset: config.utc_offset,
+            config_jobs: config.jobs,
+            jobs: Mutex::new(jobs),
+        }
+    }
+
+    /// All jobs, the ones from the config first
+    pub fn jobs(&self) -> Vec<Job> {
+        let jobs = self.jobs.lock().unwrap();
+
+        self.config_jobs
+            .iter()
+            .chain(jobs.iter())
+            .cloned()
+            .collect()
+    }
+
+    pub fn add(&self, job: Job) {
+        info!("Scheduled {}", job);
+        let mut jobs = self.jobs.lock().unwrap();
+        jobs.push(job);
+        self.save(&jobs);
+    }
+
+    /// Removes the job with the 1-based `number` from `jobs`
+    pub fn remove(&self, number: usize) -> Result<Job, String> {
+        let index = match number.checked_sub(1) {
+            Some(index) => index,
+            None => return Err(String::from("Jobs are numbered starting at 1")),
+        };
+        if index < self.config_jobs.len() {
+            return Err(format!(
+                "Job {} is set in the config file and can only be removed there",
+                number
+            ));
+        }
+
+        let mut jobs = self.jobs.lock().unwrap();
+        let index = index - self.config_jobs.len();
+        if index >= jobs.len() {
+            return Err(format!("There is no job {}", number));
+        }
+        let job = jobs.remove(index);
+        self.save(&jobs);
+        info!("Unscheduled {}", job);
+
+        Ok(job)
+    }
+
+    /// Handles `!schedule`, only admins should get here
+    pub fn on_command(&self, action: ScheduleAction, user: &str) -> String {
+        match action {
+            ScheduleAction::List => {
+                let jobs = self.jobs();
+                if jobs.is_empty() {
+                    return String::from("Nothing is scheduled");
+                }
+
+                let mut text = format!("\nTimes are in UTC{}", String::from(self.utc_offset));
+                for (i, job) in jobs.iter().enumerate() {
+                    text.push_str(&format!("\n{}. {}", i + 1, job));
+                    if i < self.config_jobs.len() {
+                        text.push_str(" (config)");
+                    } else if let Some(added_by) = &job.added_by {
+                        text.push_str(&format!(" (by {})", added_by));
+                    }
+                }
+
+                text
+            }
+            ScheduleAction::Play {
+                days,
+                time,
+                url,
+                channel,
+            } => {
+                // strip bbcode tags from url
+                let url = url.replace("[URL]", "").replace("[/URL]", "");
+                self.add_by_command(
+                    When { days, time },
+                    JobAction::Play {
+                        channel: channel.join(" "),
+                        url,
+                    },
+                    user,
+                )
+            }
+            ScheduleAction::Despawn { days, time } => {
+                self.add_by_command(When { days, time }, JobAction::Despawn, user)
+            }
+            ScheduleAction::Remove { number } => match self.remove(number) {
+                Ok(job) => format!("Removed {}", job),
+                Err(e) => e,
+            },
+        }
+    }
+
+    fn add_by_command(&self, when: When, action: JobAction, user: &str) -> String {
+        let job = Job {
+            when,
+            action,
+            added_by: Some(user.to_owned()),
+        };
+        let reply = format!("Scheduled {}", ts::bold(&job));
+        self.add(job);
+
+        reply
+    }
+
+    /// Jobs that run in the given minute since the epoch in local time
+    fn due(&self, minute: i64) -> Vec<Job> {
+        self.jobs()
+            .into_iter()
+            .filter(|job| job.when.matches(minute))
+            .collect()
+    }
+
+    /// The current minute since the epoch in local time
+    fn current_minute(&self) -> i64 {
+        let secs = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+
+        secs as i64 / 60 + self.utc_offset.0
+    }
+
+    fn save(&self, jobs: &[Job]) {
+        let result = serde_json::to_vec_pretty(jobs)
+            .map_err(io::Error::from)
+            .and_then(|data| fs::write(&self.path, data));
+        if let Err(e) = result {
+            error!("Failed to save schedule to {:?}: {}", self.path, e);
+        }
+    }
+}
+
+/// Runs the jobs of the master once they are due, runs until the program stops
+pub async fn run(bots: Arc<dyn BotManager>) {
+    let schedule = bots.schedule();
+    let mut last = schedule.current_minute();
+    let mut interval = tokio::time::interval(CHECK_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        // Nothing runs twice if the clock goes back
+        let now = schedule.current_minute();
+        let first = (last + 1).max(now - MAX_CATCH_UP_MINUTES + 1);
+        for minute in first..=now {
+            for job in schedule.due(minute) {
+                run_job(&*bots, &job).await;
+            }
+        }
+        last = last.max(now);
+    }
+}
+
+async fn run_job(bots: &dyn BotManager, job: &Job) {
+    info!("Running scheduled job {}", job);
+
+    match &job.action {
+        JobAction::Play { channel, url } => {
+            let name = match bots.spawn_bot_in(channel).await {
+                Ok(name) => name,
+                Err(MasterActionError::BotCreation(BotCreationError::MultipleBots(name))) => name,
+                Err(e) => {
+                    warn!("Failed to spawn a bot in {} for {}: {}", channel, job, e);
+                    return;
+                }
+            };
+
+            match bots.music_bot(&name) {
+                Some(bot) => {
+                    bot.add_audio(url.clone(), String::from("Schedule"), true)
+                        .await
+                }
+                None => warn!("{} left before {} could be played", name, url),
+            }
+        }
+        JobAction::Despawn => {
+            for name in bots.bot_names() {
+                if let Some(bot) = bots.music_bot(&name) {
+                    bot.quit(String::from("Scheduled despawn"));
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,322 @@
+use std::collections::HashMap;
+use std::iter;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tracing::{error, info, warn};
+
+use crate::youtube_dl::AudioMetadata;
+
+const LASTFM_API: &str = "https://ws.audioscrobbler.com/2.0/";
+const LISTENBRAINZ_API: &str = "https://api.listenbrainz.org/1/submit-listens";
+
+/// Last.fm ignores shorter tracks, ListenBrainz is kept consistent with it
+const MIN_TRACK_LENGTH: Duration = Duration::from_secs(30);
+/// A track counts as listened to after half of it or this long, whichever comes first
+const MAX_LISTEN_TIME: Duration = Duration::from_secs(4 * 60);
+
+/// Words in brackets after a video title that are not part of the track name
+const TITLE_NOISE: &[&str] = &[
+    "official",
+    "video",
+    "audio",
+    "lyric",
+    "visualizer",
+    "hd",
+    "hq",
+    "4k",
+];
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ScrobbleConfig {
+    /// Credentials of a Last.fm API account, needed for any Last.fm session
+    pub lastfm_api_key: Option<String>,
+    pub lastfm_api_secret: Option<String>,
+    /// Receives every track the bots finish
+    #[serde(default)]
+    pub bot: ScrobbleAccount,
+    /// Accounts of users by their unique id, they receive the tracks they listened to
+    #[serde(default)]
+    pub users: HashMap<String, ScrobbleAccount>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ScrobbleAccount {
+    /// Session key from the Last.fm web authentication
+    pub lastfm_session_key: Option<String>,
+    /// User token from the ListenBrainz profile page
+    pub listenbrainz_token: Option<String>,
+}
+
+impl ScrobbleAccount {
+    fn is_empty(&self) -> bool {
+        self.lastfm_session_key.is_none() && self.listenbrainz_token.is_none()
+    }
+}
+
+/// When a track started and how long it was heard, time spent paused does not count
+#[derive(Debug, Clone, Copy)]
+pub struct ListenTime {
+    started: SystemTime,
+    /// `None` while paused
+    playing_since: Option<Instant>,
+    /// Heard before the last pause
+    listened: Duration,
+}
+
+impl ListenTime {
+    pub fn start() -> Self {
+        Self {
+            started: SystemTime::now(),
+            playing_since: Some(Instant::now()),
+            listened: Duration::from_secs(0),
+        }
+    }
+
+    pub fn pause(&mut self) {
+        if let Some(since) = self.playing_since.take() {
+            self.listened += since.elapsed();
+        }
+    }
+
+    pub fn resume(&mut self) {
+        if self.playing_since.is_none() {
+            self.playing_since = Some(Instant::now());
+        }
+    }
+
+    fn listened(&self) -> Duration {
+        self.listened
+            + self
+                .playing_since
+                .map(|since| since.elapsed())
+                .unwrap_or_default()
+    }
+}
+
+/// A track that was listened to long enough to be submitted
+#[derive(Debug, Clone)]
+pub struct Scrobble {
+    pub artist: String,
+    pub track: String,
+    pub started: SystemTime,
+    pub duration: Option<Duration>,
+    pub url: String,
+}
+
+impl Scrobble {
+    /// Returns `None` if no artist can be found or the track was skipped early
+    pub fn new(metadata: &AudioMetadata, time: ListenTime) -> Option<Self> {
+        let listened = time.listened();
+        let required = match metadata.duration {
+            Some(duration) if duration < MIN_TRACK_LENGTH => return None,
+            Some(duration) => (duration / 2).min(MAX_LISTEN_TIME),
+            None => MAX_LISTEN_TIME,
+        };
+        if listened < required {
+            return None;
+        }
+
+        let (artist, track) = match (&metadata.artist, &metadata.track) {
+            (Some(artist), Some(track)) => (artist.clone(), track.clone()),
+            _ => parse_title(&metadata.title)?,
+        };
+
+        Some(Self {
+            artist,
+            track,
+            started: time.started,
+            duration: metadata.duration,
+            url: metadata.webpage_url.clone(),
+        })
+    }
+
+    fn timestamp(&self) -> u64 {
+        self.started
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs()
+    }
+}
+
+/// Splits video titles like "Artist - Track (Official Video)" into artist and track
+pub fn parse_title(title: &str) -> Option<(String, String)> {
+    let split = [" - ", " – ", " — "]
+        .iter()
+        .filter_map(|sep| title.find(sep).map(|i| (i, sep.len())))
+        .min_by_key(|(i, _)| *i);
+    let (index, len) = split?;
+
+    let artist = title[..index].trim();
+    let mut track = title[index + len..].trim();
+    while let Some(stripped) = strip_noise(track) {
+        track = stripped;
+    }
+
+    if artist.is_empty() || track.is_empty() {
+        return None;
+    }
+
+    Some((artist.to_owned(), track.to_owned()))
+}
+
+/// Removes a trailing group like "(Official Video)" or "[HD]"
+fn strip_noise(track: &str) -> Option<&str> {
+    let close = track.chars().last()?;
+    let open = match close {
+        ')' => '(',
+        ']' => '[',
+        _ => return None,
+    };
+    let start = track.rfind(open)?;
+
+    let group = track[start + 1..track.len() - 1].to_lowercase();
+    if !TITLE_NOISE.iter().any(|word| group.contains(word)) {
+        return None;
+    }
+
+    Some(track[..start].trim_end())
+}
+
+pub struct Scrobbler {
+    config: ScrobbleConfig,
+    http: reqwest::Client,
+}
+
+/// Sets up scrobbling if any account is configured
+pub fn open(config: Option<ScrobbleConfig>) -> Option<Arc<Scrobbler>> {
+    let config = config?;
+
+    let accounts = iter::once(&config.bot)
+        .chain(config.users.values())
+        .filter(|a| !a.is_empty())
+        .count();
+    if accounts == 0 {
+        return None;
+    }
+
+    let has_lastfm_key = config.lastfm_api_key.is_some() && config.lastfm_api_secret.is_some();
+    let needs_lastfm_key = iter::once(&config.bot)
+        .chain(config.users.values())
+        .any(|a| a.lastfm_session_key.is_some());
+    if needs_lastfm_key && !has_lastfm_key {
+        warn!("Last.fm sessions are configured without an API key and secret, they are ignored");
+    }
+
+    info!("Scrobbling to {} accounts", accounts);
+    Some(Arc::new(Scrobbler {
+        config,
+        http: reqwest::Client::new(),
+    }))
+}
+
+impl Scrobbler {
+    /// Submits the track to the bot account and the accounts of the listeners
+    pub async fn submit(&self, scrobble: Scrobble, listeners: &[String]) {
+        info!(
+            "Scrobbling {} - {} for {} listeners",
+            scrobble.artist,
+            scrobble.track,
+            listeners.len()
+        );
+
+        let accounts = iter::once(&self.config.bot).chain(
+            listeners
+                .iter()
+                .filter_map(|uid| self.config.users.get(uid)),
+        );
+        for account in accounts {
+            if let Some(session_key) = &account.lastfm_session_key {
+                if let Err(e) = self.submit_lastfm(session_key, &scrobble).await {
+                    error!("Failed to scrobble to Last.fm: {}", e);
+                }
+            }
+            if let Some(token) = &account.listenbrainz_token {
+                if let Err(e) = self.submit_listenbrainz(token, &scrobble).await {
+                    error!("Failed to submit listen to ListenBrainz: {}", e);
+                }
+            }
+        }
+    }
+
+    async fn submit_lastfm(&self, session_key: &str, scrobble: &Scrobble) -> Result<(), String> {
+        let (api_key, secret) = match (&self.config.lastfm_api_key, &self.config.lastfm_api_secret)
+        {
+            (Some(key), Some(secret)) => (key, secret),
+            _ => return Ok(()),
+        };
+
+        let mut params = vec![
+            ("api_key", api_key.clone()),
+            ("artist", scrobble.artist.clone()),
+            ("method", String::from("track.scrobble")),
+            ("sk", session_key.to_owned()),
+            ("timestamp", scrobble.timestamp().to_string()),
+            ("track", scrobble.track.clone()),
+        ];
+        if let Some(duration) = scrobble.duration {
+            params.push(("duration", duration.as_secs().to_string()));
+        }
+
+        // The signature covers all parameters sorted by name, except for the format
+        params.sort();
+        let mut signed = params
+            .iter()
+            .map(|(key, value)| format!("{}{}", key, value))
+            .collect::<String>();
+        signed.push_str(secret);
+        params.push(("api_sig", format!("{:x}", md5::compute(signed))));
+        params.push(("format", String::from("json")));
+
+        let response = self.http.post(LASTFM_API).form(&params);
+        let body = send(response).await?;
+        if let Some(message) = body["message"].as_str() {
+            return Err(message.to_owned());
+        }
+
+        Ok(())
+    }
+
+    async fn submit_listenbrainz(&self, token: &str, scrobble: &Scrobble) -> Result<(), String> {
+        let mut additional_info = json!({ "origin_url": scrobble.url });
+        if let Some(duration) = scrobble.duration {
+            additional_info["duration_ms"] = json!(duration.as_millis() as u64);
+        }
+
+        let listen = json!({
+            "listen_type": "single",
+            "payload": [{
+                "listened_at": scrobble.timestamp(),
+                "track_metadata": {
+                    "artist_name": scrobble.artist,
+                    "track_name": scrobble.track,
+                    "additional_info": additional_info,
+                }
+            }]
+        });
+
+        let response = self
+            .http
+            .post(LISTENBRAINZ_API)
+            .header("Authorization", format!("Token {}", token))
+            .header("Content-Type", "application/json")
+            .body(listen.to_string());
+        send(response).await?;
+
+        Ok(())
+    }
+}
+
+async fn send(request: reqwest::RequestBuilder) -> Result<Value, String> {
+    let response = request.send().await.map_err(|e| e.to_string())?;
+    let status = response.status();
+    let body = response.text().await.map_err(|e| e.to_string())?;
+
+    if !status.is_success() {
+        return Err(format!("{}: {}", status, body));
+    }
+
+    serde_json::from_str(&body).map_err(|e| e.to_string())
+}
@@ -0,0 +1,365 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use futures::future::join_all;
+use tracing::{error, info};
+use tsclientlib::ChannelId;
+
+use crate::blacklist::BlacklistStore;
+use crate::bot::{MasterArgs, MusicBot, MusicBotArgs, MusicBotMessage};
+use crate::channel_settings::ChannelSettingsStore;
+use crate::error::Error;
+use crate::frontend::{Frontend, Whisper};
+use crate::i18n;
+use crate::rate_limit::{RateLimitConfig, RateLimiter};
+use crate::reports::{ReportConfig, ReportStore};
+use crate::request_id::RequestId;
+use crate::saved_playlists::PlaylistStore;
+use crate::user_settings::UserSettingsStore;
+use crate::youtube_dl::{self, AudioMetadata, DownloaderConfig};
+
+/// Length of the generated track, short enough that bots switch tracks often
+const TRACK_LENGTH: Duration = Duration::from_secs(15);
+const SAMPLE_RATE: u32 = 48_000;
+const CHANNELS: u16 = 2;
+/// Tracks kept in the queue of every bot
+const QUEUE_LENGTH: usize = 3;
+/// How often the queues are refilled and the message loops are probed
+const PROBE_INTERVAL: Duration = Duration::from_secs(1);
+const REPORT_INTERVAL: Duration = Duration::from_secs(60);
+
+/// What the mock transports saw since the last report
+#[derive(Default)]
+struct Measurements {
+    /// Longest time between two audio packets of one bot
+    max_packet_gap: Duration,
+    /// Longest time a message waited in the queue of a bot
+    max_message_delay: Duration,
+    /// Longest time it took to read the queue of a bot
+    max_lock_wait: Duration,
+}
+
+/// Shared by all mock transports so the reports cover every bot
+#[derive(Default)]
+struct Counters {
+    packets: AtomicU64,
+    audio_ends: AtomicU64,
+    messages: AtomicU64,
+    measurements: Mutex<Measurements>,
+}
+
+impl Counters {
+    fn record(&self, update: impl FnOnce(&mut Measurements)) {
+        update(&mut self.measurements.lock().unwrap());
+    }
+}
+
+/// A transport that accepts everything and only measures what the bot sends
+struct MockFrontend {
+    channel: ChannelId,
+    counters: Arc<Counters>,
+    last_packet: Mutex<Option<Instant>>,
+    /// When the message that is answered by `listener_count` was posted
+    probe_sent: Mutex<Option<Instant>>,
+}
+
+impl MockFrontend {
+    fn new(channel: ChannelId, counters: Arc<Counters>) -> Self {
+        Self {
+            channel,
+            counters,
+            last_packet: Mutex::new(None),
+            probe_sent: Mutex::new(None),
+        }
+    }
+}
+
+#[async_trait]
+impl Frontend for MockFrontend {
+    async fn send_audio_packet(&self, _samples: &[u8], _whisper: &Whisper) {
+        let now = Instant::now();
+        self.counters.packets.fetch_add(1, Ordering::Relaxed);
+
+        let previous = self.last_packet.lock().unwrap().replace(now);
+        if let Some(previous) = previous {
+            let gap = now - previous;
+            self.counters
+                .record(|m| m.max_packet_gap = m.max_packet_gap.max(gap));
+        }
+    }
+
+    async fn send_audio_end(&self, _whisper: &Whisper) {
+        self.counters.audio_ends.fetch_add(1, Ordering::Relaxed);
+        // Gaps between tracks are not a problem of the bot
+        *self.last_packet.lock().unwrap() = None;
+    }
+
+    async fn send_comfort_noise(&self, _whisper: &Whisper) {}
+
+    async fn send_message_to_channel(&self, _text: String) {
+        self.counters.messages.fetch_add(1, Ordering::Relaxed);
+    }
+
+    async fn set_nickname(&self, _name: String) {}
+
+    async fn set_description(&self, _desc: String) {}
+
+    async fn set_avatar(&self, _data: Vec<u8>) -> Result<(), String> {
+        Ok(())
+    }
+
+    async fn remove_avatar(&self) -> Result<(), String> {
+        Ok(())
+    }
+
+    async fn my_channel(&self) -> Option<ChannelId> {
+        Some(self.channel)
+    }
+
+    async fn listener_count(&self, _ignored: Arc<Vec<String>>) -> u32 {
+        if let Some(sent) = self.probe_sent.lock().unwrap().take() {
+            let delay = sent.elapsed();
+            self.counters
+                .record(|m| m.max_message_delay = m.max_message_delay.max(delay));
+        }
+
+        // Bots without listeners pause and leave
+        1
+    }
+
+    async fn disconnect(&self, _reason: &str) {}
+}
+
+/// Spawns `count` bots on mock transports that play a generated track forever
+/// and logs how the process holds up until it is stopped.
+pub async fn run(args: MasterArgs, count: usize) -> Result<(), Error> {
+    let dir = std::env::temp_dir().join("pokebot-soak");
+    let track = write_track(&dir).map_err(|e| Error::Soak(e.to_string()))?;
+    info!("Soak testing {} bots with {:?}", count, track);
+
+    let counters = Arc::new(Counters::default());
+    let channel_settings = Arc::new(ChannelSettingsStore::new(
+        dir.join("channel_settings.json"),
+        "soak",
+    ));
+    let user_settings = Arc::new(UserSettingsStore::new(dir.join("user_settings.json")));
+    let blacklist = Arc::new(BlacklistStore::new(dir.join("blacklist.json"), "soak"));
+    let rate_limiter = Arc::new(RateLimiter::new(RateLimitConfig {
+        burst: 0,
+        path: dir.join("rate_limits.json"),
+        ..RateLimitConfig::default()
+    }));
+    let reports = Arc::new(ReportStore::new(ReportConfig {
+        path: dir.join("reports.json"),
+        webhook: None,
+    }));
+    let playlists = Arc::new(PlaylistStore::new(dir.join("playlists"), "soak"));
+    // The generated track does not need it, nothing should be updated during the test
+    let downloader = youtube_dl::open(DownloaderConfig {
+        update_interval: None,
+        ..args.downloader.clone()
+    })
+    .await;
+    let locales = i18n::open(args.i18n.clone(), &args.templates.messages);
+
+    let mut bots = Vec::with_capacity(count);
+    for index in 0..count {
+        let frontend = Arc::new(MockFrontend::new(
+            ChannelId(index as u64 + 1),
+            counters.clone(),
+        ));
+        let name = format!("Soak{}", index + 1);
+
+        let bot_args = MusicBotArgs {
+            name: name.clone(),
+            name_index: index,
+            id_index: index,
+            local: false,
+            address: String::from("soak"),
+            id: None,
+            identities: None,
+            server_password: None,
+            privilege_key: None,
+            frontend: Some(frontend.clone()),
+            channel: name.clone(),
+            channel_password: None,
+            summoner: None,
+            private_session: None,
+            bot_uids: Arc::new(Vec::new()),
+            empty_channel_timeout: args.empty_channel_timeout,
+            verbose: 0,
+            commands: args.commands.clone(),
+            thumbnail_avatars: false,
+            max_playlist_tracks: args.max_playlist_tracks,
+            queue_limits: args.queue_limits,
+            sources: args.sources.clone(),
+            blacklist: blacklist.clone(),
+            no_duplicates: args.no_duplicates,
+            file_playback: None,
+            ducking: None,
+            encoder: args.encoder,
+            watchdog: args.watchdog,
+            audio_output: args.audio_output.clone(),
+            admins: Vec::new(),
+            badges: Vec::new(),
+            client_version: args.client_version,
+            idle_audio: args.idle_audio,
+            end_of_queue: args.end_of_queue.clone(),
+            autoplay: args.autoplay,
+            progress_bar: args.progress_bar,
+            channel_settings: channel_settings.clone(),
+            user_settings: user_settings.clone(),
+            reports: reports.clone(),
+            rate_limiter: rate_limiter.clone(),
+            web_url: None,
+            playlists: playlists.clone(),
+            audio_cache: None,
+            stats: None,
+            cards: None,
+            scrobbler: None,
+            lyrics: None,
+            musicbrainz: None,
+            downloader: downloader.clone(),
+            locales: locales.clone(),
+            resume: None,
+            disconnect_cb: Box::new(|name, _, _| error!("{} left during the soak test", name)),
+            reconnect_cb: None,
+        };
+
+        let (bot, fut) = MusicBot::new(bot_args).await?;
+        tokio::spawn(fut);
+        bots.push((bot, frontend));
+    }
+
+    let started = Instant::now();
+    let start_memory = resident_memory();
+    let mut last_report = Instant::now();
+    let mut last_packets = 0;
+
+    loop {
+        tokio::time::delay_for(PROBE_INTERVAL).await;
+
+        let probes = bots.iter().map(|(bot, frontend)| {
+            let track = track.clone();
+
+            async move {
+                let waited = Instant::now();
+                let queued = bot.queue_with_current().len();
+                let lock_wait = waited.elapsed();
+                frontend
+                    .counters
+                    .record(|m| m.max_lock_wait = m.max_lock_wait.max(lock_wait));
+
+                if queued < QUEUE_LENGTH {
+                    let tracks = (queued..QUEUE_LENGTH)
+                        .map(|_| synthetic_track(&track))
+                        .collect();
+                    bot.enqueue(tracks, "Soak").await;
+                }
+
+                *frontend.probe_sent.lock().unwrap() = Some(Instant::now());
+                bot.post(MusicBotMessage::ListenersChanged);
+            }
+        });
+        join_all(probes).await;
+
+        if last_report.elapsed() < REPORT_INTERVAL {
+            continue;
+        }
+
+        let elapsed = last_report.elapsed();
+        last_report = Instant::now();
+
+        let packets = counters.packets.load(Ordering::Relaxed);
+        let packet_rate = (packets - last_packets) as f64 / elapsed.as_secs_f64() / count as f64;
+        last_packets = packets;
+
+        let measurements = std::mem::take(&mut *counters.measurements.lock().unwrap());
+        let memory = resident_memory();
+        let growth = match (memory, start_memory) {
+            (Some(now), Some(start)) => format!("{:+} kB", now as i64 - start as i64),
+            _ => String::from("unknown"),
+        };
+
+        info!(
+            "Soak {}: {} bots, RSS {} kB ({} since start), {:.1} packets/s per bot (50 expected), \
+             {} tracks finished, {} messages, max packet gap {:?}, max message delay {:?}, max lock wait {:?}",
+            humantime::format_duration(Duration::from_secs(started.elapsed().as_secs())),
+            count,
+            memory.map_or_else(|| String::from("?"), |m| m.to_string()),
+            growth,
+            packet_rate,
+            counters.audio_ends.load(Ordering::Relaxed),
+            counters.messages.load(Ordering::Relaxed),
+            measurements.max_packet_gap,
+            measurements.max_message_delay,
+            measurements.max_lock_wait,
+        );
+    }
+}
+
+fn synthetic_track(path: &Path) -> AudioMetadata {
+    AudioMetadata {
+        url: format!("file://{}", path.display()),
+        webpage_url: format!("file://{}", path.display()),
+        title: String::from("Soak test tone"),
+        thumbnail: None,
+        duration: Some(TRACK_LENGTH),
+        artist: None,
+        track: None,
+        album: None,
+        recording: None,
+        chapters: Vec::new(),
+        added_by: String::new(),
+        request_id: RequestId::next(),
+    }
+}
+
+/// Writes a 440 Hz tone as a 16 bit PCM WAV file
+fn write_track(dir: &Path) -> io::Result<PathBuf> {
+    fs::create_dir_all(dir)?;
+    let path = dir.join("tone.wav");
+
+    let frames = SAMPLE_RATE * TRACK_LENGTH.as_secs() as u32;
+    let block_align = CHANNELS * 2;
+    let data_len = frames * u32::from(block_align);
+
+    let mut wav = Vec::with_capacity(44 + data_len as usize);
+    wav.extend_from_slice(b"RIFF");
+    wav.extend_from_slice(&(36 + data_len).to_le_bytes());
+    wav.extend_from_slice(b"WAVEfmt ");
+    wav.extend_from_slice(&16u32.to_le_bytes());
+    wav.extend_from_slice(&1u16.to_le_bytes());
+    wav.extend_from_slice(&CHANNELS.to_le_bytes());
+    wav.extend_from_slice(&SAMPLE_RATE.to_le_bytes());
+    wav.extend_from_slice(&(SAMPLE_RATE * u32::from(block_align)).to_le_bytes());
+    wav.extend_from_slice(&block_align.to_le_bytes());
+    wav.extend_from_slice(&16u16.to_le_bytes());
+    wav.extend_from_slice(b"data");
+    wav.extend_from_slice(&data_len.to_le_bytes());
+
+    for frame in 0..frames {
+        let t = frame as f64 / f64::from(SAMPLE_RATE);
+        let sample = ((t * 440.0 * 2.0 * std::f64::consts::PI).sin() * 8000.0) as i16;
+        for _ in 0..CHANNELS {
+            wav.extend_from_slice(&sample.to_le_bytes());
+        }
+    }
+
+    fs::write(&path, wav)?;
+
+    Ok(path)
+}
+
+/// Resident set size of the process in kB, only available on Linux
+fn resident_memory() -> Option<u64> {
+    let status = fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|l| l.starts_with("VmRSS:"))?;
+
+    line.split_whitespace().nth(1)?.parse().ok()
+}
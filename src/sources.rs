@@ -0,0 +1,37 @@
+use serde::{Deserialize, Serialize};
+
+/// Domains users may add tracks from, a domain also covers its subdomains
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SourceRules {
+    /// Only these domains are allowed if it is not empty
+    #[serde(default)]
+    pub allow: Vec<String>,
+    #[serde(default)]
+    pub block: Vec<String>,
+}
+
+impl SourceRules {
+    /// Returns the domain if tracks from `url` may not be added.
+    ///
+    /// Inputs without a host like search queries pass, the page they
+    /// resolve to is checked once it is known.
+    pub fn rejected_domain(&self, url: &str) -> Option<String> {
+        let host = reqwest::Url::parse(url).ok()?.host_str()?.to_lowercase();
+
+        let blocked = self.block.iter().any(|domain| covers(domain, &host));
+        let allowed =
+            self.allow.is_empty() || self.allow.iter().any(|domain| covers(domain, &host));
+
+        if blocked || !allowed {
+            Some(host)
+        } else {
+            None
+        }
+    }
+}
+
+fn covers(domain: &str, host: &str) -> bool {
+    let domain = domain.trim_start_matches("*.").to_lowercase();
+
+    host == domain || host.ends_with(&format!(".{}", domain))
+}
@@ -0,0 +1,211 @@
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::Serialize;
+use tracing::{error, info};
+
+use crate::youtube_dl::AudioMetadata;
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS plays (
+        id INTEGER PRIMARY KEY,
+        server TEXT NOT NULL,
+        channel TEXT NOT NULL,
+        source TEXT NOT NULL,
+        url TEXT NOT NULL,
+        title TEXT NOT NULL,
+        requester TEXT NOT NULL,
+        duration REAL,
+        played_at INTEGER NOT NULL,
+        recording TEXT
+    );
+    CREATE INDEX IF NOT EXISTS plays_by_server ON plays (server, played_at);
+";
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StatsSummary {
+    pub plays: u64,
+    pub tracks: u64,
+    pub requesters: u64,
+    pub total_duration: Duration,
+    /// Unix time of the first recorded play
+    pub since: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TrackCount {
+    pub title: String,
+    pub url: String,
+    pub plays: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RequesterCount {
+    pub requester: String,
+    pub plays: u64,
+    pub total_duration: Duration,
+}
+
+/// Records the tracks played on one server in a SQLite database,
+/// several servers can share the same file.
+pub struct StatsStore {
+    connection: Mutex<Connection>,
+    server: String,
+}
+
+/// Opens the database if one is configured, statistics are disabled if that fails
+pub fn open(path: Option<PathBuf>, server: &str) -> Option<Arc<StatsStore>> {
+    let path = path?;
+
+    match StatsStore::new(&path, server) {
+        Ok(store) => {
+            info!("Recording play statistics in {:?}", path);
+            Some(Arc::new(store))
+        }
+        Err(e) => {
+            error!(
+                "Failed to open statistics database {:?}, statistics are disabled: {}",
+                path, e
+            );
+            None
+        }
+    }
+}
+
+impl StatsStore {
+    fn new(path: &PathBuf, server: &str) -> rusqlite::Result<Self> {
+        let connection = Connection::open(path)?;
+        connection.execute_batch(SCHEMA)?;
+        // Added with MusicBrainz lookups, older databases do not have it
+        let has_recording = connection
+            .prepare("SELECT recording FROM plays LIMIT 0")
+            .is_ok();
+        if !has_recording {
+            connection.execute_batch("ALTER TABLE plays ADD COLUMN recording TEXT")?;
+        }
+
+        Ok(Self {
+            connection: Mutex::new(connection),
+            server: server.to_owned(),
+        })
+    }
+
+    /// Stores a track that started playing in `channel`
+    pub fn record(&self, metadata: &AudioMetadata, channel: &str) {
+        let played_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs() as i64;
+
+        let result = self.connection.lock().unwrap().execute(
+            "INSERT INTO plays (server, channel, source, url, title, requester, duration, played_at,
+                                recording)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            params![
+                self.server,
+                channel,
+                source_of(&metadata.webpage_url),
+                metadata.webpage_url,
+                metadata.title,
+                metadata.added_by,
+                metadata.duration.map(|d| d.as_secs_f64()),
+                played_at,
+                metadata.recording,
+            ],
+        );
+        if let Err(e) = result {
+            error!("Failed to record play of {}: {}", metadata.webpage_url, e);
+        }
+    }
+
+    pub fn summary(&self) -> rusqlite::Result<StatsSummary> {
+        let connection = self.connection.lock().unwrap();
+        connection.query_row(
+            "SELECT COUNT(*), COUNT(DISTINCT COALESCE(recording, url)), COUNT(DISTINCT requester),
+                    COALESCE(SUM(duration), 0), MIN(played_at)
+             FROM plays WHERE server = ?1",
+            params![self.server],
+            |row| {
+                Ok(StatsSummary {
+                    plays: row.get::<_, i64>(0)? as u64,
+                    tracks: row.get::<_, i64>(1)? as u64,
+                    requesters: row.get::<_, i64>(2)? as u64,
+                    total_duration: Duration::from_secs_f64(row.get(3)?),
+                    since: row.get::<_, Option<i64>>(4)?.map(|t| t as u64),
+                })
+            },
+        )
+    }
+
+    /// The most played tracks, the title and url are the ones of the last play.
+    ///
+    /// Plays of the same MusicBrainz recording from different urls count as one track.
+    pub fn top_tracks(&self, limit: usize) -> rusqlite::Result<Vec<TrackCount>> {
+        let connection = self.connection.lock().unwrap();
+        let mut statement = connection.prepare(
+            "SELECT COALESCE(recording, url) AS track, COUNT(*) AS plays, MAX(played_at)
+             FROM plays WHERE server = ?1 GROUP BY track
+             ORDER BY plays DESC, MAX(played_at) DESC LIMIT ?2",
+        )?;
+        let counts = statement
+            .query_map(params![self.server, limit as i64], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        counts
+            .into_iter()
+            .map(|(track, plays)| {
+                let (title, url) = connection
+                    .query_row(
+                        "SELECT title, url FROM plays
+                         WHERE server = ?1 AND COALESCE(recording, url) = ?2
+                         ORDER BY played_at DESC LIMIT 1",
+                        params![self.server, track],
+                        |row| Ok((row.get(0)?, row.get(1)?)),
+                    )
+                    .optional()?
+                    .unwrap_or_else(|| (track.clone(), track.clone()));
+
+                Ok(TrackCount {
+                    title,
+                    url,
+                    plays: plays as u64,
+                })
+            })
+            .collect()
+    }
+
+    pub fn top_requesters(&self, limit: usize) -> rusqlite::Result<Vec<RequesterCount>> {
+        let connection = self.connection.lock().unwrap();
+        let mut statement = connection.prepare(
+            "SELECT requester, COUNT(*) AS plays, COALESCE(SUM(duration), 0) FROM plays
+             WHERE server = ?1 AND requester != '' GROUP BY requester
+             ORDER BY plays DESC LIMIT ?2",
+        )?;
+        let requesters = statement
+            .query_map(params![self.server, limit as i64], |row| {
+                Ok(RequesterCount {
+                    requester: row.get(0)?,
+                    plays: row.get::<_, i64>(1)? as u64,
+                    total_duration: Duration::from_secs_f64(row.get(2)?),
+                })
+            })?
+            .collect();
+
+        requesters
+    }
+}
+
+/// The host of the page a track came from without `www.`, e.g. `youtube.com`
+fn source_of(url: &str) -> String {
+    reqwest::Url::parse(url)
+        .ok()
+        .and_then(|url| {
+            url.host_str()
+                .map(|host| host.trim_start_matches("www.").to_owned())
+        })
+        .unwrap_or_default()
+}
@@ -0,0 +1,169 @@
+//! Identities with an increased security level.
+//!
+//! Increasing the level of an identity can take minutes, so the results are
+//! kept in a file next to the config and used instead of the identities from
+//! the config on the next start.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tracing::{error, info, warn};
+use tsclientlib::Identity;
+
+use super::identity_uid;
+
+/// How often the progress of an identity level increase is logged
+const LEVEL_PROGRESS_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Identities by their unique id
+pub struct IdentityStore {
+    path: PathBuf,
+    identities: Mutex<HashMap<String, Identity>>,
+}
+
+impl IdentityStore {
+    pub fn new(path: PathBuf) -> Self {
+        let identities = match fs::read(&path) {
+            Ok(data) => match serde_json::from_slice(&data) {
+                Ok(identities) => identities,
+                Err(e) => {
+                    error!("Failed to parse identities in {:?}: {}", path, e);
+                    HashMap::new()
+                }
+            },
+            Err(e) if e.kind() == io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => {
+                error!("Failed to read identities from {:?}: {}", path, e);
+                HashMap::new()
+            }
+        };
+
+        Self {
+            path,
+            identities: Mutex::new(identities),
+        }
+    }
+
+    /// The stored identity with the same key if its level is higher
+    pub fn current(&self, id: &Identity) -> Identity {
+        let identities = self.identities.lock().unwrap();
+        match identities.get(&identity_uid(id)) {
+            Some(stored) if level(stored) > level(id) => stored.clone(),
+            _ => id.clone(),
+        }
+    }
+
+    /// Keeps an identity whose level was increased
+    pub fn store(&self, id: &Identity) {
+        let uid = identity_uid(id);
+        let mut identities = self.identities.lock().unwrap();
+        if identities
+            .get(&uid)
+            .map_or(false, |s| level(s) >= level(id))
+        {
+            return;
+        }
+        info!("Storing identity {} with security level {}", uid, level(id));
+        identities.insert(uid, id.clone());
+
+        let result = serde_json::to_vec_pretty(&*identities)
+            .map_err(io::Error::from)
+            .and_then(|data| write_private(&self.path, &data));
+        if let Err(e) = result {
+            error!("Failed to save identities to {:?}: {}", self.path, e);
+        }
+    }
+
+    /// The identity to connect with, raised to `level` if one is configured
+    pub async fn prepare(&self, id: Identity, level: Option<u8>) -> Identity {
+        match level {
+            Some(level) => self.raise(id, level).await,
+            None => self.current(&id),
+        }
+    }
+
+    /// Increases the level of the identity if it is lower and stores the result
+    pub async fn raise(&self, id: Identity, wanted: u8) -> Identity {
+        let id = self.current(&id);
+        if level(&id) >= wanted {
+            return id;
+        }
+
+        let uid = identity_uid(&id);
+        info!(
+            "Increasing the security level of {} from {} to {}, this can take a while",
+            uid,
+            level(&id),
+            wanted
+        );
+        let start = Instant::now();
+        let increasing = Arc::new(AtomicBool::new(true));
+        tokio::spawn(log_level_progress(increasing.clone()));
+        let mut upgraded = id.clone();
+        let result = tokio::task::spawn_blocking(move || {
+            let result = upgraded.upgrade_level(wanted).map_err(|e| e.to_string());
+            result.map(|_| upgraded)
+        })
+        .await;
+        increasing.store(false, Ordering::Relaxed);
+
+        match result {
+            Ok(Ok(upgraded)) => {
+                info!(
+                    "Increased the security level of {} to {} in {}s",
+                    uid,
+                    level(&upgraded),
+                    start.elapsed().as_secs()
+                );
+                self.store(&upgraded);
+                upgraded
+            }
+            Ok(Err(e)) => {
+                warn!("Failed to increase the security level of {}: {}", uid, e);
+                id
+            }
+            Err(e) => {
+                warn!("Failed to increase the security level of {}: {}", uid, e);
+                id
+            }
+        }
+    }
+}
+
+/// Logs until the identity level was increased, which can take minutes
+pub(super) async fn log_level_progress(increasing: Arc<AtomicBool>) {
+    let start = Instant::now();
+    loop {
+        tokio::time::delay_for(LEVEL_PROGRESS_INTERVAL).await;
+        if !increasing.load(Ordering::Relaxed) {
+            break;
+        }
+        info!(
+            "Still increasing the identity security level after {}s",
+            start.elapsed().as_secs()
+        );
+    }
+}
+
+/// Writes a file only the owner can read, the identities contain private keys
+fn write_private(path: &Path, data: &[u8]) -> io::Result<()> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+
+        options.mode(0o600);
+    }
+
+    options.open(path)?.write_all(data)
+}
+
+fn level(id: &Identity) -> u8 {
+    id.level().unwrap_or(0)
+}
@@ -1,26 +1,113 @@
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
 
+use async_trait::async_trait;
 use futures::stream::StreamExt;
-use tokio::sync::mpsc::UnboundedSender;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
 
 use tsclientlib::data::exts::{M2BClientEditExt, M2BClientUpdateExt};
 use tsclientlib::{
     events::Event,
     sync::{SyncConnection, SyncConnectionHandle, SyncStreamItem},
-    ChannelId, ClientId, ConnectOptions, DisconnectOptions, MessageTarget, OutCommandExt, Reason,
+    ChannelId, ChannelType, ClientId, ConnectOptions, DisconnectOptions, Identity, Invoker,
+    MessageTarget, OutCommandExt, Reason,
 };
 
-use log::{debug, error};
+use tracing::{debug, error, info, warn};
+use tsproto_packets::messages::c2s;
 
-use crate::bot::{Message, MusicBotMessage};
+use crate::bot::{Kick, KickKind, Message, MessageSender, MusicBotMessage};
+use crate::frontend::{Frontend, Whisper, COMFORT_NOISE_PACKET};
 
 mod bbcode;
+mod identity;
+pub mod query;
+mod tree;
+mod version;
 
 pub use bbcode::*;
+pub use identity::IdentityStore;
+pub use tree::ChannelTree;
+pub use version::ClientVersion;
+
+use identity::log_level_progress;
+use tree::TreeChanges;
+
+/// How long a file download may take, a stalled transfer is given up
+const FILE_TRANSFER_TIMEOUT: Duration = Duration::from_secs(120);
 
 #[derive(Clone)]
 pub struct TeamSpeakConnection {
     handle: SyncConnectionHandle,
+    host: Arc<String>,
+    /// Clients that opted in with `!listen`, `None` while the channel hears the bot
+    listeners: Arc<RwLock<Option<Vec<ClientId>>>>,
+    tree: Arc<RwLock<Arc<ChannelTree>>>,
+    /// When a client last stopped talking
+    talk_stopped: Arc<RwLock<Option<Instant>>>,
+}
+
+/// Which events a connection passes on to its bot
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventFilter {
+    /// Everything music bots react to
+    All,
+    /// Pokes, private messages, new channels, clients leaving and changes of
+    /// the own client, including when it is moved.
+    ///
+    /// On large servers most events are clients moving around, which the
+    /// master does not care about.
+    Master,
+}
+
+impl EventFilter {
+    /// `own_id` is 0 until the connection is established, events of all
+    /// clients are passed on until then.
+    fn accepts(self, event: &Event, own_id: ClientId) -> bool {
+        use tsclientlib::events::PropertyId;
+
+        if self == EventFilter::All {
+            return true;
+        }
+
+        let is_own = |id: &ClientId| own_id == ClientId(0) || *id == own_id;
+        match event {
+            Event::Message { target, .. } => match target {
+                MessageTarget::Poke(_) | MessageTarget::Client(_) => true,
+                _ => false,
+            },
+            Event::PropertyAdded { id, .. } => match id {
+                PropertyId::Channel(_) => true,
+                PropertyId::Client(client) => is_own(client),
+                _ => false,
+            },
+            Event::PropertyChanged { id, .. } => match id {
+                PropertyId::ClientChannel(client) => is_own(client),
+                _ => false,
+            },
+            // Clients in line or with an offer are forgotten once they leave
+            Event::PropertyRemoved { id, .. } => match id {
+                PropertyId::Client(_) => true,
+                _ => false,
+            },
+            _ => false,
+        }
+    }
+}
+
+/// A client started or stopped talking
+fn is_talk_status(event: &Event) -> bool {
+    use tsclientlib::events::PropertyId;
+
+    match event {
+        Event::PropertyChanged {
+            id: PropertyId::ClientTalking(_),
+            ..
+        } => true,
+        _ => false,
+    }
 }
 
 fn get_message(event: &Event) -> Option<MusicBotMessage> {
@@ -61,6 +148,10 @@ fn get_message(event: &Event) -> Option<MusicBotMessage> {
                     None
                 }
             }
+            PropertyId::ClientTalkPower(_)
+            | PropertyId::ClientIsTalker(_)
+            | PropertyId::ChannelNeededTalkPower(_) => Some(MusicBotMessage::TalkPowerChanged),
+            PropertyId::ClientTalking(_) => Some(MusicBotMessage::ClientTalking),
             _ => None,
         },
         Event::PropertyRemoved {
@@ -85,61 +176,249 @@ fn get_message(event: &Event) -> Option<MusicBotMessage> {
     }
 }
 
+/// Finds out if the own client was kicked from its channel or the server
+fn get_kick(event: &Event, own_id: ClientId) -> Option<Kick> {
+    use tsclientlib::events::PropertyId;
+
+    let (client, invoker, extra) = match event {
+        Event::PropertyChanged {
+            id: PropertyId::ClientChannel(client),
+            invoker,
+            extra,
+            ..
+        } => (client, invoker, extra),
+        Event::PropertyRemoved {
+            id: PropertyId::Client(client),
+            invoker,
+            extra,
+            ..
+        } => (client, invoker, extra),
+        _ => return None,
+    };
+    if *client != own_id {
+        return None;
+    }
+
+    let kind = match extra.reason? {
+        Reason::KickChannel => KickKind::Channel,
+        Reason::KickServer => KickKind::Server,
+        Reason::KickServerBan => KickKind::Ban,
+        _ => return None,
+    };
+
+    Some(Kick {
+        kind,
+        by: invoker.as_ref().map(|i| i.name.clone()),
+    })
+}
+
 impl TeamSpeakConnection {
     pub async fn new(
-        tx: Arc<RwLock<UnboundedSender<MusicBotMessage>>>,
+        tx: Arc<RwLock<MessageSender>>,
+        address: &str,
         options: ConnectOptions,
+        filter: EventFilter,
+        identities: Option<Arc<IdentityStore>>,
     ) -> Result<TeamSpeakConnection, tsclientlib::Error> {
         let conn = options.connect()?;
         let conn = SyncConnection::from(conn);
         let mut handle = conn.get_handle();
-
-        tokio::spawn(conn.for_each(move |i| {
-            let tx = tx.clone();
-            async move {
+        let level_handle = handle.clone();
+        let increasing = Arc::new(AtomicBool::new(false));
+        let tree = Arc::new(RwLock::new(Arc::new(ChannelTree::default())));
+
+        // The stream is not polled while an item is handled, so the tree is
+        // updated by a separate task that can wait for the connection.
+        let (events_tx, mut events_rx) = tokio::sync::mpsc::unbounded_channel::<Vec<Event>>();
+        let talk_stopped = Arc::new(RwLock::new(None));
+        let ctalk_stopped = talk_stopped.clone();
+        tokio::spawn(async move {
+            conn.for_each(move |i| {
                 match i {
                     Ok(SyncStreamItem::ConEvents(events)) => {
-                        for event in &events {
-                            if let Some(msg) = get_message(event) {
-                                let tx = tx.read().expect("RwLock was not poisoned");
-                                // Ignore the result because the receiver might get dropped first.
-                                let _ = tx.send(msg);
-                            }
-                        }
+                        // Ignore the result because the worker stops after a disconnect
+                        let _ = events_tx.send(events);
                     }
                     Err(e) => error!("Error occured during event reading: {}", e),
                     Ok(SyncStreamItem::DisconnectedTemporarily) => debug!("Temporary disconnect!"),
+                    Ok(SyncStreamItem::IdentityLevelIncreasing(level)) => {
+                        warn!(
+                            "The server needs identity security level {}, increasing it, this can take a while",
+                            level
+                        );
+                        increasing.store(true, Ordering::Relaxed);
+                        tokio::spawn(log_level_progress(increasing.clone()));
+                    }
+                    Ok(SyncStreamItem::IdentityLevelIncreased) => {
+                        increasing.store(false, Ordering::Relaxed);
+                        info!("Increased the identity security level");
+                        if let Some(identities) = identities.clone() {
+                            let mut handle = level_handle.clone();
+                            tokio::spawn(async move {
+                                let id = handle
+                                    .with_connection(|conn| {
+                                        conn.get_options().get_identity().cloned()
+                                    })
+                                    .await;
+                                if let Ok(Some(id)) = id {
+                                    identities.store(&id);
+                                }
+                            });
+                        }
+                    }
                     _ => (),
                 }
+
+                futures::future::ready(())
+            })
+            .await;
+        });
+
+        let mut chandle = handle.clone();
+        let ctree = tree.clone();
+        tokio::spawn(async move {
+            while let Some(events) = events_rx.recv().await {
+                if events.iter().any(is_talk_status) {
+                    *ctalk_stopped.write().expect("RwLock was not poisoned") = Some(Instant::now());
+                }
+
+                // Update the tree before the bot sees the events so it can rely on it
+                let changes = TreeChanges::from_events(&events);
+                if !changes.is_empty() {
+                    let ctree = ctree.clone();
+                    // Fails once the connection is gone, the events are still
+                    // passed on because the last ones can tell about a kick
+                    let _ = chandle
+                        .with_connection(move |conn| {
+                            if let Ok(state) = conn.get_state() {
+                                let mut tree = ctree.write().expect("RwLock was not poisoned");
+                                Arc::make_mut(&mut *tree).apply(state, &changes);
+                            }
+                        })
+                        .await;
+                }
+
+                let own_id = ctree.read().expect("RwLock was not poisoned").own_client;
+                let events = events.iter().filter(|e| filter.accepts(e, own_id));
+                for event in events {
+                    let kick = get_kick(event, own_id).map(MusicBotMessage::Kicked);
+                    for msg in get_message(event).into_iter().chain(kick) {
+                        let tx = tx.read().expect("RwLock was not poisoned");
+                        // Ignore the result because the receiver might get dropped first.
+                        let _ = tx.send(msg);
+                    }
+                }
             }
-        }));
+
+            // The stream ended and all of its events were handled, so a kick
+            // is always seen before the lost connection.
+            // Nobody is listening anymore if the bot disconnected on purpose.
+            let tx = tx.read().expect("RwLock was not poisoned");
+            let _ = tx.send(MusicBotMessage::ConnectionLost);
+        });
 
         handle.wait_until_connected().await?;
 
         let mut chandle = handle.clone();
+        let ctree = tree.clone();
         chandle
-            .with_connection(|mut conn| {
-                conn.get_state()
-                    .expect("is connected")
-                    .server
-                    .set_subscribed(true)
-                    .send(&mut conn)
-                    .unwrap()
+            .with_connection(move |mut conn| {
+                let state = conn.get_state().expect("is connected");
+                *ctree.write().expect("RwLock was not poisoned") =
+                    Arc::new(ChannelTree::from_state(state));
+
+                state.server.set_subscribed(true).send(&mut conn).unwrap()
             })
             .await
             .unwrap();
 
-        Ok(TeamSpeakConnection { handle })
+        let connection = TeamSpeakConnection {
+            handle,
+            host: Arc::new(host_of(address).to_owned()),
+            listeners: Arc::new(RwLock::new(None)),
+            tree,
+            talk_stopped,
+        };
+
+        Ok(connection)
     }
 
+    /// Talks to the channel, or only whispers to the listeners in listen mode
     pub async fn send_audio_packet(&mut self, samples: &[u8]) {
-        let packet =
-            tsproto_packets::packets::OutAudio::new(&tsproto_packets::packets::AudioData::C2S {
+        let listeners = self
+            .listeners
+            .read()
+            .expect("RwLock was not poisoned")
+            .clone();
+        let packet = match listeners {
+            Some(clients) => tsproto_packets::packets::OutAudio::new(
+                &tsproto_packets::packets::AudioData::C2SWhisper {
+                    id: 0,
+                    codec: tsproto_packets::packets::CodecType::OpusMusic,
+                    channels: Vec::new(),
+                    clients: clients.iter().map(|c| c.0).collect(),
+                    data: samples,
+                },
+            ),
+            None => {
+                tsproto_packets::packets::OutAudio::new(&tsproto_packets::packets::AudioData::C2S {
+                    id: 0,
+                    codec: tsproto_packets::packets::CodecType::OpusMusic,
+                    data: samples,
+                })
+            }
+        };
+
+        self.send_voice_packet(packet).await;
+    }
+
+    /// Whispers audio to everyone in the given channels and to the given clients.
+    pub async fn send_whisper_packet(
+        &mut self,
+        samples: &[u8],
+        channels: &[ChannelId],
+        clients: &[ClientId],
+    ) {
+        let packet = tsproto_packets::packets::OutAudio::new(
+            &tsproto_packets::packets::AudioData::C2SWhisper {
                 id: 0,
                 codec: tsproto_packets::packets::CodecType::OpusMusic,
+                channels: channels.iter().map(|c| c.0).collect(),
+                clients: clients.iter().map(|c| c.0).collect(),
                 data: samples,
-            });
+            },
+        );
+
+        self.send_voice_packet(packet).await;
+    }
+
+    /// Sends audio to the own channel and the whisper targets, only the
+    /// clients of a private session hear it if there is one.
+    pub async fn send_audio_to(&mut self, samples: &[u8], whisper: &Whisper) {
+        if whisper.is_private() {
+            self.send_whisper_packet(samples, &[], &whisper.clients)
+                .await;
+            return;
+        }
+
+        self.send_audio_packet(samples).await;
+        if !whisper.channels.is_empty() {
+            self.send_whisper_packet(samples, &whisper.channels, &[])
+                .await;
+        }
+    }
+
+    /// An empty voice packet ends the transmission, clients show the bot
+    /// as talking until they receive one.
+    pub async fn send_audio_end(&mut self, whisper: &Whisper) {
+        self.send_audio_to(&[], whisper).await;
+    }
 
+    pub async fn send_comfort_noise(&mut self, whisper: &Whisper) {
+        self.send_audio_to(COMFORT_NOISE_PACKET, whisper).await;
+    }
+
+    async fn send_voice_packet(&mut self, packet: tsproto_packets::packets::OutPacket) {
         self.handle
             .with_connection(|conn| {
                 if let Err(e) = conn
@@ -154,90 +433,160 @@ impl TeamSpeakConnection {
             .unwrap();
     }
 
+    /// A consistent view of the channels and clients, cheap to take.
+    pub fn channel_tree(&self) -> Arc<ChannelTree> {
+        self.tree.read().expect("RwLock was not poisoned").clone()
+    }
+
+    /// Adds or removes a listener, listen mode ends with the last one.
+    ///
+    /// Returns how many clients listen now or `None` if nothing changed.
+    pub fn set_listening(&self, client: ClientId, on: bool) -> Option<usize> {
+        let mut listeners = self.listeners.write().expect("RwLock was not poisoned");
+        let listening = listeners.as_ref().map_or(false, |l| l.contains(&client));
+        match (on, listening) {
+            (true, false) => {
+                let clients = listeners.get_or_insert_with(Vec::new);
+                clients.push(client);
+                Some(clients.len())
+            }
+            (false, true) => {
+                let clients = listeners.as_mut().expect("client is listening");
+                clients.retain(|c| *c != client);
+                let count = clients.len();
+                if count == 0 {
+                    *listeners = None;
+                }
+                Some(count)
+            }
+            _ => None,
+        }
+    }
+
     pub async fn channel_of_user(&mut self, id: ClientId) -> Option<ChannelId> {
-        self.handle
-            .with_connection(move |conn| {
-                conn.get_state()
-                    .expect("can get state")
-                    .clients
-                    .get(&id)
-                    .map(|c| c.channel)
-            })
-            .await
-            .unwrap()
+        self.channel_tree().channel_of(id)
     }
 
     pub async fn channel_path_of_user(&mut self, id: ClientId) -> Option<String> {
-        self.handle
-            .with_connection(move |conn| {
-                let state = conn.get_state().expect("can get state");
-
-                let channel_id = state.clients.get(&id)?.channel;
+        let tree = self.channel_tree();
 
-                let mut channel = state
-                    .channels
-                    .get(&channel_id)
-                    .expect("can find user channel");
+        tree.channel_path(tree.channel_of(id)?)
+    }
 
-                let mut names = vec![&channel.name[..]];
+    /// The path of a channel in the format used by bookmarks, e.g. `Lobby/Music`.
+    pub async fn channel_path(&mut self, id: ChannelId) -> Option<String> {
+        self.channel_tree().channel_path(id)
+    }
 
-                // Channel 0 is the root channel
-                while channel.parent != ChannelId(0) {
-                    names.push("/");
-                    channel = state
-                        .channels
-                        .get(&channel.parent)
-                        .expect("can find user channel");
-                    names.push(&channel.name);
-                }
+    /// Finds a channel by its path, e.g. `Lobby/Music`.
+    pub async fn find_channel(&mut self, path: &str) -> Option<ChannelId> {
+        self.channel_tree().find_channel(path)
+    }
 
-                let mut path = String::new();
-                while let Some(name) = names.pop() {
-                    path.push_str(name);
-                }
+    /// Finds a channel by its id, its path or its name if no other channel has the same name.
+    pub async fn resolve_channel(&mut self, channel: &str) -> Option<ChannelId> {
+        self.channel_tree().resolve_channel(channel)
+    }
 
-                Some(path)
+    pub async fn move_to_channel(&mut self, channel: ChannelId) {
+        self.handle
+            .with_connection(move |mut conn| {
+                let state = conn.get_state().expect("can get state");
+                let _ = state
+                    .clients
+                    .get(&state.own_client)
+                    .expect("can get myself")
+                    .set_channel(channel)
+                    .send(&mut conn)
+                    .map_err(|e| error!("Failed to move to channel: {}", e));
             })
             .await
             .unwrap()
     }
 
-    pub async fn my_channel(&mut self) -> ChannelId {
+    /// Moves another client, which needs the permission to move clients
+    pub async fn move_client(
+        &mut self,
+        client: ClientId,
+        channel: ChannelId,
+    ) -> Result<(), String> {
         self.handle
-            .with_connection(move |conn| {
+            .with_connection(move |mut conn| {
                 let state = conn.get_state().expect("can get state");
                 state
                     .clients
-                    .get(&state.own_client)
-                    .expect("can find myself")
-                    .channel
+                    .get(&client)
+                    .ok_or_else(|| String::from("Client not found"))?
+                    .set_channel(channel)
+                    .send(&mut conn)
+                    .map(|_| ())
+                    .map_err(|e| e.to_string())
             })
             .await
-            .unwrap()
+            .map_err(|e| e.to_string())?
     }
 
-    pub async fn my_id(&mut self) -> ClientId {
+    /// Creates a semi-permanent channel, it shows up in the channel tree once the server added it
+    pub async fn create_channel(&mut self, name: String, parent: ChannelId) -> Result<(), String> {
         self.handle
-            .with_connection(move |conn| conn.get_state().expect("can get state").own_client)
+            .with_connection(move |mut conn| {
+                conn.get_state()
+                    .expect("can get state")
+                    .add_channel(&name)
+                    .parent(parent)
+                    .channel_type(ChannelType::SemiPermanent)
+                    .send(&mut conn)
+                    .map(|_| ())
+                    .map_err(|e| e.to_string())
+            })
             .await
-            .unwrap()
+            .map_err(|e| e.to_string())?
     }
 
-    pub async fn user_count(&mut self, channel: ChannelId) -> u32 {
-        self.handle
-            .with_connection(move |conn| {
-                let state = conn.get_state().expect("can get state");
-                let mut count = 0;
-                for client in state.clients.values() {
-                    if client.channel == channel {
-                        count += 1;
-                    }
-                }
+    /// `None` while the own client is missing from the tree, e.g. right after a reconnect
+    pub async fn my_channel(&mut self) -> Option<ChannelId> {
+        self.channel_tree().own_channel()
+    }
 
-                count
-            })
-            .await
-            .unwrap()
+    /// Whether someone else talks in the channel, except for the clients with the `ignored` unique ids
+    pub fn is_anyone_talking(&self, ignored: &[String]) -> bool {
+        let tree = self.channel_tree();
+        match tree.own_channel() {
+            Some(channel) => tree.is_anyone_talking(channel, ignored),
+            None => false,
+        }
+    }
+
+    /// When someone else was last heard talking in the channel, now while they still talk
+    pub fn last_voice(&self, ignored: &[String]) -> Option<Instant> {
+        if self.is_anyone_talking(ignored) {
+            return Some(Instant::now());
+        }
+
+        *self.talk_stopped.read().expect("RwLock was not poisoned")
+    }
+
+    pub async fn my_id(&mut self) -> ClientId {
+        self.channel_tree().own_client
+    }
+
+    /// Finds a client on the server by its nickname, ignoring the case
+    pub async fn find_client(&mut self, name: &str) -> Option<ClientId> {
+        self.channel_tree().find_client(name)
+    }
+
+    pub async fn client_name(&mut self, id: ClientId) -> Option<String> {
+        self.channel_tree().client_name(id)
+    }
+
+    /// Unique ids of the clients in a channel
+    pub async fn uids_in_channel(&mut self, channel: ChannelId) -> Vec<String> {
+        self.channel_tree().uids_in_channel(channel)
+    }
+
+    /// Counts the clients in a channel, ignoring those with one of the given unique ids
+    pub async fn listener_count(&mut self, channel: ChannelId, ignored: Arc<Vec<String>>) -> u32 {
+        self.channel_tree().listener_count(channel, &ignored)
     }
 
     pub async fn set_nickname(&mut self, name: String) {
@@ -255,6 +604,45 @@ impl TeamSpeakConnection {
             .unwrap();
     }
 
+    /// Shows the given badges next to the name, `guids` are the ids of at most 3 badges.
+    pub async fn set_badges(&mut self, guids: &[String]) {
+        let badges = format!("overwolf=0:badges={}", guids.join(","));
+
+        let result = self
+            .handle
+            .with_connection(move |mut conn| {
+                conn.get_state()
+                    .expect("can get state")
+                    .client_update()
+                    .set_badges(&badges)
+                    .send(&mut conn)
+                    .map_err(|e| e.to_string())
+            })
+            .await
+            .map_err(|e| e.to_string())
+            .and_then(|r| r);
+        if let Err(e) = result {
+            error!("Failed to set badges: {}", e);
+        }
+    }
+
+    /// Asks for talk power, channel admins see `message` next to the name
+    pub async fn request_talk_power(&mut self, message: String) {
+        self.handle
+            .with_connection(move |mut conn| {
+                let _ = conn
+                    .get_state()
+                    .expect("can get state")
+                    .client_update()
+                    .set_talk_power_request(true)
+                    .set_talk_power_request_message(&message)
+                    .send(&mut conn)
+                    .map_err(|e| error!("Failed to request talk power: {}", e));
+            })
+            .await
+            .unwrap()
+    }
+
     pub async fn set_description(&mut self, desc: String) {
         self.handle
             .with_connection(move |mut conn| {
@@ -272,6 +660,22 @@ impl TeamSpeakConnection {
             .unwrap()
     }
 
+    /// Redeems a privilege key for the current identity, which puts it into
+    /// the server or channel group the key was created for
+    pub async fn use_privilege_key(&mut self, key: String) {
+        info!("Redeeming privilege key");
+        self.handle
+            .with_connection(move |mut conn| {
+                let _ = c2s::OutPrivilegeKeyUseMessage::new(&mut std::iter::once(
+                    c2s::OutPrivilegeKeyUsePart { token: &key },
+                ))
+                .send(&mut conn)
+                .map_err(|e| error!("Failed to redeem privilege key: {}", e));
+            })
+            .await
+            .unwrap()
+    }
+
     pub async fn send_message_to_channel(&mut self, text: String) {
         self.handle
             .with_connection(move |mut conn| {
@@ -300,6 +704,107 @@ impl TeamSpeakConnection {
             .unwrap()
     }
 
+    /// Uploads `data` as our avatar through the file transfer and announces its hash.
+    pub async fn set_avatar(&mut self, data: Vec<u8>) -> Result<(), String> {
+        let upload = self
+            .handle
+            .upload_file(
+                ChannelId(0),
+                String::from("/avatar"),
+                None,
+                data.len() as u64,
+                true,
+                false,
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+
+        let mut stream = TcpStream::connect((self.host.as_str(), upload.port))
+            .await
+            .map_err(|e| e.to_string())?;
+        stream
+            .write_all(upload.key.as_bytes())
+            .await
+            .map_err(|e| e.to_string())?;
+        stream.write_all(&data).await.map_err(|e| e.to_string())?;
+        stream.flush().await.map_err(|e| e.to_string())?;
+
+        let hash = format!("{:x}", md5::compute(&data));
+        self.set_avatar_flag(hash).await
+    }
+
+    /// Downloads a file from the file browser of `channel` through the file transfer.
+    ///
+    /// Files larger than `max_size` bytes are not downloaded, transfers that
+    /// take longer than `FILE_TRANSFER_TIMEOUT` are given up.
+    pub async fn download_file(
+        &mut self,
+        channel: ChannelId,
+        path: String,
+        password: Option<String>,
+        max_size: u64,
+    ) -> Result<Vec<u8>, String> {
+        tokio::time::timeout(
+            FILE_TRANSFER_TIMEOUT,
+            self.transfer_file(channel, path, password, max_size),
+        )
+        .await
+        .map_err(|_| String::from("the file transfer timed out"))?
+    }
+
+    async fn transfer_file(
+        &mut self,
+        channel: ChannelId,
+        path: String,
+        password: Option<String>,
+        max_size: u64,
+    ) -> Result<Vec<u8>, String> {
+        let download = self
+            .handle
+            .download_file(channel, path, password, None)
+            .await
+            .map_err(|e| e.to_string())?;
+        if download.size > max_size {
+            return Err(format!(
+                "the file is larger than {} MB",
+                max_size / 1024 / 1024
+            ));
+        }
+
+        let mut stream = TcpStream::connect((self.host.as_str(), download.port))
+            .await
+            .map_err(|e| e.to_string())?;
+        stream
+            .write_all(download.key.as_bytes())
+            .await
+            .map_err(|e| e.to_string())?;
+        let mut data = vec![0; download.size as usize];
+        stream
+            .read_exact(&mut data)
+            .await
+            .map_err(|e| e.to_string())?;
+
+        Ok(data)
+    }
+
+    pub async fn remove_avatar(&mut self) -> Result<(), String> {
+        self.set_avatar_flag(String::new()).await
+    }
+
+    async fn set_avatar_flag(&mut self, hash: String) -> Result<(), String> {
+        self.handle
+            .with_connection(move |mut conn| {
+                conn.get_state()
+                    .expect("can get state")
+                    .client_update()
+                    .set_flag_avatar(&hash)
+                    .send(&mut conn)
+                    .map_err(|e| e.to_string())
+            })
+            .await
+            .map_err(|e| e.to_string())?
+    }
+
     pub async fn subscribe(&mut self, id: ChannelId) {
         self.handle
             .with_connection(move |mut conn| {
@@ -326,3 +831,101 @@ impl TeamSpeakConnection {
         self.handle.disconnect(opt).await.unwrap();
     }
 }
+
+// The inherent methods take `&mut self`, so they are called on a clone and
+// spelled out to not resolve to the trait methods of the same name.
+#[async_trait]
+impl Frontend for TeamSpeakConnection {
+    async fn send_audio_packet(&self, samples: &[u8], whisper: &Whisper) {
+        self.clone().send_audio_to(samples, whisper).await
+    }
+
+    async fn send_audio_end(&self, whisper: &Whisper) {
+        TeamSpeakConnection::send_audio_end(&mut self.clone(), whisper).await
+    }
+
+    async fn send_comfort_noise(&self, whisper: &Whisper) {
+        TeamSpeakConnection::send_comfort_noise(&mut self.clone(), whisper).await
+    }
+
+    async fn send_message_to_channel(&self, text: String) {
+        TeamSpeakConnection::send_message_to_channel(&mut self.clone(), text).await
+    }
+
+    async fn send_message_to_user(&self, invoker: &Invoker, text: String) {
+        // Users of a bridge are not on the server, and private replies must not go to the channel
+        if invoker.id == ClientId(0) {
+            warn!(
+                "Dropping a private reply to {}, who is not on the server",
+                invoker.name
+            );
+            return;
+        }
+
+        TeamSpeakConnection::send_message_to_user(&mut self.clone(), invoker.id, text).await
+    }
+
+    async fn set_nickname(&self, name: String) {
+        TeamSpeakConnection::set_nickname(&mut self.clone(), name).await
+    }
+
+    async fn set_description(&self, desc: String) {
+        TeamSpeakConnection::set_description(&mut self.clone(), desc).await
+    }
+
+    async fn set_avatar(&self, data: Vec<u8>) -> Result<(), String> {
+        TeamSpeakConnection::set_avatar(&mut self.clone(), data).await
+    }
+
+    async fn remove_avatar(&self) -> Result<(), String> {
+        TeamSpeakConnection::remove_avatar(&mut self.clone()).await
+    }
+
+    async fn my_channel(&self) -> Option<ChannelId> {
+        TeamSpeakConnection::my_channel(&mut self.clone()).await
+    }
+
+    async fn listener_count(&self, ignored: Arc<Vec<String>>) -> u32 {
+        let mut ts = self.clone();
+        let channel = match TeamSpeakConnection::my_channel(&mut ts).await {
+            Some(channel) => channel,
+            None => return 0,
+        };
+        TeamSpeakConnection::listener_count(&mut ts, channel, ignored).await
+    }
+
+    async fn max_bitrate(&self) -> Option<u32> {
+        let tree = self.channel_tree();
+        tree.max_bitrate(tree.own_channel()?)
+    }
+
+    async fn disconnect(&self, reason: &str) {
+        TeamSpeakConnection::disconnect(&mut self.clone(), reason).await
+    }
+}
+
+/// Strips the port from a server address like `ts.example.com:9987` or `[::1]:9987`.
+fn host_of(address: &str) -> &str {
+    if address.starts_with('[') {
+        if let Some(end) = address.find(']') {
+            return &address[1..end];
+        }
+    }
+
+    match address.rfind(':') {
+        // More than one colon means this is a plain IPv6 address without a port
+        Some(i) if address[..i].find(':').is_none() => &address[..i],
+        _ => address,
+    }
+}
+
+/// The unique id of an identity as shown by TeamSpeak, used to grant server permissions.
+pub fn identity_uid(id: &Identity) -> String {
+    match id.key().to_pub().get_uid() {
+        Ok(uid) => uid,
+        Err(e) => {
+            error!("Failed to compute uid of identity: {}", e);
+            String::new()
+        }
+    }
+}
@@ -0,0 +1,336 @@
+//! A ServerQuery client for what the bots are not allowed to do as normal clients.
+//!
+//! Only the raw interface is supported, the SSH interface (port 10022 by default)
+//! is not. The raw one sends the password in plain text, so it should only be
+//! reachable from the host of the bots, e.g. on localhost or through an SSH tunnel.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::sync::Mutex;
+use tracing::{debug, info};
+use tsclientlib::{ChannelId, ClientId};
+
+/// Returned when a client is already in a server group
+const DUPLICATE_ENTRY: u32 = 2561;
+/// How long connecting and each command may take before the query is given up
+const QUERY_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServerQueryConfig {
+    /// Host and port of the raw query interface, the SSH interface is not supported
+    #[serde(default = "default_address")]
+    pub address: String,
+    pub login: String,
+    pub password: String,
+    /// Voice port of the virtual server the bots are on
+    #[serde(default = "default_server_port")]
+    pub server_port: u16,
+    /// Name of the query client, shown as the sender of its messages
+    pub nickname: Option<String>,
+    /// Server groups the master and the music bots are put in when they connect
+    #[serde(default)]
+    pub server_groups: Vec<u64>,
+    /// Sends the private messages of the master through the query, which has no flood limit
+    #[serde(default)]
+    pub send_messages: bool,
+}
+
+fn default_address() -> String {
+    String::from("localhost:10011")
+}
+
+fn default_server_port() -> u16 {
+    9987
+}
+
+/// A voice client as listed by the query, including those in unsubscribed channels
+#[derive(Debug, Clone)]
+pub struct QueryClient {
+    pub id: ClientId,
+    pub channel: ChannelId,
+    pub name: String,
+}
+
+#[derive(Debug)]
+enum QueryError {
+    Io(String),
+    Server { id: u32, message: String },
+    Timeout,
+}
+
+impl From<std::io::Error> for QueryError {
+    fn from(e: std::io::Error) -> Self {
+        QueryError::Io(e.to_string())
+    }
+}
+
+impl std::fmt::Display for QueryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            QueryError::Io(e) => write!(f, "{}", e),
+            QueryError::Server { id, message } => write!(f, "{} (error {})", message, id),
+            QueryError::Timeout => write!(f, "The ServerQuery did not answer in time"),
+        }
+    }
+}
+
+type Entry = HashMap<String, String>;
+
+pub struct ServerQuery {
+    config: ServerQueryConfig,
+    /// Connected on first use and again after the server closed an idle connection
+    connection: Mutex<Option<BufReader<TcpStream>>>,
+}
+
+/// Sets up the ServerQuery client if it is configured
+pub fn open(config: Option<ServerQueryConfig>) -> Option<Arc<ServerQuery>> {
+    let config = config?;
+
+    info!("Using the ServerQuery at {}", config.address);
+    Some(Arc::new(ServerQuery {
+        config,
+        connection: Mutex::new(None),
+    }))
+}
+
+impl ServerQuery {
+    /// If private messages of the master should be sent by the query
+    pub fn sends_messages(&self) -> bool {
+        self.config.send_messages
+    }
+
+    /// Puts the client with the unique id in the configured server groups.
+    ///
+    /// The client has to have connected once, clients are not in the database before.
+    pub async fn add_to_server_groups(&self, uid: &str) -> Result<(), String> {
+        if self.config.server_groups.is_empty() {
+            return Ok(());
+        }
+
+        let reply = self
+            .command(&format!("clientgetdbidfromuid cluid={}", escape(uid)))
+            .await
+            .map_err(|e| e.to_string())?;
+        let db_id = reply
+            .first()
+            .and_then(|entry| entry.get("cldbid"))
+            .ok_or_else(|| String::from("The server did not return a database id"))?;
+
+        for group in &self.config.server_groups {
+            let command = format!("servergroupaddclient sgid={} cldbid={}", group, db_id);
+            match self.command(&command).await {
+                Ok(_) => debug!("Added {} to server group {}", uid, group),
+                Err(QueryError::Server { id, .. }) if id == DUPLICATE_ENTRY => (),
+                Err(e) => return Err(format!("Failed to add to group {}: {}", group, e)),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Creates a semi-permanent channel, which needs no channel permissions of the master
+    pub async fn create_channel(&self, name: &str, parent: ChannelId) -> Result<(), String> {
+        let command = format!(
+            "channelcreate channel_name={} cpid={} channel_flag_semi_permanent=1",
+            escape(name),
+            parent.0
+        );
+
+        self.command(&command)
+            .await
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    }
+
+    /// All voice clients on the server, no matter which channels are subscribed
+    pub async fn clients(&self) -> Result<Vec<QueryClient>, String> {
+        let reply = self
+            .command("clientlist")
+            .await
+            .map_err(|e| e.to_string())?;
+
+        Ok(reply
+            .iter()
+            // Type 1 are query clients like this one
+            .filter(|entry| entry.get("client_type").map(String::as_str) == Some("0"))
+            .filter_map(|entry| {
+                Some(QueryClient {
+                    id: ClientId(entry.get("clid")?.parse().ok()?),
+                    channel: ChannelId(entry.get("cid")?.parse().ok()?),
+                    name: entry.get("client_nickname")?.clone(),
+                })
+            })
+            .collect())
+    }
+
+    pub async fn send_message(&self, client: ClientId, text: &str) -> Result<(), String> {
+        let command = format!(
+            "sendtextmessage targetmode=1 target={} msg={}",
+            client.0,
+            escape(text)
+        );
+
+        self.command(&command)
+            .await
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    }
+
+    async fn command(&self, command: &str) -> Result<Vec<Entry>, QueryError> {
+        let mut connection = self.connection.lock().await;
+
+        if let Some(stream) = connection.as_mut() {
+            match timed(send(stream, command)).await {
+                Err(QueryError::Io(e)) => debug!("ServerQuery connection lost: {}", e),
+                // The reply may still arrive and would be read as the next one
+                Err(QueryError::Timeout) => {
+                    *connection = None;
+                    return Err(QueryError::Timeout);
+                }
+                result => return result,
+            }
+        }
+
+        *connection = None;
+        let mut stream = timed(self.connect()).await?;
+        let result = timed(send(&mut stream, command)).await;
+        if !matches!(result, Err(QueryError::Timeout)) {
+            *connection = Some(stream);
+        }
+
+        result
+    }
+
+    async fn connect(&self) -> Result<BufReader<TcpStream>, QueryError> {
+        let stream = TcpStream::connect(self.config.address.as_str()).await?;
+        let mut stream = BufReader::new(stream);
+
+        // The greeting is `TS3` and a line explaining the help command
+        for i in 0..2 {
+            let mut line = String::new();
+            if stream.read_line(&mut line).await? == 0 {
+                return Err(QueryError::Io(String::from("Connection closed")));
+            }
+            if i == 0 && line.starts_with("SSH-") {
+                return Err(QueryError::Io(String::from(
+                    "This is the SSH query interface, only the raw one is supported",
+                )));
+            }
+        }
+
+        let login = format!(
+            "login client_login_name={} client_login_password={}",
+            escape(&self.config.login),
+            escape(&self.config.password)
+        );
+        send(&mut stream, &login).await?;
+        send(
+            &mut stream,
+            &format!("use port={}", self.config.server_port),
+        )
+        .await?;
+        if let Some(nickname) = &self.config.nickname {
+            let update = format!("clientupdate client_nickname={}", escape(nickname));
+            send(&mut stream, &update).await?;
+        }
+        info!("Connected to the ServerQuery at {}", self.config.address);
+
+        Ok(stream)
+    }
+}
+
+/// Gives up on a hung ServerQuery, which would otherwise stall the master
+async fn timed<T>(
+    future: impl std::future::Future<Output = Result<T, QueryError>>,
+) -> Result<T, QueryError> {
+    tokio::time::timeout(QUERY_TIMEOUT, future)
+        .await
+        .unwrap_or(Err(QueryError::Timeout))
+}
+
+/// Sends a command and reads lines until the `error` line which ends every reply
+async fn send(stream: &mut BufReader<TcpStream>, command: &str) -> Result<Vec<Entry>, QueryError> {
+    stream.write_all(command.as_bytes()).await?;
+    stream.write_all(b"\n").await?;
+
+    let mut data = String::new();
+    loop {
+        let mut line = String::new();
+        if stream.read_line(&mut line).await? == 0 {
+            return Err(QueryError::Io(String::from("Connection closed")));
+        }
+        // Lines end with "\n\r", so the "\r" is left at the start of the next one
+        let line = line.trim();
+
+        if line.starts_with("error ") {
+            let status = parse_entry(&line["error ".len()..]);
+            let id = status.get("id").and_then(|id| id.parse().ok()).unwrap_or(0);
+            if id != 0 {
+                let message = status.get("msg").cloned().unwrap_or_default();
+                return Err(QueryError::Server { id, message });
+            }
+
+            return Ok(data.split('|').map(parse_entry).collect());
+        }
+        if !line.is_empty() && !line.starts_with("notify") {
+            data.push_str(line);
+        }
+    }
+}
+
+/// Parses `key=value` pairs separated by spaces, keys without a value are empty
+fn parse_entry(entry: &str) -> Entry {
+    entry
+        .split(' ')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| match pair.find('=') {
+            Some(i) => (pair[..i].to_owned(), unescape(&pair[i + 1..])),
+            None => (pair.to_owned(), String::new()),
+        })
+        .collect()
+}
+
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '/' => escaped.push_str("\\/"),
+            ' ' => escaped.push_str("\\s"),
+            '|' => escaped.push_str("\\p"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+fn unescape(value: &str) -> String {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('s') => unescaped.push(' '),
+            Some('p') => unescaped.push('|'),
+            Some('n') => unescaped.push('\n'),
+            Some('r') => unescaped.push('\r'),
+            Some('t') => unescaped.push('\t'),
+            Some(c) => unescaped.push(c),
+            None => (),
+        }
+    }
+
+    unescaped
+}
@@ -0,0 +1,322 @@
+use std::collections::{HashMap, HashSet};
+
+use tsclientlib::data;
+use tsclientlib::events::{Event, PropertyId};
+use tsclientlib::{ChannelId, ClientId, Codec};
+
+/// The channels and clients of a server as far as the bots need them.
+///
+/// The tree is replaced as a whole when it changes so readers always see
+/// a consistent snapshot, even while events are being applied.
+#[derive(Debug, Clone)]
+pub struct ChannelTree {
+    pub own_client: ClientId,
+    channels: HashMap<ChannelId, ChannelNode>,
+    clients: HashMap<ClientId, ClientNode>,
+}
+
+#[derive(Debug, Clone)]
+struct ChannelNode {
+    name: String,
+    parent: ChannelId,
+    has_password: bool,
+    codec: Codec,
+    codec_quality: u8,
+    needed_talk_power: i32,
+}
+
+#[derive(Debug, Clone)]
+struct ClientNode {
+    name: String,
+    channel: ChannelId,
+    uid: Option<String>,
+    talk_power: i32,
+    /// Granted by a channel admin, allows talking without enough talk power
+    is_talker: bool,
+    /// Whether the client is talking right now
+    talking: bool,
+}
+
+/// Channels and clients touched by a batch of events
+#[derive(Debug, Default)]
+pub struct TreeChanges {
+    channels: HashSet<ChannelId>,
+    clients: HashSet<ClientId>,
+}
+
+impl TreeChanges {
+    pub fn from_events(events: &[Event]) -> Self {
+        let mut changes = TreeChanges::default();
+
+        for event in events {
+            let property = match event {
+                Event::PropertyAdded { id, .. } => id,
+                Event::PropertyChanged { id, .. } => id,
+                Event::PropertyRemoved { id, .. } => id,
+                _ => continue,
+            };
+
+            match property {
+                PropertyId::Channel(id)
+                | PropertyId::ChannelName(id)
+                | PropertyId::ChannelParent(id)
+                | PropertyId::ChannelHasPassword(id)
+                | PropertyId::ChannelCodec(id)
+                | PropertyId::ChannelCodecQuality(id)
+                | PropertyId::ChannelNeededTalkPower(id) => {
+                    changes.channels.insert(*id);
+                }
+                PropertyId::Client(id)
+                | PropertyId::ClientName(id)
+                | PropertyId::ClientChannel(id)
+                | PropertyId::ClientTalkPower(id)
+                | PropertyId::ClientIsTalker(id)
+                | PropertyId::ClientTalking(id) => {
+                    changes.clients.insert(*id);
+                }
+                _ => (),
+            }
+        }
+
+        changes
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.channels.is_empty() && self.clients.is_empty()
+    }
+}
+
+impl Default for ChannelTree {
+    fn default() -> Self {
+        Self {
+            own_client: ClientId(0),
+            channels: HashMap::new(),
+            clients: HashMap::new(),
+        }
+    }
+}
+
+impl ChannelTree {
+    pub fn from_state(state: &data::Connection) -> Self {
+        let mut tree = Self {
+            own_client: state.own_client,
+            ..Self::default()
+        };
+        for id in state.channels.keys() {
+            tree.update_channel(state, *id);
+        }
+        for id in state.clients.keys() {
+            tree.update_client(state, *id);
+        }
+
+        tree
+    }
+
+    /// Copies the touched channels and clients from the current state
+    pub fn apply(&mut self, state: &data::Connection, changes: &TreeChanges) {
+        self.own_client = state.own_client;
+        for id in &changes.channels {
+            self.update_channel(state, *id);
+        }
+        for id in &changes.clients {
+            self.update_client(state, *id);
+        }
+    }
+
+    fn update_channel(&mut self, state: &data::Connection, id: ChannelId) {
+        match state.channels.get(&id) {
+            Some(channel) => {
+                self.channels.insert(
+                    id,
+                    ChannelNode {
+                        name: channel.name.clone(),
+                        parent: channel.parent,
+                        has_password: channel.has_password,
+                        codec: channel.codec,
+                        codec_quality: channel.codec_quality,
+                        needed_talk_power: channel.needed_talk_power,
+                    },
+                );
+            }
+            None => {
+                self.channels.remove(&id);
+            }
+        }
+    }
+
+    fn update_client(&mut self, state: &data::Connection, id: ClientId) {
+        match state.clients.get(&id) {
+            Some(client) => {
+                self.clients.insert(
+                    id,
+                    ClientNode {
+                        name: client.name.clone(),
+                        channel: client.channel,
+                        uid: client.uid.as_ref().map(|uid| uid.0.clone()),
+                        talk_power: client.talk_power,
+                        is_talker: client.is_talker,
+                        talking: client.talking,
+                    },
+                );
+            }
+            None => {
+                self.clients.remove(&id);
+            }
+        }
+    }
+
+    pub fn channel_of(&self, client: ClientId) -> Option<ChannelId> {
+        self.clients.get(&client).map(|c| c.channel)
+    }
+
+    pub fn own_channel(&self) -> Option<ChannelId> {
+        self.channel_of(self.own_client)
+    }
+
+    /// The talk power the own channel needs and the one of the own client
+    /// if it is not heard there, `None` if it is
+    pub fn missing_talk_power(&self) -> Option<(i32, i32)> {
+        let client = self.clients.get(&self.own_client)?;
+        let channel = self.channels.get(&client.channel)?;
+        if client.is_talker || client.talk_power >= channel.needed_talk_power {
+            return None;
+        }
+
+        Some((channel.needed_talk_power, client.talk_power))
+    }
+
+    pub fn contains_channel(&self, id: ChannelId) -> bool {
+        self.channels.contains_key(&id)
+    }
+
+    /// Whether joining the channel needs a password
+    pub fn has_password(&self, id: ChannelId) -> bool {
+        self.channels.get(&id).map_or(false, |c| c.has_password)
+    }
+
+    /// The highest bitrate in kbit/s bots send in a channel.
+    ///
+    /// It grows with the codec quality from 16 kbit/s to 192 kbit/s for
+    /// Opus Music, voice codecs get half of that.
+    pub fn max_bitrate(&self, id: ChannelId) -> Option<u32> {
+        let channel = self.channels.get(&id)?;
+        let max = 16 + u32::from(channel.codec_quality.min(10)) * 176 / 10;
+
+        if channel.codec == Codec::OpusMusic {
+            Some(max)
+        } else {
+            Some(max / 2)
+        }
+    }
+
+    /// The path of a channel in the format used by bookmarks, e.g. `Lobby/Music`.
+    pub fn channel_path(&self, id: ChannelId) -> Option<String> {
+        let mut channel = self.channels.get(&id)?;
+        let mut names = vec![&channel.name[..]];
+
+        // Channel 0 is the root channel
+        while channel.parent != ChannelId(0) {
+            channel = self.channels.get(&channel.parent)?;
+            names.push(&channel.name);
+        }
+        names.reverse();
+
+        Some(names.join("/"))
+    }
+
+    /// Finds a channel by its path, e.g. `Lobby/Music`.
+    pub fn find_channel(&self, path: &str) -> Option<ChannelId> {
+        let names = path.split('/').map(str::trim).filter(|s| !s.is_empty());
+
+        // Channel 0 is the root channel
+        let mut parent = ChannelId(0);
+        for name in names {
+            parent = self
+                .channels
+                .iter()
+                .find(|(_, c)| c.parent == parent && c.name == name)
+                .map(|(id, _)| *id)?;
+        }
+
+        if parent == ChannelId(0) {
+            None
+        } else {
+            Some(parent)
+        }
+    }
+
+    /// Finds a channel by its id, its path or its name if no other channel has the same name.
+    pub fn resolve_channel(&self, channel: &str) -> Option<ChannelId> {
+        let channel = channel.trim();
+
+        if let Ok(id) = channel.parse::<u64>() {
+            if self.contains_channel(ChannelId(id)) {
+                return Some(ChannelId(id));
+            }
+        }
+
+        if let Some(id) = self.find_channel(channel) {
+            return Some(id);
+        }
+
+        let mut matching = self.channels.iter().filter(|(_, c)| c.name == channel);
+        match (matching.next(), matching.next()) {
+            (Some((id, _)), None) => Some(*id),
+            _ => None,
+        }
+    }
+
+    /// Finds a client by its nickname, ignoring the case
+    pub fn find_client(&self, name: &str) -> Option<ClientId> {
+        let name = name.trim();
+        let exact = self
+            .clients
+            .iter()
+            .find(|(_, c)| c.name == name)
+            .map(|(id, _)| *id);
+
+        exact.or_else(|| {
+            self.clients
+                .iter()
+                .find(|(_, c)| c.name.eq_ignore_ascii_case(name))
+                .map(|(id, _)| *id)
+        })
+    }
+
+    pub fn client_name(&self, id: ClientId) -> Option<String> {
+        self.clients.get(&id).map(|c| c.name.clone())
+    }
+
+    /// Unique ids of the clients in a channel
+    pub fn uids_in_channel(&self, channel: ChannelId) -> Vec<String> {
+        self.clients
+            .values()
+            .filter(|c| c.channel == channel)
+            .filter_map(|c| c.uid.clone())
+            .collect()
+    }
+
+    /// Whether someone other than the own client talks in a channel, ignoring
+    /// those with one of the given unique ids
+    pub fn is_anyone_talking(&self, channel: ChannelId, ignored: &[String]) -> bool {
+        self.clients
+            .iter()
+            .filter(|(id, c)| **id != self.own_client && c.channel == channel && c.talking)
+            .any(|(_, c)| match &c.uid {
+                Some(uid) => !ignored.contains(uid),
+                None => true,
+            })
+    }
+
+    /// Counts the clients in a channel, ignoring those with one of the given unique ids
+    pub fn listener_count(&self, channel: ChannelId, ignored: &[String]) -> u32 {
+        self.clients
+            .values()
+            .filter(|c| c.channel == channel)
+            .filter(|c| match &c.uid {
+                Some(uid) => !ignored.contains(uid),
+                None => true,
+            })
+            .count() as u32
+    }
+}
@@ -0,0 +1,31 @@
+use serde::{Deserialize, Serialize};
+use tsclientlib::Version;
+
+/// The client version reported to the server.
+///
+/// Only versions with a known signature can be used, servers reject anything else.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ClientVersion {
+    #[serde(rename = "Linux 3.3.2")]
+    Linux,
+    #[serde(rename = "Windows 3.3.2")]
+    Windows,
+    #[serde(rename = "macOS 3.3.2")]
+    MacOs,
+}
+
+impl Default for ClientVersion {
+    fn default() -> Self {
+        ClientVersion::Linux
+    }
+}
+
+impl ClientVersion {
+    pub fn version(self) -> Version {
+        match self {
+            ClientVersion::Linux => Version::Linux_3_3_2,
+            ClientVersion::Windows => Version::Windows_3_3_2,
+            ClientVersion::MacOs => Version::MacOs_3_3_2,
+        }
+    }
+}
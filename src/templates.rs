@@ -0,0 +1,41 @@
+//! Texts of the master that admins can replace with their own.
+//!
+//! Besides the placeholders of a message, templates can use `{channel}`,
+//! `{user}` and `{botname}`, which are filled in for the user the master
+//! replies to. Only the configured texts are filled, so a `{user}` in a
+//! track title or an error stays as it is.
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::i18n;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TemplatesConfig {
+    /// Description of the master, "Poke me if you want a music bot!" if not set
+    pub description: Option<String>,
+    /// Sent to the client a bot was spawned for once it joined, nothing is sent if not set
+    pub greeting: Option<String>,
+    /// Replaces messages by their id in every language, like a catalog of `[i18n]`
+    #[serde(default)]
+    pub messages: HashMap<String, String>,
+}
+
+/// What the variables of a template stand for, unknown values are left empty
+pub struct TemplateVars<'a> {
+    /// Path of the channel of the user, or of the bot if there is no user
+    pub channel: Option<&'a str>,
+    pub user: Option<&'a str>,
+    pub botname: &'a str,
+}
+
+/// Replaces the variables in a template, other placeholders are left for the message
+pub fn fill(text: &str, vars: &TemplateVars) -> String {
+    i18n::fill_placeholders(text, |name| match name {
+        "channel" => Some(vars.channel.unwrap_or_default().to_owned()),
+        "user" => Some(vars.user.unwrap_or_default().to_owned()),
+        "botname" => Some(vars.botname.to_owned()),
+        _ => None,
+    })
+}
@@ -0,0 +1,85 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+use tracing::{error, info};
+
+/// Preferences users store for themselves with chat commands
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct UserPreferences {
+    /// Proposed when the user is the only listener, the volume is not changed on its own
+    pub volume: Option<f64>,
+    /// Replies to the commands of the user are in this language instead of the one of the server
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+}
+
+impl UserPreferences {
+    fn is_empty(&self) -> bool {
+        self.volume.is_none() && self.language.is_none()
+    }
+}
+
+/// Stores the preferences of all users in a single JSON file, keyed by unique id.
+pub struct UserSettingsStore {
+    path: PathBuf,
+    preferences: Mutex<HashMap<String, UserPreferences>>,
+}
+
+impl UserSettingsStore {
+    pub fn new(path: PathBuf) -> Self {
+        let preferences = match fs::read(&path) {
+            Ok(data) => match serde_json::from_slice(&data) {
+                Ok(preferences) => preferences,
+                Err(e) => {
+                    error!("Failed to parse user settings in {:?}: {}", path, e);
+                    HashMap::new()
+                }
+            },
+            Err(e) if e.kind() == io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => {
+                error!("Failed to read user settings from {:?}: {}", path, e);
+                HashMap::new()
+            }
+        };
+
+        Self {
+            path,
+            preferences: Mutex::new(preferences),
+        }
+    }
+
+    pub fn get(&self, uid: &str) -> UserPreferences {
+        self.preferences
+            .lock()
+            .unwrap()
+            .get(uid)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    pub fn set_volume(&self, uid: &str, volume: Option<f64>) {
+        self.update(uid, |p| p.volume = volume);
+    }
+
+    pub fn set_language(&self, uid: &str, language: Option<String>) {
+        self.update(uid, |p| p.language = language);
+    }
+
+    fn update(&self, uid: &str, change: impl FnOnce(&mut UserPreferences)) {
+        let mut preferences = self.preferences.lock().unwrap();
+        change(preferences.entry(uid.to_owned()).or_default());
+        preferences.retain(|_, p| !p.is_empty());
+
+        let result = serde_json::to_vec_pretty(&*preferences)
+            .map_err(io::Error::from)
+            .and_then(|data| fs::write(&self.path, data));
+        match result {
+            Ok(()) => info!("Saved preferences of {}", uid),
+            Err(e) => error!("Failed to save user settings to {:?}: {}", self.path, e),
+        }
+    }
+}
@@ -2,57 +2,158 @@ use std::sync::Arc;
 use std::time::Duration;
 
 use actix::{Actor, Addr};
-use actix_web::{get, middleware::Logger, post, web, App, HttpServer, Responder};
+use actix_web::{
+    get, http::header, middleware::Logger, post, web, App, HttpResponse, HttpServer, Responder,
+};
 use askama::Template;
 use askama_actix::TemplateIntoResponse;
 use serde::{Deserialize, Serialize};
+use tracing::info;
 
-use crate::bot::MasterBot;
-use crate::youtube_dl::AudioMetadata;
+use crate::bot::BotManager;
+use crate::i18n::Locale;
+use crate::logging::LogFilter;
+use crate::resource_usage::ResourceUsage;
+use crate::tr;
+use crate::youtube_dl::{AudioMetadata, Chapter};
 
 mod api;
+mod auth;
 mod bot_executor;
 mod default;
 mod front_end_cookie;
+mod health;
+mod live;
 mod tmtu;
+use auth::{AdminToken, BotControl, PublicAccess, Viewer};
 pub use bot_executor::*;
 use front_end_cookie::FrontEnd;
 
+#[derive(Clone)]
 pub struct WebServerArgs {
     pub domain: String,
     pub bind_address: String,
-    pub bot: Arc<MasterBot>,
+    pub admin_token: Option<String>,
+    pub public: bool,
+    pub bot: Arc<dyn BotManager>,
+    pub log_filter: Arc<LogFilter>,
 }
 
 #[actix_rt::main]
 pub async fn start(args: WebServerArgs) -> std::io::Result<()> {
     let cbot = args.bot.clone();
     let bot_addr: Addr<BotExecutor> = BotExecutor(cbot.clone()).start();
+    let admin_token = AdminToken(args.admin_token.clone());
+    let public = PublicAccess(args.public);
+    let log_filter = args.log_filter.clone();
 
-    HttpServer::new(move || {
+    let server = HttpServer::new(move || {
         App::new()
             .data(bot_addr.clone())
+            .data(admin_token.clone())
+            .data(public.clone())
+            .data(log_filter.clone())
             .wrap(Logger::default())
             .service(index)
             .service(get_bot)
             .service(post_front_end)
+            .service(post_login)
+            .service(post_bot_import)
+            .service(post_bot_pick)
+            .service(get_app)
+            .service(health::get_healthz)
+            .service(health::get_readyz)
             .service(
                 web::scope("/api")
                     .service(api::get_bot_list)
-                    .service(api::get_bot),
+                    .service(api::post_bot_batch)
+                    .service(api::get_bot)
+                    .service(api::get_bot_playlist_m3u)
+                    .service(api::get_bot_queue_text)
+                    .service(api::get_bot_card)
+                    .service(api::get_bot_logs)
+                    .service(api::post_bot_import)
+                    .service(api::post_bot_pick)
+                    .service(api::post_bot_control)
+                    .service(api::post_bot_add)
+                    .service(api::post_bot_command)
+                    .service(api::post_bot_queue_move)
+                    .service(api::post_bot_queue_remove)
+                    .service(api::get_stats)
+                    .service(api::get_stats_top_tracks)
+                    .service(api::get_stats_top_requesters)
+                    .service(api::get_reports)
+                    .service(api::post_report_resolve)
+                    .service(api::get_master)
+                    .service(api::get_master_identities)
+                    .service(api::post_master_description)
+                    .service(api::post_master_channel)
+                    .service(api::post_master_spawn)
+                    .service(api::post_master_maintenance)
+                    .service(api::get_version)
+                    .service(api::get_log_filter)
+                    .service(api::post_log_filter)
+                    .service(live::get_live),
             )
             .service(web::scope("/docs").service(get_api_docs))
             .service(actix_files::Files::new("/static", "web_server/static/"))
-    })
-    .bind(args.bind_address)?
-    .run()
-    .await?;
+    });
 
-    args.bot.quit(String::from("Stopping"));
+    let server = if let Some(listener) = socket_activation_listener() {
+        info!("Using socket passed by systemd");
+        server.listen(listener)?
+    } else {
+        server.bind(args.bind_address)?
+    };
+
+    let addrs = server.addrs();
+    for addr in &addrs {
+        info!("Web server listening on http://{}", addr);
+    }
+    args.bot.set_web_server_status(WebServerStatus::Listening {
+        addresses: addrs.iter().map(|a| a.to_string()).collect(),
+    });
+
+    server.run().await?;
+
+    args.bot.quit(String::from("Stopping")).await;
 
     Ok(())
 }
 
+/// Takes over the listening socket if we were started by systemd socket activation.
+///
+/// See `sd_listen_fds(3)`, the first passed file descriptor is always 3.
+#[cfg(unix)]
+fn socket_activation_listener() -> Option<std::net::TcpListener> {
+    use std::os::unix::io::FromRawFd;
+
+    const SD_LISTEN_FDS_START: i32 = 3;
+
+    let pid = std::env::var("LISTEN_PID").ok()?;
+    if pid.parse::<u32>().ok()? != std::process::id() {
+        return None;
+    }
+
+    let fds = std::env::var("LISTEN_FDS").ok()?.parse::<i32>().ok()?;
+    if fds < 1 {
+        return None;
+    }
+
+    // Make sure child processes like youtube-dl don't think the sockets are meant for them
+    std::env::remove_var("LISTEN_PID");
+    std::env::remove_var("LISTEN_FDS");
+    std::env::remove_var("LISTEN_FDNAMES");
+
+    // Safe because systemd passes ownership of the socket to us
+    Some(unsafe { std::net::TcpListener::from_raw_fd(SD_LISTEN_FDS_START) })
+}
+
+#[cfg(not(unix))]
+fn socket_activation_listener() -> Option<std::net::TcpListener> {
+    None
+}
+
 #[derive(Deserialize)]
 #[serde(rename_all = "kebab-case")]
 struct FrontEndForm {
@@ -64,36 +165,306 @@ async fn post_front_end(form: web::Form<FrontEndForm>) -> impl Responder {
     front_end_cookie::set_front_end(form.into_inner().front_end).await
 }
 
+#[derive(Deserialize)]
+struct LoginForm {
+    token: String,
+}
+
+#[post("/login")]
+async fn post_login(
+    admin_token: web::Data<AdminToken>,
+    form: web::Form<LoginForm>,
+) -> impl Responder {
+    let token = form.into_inner().token;
+    // A wrong token would only be noticed once the next page fails to load
+    if !auth::is_admin_token(&admin_token, &token) {
+        return LoginTemplate.into_response();
+    }
+
+    Ok(auth::set_admin_cookie(&token))
+}
+
+#[derive(Deserialize)]
+struct ImportForm {
+    playlist: String,
+}
+
+#[post("/bot/{name}/import")]
+async fn post_bot_import(
+    bot: web::Data<Addr<BotExecutor>>,
+    name: web::Path<String>,
+    form: web::Form<ImportForm>,
+    control: BotControl,
+) -> Result<HttpResponse, api::ApiErrorKind> {
+    let name = name.into_inner();
+    let request = PlaylistImportRequest {
+        bot: name.clone(),
+        text: form.into_inner().playlist,
+        admin: control.admin,
+    };
+
+    match bot.send(request).await.unwrap() {
+        Some(Ok(_)) => Ok(HttpResponse::Found()
+            .header(header::LOCATION, format!("/bot/{}", name))
+            .finish()),
+        Some(Err(e)) => Err(api::ApiErrorKind::BadRequest(e)),
+        None => Err(api::ApiErrorKind::NotFound),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct PickForm {
+    user: String,
+    number: usize,
+}
+
+#[post("/bot/{name}/pick")]
+async fn post_bot_pick(
+    bot: web::Data<Addr<BotExecutor>>,
+    name: web::Path<String>,
+    form: web::Form<PickForm>,
+    control: BotControl,
+) -> Result<HttpResponse, api::ApiErrorKind> {
+    let name = name.into_inner();
+    let form = form.into_inner();
+    let request = PickRequest {
+        bot: name.clone(),
+        user: form.user,
+        number: form.number,
+        admin: control.admin,
+    };
+
+    match bot.send(request).await.unwrap() {
+        Some(Ok(())) => Ok(HttpResponse::Found()
+            .header(header::LOCATION, format!("/bot/{}", name))
+            .finish()),
+        Some(Err(e)) => Err(api::ApiErrorKind::BadRequest(e)),
+        None => Err(api::ApiErrorKind::NotFound),
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct BotSummary {
+    pub name: String,
+    pub state: crate::bot::State,
+    pub current_title: Option<String>,
+    pub position: Option<Duration>,
+    pub playback_mode: crate::bot::PlaybackMode,
+    /// Only the summoner and admins can change the queue
+    pub locked: bool,
+    pub resources: ResourceUsage,
+}
+
 #[derive(Debug, Serialize)]
-pub struct BotData {
+pub struct BotDetails {
     pub name: String,
     pub state: crate::bot::State,
     pub volume: f64,
+    /// Multiplied with the volume until the next track starts
+    pub track_volume: f64,
+    /// Silenced with `!mute` while the track keeps playing
+    pub muted: bool,
     pub position: Option<Duration>,
     pub currently_playing: Option<AudioMetadata>,
+    pub chapter: Option<Chapter>,
     pub playlist: Vec<AudioMetadata>,
+    pub settings: crate::bot::BotSettings,
+    pub history: Vec<AudioMetadata>,
+    pub searches: Vec<crate::bot::SearchResults>,
+    pub broadcast: Vec<String>,
+    pub private_session: Option<crate::bot::PrivateSession>,
+    /// Only the summoner and admins can change the queue
+    pub locked: bool,
+    pub prefetch: crate::bot::PrefetchStatus,
+    /// How full the buffer of a streamed track is from 0 to 1
+    pub buffer_level: Option<f64>,
+    pub resources: ResourceUsage,
+    /// Events of the server the bot skipped because too many were waiting
+    pub messages: crate::bot::MailboxStats,
 }
 
+#[derive(Debug, Serialize)]
+pub struct MasterData {
+    pub name: String,
+    pub channel: Option<String>,
+    pub description: String,
+    pub uptime: Duration,
+    pub connected: bool,
+    pub maintenance: bool,
+    pub available_names: Vec<String>,
+    pub available_identities: usize,
+    pub connected_bots: Vec<String>,
+    pub web_server: WebServerStatus,
+    /// Only set for frontends that send events to the master
+    pub messages: Option<crate::bot::MailboxStats>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Health {
+    /// Whether the master is connected to the server
+    pub connected: bool,
+    /// Whether the message loop of the master answered in time
+    pub master_responding: bool,
+    /// Music bots whose message loop did not answer in time
+    pub stalled_bots: Vec<String>,
+    /// Music bots that lost their connection and are reconnecting, they do not fail a check
+    pub reconnecting_bots: Vec<String>,
+}
+
+/// A single operation of a batch request
+#[derive(Debug, Deserialize)]
+pub struct BatchItem {
+    pub bot: String,
+    #[serde(flatten)]
+    pub operation: BotOperation,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "action")]
+pub enum BotOperation {
+    Play,
+    Pause,
+    Stop,
+    Next,
+    Clear,
+    /// Only for the admin token
+    Lock,
+    /// Only for the admin token
+    Unlock,
+    /// Volume in percent
+    Volume {
+        volume: f64,
+    },
+    /// Volume of the current track in percent of the volume of the bot
+    TrackVolume {
+        volume: f64,
+    },
+    /// Position in seconds
+    Seek {
+        position: f64,
+    },
+}
+
+#[derive(Debug, Serialize)]
+pub struct BatchResult {
+    pub bot: String,
+    pub ok: bool,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct IdentityInfo {
+    pub uid: String,
+    pub level: Option<u8>,
+    pub used_by: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "snake_case", tag = "state")]
+pub enum WebServerStatus {
+    Starting,
+    Listening { addresses: Vec<String> },
+    Failed { error: String, retry_in: Duration },
+}
+
+impl WebServerStatus {
+    pub fn localize(&self, locale: &Locale) -> String {
+        match self {
+            WebServerStatus::Starting => tr!(locale, "web-server-starting", "Starting"),
+            WebServerStatus::Listening { addresses } => tr!(
+                locale,
+                "web-server-listening",
+                "Listening on {addresses}",
+                addresses = addresses.join(", "),
+            ),
+            WebServerStatus::Failed { error, retry_in } => tr!(
+                locale,
+                "web-server-failed",
+                "Failed ({error}), retrying in {retry_in}",
+                error = error,
+                retry_in = humantime::format_duration(*retry_in),
+            ),
+        }
+    }
+}
+
+impl std::fmt::Display for WebServerStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.localize(&Locale::english()))
+    }
+}
+
+#[derive(Template)]
+#[template(path = "login.htm")]
+struct LoginTemplate;
+
 #[get("/")]
-async fn index(bot: web::Data<Addr<BotExecutor>>, front: FrontEnd) -> impl Responder {
+async fn index(
+    bot: web::Data<Addr<BotExecutor>>,
+    front: FrontEnd,
+    viewer: Result<Viewer, api::ApiErrorKind>,
+) -> impl Responder {
+    if viewer.is_err() {
+        return LoginTemplate.into_response();
+    }
+
     match front {
         FrontEnd::Default => default::index(bot).await,
         FrontEnd::Tmtu => tmtu::index(bot).await,
     }
 }
 
+#[derive(Deserialize)]
+struct SessionQuery {
+    token: Option<String>,
+}
+
 #[get("/bot/{name}")]
 async fn get_bot(
     bot: web::Data<Addr<BotExecutor>>,
     name: web::Path<String>,
+    query: web::Query<SessionQuery>,
     front: FrontEnd,
+    viewer: Result<Viewer, api::ApiErrorKind>,
+    control: Result<BotControl, api::ApiErrorKind>,
 ) -> impl Responder {
+    // Links from the bot description carry the session token, remember it in a cookie
+    if let Some(token) = &query.token {
+        let expected = bot
+            .send(SessionTokenRequest((*name).clone()))
+            .await
+            .unwrap();
+        if !auth::is_session_token(expected.as_deref(), token) {
+            return LoginTemplate.into_response();
+        }
+
+        return Ok(auth::set_session_cookie(&name, token));
+    }
+
+    if viewer.is_err() && control.is_err() {
+        return LoginTemplate.into_response();
+    }
+
     match front {
         FrontEnd::Default => default::get_bot(bot, name.into_inner()).await,
         FrontEnd::Tmtu => tmtu::get_bot(bot, name.into_inner()).await,
     }
 }
 
+/// The single page app is compiled in so it always matches the API of this build
+const APP_HTML: &str = include_str!("../web_server/app.htm");
+
+#[get("/app")]
+async fn get_app(viewer: Result<Viewer, api::ApiErrorKind>) -> impl Responder {
+    if viewer.is_err() {
+        return LoginTemplate.into_response();
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(APP_HTML))
+}
+
 #[derive(Template)]
 #[template(path = "docs/api.htm")]
 struct ApiDocsTemplate;
@@ -108,11 +479,7 @@ mod filters {
 
     pub fn fmt_duration(duration: &Option<Duration>) -> Result<String, askama::Error> {
         if let Some(duration) = duration {
-            let secs = duration.as_secs();
-            let mins = secs / 60;
-            let submin_secs = secs % 60;
-
-            Ok(format!("{:02}:{:02}", mins, submin_secs))
+            Ok(crate::duration::format_timestamp(*duration))
         } else {
             Ok(String::from("--:--"))
         }
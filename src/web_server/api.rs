@@ -1,29 +1,539 @@
+use std::sync::Arc;
+
 use actix::Addr;
-use actix_web::{get, web, HttpResponse, Responder, ResponseError};
+use actix_web::{get, http::header, post, web, HttpResponse, Responder, ResponseError};
 use derive_more::Display;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
+
+use crate::logging::LogFilter;
+use crate::stats::StatsStore;
+use crate::web_server::auth::{Admin, BotControl, Viewer};
+use crate::web_server::{
+    AddRequest, BatchItem, BatchRequest, BotDetailsRequest, BotExecutor, BotLogRequest,
+    BotOperation, BotSummaryListRequest, CardRequest, CommandRequest, ControlRequest,
+    DownloaderRequest, IdentityListRequest, MasterAction, MasterDataRequest, PickForm, PickRequest,
+    PlaylistExportRequest, PlaylistImportRequest, QueueEdit, QueueEditRequest, QueueTextRequest,
+    ReportStoreRequest, StatsStoreRequest,
+};
+use crate::youtube_dl::DownloaderKind;
 
-use crate::web_server::{BotDataListRequest, BotDataRequest, BotExecutor};
+/// Number of entries in the leaderboards if the request does not set a limit
+const DEFAULT_TOP_LIMIT: usize = 10;
+const MAX_TOP_LIMIT: usize = 100;
+/// Number of log entries if the request does not set a limit
+const DEFAULT_LOG_LIMIT: usize = 50;
 
 #[get("/bots")]
-pub async fn get_bot_list(bot: web::Data<Addr<BotExecutor>>) -> impl Responder {
-    let bot_datas = match bot.send(BotDataListRequest).await.unwrap() {
+pub async fn get_bot_list(bot: web::Data<Addr<BotExecutor>>, _: Viewer) -> impl Responder {
+    let bot_summaries = match bot.send(BotSummaryListRequest).await.unwrap() {
+        Ok(data) => data,
+        Err(_) => Vec::with_capacity(0),
+    };
+
+    web::Json(bot_summaries)
+}
+
+#[post("/bots/_batch")]
+pub async fn post_bot_batch(
+    bot: web::Data<Addr<BotExecutor>>,
+    items: web::Json<Vec<BatchItem>>,
+    _: Admin,
+) -> impl Responder {
+    let results = match bot.send(BatchRequest(items.into_inner())).await.unwrap() {
         Ok(data) => data,
         Err(_) => Vec::with_capacity(0),
     };
 
-    web::Json(bot_datas)
+    web::Json(results)
 }
 
 #[get("/bots/{name}")]
-pub async fn get_bot(bot: web::Data<Addr<BotExecutor>>, name: web::Path<String>) -> impl Responder {
-    if let Some(bot_data) = bot.send(BotDataRequest(name.into_inner())).await.unwrap() {
-        Ok(web::Json(bot_data))
+pub async fn get_bot(
+    bot: web::Data<Addr<BotExecutor>>,
+    name: web::Path<String>,
+    _: Viewer,
+) -> impl Responder {
+    if let Some(bot_details) = bot
+        .send(BotDetailsRequest(name.into_inner()))
+        .await
+        .unwrap()
+    {
+        Ok(web::Json(bot_details))
     } else {
         Err(ApiErrorKind::NotFound)
     }
 }
 
+#[get("/bots/{name}/playlist.m3u")]
+pub async fn get_bot_playlist_m3u(
+    bot: web::Data<Addr<BotExecutor>>,
+    name: web::Path<String>,
+    _: Viewer,
+) -> Result<HttpResponse, ApiErrorKind> {
+    match bot
+        .send(PlaylistExportRequest(name.into_inner()))
+        .await
+        .unwrap()
+    {
+        Some(m3u) => Ok(HttpResponse::Ok().content_type("audio/x-mpegurl").body(m3u)),
+        None => Err(ApiErrorKind::NotFound),
+    }
+}
+
+#[get("/bots/{name}/queue.txt")]
+pub async fn get_bot_queue_text(
+    bot: web::Data<Addr<BotExecutor>>,
+    name: web::Path<String>,
+    _: Viewer,
+) -> Result<HttpResponse, ApiErrorKind> {
+    match bot.send(QueueTextRequest(name.into_inner())).await.unwrap() {
+        Some(text) => Ok(HttpResponse::Ok()
+            .content_type("text/plain; charset=utf-8")
+            .body(text)),
+        None => Err(ApiErrorKind::NotFound),
+    }
+}
+
+#[get("/bots/{name}/card.png")]
+pub async fn get_bot_card(
+    bot: web::Data<Addr<BotExecutor>>,
+    name: web::Path<String>,
+    _: Viewer,
+) -> Result<HttpResponse, ApiErrorKind> {
+    match bot.send(CardRequest(name.into_inner())).await.unwrap() {
+        // The progress changes all the time
+        Some(png) => Ok(HttpResponse::Ok()
+            .content_type("image/png")
+            .header(header::CACHE_CONTROL, "no-cache")
+            .body(png)),
+        None => Err(ApiErrorKind::NotFound),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct LogQuery {
+    limit: Option<usize>,
+}
+
+#[get("/bots/{name}/logs")]
+pub async fn get_bot_logs(
+    bot: web::Data<Addr<BotExecutor>>,
+    name: web::Path<String>,
+    query: web::Query<LogQuery>,
+    _: Admin,
+) -> Result<HttpResponse, ApiErrorKind> {
+    let request = BotLogRequest {
+        bot: name.into_inner(),
+        count: query.limit.unwrap_or(DEFAULT_LOG_LIMIT),
+    };
+
+    match bot.send(request).await.unwrap() {
+        Some(entries) => Ok(HttpResponse::Ok().json(entries)),
+        None => Err(ApiErrorKind::NotFound),
+    }
+}
+
+#[derive(Serialize)]
+struct ImportResult {
+    added: usize,
+}
+
+#[post("/bots/{name}/import")]
+pub async fn post_bot_import(
+    bot: web::Data<Addr<BotExecutor>>,
+    name: web::Path<String>,
+    playlist: String,
+    control: BotControl,
+) -> Result<HttpResponse, ApiErrorKind> {
+    let request = PlaylistImportRequest {
+        bot: name.into_inner(),
+        text: playlist,
+        admin: control.admin,
+    };
+
+    match bot.send(request).await.unwrap() {
+        Some(Ok(added)) => Ok(HttpResponse::Ok().json(ImportResult { added })),
+        Some(Err(e)) => Err(ApiErrorKind::BadRequest(e)),
+        None => Err(ApiErrorKind::NotFound),
+    }
+}
+
+#[post("/bots/{name}/pick")]
+pub async fn post_bot_pick(
+    bot: web::Data<Addr<BotExecutor>>,
+    name: web::Path<String>,
+    form: web::Json<PickForm>,
+    control: BotControl,
+) -> Result<HttpResponse, ApiErrorKind> {
+    let form = form.into_inner();
+    let request = PickRequest {
+        bot: name.into_inner(),
+        user: form.user,
+        number: form.number,
+        admin: control.admin,
+    };
+
+    match bot.send(request).await.unwrap() {
+        Some(Ok(())) => Ok(HttpResponse::NoContent().finish()),
+        Some(Err(e)) => Err(ApiErrorKind::BadRequest(e)),
+        None => Err(ApiErrorKind::NotFound),
+    }
+}
+
+#[post("/bots/{name}/control")]
+pub async fn post_bot_control(
+    bot: web::Data<Addr<BotExecutor>>,
+    name: web::Path<String>,
+    operation: web::Json<BotOperation>,
+    control: BotControl,
+) -> Result<HttpResponse, ApiErrorKind> {
+    let request = ControlRequest {
+        bot: name.into_inner(),
+        operation: operation.into_inner(),
+        admin: control.admin,
+    };
+
+    match bot.send(request).await.unwrap() {
+        Some(Ok(())) => Ok(HttpResponse::NoContent().finish()),
+        Some(Err(e)) => Err(ApiErrorKind::BadRequest(e)),
+        None => Err(ApiErrorKind::NotFound),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct CommandForm {
+    command: String,
+}
+
+#[post("/bots/{name}/command")]
+pub async fn post_bot_command(
+    bot: web::Data<Addr<BotExecutor>>,
+    name: web::Path<String>,
+    form: web::Json<CommandForm>,
+    control: BotControl,
+) -> Result<HttpResponse, ApiErrorKind> {
+    let request = CommandRequest {
+        bot: name.into_inner(),
+        command: form.into_inner().command,
+        admin: control.admin,
+    };
+
+    match bot.send(request).await.unwrap() {
+        Some(Ok(())) => Ok(HttpResponse::NoContent().finish()),
+        Some(Err(e)) => Err(ApiErrorKind::BadRequest(e)),
+        None => Err(ApiErrorKind::NotFound),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct AddForm {
+    url: String,
+}
+
+#[post("/bots/{name}/add")]
+pub async fn post_bot_add(
+    bot: web::Data<Addr<BotExecutor>>,
+    name: web::Path<String>,
+    form: web::Json<AddForm>,
+    control: BotControl,
+) -> Result<HttpResponse, ApiErrorKind> {
+    let request = AddRequest {
+        bot: name.into_inner(),
+        url: form.into_inner().url,
+        admin: control.admin,
+    };
+
+    match bot.send(request).await.unwrap() {
+        Some(Ok(())) => Ok(HttpResponse::NoContent().finish()),
+        Some(Err(e)) => Err(ApiErrorKind::BadRequest(e)),
+        None => Err(ApiErrorKind::NotFound),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct QueueMoveForm {
+    from: usize,
+    to: usize,
+}
+
+#[post("/bots/{name}/queue/move")]
+pub async fn post_bot_queue_move(
+    bot: web::Data<Addr<BotExecutor>>,
+    name: web::Path<String>,
+    form: web::Json<QueueMoveForm>,
+    control: BotControl,
+) -> Result<HttpResponse, ApiErrorKind> {
+    let form = form.into_inner();
+    let edit = QueueEdit::Move {
+        from: form.from,
+        to: form.to,
+    };
+
+    edit_queue(bot, name.into_inner(), edit, control).await
+}
+
+#[derive(Deserialize)]
+pub struct QueueRemoveForm {
+    position: usize,
+}
+
+#[post("/bots/{name}/queue/remove")]
+pub async fn post_bot_queue_remove(
+    bot: web::Data<Addr<BotExecutor>>,
+    name: web::Path<String>,
+    form: web::Json<QueueRemoveForm>,
+    control: BotControl,
+) -> Result<HttpResponse, ApiErrorKind> {
+    let edit = QueueEdit::Remove {
+        position: form.into_inner().position,
+    };
+
+    edit_queue(bot, name.into_inner(), edit, control).await
+}
+
+async fn edit_queue(
+    bot: web::Data<Addr<BotExecutor>>,
+    name: String,
+    edit: QueueEdit,
+    control: BotControl,
+) -> Result<HttpResponse, ApiErrorKind> {
+    let request = QueueEditRequest {
+        bot: name,
+        edit,
+        admin: control.admin,
+    };
+
+    match bot.send(request).await.unwrap() {
+        Some(Ok(())) => Ok(HttpResponse::NoContent().finish()),
+        Some(Err(e)) => Err(ApiErrorKind::BadRequest(e)),
+        None => Err(ApiErrorKind::NotFound),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct TopQuery {
+    limit: Option<usize>,
+}
+
+impl TopQuery {
+    fn limit(&self) -> usize {
+        self.limit.unwrap_or(DEFAULT_TOP_LIMIT).min(MAX_TOP_LIMIT)
+    }
+}
+
+#[get("/stats")]
+pub async fn get_stats(
+    bot: web::Data<Addr<BotExecutor>>,
+    _: Viewer,
+) -> Result<HttpResponse, ApiErrorKind> {
+    let stats = stats_store(&bot).await?;
+
+    match web::block(move || stats.summary()).await {
+        Ok(summary) => Ok(HttpResponse::Ok().json(summary)),
+        Err(e) => Err(ApiErrorKind::Internal(e.to_string())),
+    }
+}
+
+#[get("/stats/top/tracks")]
+pub async fn get_stats_top_tracks(
+    bot: web::Data<Addr<BotExecutor>>,
+    query: web::Query<TopQuery>,
+    _: Viewer,
+) -> Result<HttpResponse, ApiErrorKind> {
+    let stats = stats_store(&bot).await?;
+
+    let limit = query.limit();
+    match web::block(move || stats.top_tracks(limit)).await {
+        Ok(tracks) => Ok(HttpResponse::Ok().json(tracks)),
+        Err(e) => Err(ApiErrorKind::Internal(e.to_string())),
+    }
+}
+
+#[get("/stats/top/requesters")]
+pub async fn get_stats_top_requesters(
+    bot: web::Data<Addr<BotExecutor>>,
+    query: web::Query<TopQuery>,
+    _: Viewer,
+) -> Result<HttpResponse, ApiErrorKind> {
+    let stats = stats_store(&bot).await?;
+
+    let limit = query.limit();
+    match web::block(move || stats.top_requesters(limit)).await {
+        Ok(requesters) => Ok(HttpResponse::Ok().json(requesters)),
+        Err(e) => Err(ApiErrorKind::Internal(e.to_string())),
+    }
+}
+
+async fn stats_store(bot: &Addr<BotExecutor>) -> Result<Arc<StatsStore>, ApiErrorKind> {
+    bot.send(StatsStoreRequest)
+        .await
+        .unwrap()
+        .ok_or(ApiErrorKind::NotFound)
+}
+
+#[derive(Deserialize)]
+pub struct ReportQuery {
+    #[serde(default)]
+    resolved: bool,
+}
+
+#[get("/reports")]
+pub async fn get_reports(
+    bot: web::Data<Addr<BotExecutor>>,
+    query: web::Query<ReportQuery>,
+    _: Admin,
+) -> impl Responder {
+    let reports = bot.send(ReportStoreRequest).await.unwrap();
+
+    web::Json(reports.list(query.resolved))
+}
+
+#[post("/reports/{id}/resolve")]
+pub async fn post_report_resolve(
+    bot: web::Data<Addr<BotExecutor>>,
+    id: web::Path<u64>,
+    _: Admin,
+) -> Result<HttpResponse, ApiErrorKind> {
+    let reports = bot.send(ReportStoreRequest).await.unwrap();
+
+    if reports.resolve(id.into_inner()) {
+        Ok(HttpResponse::NoContent().finish())
+    } else {
+        Err(ApiErrorKind::NotFound)
+    }
+}
+
+#[get("/master")]
+pub async fn get_master(bot: web::Data<Addr<BotExecutor>>, _: Viewer) -> impl Responder {
+    web::Json(bot.send(MasterDataRequest).await.unwrap())
+}
+
+#[get("/master/identities")]
+pub async fn get_master_identities(bot: web::Data<Addr<BotExecutor>>, _: Admin) -> impl Responder {
+    let identities = match bot.send(IdentityListRequest).await.unwrap() {
+        Ok(data) => data,
+        Err(_) => Vec::with_capacity(0),
+    };
+
+    web::Json(identities)
+}
+
+#[derive(Deserialize)]
+pub struct DescriptionForm {
+    description: String,
+}
+
+#[post("/master/description")]
+pub async fn post_master_description(
+    bot: web::Data<Addr<BotExecutor>>,
+    form: web::Json<DescriptionForm>,
+    _: Admin,
+) -> impl Responder {
+    let action = MasterAction::SetDescription(form.into_inner().description);
+
+    execute_master_action(bot, action).await
+}
+
+#[derive(Deserialize)]
+pub struct ChannelForm {
+    channel: String,
+}
+
+#[post("/master/channel")]
+pub async fn post_master_channel(
+    bot: web::Data<Addr<BotExecutor>>,
+    form: web::Json<ChannelForm>,
+    _: Admin,
+) -> impl Responder {
+    let action = MasterAction::MoveToChannel(form.into_inner().channel);
+
+    execute_master_action(bot, action).await
+}
+
+#[post("/master/spawn")]
+pub async fn post_master_spawn(
+    bot: web::Data<Addr<BotExecutor>>,
+    form: web::Json<ChannelForm>,
+    _: Admin,
+) -> impl Responder {
+    let action = MasterAction::SpawnBot(form.into_inner().channel);
+
+    execute_master_action(bot, action).await
+}
+
+#[derive(Deserialize)]
+pub struct MaintenanceForm {
+    enabled: bool,
+}
+
+#[post("/master/maintenance")]
+pub async fn post_master_maintenance(
+    bot: web::Data<Addr<BotExecutor>>,
+    form: web::Json<MaintenanceForm>,
+    _: Admin,
+) -> impl Responder {
+    let action = MasterAction::SetMaintenance(form.into_inner().enabled);
+
+    execute_master_action(bot, action).await
+}
+
+#[derive(Serialize)]
+pub struct VersionInfo {
+    pokebot: &'static str,
+    downloader: DownloaderKind,
+    /// Not set if the downloader cannot be run
+    downloader_version: Option<String>,
+}
+
+#[get("/version")]
+pub async fn get_version(bot: web::Data<Addr<BotExecutor>>, _: Viewer) -> impl Responder {
+    let downloader = bot.send(DownloaderRequest).await.unwrap();
+
+    web::Json(VersionInfo {
+        pokebot: env!("CARGO_PKG_VERSION"),
+        downloader: downloader.kind(),
+        downloader_version: downloader.version().await.ok(),
+    })
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct LogFilterForm {
+    filter: String,
+}
+
+#[get("/log-filter")]
+pub async fn get_log_filter(
+    log_filter: web::Data<Arc<LogFilter>>,
+    _: Admin,
+) -> Result<web::Json<LogFilterForm>, ApiErrorKind> {
+    match log_filter.get() {
+        Ok(filter) => Ok(web::Json(LogFilterForm { filter })),
+        Err(e) => Err(ApiErrorKind::Internal(e)),
+    }
+}
+
+#[post("/log-filter")]
+pub async fn post_log_filter(
+    log_filter: web::Data<Arc<LogFilter>>,
+    form: web::Json<LogFilterForm>,
+    _: Admin,
+) -> Result<HttpResponse, ApiErrorKind> {
+    match log_filter.set(&form.filter) {
+        Ok(()) => Ok(HttpResponse::NoContent().finish()),
+        Err(e) => Err(ApiErrorKind::BadRequest(e)),
+    }
+}
+
+async fn execute_master_action(
+    bot: web::Data<Addr<BotExecutor>>,
+    action: MasterAction,
+) -> Result<HttpResponse, ApiErrorKind> {
+    match bot.send(action).await.unwrap() {
+        Ok(()) => Ok(HttpResponse::NoContent().finish()),
+        Err(e) => Err(ApiErrorKind::BadRequest(e.to_string())),
+    }
+}
+
 #[derive(Serialize)]
 struct ApiError {
     error: String,
@@ -31,9 +541,17 @@ struct ApiError {
 }
 
 #[derive(Debug, Display)]
-enum ApiErrorKind {
+pub enum ApiErrorKind {
     #[display(fmt = "Not Found")]
     NotFound,
+    #[display(fmt = "Bad Request")]
+    BadRequest(String),
+    #[display(fmt = "Unauthorized")]
+    Unauthorized,
+    #[display(fmt = "Forbidden")]
+    Forbidden,
+    #[display(fmt = "Internal Server Error")]
+    Internal(String),
 }
 
 impl ResponseError for ApiErrorKind {
@@ -43,6 +561,26 @@ impl ResponseError for ApiErrorKind {
                 error: self.to_string(),
                 description: String::from("The requested resource was not found"),
             }),
+            ApiErrorKind::BadRequest(ref description) => {
+                HttpResponse::BadRequest().json(ApiError {
+                    error: self.to_string(),
+                    description: description.clone(),
+                })
+            }
+            ApiErrorKind::Unauthorized => HttpResponse::Unauthorized().json(ApiError {
+                error: self.to_string(),
+                description: String::from("A valid admin token is required"),
+            }),
+            ApiErrorKind::Forbidden => HttpResponse::Forbidden().json(ApiError {
+                error: self.to_string(),
+                description: String::from("Admin actions are disabled"),
+            }),
+            ApiErrorKind::Internal(ref description) => {
+                HttpResponse::InternalServerError().json(ApiError {
+                    error: self.to_string(),
+                    description: description.clone(),
+                })
+            }
         }
     }
 }
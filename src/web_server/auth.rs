@@ -0,0 +1,244 @@
+use futures::future::{err, ok, FutureExt, LocalBoxFuture, Ready};
+
+use actix::Addr;
+use actix_web::{
+    dev::Payload,
+    http::header::{AUTHORIZATION, COOKIE, LOCATION, SET_COOKIE},
+    FromRequest, HttpRequest, HttpResponse,
+};
+
+use crate::web_server::api::ApiErrorKind;
+use crate::web_server::{BotExecutor, SessionTokenRequest};
+
+/// The token required for administrative actions, `None` disables them.
+#[derive(Clone)]
+pub struct AdminToken(pub Option<String>);
+
+/// Whether bots can be viewed without the admin token.
+#[derive(Clone)]
+pub struct PublicAccess(pub bool);
+
+const COOKIE_NAME: &str = "admin-token";
+
+/// Extractor that only succeeds if the request carries the admin token,
+/// either as `Authorization: Bearer <token>` or in the `admin-token` cookie.
+pub struct Admin;
+
+impl FromRequest for Admin {
+    type Error = ApiErrorKind;
+    type Future = Ready<Result<Self, ApiErrorKind>>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let token = match req.get_app_data::<AdminToken>() {
+            Some(token) => token,
+            None => return err(ApiErrorKind::Forbidden),
+        };
+
+        let expected = match &token.0 {
+            Some(expected) => expected,
+            None => return err(ApiErrorKind::Forbidden),
+        };
+
+        if has_token(req, COOKIE_NAME, expected) {
+            ok(Admin)
+        } else {
+            err(ApiErrorKind::Unauthorized)
+        }
+    }
+}
+
+/// Extractor for read-only access.
+///
+/// Succeeds for everyone if public access is enabled or no admin token is configured,
+/// otherwise the admin token is required.
+pub struct Viewer;
+
+impl FromRequest for Viewer {
+    type Error = ApiErrorKind;
+    type Future = Ready<Result<Self, ApiErrorKind>>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        if let Some(public) = req.get_app_data::<PublicAccess>() {
+            if public.0 {
+                return ok(Viewer);
+            }
+        }
+
+        let expected = match req.get_app_data::<AdminToken>() {
+            Some(token) => match &token.0 {
+                Some(expected) => expected.clone(),
+                None => return ok(Viewer),
+            },
+            None => return ok(Viewer),
+        };
+
+        if has_token(req, COOKIE_NAME, &expected) {
+            ok(Viewer)
+        } else {
+            err(ApiErrorKind::Unauthorized)
+        }
+    }
+}
+
+/// Extractor for actions on the bot named in the path.
+///
+/// Accepts the admin token as well as the session token the bot links to in
+/// its description, either as bearer token or in the `session-<bot name>` cookie.
+pub struct BotControl {
+    /// Came with the admin token, which may also change the queue of a locked bot
+    pub admin: bool,
+}
+
+impl FromRequest for BotControl {
+    type Error = ApiErrorKind;
+    type Future = LocalBoxFuture<'static, Result<Self, ApiErrorKind>>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        if let Some(token) = req.get_app_data::<AdminToken>() {
+            if let Some(expected) = &token.0 {
+                if has_token(req, COOKIE_NAME, expected) {
+                    return ok(BotControl { admin: true }).boxed_local();
+                }
+            }
+        }
+
+        let name = match req.match_info().get("name") {
+            Some(name) => name.to_owned(),
+            None => return err(ApiErrorKind::Unauthorized).boxed_local(),
+        };
+        let cookie_name = session_cookie_name(&name);
+        let provided = bearer_token(req).or_else(|| cookie(req, &cookie_name));
+        let bot = req.get_app_data::<Addr<BotExecutor>>();
+
+        async move {
+            let (bot, provided) = match (bot, provided) {
+                (Some(bot), Some(provided)) => (bot, provided),
+                _ => return Err(ApiErrorKind::Unauthorized),
+            };
+
+            match bot.send(SessionTokenRequest(name)).await.unwrap() {
+                Some(token) if constant_time_eq(&token, &provided) => {
+                    Ok(BotControl { admin: false })
+                }
+                Some(_) => Err(ApiErrorKind::Unauthorized),
+                None => Err(ApiErrorKind::NotFound),
+            }
+        }
+        .boxed_local()
+    }
+}
+
+/// Whether `token` is the configured admin token, always `false` if none is configured
+pub fn is_admin_token(admin_token: &AdminToken, token: &str) -> bool {
+    match &admin_token.0 {
+        Some(expected) => constant_time_eq(token, expected),
+        None => false,
+    }
+}
+
+/// Whether `token` is the session token of a bot, always `false` if the bot does not exist
+pub fn is_session_token(expected: Option<&str>, token: &str) -> bool {
+    match expected {
+        Some(expected) => constant_time_eq(token, expected),
+        None => false,
+    }
+}
+
+fn has_token(req: &HttpRequest, cookie_name: &str, expected: &str) -> bool {
+    let matches = |provided: Option<String>| match provided {
+        Some(provided) => constant_time_eq(&provided, expected),
+        None => false,
+    };
+
+    matches(bearer_token(req)) || matches(cookie(req, cookie_name))
+}
+
+/// Takes as long for every token of the same length, so it cannot be guessed by timing
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+
+    a.bytes()
+        .zip(b.bytes())
+        .fold(0, |diff, (a, b)| diff | (a ^ b))
+        == 0
+}
+
+fn bearer_token(req: &HttpRequest) -> Option<String> {
+    for header in req.headers().get_all(AUTHORIZATION) {
+        if let Ok(value) = header.to_str() {
+            let mut split = value.splitn(2, ' ');
+            if Some("Bearer") == split.next() {
+                return split.next().map(String::from);
+            }
+        }
+    }
+
+    None
+}
+
+fn cookie(req: &HttpRequest, name: &str) -> Option<String> {
+    for header in req.headers().get_all(COOKIE) {
+        if let Ok(value) = header.to_str() {
+            for c in value.split(';').map(|s| s.trim()) {
+                let mut split = c.splitn(2, '=');
+                if Some(name) == split.next() {
+                    return split.next().map(String::from);
+                }
+            }
+        }
+    }
+
+    None
+}
+
+/// Bot names may contain any character, but cookie names may not
+fn session_cookie_name(bot: &str) -> String {
+    format!("session-{}", percent_encode(bot))
+}
+
+/// Escapes everything but letters, digits and `-._~`
+fn percent_encode(text: &str) -> String {
+    let mut encoded = String::with_capacity(text.len());
+    for byte in text.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+
+    encoded
+}
+
+/// Stores the token in a cookie so the web interface can perform admin actions
+pub fn set_admin_cookie(token: &str) -> HttpResponse {
+    let cookie = format!(
+        "{}={}; Path=/; HttpOnly; SameSite=Strict",
+        COOKIE_NAME, token
+    );
+
+    HttpResponse::Found()
+        .header(SET_COOKIE, cookie)
+        .header(LOCATION, "/")
+        .finish()
+}
+
+/// Stores the session token of a bot so the web interface can control it
+pub fn set_session_cookie(bot: &str, token: &str) -> HttpResponse {
+    let cookie = format!(
+        "{}={}; Path=/; HttpOnly; SameSite=Lax",
+        session_cookie_name(bot),
+        token
+    );
+
+    HttpResponse::Found()
+        .header(SET_COOKIE, cookie)
+        .header(LOCATION, format!("/bot/{}", percent_encode(bot)))
+        .finish()
+}
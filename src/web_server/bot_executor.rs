@@ -1,11 +1,23 @@
 use std::sync::Arc;
+use std::time::Duration;
 
-use actix::{Actor, Context, Handler, Message};
+use actix::{Actor, Context, Handler, Message, ResponseFuture};
+use futures::future::join_all;
+use tracing::info;
 
-use crate::bot::MasterBot;
-use crate::web_server::BotData;
+use crate::bot::{BotManager, MasterActionError, MusicBot};
+use crate::bot_log::LogEntry;
+use crate::command::VolumeChange;
+use crate::queue_text::Target;
+use crate::reports::ReportStore;
+use crate::request_id::RequestId;
+use crate::stats::StatsStore;
+use crate::web_server::{
+    BatchItem, BatchResult, BotDetails, BotOperation, BotSummary, Health, IdentityInfo, MasterData,
+};
+use crate::youtube_dl::Downloader;
 
-pub struct BotExecutor(pub Arc<MasterBot>);
+pub struct BotExecutor(pub Arc<dyn BotManager>);
 
 impl Actor for BotExecutor {
     type Context = Context<Self>;
@@ -28,36 +40,517 @@ impl Handler<BotNameListRequest> for BotExecutor {
     }
 }
 
-pub struct BotDataListRequest;
+pub struct IdentityListRequest;
 
-impl Message for BotDataListRequest {
+impl Message for IdentityListRequest {
     // A plain Vec does not work for some reason
-    type Result = Result<Vec<BotData>, ()>;
+    type Result = Result<Vec<IdentityInfo>, ()>;
 }
 
-impl Handler<BotDataListRequest> for BotExecutor {
-    type Result = Result<Vec<BotData>, ()>;
+impl Handler<IdentityListRequest> for BotExecutor {
+    type Result = Result<Vec<IdentityInfo>, ()>;
 
-    fn handle(&mut self, _: BotDataListRequest, _: &mut Self::Context) -> Self::Result {
+    fn handle(&mut self, _: IdentityListRequest, _: &mut Self::Context) -> Self::Result {
         let bot = &self.0;
 
-        Ok(bot.bot_datas())
+        Ok(bot.identities())
     }
 }
 
-pub struct BotDataRequest(pub String);
+pub struct BotSummaryListRequest;
 
-impl Message for BotDataRequest {
-    type Result = Option<BotData>;
+impl Message for BotSummaryListRequest {
+    // A plain Vec does not work for some reason
+    type Result = Result<Vec<BotSummary>, ()>;
 }
 
-impl Handler<BotDataRequest> for BotExecutor {
-    type Result = Option<BotData>;
+impl Handler<BotSummaryListRequest> for BotExecutor {
+    type Result = Result<Vec<BotSummary>, ()>;
+
+    fn handle(&mut self, _: BotSummaryListRequest, _: &mut Self::Context) -> Self::Result {
+        let bot = &self.0;
+
+        Ok(bot.bot_summaries())
+    }
+}
 
-    fn handle(&mut self, r: BotDataRequest, _: &mut Self::Context) -> Self::Result {
+pub struct BotDetailsRequest(pub String);
+
+impl Message for BotDetailsRequest {
+    type Result = Option<BotDetails>;
+}
+
+impl Handler<BotDetailsRequest> for BotExecutor {
+    type Result = Option<BotDetails>;
+
+    fn handle(&mut self, r: BotDetailsRequest, _: &mut Self::Context) -> Self::Result {
         let name = r.0;
         let bot = &self.0;
 
-        bot.bot_data(name)
+        bot.bot_details(name)
+    }
+}
+
+pub struct StatsStoreRequest;
+
+impl Message for StatsStoreRequest {
+    type Result = Option<Arc<StatsStore>>;
+}
+
+impl Handler<StatsStoreRequest> for BotExecutor {
+    type Result = Option<Arc<StatsStore>>;
+
+    fn handle(&mut self, _: StatsStoreRequest, _: &mut Self::Context) -> Self::Result {
+        self.0.stats()
+    }
+}
+
+pub struct DownloaderRequest;
+
+impl Message for DownloaderRequest {
+    type Result = Arc<Downloader>;
+}
+
+impl Handler<DownloaderRequest> for BotExecutor {
+    type Result = Arc<Downloader>;
+
+    fn handle(&mut self, _: DownloaderRequest, _: &mut Self::Context) -> Self::Result {
+        self.0.downloader()
+    }
+}
+
+pub struct ReportStoreRequest;
+
+impl Message for ReportStoreRequest {
+    type Result = Arc<ReportStore>;
+}
+
+impl Handler<ReportStoreRequest> for BotExecutor {
+    type Result = Arc<ReportStore>;
+
+    fn handle(&mut self, _: ReportStoreRequest, _: &mut Self::Context) -> Self::Result {
+        self.0.reports()
+    }
+}
+
+pub struct MasterDataRequest;
+
+impl Message for MasterDataRequest {
+    type Result = MasterData;
+}
+
+impl Handler<MasterDataRequest> for BotExecutor {
+    type Result = ResponseFuture<MasterData>;
+
+    fn handle(&mut self, _: MasterDataRequest, _: &mut Self::Context) -> Self::Result {
+        let bot = self.0.clone();
+
+        Box::pin(async move { bot.master_data().await })
+    }
+}
+
+pub struct HealthRequest;
+
+impl Message for HealthRequest {
+    type Result = Health;
+}
+
+impl Handler<HealthRequest> for BotExecutor {
+    type Result = ResponseFuture<Health>;
+
+    fn handle(&mut self, _: HealthRequest, _: &mut Self::Context) -> Self::Result {
+        let bot = self.0.clone();
+
+        Box::pin(async move {
+            // Bots that lost their connection have no message loop to ask until they are back
+            let (bots, reconnecting): (Vec<_>, Vec<_>) = bot
+                .bot_names()
+                .into_iter()
+                .filter_map(|name| bot.music_bot(&name))
+                .partition(|music_bot| music_bot.is_connected());
+            let pings = join_all(bots.iter().map(|music_bot| music_bot.ping())).await;
+            let stalled_bots = bots
+                .iter()
+                .zip(pings)
+                .filter(|(_, answered)| !answered)
+                .map(|(music_bot, _)| music_bot.name().to_owned())
+                .collect();
+
+            Health {
+                connected: bot.master_data().await.connected,
+                master_responding: bot.ping().await,
+                stalled_bots,
+                reconnecting_bots: reconnecting
+                    .iter()
+                    .map(|music_bot| music_bot.name().to_owned())
+                    .collect(),
+            }
+        })
+    }
+}
+
+pub enum MasterAction {
+    SetDescription(String),
+    MoveToChannel(String),
+    SetMaintenance(bool),
+    SpawnBot(String),
+}
+
+impl Message for MasterAction {
+    type Result = Result<(), MasterActionError>;
+}
+
+impl Handler<MasterAction> for BotExecutor {
+    type Result = ResponseFuture<Result<(), MasterActionError>>;
+
+    fn handle(&mut self, action: MasterAction, _: &mut Self::Context) -> Self::Result {
+        let bot = self.0.clone();
+
+        Box::pin(async move {
+            match action {
+                MasterAction::SetDescription(description) => bot.set_description(description).await,
+                MasterAction::MoveToChannel(channel) => bot.move_to_channel(channel).await,
+                MasterAction::SetMaintenance(enabled) => {
+                    bot.set_maintenance(enabled);
+                    Ok(())
+                }
+                MasterAction::SpawnBot(channel) => bot.spawn_bot_in(&channel).await.map(|_| ()),
+            }
+        })
+    }
+}
+
+pub struct PlaylistExportRequest(pub String);
+
+impl Message for PlaylistExportRequest {
+    type Result = Option<String>;
+}
+
+impl Handler<PlaylistExportRequest> for BotExecutor {
+    type Result = Option<String>;
+
+    fn handle(&mut self, r: PlaylistExportRequest, _: &mut Self::Context) -> Self::Result {
+        let bot = self.0.music_bot(&r.0)?;
+
+        Some(crate::playlist_file::to_m3u(&bot.queue_with_current()))
+    }
+}
+
+pub struct QueueTextRequest(pub String);
+
+impl Message for QueueTextRequest {
+    type Result = Option<String>;
+}
+
+impl Handler<QueueTextRequest> for BotExecutor {
+    type Result = Option<String>;
+
+    fn handle(&mut self, r: QueueTextRequest, _: &mut Self::Context) -> Self::Result {
+        let bot = self.0.music_bot(&r.0)?;
+
+        Some(bot.queue_text(Target::Text).to_string())
+    }
+}
+
+pub struct PlaylistImportRequest {
+    pub bot: String,
+    pub text: String,
+    /// Sent with the admin token, which may change the queue of a locked bot
+    pub admin: bool,
+}
+
+impl Message for PlaylistImportRequest {
+    type Result = Option<Result<usize, String>>;
+}
+
+impl Handler<PlaylistImportRequest> for BotExecutor {
+    type Result = ResponseFuture<Option<Result<usize, String>>>;
+
+    fn handle(&mut self, r: PlaylistImportRequest, _: &mut Self::Context) -> Self::Result {
+        let bot = self.0.music_bot(&r.bot);
+
+        Box::pin(async move {
+            let bot = bot?;
+            if !r.admin {
+                if let Err(e) = bot.check_unlocked() {
+                    return Some(Err(e));
+                }
+            }
+            let request_id = RequestId::next();
+            info!("{} Importing playlist file from web", request_id);
+
+            let mut entries = crate::playlist_file::parse(&r.text, request_id);
+            entries.truncate(bot.max_playlist_tracks());
+            bot.drop_rejected(&mut entries, false);
+
+            Some(Ok(bot.enqueue(entries, "Web").await))
+        })
+    }
+}
+
+pub struct PickRequest {
+    pub bot: String,
+    pub user: String,
+    pub number: usize,
+    pub admin: bool,
+}
+
+impl Message for PickRequest {
+    type Result = Option<Result<(), String>>;
+}
+
+impl Handler<PickRequest> for BotExecutor {
+    type Result = ResponseFuture<Option<Result<(), String>>>;
+
+    fn handle(&mut self, r: PickRequest, _: &mut Self::Context) -> Self::Result {
+        let bot = self.0.music_bot(&r.bot);
+
+        Box::pin(async move {
+            let bot = bot?;
+            if !r.admin {
+                if let Err(e) = bot.check_unlocked() {
+                    return Some(Err(e));
+                }
+            }
+
+            Some(bot.pick(&r.user, r.number, false).await)
+        })
+    }
+}
+
+/// A chat command without the prefix, e.g. `volume +10`
+pub struct CommandRequest {
+    pub bot: String,
+    pub command: String,
+    pub admin: bool,
+}
+
+impl Message for CommandRequest {
+    type Result = Option<Result<(), String>>;
+}
+
+impl Handler<CommandRequest> for BotExecutor {
+    type Result = ResponseFuture<Option<Result<(), String>>>;
+
+    fn handle(&mut self, r: CommandRequest, _: &mut Self::Context) -> Self::Result {
+        let bot = self.0.music_bot(&r.bot);
+
+        Box::pin(async move { Some(bot?.run_command(&r.command, r.admin).await) })
+    }
+}
+
+/// Positions are 1-based like in `!queue`
+pub enum QueueEdit {
+    Move { from: usize, to: usize },
+    Remove { position: usize },
+}
+
+pub struct QueueEditRequest {
+    pub bot: String,
+    pub edit: QueueEdit,
+    pub admin: bool,
+}
+
+impl Message for QueueEditRequest {
+    type Result = Option<Result<(), String>>;
+}
+
+impl Handler<QueueEditRequest> for BotExecutor {
+    type Result = Option<Result<(), String>>;
+
+    fn handle(&mut self, r: QueueEditRequest, _: &mut Self::Context) -> Self::Result {
+        let bot = self.0.music_bot(&r.bot)?;
+        if !r.admin {
+            if let Err(e) = bot.check_unlocked() {
+                return Some(Err(e));
+            }
+        }
+
+        Some(match r.edit {
+            QueueEdit::Move { from, to } => bot.move_entry(from, to),
+            QueueEdit::Remove { position } => bot.remove_entry(position).map(|_| ()),
+        })
+    }
+}
+
+pub struct BotLogRequest {
+    pub bot: String,
+    pub count: usize,
+}
+
+impl Message for BotLogRequest {
+    type Result = Option<Vec<LogEntry>>;
+}
+
+impl Handler<BotLogRequest> for BotExecutor {
+    type Result = Option<Vec<LogEntry>>;
+
+    fn handle(&mut self, r: BotLogRequest, _: &mut Self::Context) -> Self::Result {
+        let bot = self.0.music_bot(&r.bot)?;
+
+        Some(bot.recent_log(r.count))
+    }
+}
+
+pub struct SessionTokenRequest(pub String);
+
+impl Message for SessionTokenRequest {
+    type Result = Option<String>;
+}
+
+impl Handler<SessionTokenRequest> for BotExecutor {
+    type Result = Option<String>;
+
+    fn handle(&mut self, r: SessionTokenRequest, _: &mut Self::Context) -> Self::Result {
+        let bot = self.0.music_bot(&r.0)?;
+
+        Some(bot.session_token().to_owned())
+    }
+}
+
+pub struct BatchRequest(pub Vec<BatchItem>);
+
+impl Message for BatchRequest {
+    // A plain Vec does not work for some reason
+    type Result = Result<Vec<BatchResult>, ()>;
+}
+
+impl Handler<BatchRequest> for BotExecutor {
+    type Result = ResponseFuture<Result<Vec<BatchResult>, ()>>;
+
+    fn handle(&mut self, r: BatchRequest, _: &mut Self::Context) -> Self::Result {
+        let master = self.0.clone();
+
+        Box::pin(async move {
+            let results = r.0.into_iter().map(|item| {
+                let bot = master.music_bot(&item.bot);
+
+                async move {
+                    let result = match bot {
+                        Some(bot) => execute(&bot, item.operation, true).await,
+                        None => Err(String::from("Bot not found")),
+                    };
+
+                    BatchResult {
+                        bot: item.bot,
+                        ok: result.is_ok(),
+                        error: result.err(),
+                    }
+                }
+            });
+
+            Ok(join_all(results).await)
+        })
+    }
+}
+
+/// A single operation on a bot by someone who controls it
+pub struct ControlRequest {
+    pub bot: String,
+    pub operation: BotOperation,
+    pub admin: bool,
+}
+
+impl Message for ControlRequest {
+    type Result = Option<Result<(), String>>;
+}
+
+impl Handler<ControlRequest> for BotExecutor {
+    type Result = ResponseFuture<Option<Result<(), String>>>;
+
+    fn handle(&mut self, r: ControlRequest, _: &mut Self::Context) -> Self::Result {
+        let bot = self.0.music_bot(&r.bot);
+
+        Box::pin(async move { Some(execute(&bot?, r.operation, r.admin).await) })
+    }
+}
+
+/// Runs an operation, only `admin` may lock the queue or change a locked one
+async fn execute(bot: &MusicBot, operation: BotOperation, admin: bool) -> Result<(), String> {
+    match operation {
+        BotOperation::Next | BotOperation::Clear if !admin => bot.check_unlocked()?,
+        BotOperation::Lock | BotOperation::Unlock if !admin => {
+            return Err(String::from("Only admins can lock the queue"));
+        }
+        _ => (),
+    }
+
+    match operation {
+        BotOperation::Play => bot.play(),
+        BotOperation::Pause => bot.pause(),
+        BotOperation::Stop => bot.stop(),
+        BotOperation::Next => bot.next(),
+        BotOperation::Clear => {
+            bot.clear();
+            Ok(())
+        }
+        BotOperation::Lock => {
+            bot.set_locked(true);
+            Ok(())
+        }
+        BotOperation::Unlock => {
+            bot.set_locked(false);
+            Ok(())
+        }
+        BotOperation::Volume { volume } => {
+            bot.change_volume(VolumeChange::Absolute(volume * 0.01))
+                .await
+        }
+        BotOperation::TrackVolume { volume } => {
+            bot.change_track_volume(VolumeChange::Absolute(volume * 0.01))
+        }
+        BotOperation::Seek { position } => {
+            if !position.is_finite() || position < 0.0 {
+                return Err(String::from("Invalid position"));
+            }
+            bot.seek_to(Duration::from_secs_f64(position))
+        }
+    }
+    .map_err(|e| format!("{:?}", e))
+}
+
+pub struct AddRequest {
+    pub bot: String,
+    pub url: String,
+    pub admin: bool,
+}
+
+impl Message for AddRequest {
+    type Result = Option<Result<(), String>>;
+}
+
+impl Handler<AddRequest> for BotExecutor {
+    type Result = ResponseFuture<Option<Result<(), String>>>;
+
+    fn handle(&mut self, r: AddRequest, _: &mut Self::Context) -> Self::Result {
+        let bot = self.0.music_bot(&r.bot);
+
+        Box::pin(async move {
+            let bot = bot?;
+            if !r.admin {
+                if let Err(e) = bot.check_unlocked() {
+                    return Some(Err(e));
+                }
+            }
+
+            bot.add_audio(r.url, String::from("Web"), false).await;
+            Some(Ok(()))
+        })
+    }
+}
+
+pub struct CardRequest(pub String);
+
+impl Message for CardRequest {
+    type Result = Option<Vec<u8>>;
+}
+
+impl Handler<CardRequest> for BotExecutor {
+    type Result = ResponseFuture<Option<Vec<u8>>>;
+
+    fn handle(&mut self, r: CardRequest, _: &mut Self::Context) -> Self::Result {
+        let bot = self.0.music_bot(&r.0);
+
+        Box::pin(async move { bot?.now_playing_card().await })
     }
 }
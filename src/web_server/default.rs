@@ -3,13 +3,13 @@ use actix_web::{http::header, web, Error, HttpResponse};
 use askama::Template;
 use askama_actix::TemplateIntoResponse;
 
-use crate::web_server::{filters, BotData, BotDataRequest, BotExecutor, BotNameListRequest};
+use crate::web_server::{filters, BotDetails, BotDetailsRequest, BotExecutor, BotNameListRequest};
 
 #[derive(Template)]
 #[template(path = "index.htm")]
 struct OverviewTemplate<'a> {
     bot_names: &'a [String],
-    bot: Option<&'a BotData>,
+    bot: Option<&'a BotDetails>,
 }
 
 pub async fn index(bot: web::Data<Addr<BotExecutor>>) -> Result<HttpResponse, Error> {
@@ -28,7 +28,7 @@ pub async fn get_bot(
 ) -> Result<HttpResponse, Error> {
     let bot_names = bot.send(BotNameListRequest).await.unwrap().unwrap();
 
-    if let Some(bot) = bot.send(BotDataRequest(name)).await.unwrap() {
+    if let Some(bot) = bot.send(BotDetailsRequest(name)).await.unwrap() {
         OverviewTemplate {
             bot_names: &bot_names,
             bot: Some(&bot),
@@ -0,0 +1,65 @@
+use actix::Addr;
+use actix_web::{get, web, HttpResponse};
+use serde::Serialize;
+
+use crate::web_server::{BotExecutor, DownloaderRequest, Health, HealthRequest};
+use crate::youtube_dl::DownloaderKind;
+
+#[derive(Serialize)]
+struct HealthReport {
+    ok: bool,
+    #[serde(flatten)]
+    health: Health,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    downloader: Option<DownloaderKind>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    downloader_version: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    downloader_error: Option<String>,
+}
+
+impl HealthReport {
+    fn respond(self) -> HttpResponse {
+        if self.ok {
+            HttpResponse::Ok().json(self)
+        } else {
+            HttpResponse::ServiceUnavailable().json(self)
+        }
+    }
+}
+
+/// Fails if the process is stuck and should be restarted
+#[get("/healthz")]
+pub async fn get_healthz(bot: web::Data<Addr<BotExecutor>>) -> HttpResponse {
+    let health = bot.send(HealthRequest).await.unwrap();
+
+    HealthReport {
+        ok: health.master_responding && health.stalled_bots.is_empty(),
+        health,
+        downloader: None,
+        downloader_version: None,
+        downloader_error: None,
+    }
+    .respond()
+}
+
+/// Fails while the bots cannot play anything, e.g. before the master is connected
+#[get("/readyz")]
+pub async fn get_readyz(bot: web::Data<Addr<BotExecutor>>) -> HttpResponse {
+    let health = bot.send(HealthRequest).await.unwrap();
+    let downloader = bot.send(DownloaderRequest).await.unwrap();
+    // Only runs the binary if the last check is older than a minute
+    let version = downloader.version().await;
+
+    HealthReport {
+        ok: health.connected
+            && health.master_responding
+            && health.stalled_bots.is_empty()
+            && version.is_ok(),
+        health,
+        downloader: Some(downloader.kind()),
+        downloader_version: version.as_ref().ok().cloned(),
+        downloader_error: version.err(),
+    }
+    .respond()
+}
@@ -0,0 +1,118 @@
+use std::time::Duration;
+
+use actix::{Actor, ActorContext, ActorFuture, Addr, AsyncContext, StreamHandler, WrapFuture};
+use actix_web::{get, web, HttpRequest, HttpResponse};
+use actix_web_actors::ws;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, error};
+
+use crate::web_server::auth::Viewer;
+use crate::web_server::{
+    BotDetails, BotDetailsRequest, BotExecutor, BotSummary, BotSummaryListRequest,
+};
+
+/// How often the state of the bots is checked for changes
+const UPDATE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Pushes the state of all bots and of the bot the client looks at whenever it changes
+struct LiveSession {
+    executor: Addr<BotExecutor>,
+    bot: Option<String>,
+    /// The last update that was sent, to skip identical ones
+    last: String,
+}
+
+#[derive(Serialize)]
+struct LiveUpdate {
+    bots: Vec<BotSummary>,
+    bot: Option<BotDetails>,
+}
+
+/// Sent by the client to choose which bot it gets the details of
+#[derive(Deserialize)]
+struct Subscribe {
+    bot: Option<String>,
+}
+
+impl LiveSession {
+    fn send_update(&mut self, ctx: &mut ws::WebsocketContext<Self>) {
+        let executor = self.executor.clone();
+        let bot = self.bot.clone();
+
+        let update = async move {
+            let bots = executor.send(BotSummaryListRequest).await.ok()?.ok()?;
+            let bot = match bot {
+                Some(name) => executor.send(BotDetailsRequest(name)).await.ok()?,
+                None => None,
+            };
+
+            Some(LiveUpdate { bots, bot })
+        };
+
+        ctx.spawn(update.into_actor(self).map(|update, session, ctx| {
+            let update = match update {
+                Some(update) => update,
+                None => return,
+            };
+
+            match serde_json::to_string(&update) {
+                Ok(text) if text != session.last => {
+                    ctx.text(text.clone());
+                    session.last = text;
+                }
+                Ok(_) => (),
+                Err(e) => error!("Failed to serialize live update: {}", e),
+            }
+        }));
+    }
+}
+
+impl Actor for LiveSession {
+    type Context = ws::WebsocketContext<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        self.send_update(ctx);
+        ctx.run_interval(UPDATE_INTERVAL, |session, ctx| session.send_update(ctx));
+    }
+}
+
+impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for LiveSession {
+    fn handle(&mut self, msg: Result<ws::Message, ws::ProtocolError>, ctx: &mut Self::Context) {
+        match msg {
+            Ok(ws::Message::Ping(data)) => ctx.pong(&data),
+            Ok(ws::Message::Text(text)) => match serde_json::from_str::<Subscribe>(&text) {
+                Ok(subscribe) => {
+                    self.bot = subscribe.bot;
+                    self.last.clear();
+                    self.send_update(ctx);
+                }
+                Err(e) => debug!("Ignoring invalid live message: {}", e),
+            },
+            Ok(ws::Message::Close(reason)) => {
+                ctx.close(reason);
+                ctx.stop();
+            }
+            Ok(_) => (),
+            Err(e) => {
+                debug!("Live connection failed: {}", e);
+                ctx.stop();
+            }
+        }
+    }
+}
+
+#[get("/live")]
+pub async fn get_live(
+    bot: web::Data<Addr<BotExecutor>>,
+    req: HttpRequest,
+    stream: web::Payload,
+    _: Viewer,
+) -> Result<HttpResponse, actix_web::Error> {
+    let session = LiveSession {
+        executor: bot.get_ref().clone(),
+        bot: None,
+        last: String::new(),
+    };
+
+    ws::start(session, &req, stream)
+}
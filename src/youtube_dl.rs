@@ -1,11 +1,16 @@
-use std::time::Duration;
-
-use std::process::Stdio;
-use tokio::process::Command;
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdout, Stdio};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use serde::{Deserialize, Serialize};
+use tokio::process::Command;
+use tracing::{debug, error, info, warn};
 
-use log::debug;
+use crate::direct_link;
+use crate::request_id::RequestId;
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct AudioMetadata {
@@ -15,8 +20,184 @@ pub struct AudioMetadata {
     pub thumbnail: Option<String>,
     #[serde(default, deserialize_with = "duration_deserialize")]
     pub duration: Option<Duration>,
+    /// Only known for some sites, e.g. YouTube Music
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub artist: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub track: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub album: Option<String>,
+    /// MusicBrainz id of the recording, found by the metadata lookup
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub recording: Option<String>,
+    /// Only known for some sites, e.g. long YouTube videos
+    #[serde(
+        default,
+        skip_serializing_if = "Vec::is_empty",
+        deserialize_with = "chapters_deserialize"
+    )]
+    pub chapters: Vec<Chapter>,
     #[serde(skip)]
     pub added_by: String,
+    #[serde(skip_deserializing)]
+    pub request_id: RequestId,
+}
+
+impl AudioMetadata {
+    /// Playlist entries are added without a stream url and resolved right before playback
+    pub fn needs_resolving(&self) -> bool {
+        self.url.is_empty()
+    }
+
+    /// The 1-based number and the chapter that contains `position`
+    pub fn chapter_at(&self, position: Duration) -> Option<(usize, &Chapter)> {
+        self.chapters
+            .iter()
+            .enumerate()
+            .rev()
+            .find(|(_, chapter)| chapter.start_time <= position)
+            .map(|(i, chapter)| (i + 1, chapter))
+    }
+
+    /// Whether `next` belongs to the same album or playlist and should follow without a gap
+    pub fn continues_into(&self, next: &AudioMetadata) -> bool {
+        match (&self.album, &next.album) {
+            (Some(album), Some(next_album)) => album == next_album,
+            // Entries of a playlist share the request that added them
+            _ => self.request_id == next.request_id,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Chapter {
+    #[serde(default)]
+    pub title: String,
+    #[serde(deserialize_with = "seconds_deserialize")]
+    pub start_time: Duration,
+    #[serde(deserialize_with = "seconds_deserialize")]
+    pub end_time: Duration,
+}
+
+#[derive(Deserialize)]
+struct FlatPlaylist {
+    #[serde(default)]
+    entries: Vec<FlatPlaylistEntry>,
+    title: Option<String>,
+    thumbnail: Option<String>,
+    extractor_key: Option<String>,
+}
+
+impl FlatPlaylist {
+    /// Entries of albums are only named, they share the artwork and title of the album
+    fn into_metadata(self, request_id: RequestId) -> Vec<AudioMetadata> {
+        let is_album = self.extractor_key.as_deref() == Some("BandcampAlbum");
+        let album = self.title.filter(|_| is_album);
+        let thumbnail = self.thumbnail.filter(|_| is_album);
+
+        self.entries
+            .into_iter()
+            .map(|e| {
+                let mut metadata = e.into_metadata(request_id);
+                metadata.album = album.clone();
+                metadata.thumbnail = thumbnail.clone();
+                metadata
+            })
+            .collect()
+    }
+}
+
+#[derive(Deserialize)]
+struct FlatPlaylistEntry {
+    /// Missing for episodes of podcast feeds
+    #[serde(default)]
+    id: String,
+    url: String,
+    title: Option<String>,
+    ie_key: Option<String>,
+    #[serde(default, deserialize_with = "duration_deserialize")]
+    duration: Option<Duration>,
+}
+
+impl FlatPlaylistEntry {
+    fn into_metadata(self, request_id: RequestId) -> AudioMetadata {
+        let webpage_url = if self.ie_key.as_deref() == Some("Youtube") {
+            format!("https://www.youtube.com/watch?v={}", self.id)
+        } else {
+            self.url
+        };
+
+        AudioMetadata {
+            url: String::new(),
+            title: self.title.unwrap_or_else(|| webpage_url.clone()),
+            webpage_url,
+            thumbnail: None,
+            duration: self.duration,
+            artist: None,
+            track: None,
+            album: None,
+            recording: None,
+            chapters: Vec::new(),
+            added_by: String::new(),
+            request_id,
+        }
+    }
+}
+
+/// A podcast feed with its newest episodes first
+pub struct Podcast {
+    pub title: String,
+    pub episodes: Vec<AudioMetadata>,
+}
+
+/// Checks if the url points to a whole playlist instead of a single video
+pub fn is_playlist_url(url: &str) -> bool {
+    url.contains("youtube.com/playlist")
+        || (url.contains("list=") && !url.contains("v="))
+        || is_bandcamp_album_url(url)
+        || is_mixcloud_collection_url(url)
+}
+
+/// Albums are split into their tracks, track pages have `/track/` instead
+fn is_bandcamp_album_url(url: &str) -> bool {
+    url.contains("bandcamp.com/album/")
+}
+
+/// Pages of a Mixcloud user that list shows, e.g. `/<user>/uploads/`.
+///
+/// Single shows are `/<user>/<show>/` and are played like any other track.
+fn is_mixcloud_collection_url(url: &str) -> bool {
+    const COLLECTIONS: &[&str] = &["uploads", "favorites", "listens", "stream", "playlists"];
+
+    let url = match reqwest::Url::parse(url) {
+        Ok(url) => url,
+        Err(_) => return false,
+    };
+    let is_mixcloud = url.host_str().map_or(false, |host| {
+        host == "mixcloud.com" || host.ends_with(".mixcloud.com")
+    });
+    let section = url.path_segments().and_then(|mut segments| segments.nth(1));
+
+    is_mixcloud && section.map_or(false, |section| COLLECTIONS.contains(&section))
+}
+
+/// The video id of a YouTube url, e.g. `dQw4w9WgXcQ`
+fn youtube_id(url: &str) -> Option<String> {
+    let url = reqwest::Url::parse(url).ok()?;
+    let host = url.host_str()?;
+
+    if host == "youtu.be" {
+        url.path_segments()?
+            .next()
+            .filter(|id| !id.is_empty())
+            .map(String::from)
+    } else if host == "youtube.com" || host.ends_with(".youtube.com") {
+        url.query_pairs()
+            .find(|(key, _)| key == "v")
+            .map(|(_, id)| id.into_owned())
+    } else {
+        None
+    }
 }
 
 fn duration_deserialize<'de, D>(deserializer: D) -> Result<Option<Duration>, D::Error>
@@ -28,40 +209,738 @@ where
     Ok(dur.map(Duration::from_secs_f64))
 }
 
-pub async fn get_audio_download_from_url(uri: String) -> Result<AudioMetadata, String> {
-    //youtube-dl sometimes just fails, so we give it a second try
-    let ytdl_output = match run_youtube_dl(&uri).await {
-        Ok(o) => o,
-        Err(e) => {
-            if e.contains("Unable to extract video data") {
-                run_youtube_dl(&uri).await?
+fn seconds_deserialize<'de, D>(deserializer: D) -> Result<Duration, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let secs: f64 = Deserialize::deserialize(deserializer)?;
+
+    Ok(Duration::from_secs_f64(secs.max(0.0)))
+}
+
+/// youtube-dl sets `chapters` to null if there are none
+fn chapters_deserialize<'de, D>(deserializer: D) -> Result<Vec<Chapter>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let chapters: Option<Vec<Chapter>> = Deserialize::deserialize(deserializer)?;
+
+    Ok(chapters.unwrap_or_default())
+}
+
+/// Parts of the errors of sites that only show the video to logged in accounts
+const LOGIN_REQUIRED: &[&str] = &[
+    "Sign in to confirm",
+    "age-restricted",
+    "members-only",
+    "Join this channel to get access",
+    "Private video",
+    "--cookies",
+];
+
+/// How many search results of a fallback source are compared with the missing track
+const FALLBACK_RESULTS: usize = 3;
+
+/// How much the length of a replacement may differ from the missing track
+const FALLBACK_DURATION_TOLERANCE: Duration = Duration::from_secs(15);
+
+/// Whether the downloader failed because the video needs a login
+pub fn needs_login(error: &str) -> bool {
+    LOGIN_REQUIRED.iter().any(|part| error.contains(part))
+}
+
+/// The line of a downloader error that says what went wrong, without the warnings before it
+pub fn error_summary(error: &str) -> &str {
+    let mut lines = error.lines().map(str::trim).filter(|l| !l.is_empty());
+    if needs_login(error) {
+        // The explanation comes first
+        return lines.next().unwrap_or_default();
+    }
+
+    let lines: Vec<&str> = lines.collect();
+    match lines.iter().rev().find_map(|l| l.strip_prefix("ERROR: ")) {
+        Some(line) => line,
+        None => lines.last().copied().unwrap_or_default(),
+    }
+}
+
+/// Where a track is searched for by its title if it can not be extracted anymore
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FallbackSource {
+    Youtube,
+    Soundcloud,
+}
+
+impl FallbackSource {
+    fn search_prefix(self) -> &'static str {
+        match self {
+            FallbackSource::Youtube => "ytsearch",
+            FallbackSource::Soundcloud => "scsearch",
+        }
+    }
+}
+
+impl std::fmt::Display for FallbackSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FallbackSource::Youtube => f.write_str("YouTube"),
+            FallbackSource::Soundcloud => f.write_str("SoundCloud"),
+        }
+    }
+}
+
+fn default_fallbacks() -> Vec<FallbackSource> {
+    vec![FallbackSource::Youtube, FallbackSource::Soundcloud]
+}
+
+/// How long the result of `--version` is reused
+const VERSION_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The program that extracts the audio, both understand the same arguments
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DownloaderKind {
+    /// Preferred because it keeps up with site changes a lot faster
+    YtDlp,
+    YoutubeDl,
+}
+
+impl DownloaderKind {
+    fn binary(self) -> &'static str {
+        match self {
+            DownloaderKind::YtDlp => "yt-dlp",
+            DownloaderKind::YoutubeDl => "youtube-dl",
+        }
+    }
+}
+
+impl std::fmt::Display for DownloaderKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.binary())
+    }
+}
+
+/// Lets the downloader play videos that need a login, e.g. age-restricted ones
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ExtractorAuth {
+    /// Cookies of a logged in account in the Netscape format
+    pub cookies: Option<PathBuf>,
+    /// Proof of origin token for YouTube, only supported by yt-dlp
+    pub po_token: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DownloaderConfig {
+    /// Used instead of looking for yt-dlp and youtube-dl
+    pub binary: Option<PathBuf>,
+    /// How often the binary updates itself, never if not set
+    #[serde(default, with = "crate::duration::serde_human_option")]
+    pub update_interval: Option<Duration>,
+    /// Tracks at least this long are played while they are extracted, never if not set
+    #[serde(default, with = "crate::duration::serde_human_option")]
+    pub stream_longer_than: Option<Duration>,
+    #[serde(flatten)]
+    pub auth: ExtractorAuth,
+    /// Replaces parts of `auth` on a server, by address or Discord server id
+    #[serde(default)]
+    pub servers: HashMap<String, ExtractorAuth>,
+    /// Searched in order for tracks that were removed or are blocked, none if empty
+    #[serde(default = "default_fallbacks")]
+    pub fallbacks: Vec<FallbackSource>,
+}
+
+impl Default for DownloaderConfig {
+    fn default() -> Self {
+        Self {
+            binary: None,
+            update_interval: None,
+            stream_longer_than: None,
+            auth: ExtractorAuth::default(),
+            servers: HashMap::new(),
+            fallbacks: default_fallbacks(),
+        }
+    }
+}
+
+pub struct Downloader {
+    kind: DownloaderKind,
+    binary: PathBuf,
+    stream_longer_than: Option<Duration>,
+    auth: ExtractorAuth,
+    servers: HashMap<String, ExtractorAuth>,
+    fallbacks: Vec<FallbackSource>,
+    /// The last result of `--version` and when it was checked
+    version: Mutex<Option<(Instant, Result<String, String>)>>,
+    /// Probes links to audio files, which are played without the binary
+    http: reqwest::Client,
+}
+
+/// Picks yt-dlp if it is installed and youtube-dl otherwise, unless a binary is configured
+pub async fn open(config: DownloaderConfig) -> Arc<Downloader> {
+    let (kind, binary, version) = match config.binary {
+        Some(binary) => {
+            let kind = if binary.to_string_lossy().contains("yt-dlp") {
+                DownloaderKind::YtDlp
             } else {
-                return Err(e);
+                DownloaderKind::YoutubeDl
+            };
+            let version = startup_version(&binary).await;
+
+            (kind, binary, version)
+        }
+        None => {
+            let yt_dlp = PathBuf::from(DownloaderKind::YtDlp.binary());
+            match startup_version(&yt_dlp).await {
+                Ok(version) => (DownloaderKind::YtDlp, yt_dlp, Ok(version)),
+                Err(_) => {
+                    let youtube_dl = PathBuf::from(DownloaderKind::YoutubeDl.binary());
+                    let version = startup_version(&youtube_dl).await;
+
+                    (DownloaderKind::YoutubeDl, youtube_dl, version)
+                }
             }
         }
     };
 
-    let output = serde_json::from_str(&ytdl_output).map_err(|e| e.to_string())?;
+    match &version {
+        Ok(version) => info!("Using {} {} at {:?}", kind, version, binary),
+        Err(e) => error!("Failed to run {} at {:?}: {}", kind, binary, e),
+    }
+    check_auth(kind, &config.auth, "everywhere");
+    for (server, auth) in &config.servers {
+        check_auth(kind, auth, server);
+    }
+
+    let downloader = Arc::new(Downloader {
+        kind,
+        binary,
+        stream_longer_than: config.stream_longer_than,
+        auth: config.auth,
+        servers: config.servers,
+        fallbacks: config.fallbacks,
+        version: Mutex::new(Some((Instant::now(), version))),
+        http: direct_link::http_client(),
+    });
+
+    if let Some(interval) = config.update_interval {
+        tokio::spawn(downloader.clone().update_periodically(interval));
+    }
+
+    downloader
+}
+
+/// Warns about logins that cannot work, they only fail once a video needs them
+fn check_auth(kind: DownloaderKind, auth: &ExtractorAuth, scope: &str) {
+    if let Some(cookies) = &auth.cookies {
+        if !cookies.is_file() {
+            warn!("Cookies file {:?} used {} does not exist", cookies, scope);
+        }
+    }
+    if auth.po_token.is_some() && kind != DownloaderKind::YtDlp {
+        warn!(
+            "{} does not support PO tokens, the one used {} is ignored",
+            kind, scope
+        );
+    }
+}
+
+fn similar_duration(a: Option<Duration>, b: Option<Duration>) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => {
+            let difference = if a > b { a - b } else { b - a };
+            difference <= FALLBACK_DURATION_TOLERANCE
+        }
+        _ => true,
+    }
+}
+
+/// Only used once at startup, `Downloader::version` checks again later
+async fn startup_version(binary: &Path) -> Result<String, String> {
+    let binary = binary.to_owned();
+    let output = tokio::task::spawn_blocking(move || {
+        std::process::Command::new(binary)
+            .arg("--version")
+            .stdin(Stdio::null())
+            .output()
+    })
+    .await
+    .map_err(|e| e.to_string())?
+    .map_err(|e| e.to_string())?;
 
-    Ok(output)
+    if !output.status.success() {
+        return Err(String::from_utf8_lossy(&output.stderr).into_owned());
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_owned())
 }
 
-async fn run_youtube_dl(url: &str) -> Result<String, String> {
-    let ytdl_args = ["--no-playlist", "-f", "bestaudio/best", "-j", &url];
+impl Downloader {
+    pub fn kind(&self) -> DownloaderKind {
+        self.kind
+    }
+
+    /// The downloader with the login of `server` if it has its own
+    pub fn for_server(self: &Arc<Self>, server: &str) -> Arc<Downloader> {
+        let auth = match self.servers.get(server) {
+            Some(auth) => auth,
+            None => return self.clone(),
+        };
+        debug!("Using the {} login of {}", self.kind, server);
 
-    let mut cmd = Command::new("youtube-dl");
-    cmd.args(&ytdl_args);
-    cmd.stdin(Stdio::null());
+        Arc::new(Downloader {
+            kind: self.kind,
+            binary: self.binary.clone(),
+            stream_longer_than: self.stream_longer_than,
+            auth: ExtractorAuth {
+                cookies: auth.cookies.clone().or_else(|| self.auth.cookies.clone()),
+                po_token: auth.po_token.clone().or_else(|| self.auth.po_token.clone()),
+            },
+            servers: HashMap::new(),
+            fallbacks: self.fallbacks.clone(),
+            version: Mutex::new(self.version.lock().unwrap().clone()),
+            http: self.http.clone(),
+        })
+    }
+
+    fn auth_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if let Some(cookies) = &self.auth.cookies {
+            args.push(String::from("--cookies"));
+            args.push(cookies.to_string_lossy().into_owned());
+        }
+        if let Some(po_token) = &self.auth.po_token {
+            if self.kind == DownloaderKind::YtDlp {
+                args.push(String::from("--extractor-args"));
+                args.push(format!("youtube:po_token={}", po_token));
+            }
+        }
+
+        args
+    }
 
-    debug!("yt-dl command: {:?}", cmd);
-    let ytdl_output = cmd.output().await.unwrap();
+    /// Replaces errors of videos that need a login with one that says what to do about it
+    fn explain_error(&self, error: String) -> String {
+        if !needs_login(&error) {
+            return error;
+        }
+
+        let hint = if self.auth.cookies.is_some() || self.auth.po_token.is_some() {
+            "the configured login was rejected, the cookies may have expired"
+        } else {
+            "ask an admin to configure cookies for the downloader"
+        };
 
-    if !ytdl_output.status.success() {
-        let s = String::from_utf8(ytdl_output.stderr).unwrap();
-        return Err(s);
+        format!(
+            "This video needs a login (age-restricted, members-only or private), {}.\n{}",
+            hint,
+            error.trim()
+        )
     }
 
-    let output_str = String::from_utf8(ytdl_output.stdout).unwrap();
+    pub async fn get_audio_download_from_url(
+        &self,
+        uri: String,
+        request_id: RequestId,
+    ) -> Result<AudioMetadata, String> {
+        if direct_link::is_candidate(&uri) {
+            match direct_link::probe(&self.http, &uri).await {
+                Ok(Some(mut metadata)) => {
+                    info!("{} Playing {} directly", request_id, uri);
+                    metadata.request_id = request_id;
+                    return Ok(metadata);
+                }
+                Ok(None) => debug!("{} {} is not an audio file", request_id, uri),
+                Err(e) => debug!("{} Failed to probe {}: {}", request_id, uri, e),
+            }
+        }
+
+        let ytdl_args = ["--no-playlist", "-f", "bestaudio/best", "-j", &uri];
+
+        //youtube-dl sometimes just fails, so we give it a second try
+        let ytdl_output = match self.run(&ytdl_args, request_id).await {
+            Ok(o) => o,
+            Err(e) => {
+                if e.contains("Unable to extract video data") {
+                    debug!("{} Retrying {}", request_id, self.kind);
+                    self.run(&ytdl_args, request_id).await?
+                } else {
+                    return Err(e);
+                }
+            }
+        };
 
-    Ok(output_str)
+        let mut output: AudioMetadata =
+            serde_json::from_str(&ytdl_output).map_err(|e| e.to_string())?;
+        output.request_id = request_id;
+
+        Ok(output)
+    }
+
+    /// Lists up to `count` entries of a playlist starting at the 1-based index `start`
+    /// without resolving them, which is a lot faster for long playlists.
+    pub async fn get_playlist_page(
+        &self,
+        url: &str,
+        start: usize,
+        count: usize,
+        request_id: RequestId,
+    ) -> Result<Vec<AudioMetadata>, String> {
+        let start_arg = start.to_string();
+        let end_arg = (start + count - 1).to_string();
+        let ytdl_args = [
+            "--flat-playlist",
+            "--playlist-start",
+            &start_arg,
+            "--playlist-end",
+            &end_arg,
+            "-J",
+            url,
+        ];
+
+        let ytdl_output = self.run(&ytdl_args, request_id).await?;
+        let playlist: FlatPlaylist =
+            serde_json::from_str(&ytdl_output).map_err(|e| e.to_string())?;
+
+        Ok(playlist.into_metadata(request_id))
+    }
+
+    /// Reads the newest `count` episodes of the podcast feed at `url`.
+    ///
+    /// Episodes are played from the url in the feed, so they do not need to be resolved.
+    pub async fn get_podcast(
+        &self,
+        url: &str,
+        count: usize,
+        request_id: RequestId,
+    ) -> Result<Podcast, String> {
+        let end_arg = count.to_string();
+        let ytdl_args = ["--flat-playlist", "--playlist-end", &end_arg, "-J", url];
+
+        let ytdl_output = self.run(&ytdl_args, request_id).await?;
+        let feed: FlatPlaylist = serde_json::from_str(&ytdl_output).map_err(|e| e.to_string())?;
+        // Feeds are read by the generic extractor, other sites have their own
+        if feed.extractor_key.as_deref() != Some("Generic") || feed.entries.is_empty() {
+            return Err(String::from("This is not a podcast feed"));
+        }
+
+        let episodes = feed
+            .entries
+            .into_iter()
+            .map(|e| {
+                let mut episode = e.into_metadata(request_id);
+                episode.url = episode.webpage_url.clone();
+                episode
+            })
+            .collect();
+
+        Ok(Podcast {
+            title: feed.title.unwrap_or_else(|| url.to_owned()),
+            episodes,
+        })
+    }
+
+    /// Downloads the audio of `url` to the file at `path`, nothing is written
+    /// if the file is known to be larger than `max_size` bytes
+    pub async fn download_audio(
+        &self,
+        url: &str,
+        path: &std::path::Path,
+        max_size: u64,
+        request_id: RequestId,
+    ) -> Result<(), String> {
+        let path = path.to_string_lossy();
+        let max_size = max_size.to_string();
+        let ytdl_args = [
+            "--no-playlist",
+            "--no-part",
+            "--max-filesize",
+            &max_size,
+            "-f",
+            "bestaudio/best",
+            "-o",
+            &path,
+            url,
+        ];
+
+        self.run(&ytdl_args, request_id).await?;
+
+        Ok(())
+    }
+
+    /// Searches YouTube and returns up to `count` unresolved results
+    pub async fn search(
+        &self,
+        query: &str,
+        count: usize,
+        request_id: RequestId,
+    ) -> Result<Vec<AudioMetadata>, String> {
+        self.search_on(FallbackSource::Youtube, query, count, request_id)
+            .await
+    }
+
+    async fn search_on(
+        &self,
+        source: FallbackSource,
+        query: &str,
+        count: usize,
+        request_id: RequestId,
+    ) -> Result<Vec<AudioMetadata>, String> {
+        let search = format!("{}{}:{}", source.search_prefix(), count, query);
+        let ytdl_args = ["--flat-playlist", "-J", &search];
+
+        let ytdl_output = self.run(&ytdl_args, request_id).await?;
+        let results: FlatPlaylist =
+            serde_json::from_str(&ytdl_output).map_err(|e| e.to_string())?;
+
+        Ok(results
+            .entries
+            .into_iter()
+            .map(|e| e.into_metadata(request_id))
+            .collect())
+    }
+
+    /// Lists up to `count` unresolved tracks related to `track`.
+    ///
+    /// YouTube videos use their mix, which is what YouTube plays next,
+    /// tracks of other sites are searched for on YouTube by their title.
+    pub async fn related(
+        &self,
+        track: &AudioMetadata,
+        count: usize,
+        request_id: RequestId,
+    ) -> Result<Vec<AudioMetadata>, String> {
+        // The track itself is usually the first result
+        let results = match youtube_id(&track.webpage_url) {
+            Some(id) => {
+                let mix = format!("https://www.youtube.com/watch?v={}&list=RD{}", id, id);
+                self.get_playlist_page(&mix, 1, count + 1, request_id)
+                    .await?
+            }
+            None => {
+                let query = match (&track.artist, &track.track) {
+                    (Some(artist), Some(title)) => format!("{} {}", artist, title),
+                    _ => track.title.clone(),
+                };
+                self.search(&query, count + 1, request_id).await?
+            }
+        };
+
+        Ok(results
+            .into_iter()
+            .filter(|result| result.webpage_url != track.webpage_url)
+            .take(count)
+            .collect())
+    }
+
+    /// Searches the fallback sources in order for a track that could not be extracted.
+    ///
+    /// Results of a different length are skipped, they are most likely
+    /// something else with a similar title.
+    pub async fn find_replacement(
+        &self,
+        missing: &AudioMetadata,
+    ) -> Option<(FallbackSource, AudioMetadata)> {
+        let request_id = missing.request_id;
+        // Entries without a title are named after their url
+        if missing.title == missing.webpage_url {
+            return None;
+        }
+
+        for &source in &self.fallbacks {
+            debug!(
+                "{} Searching {} for {:?}",
+                request_id, source, missing.title
+            );
+            let results = match self
+                .search_on(source, &missing.title, FALLBACK_RESULTS, request_id)
+                .await
+            {
+                Ok(results) => results,
+                Err(e) => {
+                    warn!("{} Failed to search {}: {}", request_id, source, e);
+                    continue;
+                }
+            };
+
+            let candidate = results.into_iter().find(|result| {
+                result.webpage_url != missing.webpage_url
+                    && similar_duration(missing.duration, result.duration)
+            });
+            let candidate = match candidate {
+                Some(candidate) => candidate,
+                None => continue,
+            };
+
+            match self
+                .get_audio_download_from_url(candidate.webpage_url, request_id)
+                .await
+            {
+                Ok(replacement) => return Some((source, replacement)),
+                Err(e) => warn!("{} Failed to resolve {} result: {}", request_id, source, e),
+            }
+        }
+
+        None
+    }
+
+    /// Whether the track is long enough to be played while it is extracted.
+    ///
+    /// Cached files and direct links to audio files are always played directly.
+    pub fn should_stream(&self, metadata: &AudioMetadata) -> bool {
+        let direct = metadata.url.starts_with("file://") || metadata.url == metadata.webpage_url;
+
+        match (self.stream_longer_than, metadata.duration) {
+            (Some(min), Some(duration)) => duration >= min && !direct,
+            _ => false,
+        }
+    }
+
+    /// Starts downloading the extracted audio `url` to a pipe instead of a file,
+    /// the page it came from is not extracted again.
+    ///
+    /// The binary only writes as fast as the audio is read, errors are logged
+    /// and end the stream early.
+    pub fn stream(&self, url: &str, request_id: RequestId) -> Result<AudioStream, String> {
+        let mut cmd = std::process::Command::new(&self.binary);
+        cmd.args(self.auth_args());
+        cmd.args(&[
+            "--no-playlist",
+            "--quiet",
+            "--no-progress",
+            "-f",
+            "bestaudio/best",
+            "-o",
+            "-",
+            url,
+        ]);
+        cmd.stdin(Stdio::null());
+        cmd.stdout(Stdio::piped());
+        cmd.stderr(Stdio::piped());
+
+        debug!("{} {} stream command: {:?}", request_id, self.kind, cmd);
+        let mut child = cmd
+            .spawn()
+            .map_err(|e| format!("Failed to run {}: {}", self.kind, e))?;
+        let stdout = child.stdout.take().expect("stdout is piped");
+        let stderr = child.stderr.take().expect("stderr is piped");
+
+        let kind = self.kind;
+        let span = tracing::Span::current();
+        std::thread::spawn(move || {
+            let _entered = span.enter();
+            for line in BufReader::new(stderr).lines().flatten() {
+                if needs_login(&line) {
+                    warn!("{} {} stream needs a login: {}", request_id, kind, line);
+                } else {
+                    warn!("{} {} stream: {}", request_id, kind, line);
+                }
+            }
+        });
+
+        Ok(AudioStream { child, stdout })
+    }
+
+    /// The installed version, fails if the binary cannot be run.
+    ///
+    /// The binary is only run again once the last check is a minute old.
+    pub async fn version(&self) -> Result<String, String> {
+        let last = self.version.lock().unwrap().clone();
+        if let Some((checked, version)) = last {
+            if checked.elapsed() < VERSION_CHECK_INTERVAL {
+                return version;
+            }
+        }
+
+        let version = self
+            .run_without_login(&["--version"], RequestId::default())
+            .await
+            .map(|output| output.trim().to_owned());
+        *self.version.lock().unwrap() = Some((Instant::now(), version.clone()));
+
+        version
+    }
+
+    /// Replaces the binary with the latest release, fails if it was installed by a package manager
+    pub async fn update(&self) -> Result<String, String> {
+        let output = self
+            .run_without_login(&["-U"], RequestId::default())
+            .await?;
+        *self.version.lock().unwrap() = None;
+
+        Ok(output.trim().to_owned())
+    }
+
+    async fn update_periodically(self: Arc<Self>, interval: Duration) {
+        loop {
+            tokio::time::delay_for(interval).await;
+
+            match self.update().await {
+                Ok(output) => info!("Updated {}: {}", self.kind, output),
+                Err(e) => warn!("Failed to update {}: {}", self.kind, e),
+            }
+        }
+    }
+
+    async fn run(&self, ytdl_args: &[&str], request_id: RequestId) -> Result<String, String> {
+        let mut cmd = Command::new(&self.binary);
+        cmd.args(self.auth_args());
+        cmd.args(ytdl_args);
+
+        self.output(cmd, request_id)
+            .await
+            .map_err(|e| self.explain_error(e))
+    }
+
+    /// Cookies are written back to their file after every run, so they are only passed when needed
+    async fn run_without_login(
+        &self,
+        ytdl_args: &[&str],
+        request_id: RequestId,
+    ) -> Result<String, String> {
+        let mut cmd = Command::new(&self.binary);
+        cmd.args(ytdl_args);
+
+        self.output(cmd, request_id).await
+    }
+
+    async fn output(&self, mut cmd: Command, request_id: RequestId) -> Result<String, String> {
+        cmd.stdin(Stdio::null());
+
+        debug!("{} {} command: {:?}", request_id, self.kind, cmd);
+        let ytdl_output = cmd
+            .output()
+            .await
+            .map_err(|e| format!("Failed to run {}: {}", self.kind, e))?;
+
+        if !ytdl_output.status.success() {
+            let s = String::from_utf8(ytdl_output.stderr).unwrap();
+            return Err(s);
+        }
+
+        let output_str = String::from_utf8(ytdl_output.stdout).unwrap();
+
+        Ok(output_str)
+    }
+}
+
+/// Audio that is written to a pipe while it is extracted, the process is
+/// killed once it is dropped.
+pub struct AudioStream {
+    child: Child,
+    stdout: ChildStdout,
+}
+
+impl Read for AudioStream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.stdout.read(buf)
+    }
+}
+
+impl Drop for AudioStream {
+    fn drop(&mut self) {
+        // Fails if the process already exited
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
 }